@@ -3,6 +3,8 @@
 
 use crate::{Address, BlockNumber, Bloom, Keccak, Transaction, H160, H256, U256};
 
+use keccak_hash::keccak;
+use rlp::{Encodable, RlpStream};
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, PartialEq)]
@@ -11,7 +13,7 @@ pub enum BlockId {
     Hash(H256),
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct BlockHeader {
     pub parent_hash: H256,
     pub ommers_hash: H256,
@@ -30,6 +32,35 @@ pub struct BlockHeader {
     pub nonce: u64,
 }
 
+impl BlockHeader {
+    /// Keccak of rlp encoded header, it identifies block.
+    pub fn hash(&self) -> H256 {
+        keccak(rlp::encode(self))
+    }
+}
+
+impl Encodable for BlockHeader {
+    fn rlp_append(&self, stream: &mut RlpStream) {
+        stream
+            .begin_list(15)
+            .append(&self.parent_hash)
+            .append(&self.ommers_hash)
+            .append(&self.beneficiary_address)
+            .append(&self.state_root)
+            .append(&self.transactions_root)
+            .append(&self.receipts_root)
+            .append(&self.logs_bloom)
+            .append(&self.difficulty)
+            .append(&self.number)
+            .append(&self.gas_limit)
+            .append(&self.gas_used)
+            .append(&self.timestamp)
+            .append(&self.extra_data)
+            .append(&self.mix_hash)
+            .append(&self.nonce);
+    }
+}
+
 pub struct BlockReceipt {}
 
 #[derive(Clone, Debug)]
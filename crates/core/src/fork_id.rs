@@ -0,0 +1,51 @@
+// Copyright 2021 Gnosis Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+use rlp::{Decodable, DecoderError, Encodable, Rlp, RlpStream};
+
+/// CRC32 checksum of genesis hash and of all fork blocks and timestamps that passed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ForkHash(pub [u8; 4]);
+
+impl Encodable for ForkHash {
+    fn rlp_append(&self, stream: &mut RlpStream) {
+        stream.encoder().encode_value(&self.0);
+    }
+}
+
+impl Decodable for ForkHash {
+    fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        rlp.decoder().decode_value(|bytes| match bytes.len() {
+            4 => {
+                let mut hash = [0u8; 4];
+                hash.copy_from_slice(bytes);
+                Ok(ForkHash(hash))
+            }
+            len if len < 4 => Err(DecoderError::RlpIsTooShort),
+            _ => Err(DecoderError::RlpIsTooBig),
+        })
+    }
+}
+
+/// Fork identifier from eth status message, see EIP-2124 and EIP-6122. `next` is block number
+/// or timestamp of next fork we know about, zero if there is none.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ForkId {
+    pub hash: ForkHash,
+    pub next: u64,
+}
+
+impl Encodable for ForkId {
+    fn rlp_append(&self, stream: &mut RlpStream) {
+        stream.begin_list(2).append(&self.hash).append(&self.next);
+    }
+}
+
+impl Decodable for ForkId {
+    fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        Ok(ForkId {
+            hash: rlp.val_at(0)?,
+            next: rlp.val_at(1)?,
+        })
+    }
+}
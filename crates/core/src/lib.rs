@@ -3,13 +3,14 @@
 
 mod account;
 mod block;
+mod fork_id;
 pub mod transaction;
 
 // large integers
 pub use ethereum_types::{U256, U64};
 
 // special purpose hashes
-pub use ethereum_types::{Address, Bloom, H160, H256};
+pub use ethereum_types::{Address, Bloom, H160, H256, H512};
 
 pub type Keccak = H256;
 
@@ -20,4 +21,5 @@ pub type BlockNumber = u64;
 // domain types
 pub use account::Account;
 pub use block::{Block, BlockBody, BlockHeader, BlockId, BlockReceipt, WireBlock};
+pub use fork_id::{ForkHash, ForkId};
 pub use transaction::Transaction;
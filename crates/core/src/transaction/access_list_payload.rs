@@ -39,6 +39,35 @@ impl AccessListItem {
     }
 }
 
+/// Access list is shared between typed transactions, see EIP-2930.
+pub(crate) fn rlp_append_access_list(rlp: &mut RlpStream, access_list: &AccessList) {
+    rlp.begin_list(access_list.len());
+    for access in access_list.iter() {
+        rlp.begin_list(2);
+        rlp.append(&access.address);
+        rlp.begin_list(access.storage_keys.len());
+        for storage_key in access.storage_keys.iter() {
+            rlp.append(storage_key);
+        }
+    }
+}
+
+pub(crate) fn rlp_decode_access_list(rlp: &Rlp) -> Result<AccessList, DecoderError> {
+    // access_list pattern: [[{20 bytes}, [{32 bytes}...]]...]
+    let mut access_list: AccessList = Vec::new();
+
+    for account in rlp.iter() {
+        // check if there is list of 2 items
+        if account.item_count()? != 2 {
+            return Err(DecoderError::Custom(
+                "Wrong rlp access list length. We expect two items.",
+            ));
+        }
+        access_list.push(AccessListItem::new(account.val_at(0)?, account.list_at(1)?));
+    }
+    Ok(access_list)
+}
+
 impl PayloadTrait for AccessListPayload {
     fn encode(tx: &Transaction, for_signature: bool) -> Vec<u8> {
         let data = match tx.type_payload {
@@ -58,15 +87,7 @@ impl PayloadTrait for AccessListPayload {
         rlp.append(&tx.value);
         rlp.append(&tx.data);
 
-        rlp.begin_list(data.access_list.len());
-        for access in data.access_list.iter() {
-            rlp.begin_list(2);
-            rlp.append(&access.address);
-            rlp.begin_list(access.storage_keys.len());
-            for storage_key in access.storage_keys.iter() {
-                rlp.append(storage_key);
-            }
-        }
+        rlp_append_access_list(&mut rlp, &data.access_list);
 
         if !for_signature {
             tx.signature().rlp_append(&mut rlp);
@@ -89,20 +110,7 @@ impl PayloadTrait for AccessListPayload {
         let to = rlp.val_at(4)?;
         let value = rlp.val_at(5)?;
         let data = rlp.val_at(6)?;
-        let access_list_rlp = rlp.at(7)?;
-
-        // access_list pattern: [[{20 bytes}, [{32 bytes}...]]...]
-        let mut access_list: AccessList = Vec::new();
-
-        for account in access_list_rlp.iter() {
-            // check if there is list of 2 items
-            if account.item_count()? != 2 {
-                return Err(DecoderError::Custom(
-                    "Wrong rlp access list length. We expect two items.",
-                ));
-            }
-            access_list.push(AccessListItem::new(account.val_at(0)?, account.list_at(1)?));
-        }
+        let access_list = rlp_decode_access_list(&rlp.at(7)?)?;
 
         // we get signature part from here
         let signature = Signature {
@@ -0,0 +1,104 @@
+// Copyright 2021 Gnosis Ltd.
+// SPDX-License-Identifier: Apache-2.0
+use super::{
+    access_list_payload::{rlp_append_access_list, rlp_decode_access_list, AccessList},
+    type_payload::PayloadTrait,
+    CallType, Signature, Transaction, TxType, TypePayload,
+};
+use crate::{H256, U256};
+use keccak_hash::keccak;
+use rlp::{DecoderError, Rlp, RlpStream};
+
+/// Gas that one blob consumes, it is priced by blob base fee and not by base fee.
+pub const GAS_PER_BLOB: u64 = 131072;
+
+/// Fields of EIP-4844 transaction. Blobs are not part of transaction, it commits to them
+/// with versioned hashes and blobs travel next to it in sidecar.
+#[derive(Debug, Clone, Default)]
+pub struct BlobPayload {
+    pub max_priority_fee_per_gas: U256,
+    pub max_fee_per_gas: U256,
+    pub access_list: AccessList,
+    pub max_fee_per_blob_gas: U256,
+    pub blob_versioned_hashes: Vec<H256>,
+}
+
+impl PayloadTrait for BlobPayload {
+    fn encode(tx: &Transaction, for_signature: bool) -> Vec<u8> {
+        let data = match tx.type_payload {
+            TypePayload::Blob(ref data) => data,
+            _ => panic!("Wrong type send to Blob encoding"),
+        };
+        let mut rlp = RlpStream::new();
+        rlp.begin_unbounded_list();
+        rlp.append(
+            &tx.chain_id
+                .expect("ChainId should allways be present in new transaction types"),
+        );
+        rlp.append(&tx.nonce);
+        rlp.append(&data.max_priority_fee_per_gas);
+        rlp.append(&data.max_fee_per_gas);
+        rlp.append(&tx.gas_limit);
+        rlp.append(&tx.to);
+        rlp.append(&tx.value);
+        rlp.append(&tx.data);
+        rlp_append_access_list(&mut rlp, &data.access_list);
+        rlp.append(&data.max_fee_per_blob_gas);
+        rlp.append_list(&data.blob_versioned_hashes);
+
+        if !for_signature {
+            tx.signature().rlp_append(&mut rlp);
+        }
+        rlp.finalize_unbounded_list();
+        [&[TxType::Blob as u8], rlp.as_raw()].concat()
+    }
+
+    fn decode(input: &[u8]) -> Result<Transaction, DecoderError> {
+        let rlp = &Rlp::new(&input[1..]);
+
+        if rlp.item_count()? != 14 {
+            return Err(DecoderError::RlpIncorrectListLen);
+        }
+
+        let chain_id = Some(rlp.val_at(0)?);
+        let nonce = rlp.val_at(1)?;
+        let max_priority_fee_per_gas = rlp.val_at(2)?;
+        let max_fee_per_gas = rlp.val_at(3)?;
+        let gas_limit = rlp.val_at(4)?;
+        let to = rlp.val_at(5)?;
+        if to == CallType::CreateContract() {
+            return Err(DecoderError::Custom(
+                "Blob transaction can't create contract",
+            ));
+        }
+        let value = rlp.val_at(6)?;
+        let data = rlp.val_at(7)?;
+        let access_list = rlp_decode_access_list(&rlp.at(8)?)?;
+        let max_fee_per_blob_gas = rlp.val_at(9)?;
+        let blob_versioned_hashes = rlp.list_at(10)?;
+
+        let signature = Signature {
+            v: rlp.val_at(11)?,
+            r: rlp.val_at(12)?,
+            s: rlp.val_at(13)?,
+        };
+
+        Ok(Transaction::new(
+            TypePayload::Blob(BlobPayload {
+                max_priority_fee_per_gas,
+                max_fee_per_gas,
+                access_list,
+                max_fee_per_blob_gas,
+                blob_versioned_hashes,
+            }),
+            signature,
+            chain_id,
+            keccak(input),
+            nonce,
+            gas_limit,
+            to,
+            value,
+            data,
+        ))
+    }
+}
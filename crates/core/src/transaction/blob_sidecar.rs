@@ -0,0 +1,92 @@
+// Copyright 2021 Gnosis Ltd.
+// SPDX-License-Identifier: Apache-2.0
+use crate::{Bytes, H256};
+use crypto::digest::sha256;
+use rlp::{Decodable, DecoderError, Encodable, Rlp, RlpStream};
+
+/// Version byte of versioned hash for KZG commitment.
+pub const VERSIONED_HASH_VERSION_KZG: u8 = 0x01;
+/// Size of one blob, 4096 field elements of 32 bytes.
+pub const BYTES_PER_BLOB: usize = 131_072;
+/// Size of compressed G1 point used for KZG commitment.
+pub const BYTES_PER_COMMITMENT: usize = 48;
+/// Size of compressed G1 point used for KZG proof.
+pub const BYTES_PER_PROOF: usize = 48;
+
+/// Blobs of blob transaction with their KZG commitments and proofs. It is sent only in
+/// PooledTransactions and it is not part of block.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BlobSidecar {
+    pub blobs: Vec<Bytes>,
+    pub commitments: Vec<Bytes>,
+    pub proofs: Vec<Bytes>,
+}
+
+impl BlobSidecar {
+    /// Hashes that transaction needs to commit to, one for every commitment.
+    pub fn versioned_hashes(&self) -> Vec<H256> {
+        self.commitments
+            .iter()
+            .map(|commitment| {
+                let mut hash = H256::from_slice(&sha256(commitment));
+                hash.0[0] = VERSIONED_HASH_VERSION_KZG;
+                hash
+            })
+            .collect()
+    }
+
+    /// Checks that every blob, commitment and proof has its fixed size.
+    pub fn has_valid_sizes(&self) -> bool {
+        self.blobs.iter().all(|blob| blob.len() == BYTES_PER_BLOB)
+            && self
+                .commitments
+                .iter()
+                .all(|commitment| commitment.len() == BYTES_PER_COMMITMENT)
+            && self
+                .proofs
+                .iter()
+                .all(|proof| proof.len() == BYTES_PER_PROOF)
+    }
+
+    /// Number of bytes that blobs, commitments and proofs take.
+    pub fn size(&self) -> usize {
+        self.blobs
+            .iter()
+            .chain(self.commitments.iter())
+            .chain(self.proofs.iter())
+            .map(|item| item.len())
+            .sum()
+    }
+
+    /// Appends blobs, commitments and proofs as three items of surrounding list.
+    pub fn rlp_append_fields(&self, rlp: &mut RlpStream) {
+        rlp.append_list::<Bytes, Bytes>(&self.blobs);
+        rlp.append_list::<Bytes, Bytes>(&self.commitments);
+        rlp.append_list::<Bytes, Bytes>(&self.proofs);
+    }
+
+    /// Reads three items starting at `index` of list.
+    pub fn rlp_decode_fields(rlp: &Rlp, index: usize) -> Result<Self, DecoderError> {
+        Ok(BlobSidecar {
+            blobs: rlp.list_at(index)?,
+            commitments: rlp.list_at(index + 1)?,
+            proofs: rlp.list_at(index + 2)?,
+        })
+    }
+}
+
+impl Encodable for BlobSidecar {
+    fn rlp_append(&self, rlp: &mut RlpStream) {
+        rlp.begin_list(3);
+        self.rlp_append_fields(rlp);
+    }
+}
+
+impl Decodable for BlobSidecar {
+    fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        if rlp.item_count()? != 3 {
+            return Err(DecoderError::RlpIncorrectListLen);
+        }
+        Self::rlp_decode_fields(rlp, 0)
+    }
+}
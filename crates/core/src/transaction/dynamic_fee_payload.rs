@@ -0,0 +1,88 @@
+// Copyright 2021 Gnosis Ltd.
+// SPDX-License-Identifier: Apache-2.0
+use super::{
+    access_list_payload::{rlp_append_access_list, rlp_decode_access_list, AccessList},
+    type_payload::PayloadTrait,
+    Signature, Transaction, TxType, TypePayload,
+};
+use crate::U256;
+use keccak_hash::keccak;
+use rlp::{DecoderError, Rlp, RlpStream};
+
+/// Fields of EIP-1559 transaction. Sender pays base fee of block that is burned and
+/// priority fee that goes to block author, together capped by max fee.
+#[derive(Debug, Clone, Default)]
+pub struct DynamicFeePayload {
+    pub max_priority_fee_per_gas: U256,
+    pub max_fee_per_gas: U256,
+    pub access_list: AccessList,
+}
+
+impl PayloadTrait for DynamicFeePayload {
+    fn encode(tx: &Transaction, for_signature: bool) -> Vec<u8> {
+        let data = match tx.type_payload {
+            TypePayload::DynamicFee(ref data) => data,
+            _ => panic!("Wrong type send to DynamicFee encoding"),
+        };
+        let mut rlp = RlpStream::new();
+        rlp.begin_unbounded_list();
+        rlp.append(
+            &tx.chain_id
+                .expect("ChainId should allways be present in new transaction types"),
+        );
+        rlp.append(&tx.nonce);
+        rlp.append(&data.max_priority_fee_per_gas);
+        rlp.append(&data.max_fee_per_gas);
+        rlp.append(&tx.gas_limit);
+        rlp.append(&tx.to);
+        rlp.append(&tx.value);
+        rlp.append(&tx.data);
+        rlp_append_access_list(&mut rlp, &data.access_list);
+
+        if !for_signature {
+            tx.signature().rlp_append(&mut rlp);
+        }
+        rlp.finalize_unbounded_list();
+        [&[TxType::DynamicFee as u8], rlp.as_raw()].concat()
+    }
+
+    fn decode(input: &[u8]) -> Result<Transaction, DecoderError> {
+        let rlp = &Rlp::new(&input[1..]);
+
+        if rlp.item_count()? != 12 {
+            return Err(DecoderError::RlpIncorrectListLen);
+        }
+
+        let chain_id = Some(rlp.val_at(0)?);
+        let nonce = rlp.val_at(1)?;
+        let max_priority_fee_per_gas = rlp.val_at(2)?;
+        let max_fee_per_gas = rlp.val_at(3)?;
+        let gas_limit = rlp.val_at(4)?;
+        let to = rlp.val_at(5)?;
+        let value = rlp.val_at(6)?;
+        let data = rlp.val_at(7)?;
+        let access_list = rlp_decode_access_list(&rlp.at(8)?)?;
+
+        let signature = Signature {
+            v: rlp.val_at(9)?,
+            r: rlp.val_at(10)?,
+            s: rlp.val_at(11)?,
+        };
+
+        Ok(Transaction::new(
+            TypePayload::DynamicFee(DynamicFeePayload {
+                max_priority_fee_per_gas,
+                max_fee_per_gas,
+                access_list,
+            }),
+            signature,
+            chain_id,
+            keccak(input),
+            nonce,
+            gas_limit,
+            to,
+            value,
+            data,
+        ))
+    }
+}
@@ -1,6 +1,9 @@
 // Copyright 2021 Gnosis Ltd.
 // SPDX-License-Identifier: Apache-2.0
 pub mod access_list_payload;
+pub mod blob_payload;
+pub mod blob_sidecar;
+pub mod dynamic_fee_payload;
 pub mod legacy_payload;
 pub mod signature;
 pub mod transaction;
@@ -8,6 +11,9 @@ pub mod transaction_type;
 pub mod type_payload;
 
 pub use access_list_payload::AccessListPayload;
+pub use blob_payload::{BlobPayload, GAS_PER_BLOB};
+pub use blob_sidecar::BlobSidecar;
+pub use dynamic_fee_payload::DynamicFeePayload;
 pub use legacy_payload::LegacyPayload;
 pub use signature::{Author, SigV, SigVLegacy, Signature};
 pub use transaction::{ChainId, Transaction};
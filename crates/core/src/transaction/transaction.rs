@@ -1,8 +1,8 @@
 // Copyright 2021 Gnosis Ltd.
 // SPDX-License-Identifier: Apache-2.0
 use super::{
-    signature::replay_protection, type_payload::PayloadTrait, Author, CallType, Signature, TxType,
-    TypePayload,
+    signature::replay_protection, type_payload::PayloadTrait, Author, BlobPayload, BlobSidecar,
+    CallType, Signature, TxType, TypePayload,
 };
 use crate::{Bytes, H256, U256, U64};
 use crypto::publickey::{self, Secret};
@@ -102,6 +102,47 @@ impl Transaction {
         self.type_payload.txtype()
     }
 
+    /// Maximal price per unit of gas that sender offers. For dynamic fee transaction it is max fee.
+    pub fn gas_price(&self) -> U256 {
+        match self.type_payload {
+            TypePayload::Legacy(ref payload) => payload.gas_price,
+            TypePayload::AccessList(ref payload) => payload.legacy_payload.gas_price,
+            TypePayload::DynamicFee(ref payload) => payload.max_fee_per_gas,
+            TypePayload::Blob(ref payload) => payload.max_fee_per_gas,
+        }
+    }
+
+    /// Part of gas price that goes to block author. For older types it is whole gas price.
+    pub fn max_priority_fee_per_gas(&self) -> U256 {
+        match self.type_payload {
+            TypePayload::DynamicFee(ref payload) => payload.max_priority_fee_per_gas,
+            TypePayload::Blob(ref payload) => payload.max_priority_fee_per_gas,
+            _ => self.gas_price(),
+        }
+    }
+
+    /// Maximal price per unit of blob gas, zero for transactions without blobs.
+    pub fn max_fee_per_blob_gas(&self) -> U256 {
+        match self.type_payload {
+            TypePayload::Blob(ref payload) => payload.max_fee_per_blob_gas,
+            _ => U256::zero(),
+        }
+    }
+
+    pub fn blob_versioned_hashes(&self) -> &[H256] {
+        match self.type_payload {
+            TypePayload::Blob(ref payload) => &payload.blob_versioned_hashes,
+            _ => &[],
+        }
+    }
+
+    /// Tip per unit of gas that block author gets when transaction is included in block
+    /// with given base fee. None if transaction does not pay base fee.
+    pub fn effective_tip(&self, base_fee: U256) -> Option<U256> {
+        let max_fee = self.gas_price().checked_sub(base_fee)?;
+        Some(max_fee.min(self.max_priority_fee_per_gas()))
+    }
+
     /// V from signature that is received from wire in RLP.
     /// For legacy it contains V with replay protected chain_id.
     /// For new transaction types it is ordinary V field from signature.
@@ -131,14 +172,45 @@ impl Transaction {
         TypePayload::decode(input)
     }
 
+    /// Appends transaction as item of list, typed transactions are wrapped in rlp bytes.
+    pub fn rlp_append(&self, rlp: &mut rlp::RlpStream) {
+        let data = self.encode();
+        match self.txtype() {
+            TxType::Legacy => rlp.append_raw(&data, 1),
+            TxType::AccessList | TxType::DynamicFee | TxType::Blob => rlp.append(&data),
+        };
+    }
+
+    /// Network form of blob transaction that is sent in PooledTransactions, sidecar is
+    /// next to transaction fields and transaction hash does not cover it.
+    pub fn encode_with_sidecar(&self, sidecar: &BlobSidecar) -> Vec<u8> {
+        let tx = self.encode();
+        let mut rlp = rlp::RlpStream::new_list(4);
+        rlp.append_raw(&tx[1..], 1);
+        sidecar.rlp_append_fields(&mut rlp);
+        [&[TxType::Blob as u8], rlp.as_raw()].concat()
+    }
+
+    /// Decodes transaction that can be in network form, sidecar is returned for blob transactions.
+    pub fn decode_with_sidecar(
+        input: &[u8],
+    ) -> Result<(Transaction, Option<BlobSidecar>), DecoderError> {
+        if input.first() != Some(&(TxType::Blob as u8)) {
+            return Ok((TypePayload::decode(input)?, None));
+        }
+        let rlp = rlp::Rlp::new(&input[1..]);
+        if rlp.item_count()? != 4 {
+            return Ok((BlobPayload::decode(input)?, None));
+        }
+        let tx = [&[TxType::Blob as u8], rlp.at(0)?.as_raw()].concat();
+        let sidecar = BlobSidecar::rlp_decode_fields(&rlp, 1)?;
+        Ok((BlobPayload::decode(&tx)?, Some(sidecar)))
+    }
+
     pub fn rlp_append_list(rlp: &mut rlp::RlpStream, txs: &[Transaction]) {
         rlp.begin_list(txs.len());
         for tx in txs {
-            let data = tx.encode();
-            match tx.txtype() {
-                TxType::Legacy => rlp.append_raw(&data, 1),
-                TxType::AccessList => rlp.append(&data),
-            };
+            tx.rlp_append(rlp);
         }
     }
 
@@ -158,6 +230,24 @@ impl Transaction {
         }
         Ok(decoded)
     }
+
+    /// Decodes list from PooledTransactions, blob transactions come with their sidecars.
+    pub fn rlp_decode_pooled_list(
+        rlp: &rlp::Rlp,
+    ) -> Result<Vec<(Transaction, Option<BlobSidecar>)>, DecoderError> {
+        if !rlp.is_list() {
+            return Err(DecoderError::RlpIncorrectListLen);
+        }
+        let mut decoded = Vec::with_capacity(rlp.item_count()?);
+        for tx in rlp.iter() {
+            if tx.is_list() {
+                decoded.push((TypePayload::decode(tx.as_raw())?, None));
+            } else {
+                decoded.push(Self::decode_with_sidecar(tx.data()?)?);
+            }
+        }
+        Ok(decoded)
+    }
 }
 
 #[cfg(test)]
@@ -165,7 +255,10 @@ mod tests {
     use std::str::FromStr;
 
     use super::{
-        super::{access_list_payload::*, legacy_payload::*},
+        super::{
+            access_list_payload::*, blob_payload::*, blob_sidecar::*, dynamic_fee_payload::*,
+            legacy_payload::*,
+        },
         *,
     };
     use crypto::publickey::{Generator, Public};
@@ -221,6 +314,90 @@ mod tests {
         assert_eq!(hash_original, new_hash);
     }
 
+    #[test]
+    fn dynamic_fee_en_de() {
+        let keypair = crypto::publickey::Random.generate();
+        let mut tx = Transaction::default();
+        tx.chain_id = Some(100);
+        tx.nonce = 7.into();
+        tx.type_payload = TypePayload::DynamicFee(DynamicFeePayload {
+            max_priority_fee_per_gas: 2.into(),
+            max_fee_per_gas: 30.into(),
+            access_list: vec![AccessListItem::new(
+                Address::from_low_u64_be(10),
+                vec![H256::from_low_u64_be(30)],
+            )],
+        });
+        tx.sign(keypair.secret());
+
+        let tx_bytes = tx.encode();
+        assert_eq!(tx_bytes[0], TxType::DynamicFee as u8);
+        let mut tx_revived = Transaction::decode(&tx_bytes).expect("Expect decode to pass");
+        assert_eq!(tx.hash(), tx_revived.hash());
+        tx_revived.recover_author().unwrap();
+        assert_eq!(tx_revived.author(), tx.author());
+        assert_eq!(tx_revived.max_priority_fee_per_gas(), 2.into());
+        assert_eq!(tx_revived.gas_price(), 30.into());
+    }
+
+    #[test]
+    fn blob_en_de() {
+        let keypair = crypto::publickey::Random.generate();
+        let sidecar = BlobSidecar {
+            blobs: vec![vec![1; 32]],
+            commitments: vec![vec![2; 48]],
+            proofs: vec![vec![3; 48]],
+        };
+        let mut tx = Transaction::default();
+        tx.chain_id = Some(100);
+        tx.nonce = 3.into();
+        tx.to = CallType::CallMessage(Address::from_low_u64_be(10));
+        tx.type_payload = TypePayload::Blob(BlobPayload {
+            max_priority_fee_per_gas: 2.into(),
+            max_fee_per_gas: 30.into(),
+            access_list: Vec::new(),
+            max_fee_per_blob_gas: 5.into(),
+            blob_versioned_hashes: sidecar.versioned_hashes(),
+        });
+        tx.sign(keypair.secret());
+
+        let tx_bytes = tx.encode();
+        assert_eq!(tx_bytes[0], TxType::Blob as u8);
+        let tx_revived = Transaction::decode(&tx_bytes).expect("Expect decode to pass");
+        assert_eq!(tx.hash(), tx_revived.hash());
+        assert_eq!(tx_revived.max_fee_per_blob_gas(), 5.into());
+        assert_eq!(tx_revived.blob_versioned_hashes()[0].0[0], 0x01);
+
+        // network form keeps hash of transaction without sidecar
+        let network = tx.encode_with_sidecar(&sidecar);
+        let (tx_revived, sidecar_revived) = Transaction::decode_with_sidecar(&network).unwrap();
+        assert_eq!(tx.hash(), tx_revived.hash());
+        assert_eq!(sidecar_revived, Some(sidecar));
+        let (_, sidecar_revived) = Transaction::decode_with_sidecar(&tx_bytes).unwrap();
+        assert!(sidecar_revived.is_none());
+
+        let mut create = tx.clone();
+        create.to = CallType::CreateContract();
+        create.sign(keypair.secret());
+        assert!(Transaction::decode(&create.encode()).is_err());
+    }
+
+    #[test]
+    fn effective_tip() {
+        let mut tx = Transaction::default();
+        tx.type_payload = TypePayload::DynamicFee(DynamicFeePayload {
+            max_priority_fee_per_gas: 2.into(),
+            max_fee_per_gas: 30.into(),
+            access_list: Vec::new(),
+        });
+        assert_eq!(tx.effective_tip(10.into()), Some(2.into()));
+        assert_eq!(tx.effective_tip(29.into()), Some(1.into()));
+        assert_eq!(tx.effective_tip(31.into()), None);
+
+        let legacy = null_signed_dummy_legacy_tx();
+        assert_eq!(legacy.effective_tip(10.into()), Some(5.into()));
+    }
+
     fn null_signed_dummy_legacy_tx() -> Transaction {
         let tx = Transaction {
             type_payload: TypePayload::Legacy(LegacyPayload {
@@ -6,6 +6,8 @@ use ethereum_types::U64;
 #[repr(u8)]
 pub enum TxType {
     AccessList = 0x01,
+    DynamicFee = 0x02,
+    Blob = 0x03,
     Legacy = 0x00,
 }
 
@@ -16,6 +18,8 @@ impl TxType {
         match n {
             0 => Some(Self::Legacy),
             1 => Some(Self::AccessList),
+            2 => Some(Self::DynamicFee),
+            3 => Some(Self::Blob),
             _ => None,
         }
     }
@@ -23,6 +27,8 @@ impl TxType {
     pub fn try_from_wire_byte(n: u8) -> Result<Self, ()> {
         match n {
             x if x == Self::AccessList as u8 => Ok(Self::AccessList),
+            x if x == Self::DynamicFee as u8 => Ok(Self::DynamicFee),
+            x if x == Self::Blob as u8 => Ok(Self::Blob),
             x if (x & 0x80) != 0x00 => Ok(Self::Legacy),
             _ => Err(()),
         }
@@ -33,6 +39,8 @@ impl TxType {
         match n.map(|t| t.as_u64()) {
             None => Some(Self::Legacy),
             Some(0x01) => Some(Self::AccessList),
+            Some(0x02) => Some(Self::DynamicFee),
+            Some(0x03) => Some(Self::Blob),
             _ => None,
         }
     }
@@ -1,7 +1,9 @@
 // Copyright 2021 Gnosis Ltd.
 // SPDX-License-Identifier: Apache-2.0
 
-use super::{AccessListPayload, LegacyPayload, Transaction, TxType};
+use super::{
+    AccessListPayload, BlobPayload, DynamicFeePayload, LegacyPayload, Transaction, TxType,
+};
 use crate::Address;
 use rlp::{self, DecoderError, Rlp, RlpStream};
 
@@ -15,6 +17,8 @@ pub trait PayloadTrait {
 pub enum TypePayload {
     Legacy(LegacyPayload),
     AccessList(AccessListPayload),
+    DynamicFee(DynamicFeePayload),
+    Blob(BlobPayload),
 }
 
 impl TypePayload {
@@ -22,6 +26,8 @@ impl TypePayload {
         match self {
             Self::Legacy(_) => TxType::Legacy,
             Self::AccessList(_) => TxType::AccessList,
+            Self::DynamicFee(_) => TxType::DynamicFee,
+            Self::Blob(_) => TxType::Blob,
         }
     }
 }
@@ -37,6 +43,8 @@ impl PayloadTrait for TypePayload {
         match tx.txtype() {
             TxType::Legacy => LegacyPayload::encode(tx, for_signature),
             TxType::AccessList => AccessListPayload::encode(tx, for_signature),
+            TxType::DynamicFee => DynamicFeePayload::encode(tx, for_signature),
+            TxType::Blob => BlobPayload::encode(tx, for_signature),
         }
     }
 
@@ -54,6 +62,8 @@ impl PayloadTrait for TypePayload {
             // other transaction types
             match id {
                 TxType::AccessList => AccessListPayload::decode(input),
+                TxType::DynamicFee => DynamicFeePayload::decode(input),
+                TxType::Blob => BlobPayload::decode(input),
                 TxType::Legacy => return Err(DecoderError::Custom("Unknown transaction legacy")),
             }
         }
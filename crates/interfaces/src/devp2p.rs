@@ -1,26 +1,36 @@
 // Copyright 2021 Gnosis Ltd.
 // SPDX-License-Identifier: Apache-2.0
 
+use core::H512;
 use std::{
     collections::{HashMap, HashSet},
     sync::Arc,
 };
 
-pub type ProtocolIdType = [u8; 3];
+/// Capability name as it is sent in devp2p hello message.
+///
+/// Migration: this used to be `[u8; 3]`, which can't hold names longer than three bytes
+/// (snap/1 is "snap"). Adapters that stored the array should store the slice instead,
+/// `to_protocol_type` returns the same bytes for eth and par as before.
+pub type ProtocolIdType = &'static [u8];
 pub type PeerId = usize;
+/// Public key of remote node. Unlike `PeerId` it stays the same when node reconnects.
+pub type NodeId = H512;
 pub type PeerCapability = HashMap<ProtocolId, HashSet<u8>>;
 
 #[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
 pub enum ProtocolId {
     Parity,
     Eth,
+    Snap,
 }
 
 impl ProtocolId {
     pub fn to_protocol_type(self) -> ProtocolIdType {
         match self {
-            Self::Parity => *b"par",
-            Self::Eth => *b"eth",
+            Self::Parity => b"par",
+            Self::Eth => b"eth",
+            Self::Snap => b"snap",
         }
     }
 }
@@ -33,10 +43,12 @@ pub trait Adapter: Send + Sync {
     //unregister handler?
     fn send_mesage(&self, protocol: ProtocolId, peer: &PeerId, mesage_id: u8, data: &[u8]);
     fn penalize_peer(&self, peer: &PeerId, penal: PeerPenal);
+    /// Node id of connected peer, None if peer is not connected anymore.
+    fn node_id(&self, peer: &PeerId) -> Option<NodeId>;
 }
 
 /// Types of penalty that scheduler can send to devp2p
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum PeerPenal {
     Kick,
     Ban,
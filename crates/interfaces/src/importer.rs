@@ -1,11 +1,9 @@
 // Copyright 2020-2021 Gnosis Ltd.
 // SPDX-License-Identifier: Apache-2.0
 
-use core::{BlockNumber, H256, U256, WireBlock};
+use core::{BlockNumber, ForkHash, ForkId, H256, U256, WireBlock};
 use std::str::FromStr;
 
-use ethereum_forkid::{ForkHash, ForkId};
-
 pub struct ImporterStatus {
     pub total_difficulty: U256,
     pub highest_block: (BlockNumber, H256),
@@ -51,7 +49,7 @@ pub trait Importer: Send + Sync {
             .unwrap(),
             network_id: 1,
             fork: ForkId {
-                hash: ForkHash(4234472452),
+                hash: ForkHash([0xfc, 0x64, 0xec, 0x04]),
                 next: 1150000,
             },
         }
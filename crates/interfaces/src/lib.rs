@@ -5,3 +5,5 @@ pub mod blockchain;
 pub mod devp2p;
 pub mod importer;
 pub mod snapshot;
+pub mod state;
+pub mod transaction_pool;
@@ -1,34 +1,103 @@
-use core::{BlockNumber, H256};
+use core::{BlockNumber, Bytes, H256};
+use rlp::{Decodable, DecoderError, Encodable, Rlp, RlpStream};
+use std::fmt;
 
 // Copyright 2021 Gnosis Ltd.
 // SPDX-License-Identifier: Apache-2.0
 
 pub trait Snapshot: Send + Sync {
-    fn create_snapshot(&self);
+    /// Takes snapshot of state at given block and makes it latest local snapshot.
+    fn create_snapshot(&self, block_number: BlockNumber) -> Result<ManifestData, SnapshotError>;
 
-    fn manifest(&self);
-    fn status(&self);
+    /// Manifest of latest local snapshot.
+    fn manifest(&self) -> Option<ManifestData>;
+    fn status(&self) -> RestorationStatus;
 
-    fn chunk(&self);
+    /// Compressed chunk of local snapshot with given keccak hash.
+    fn chunk(&self, hash: H256) -> Option<Bytes>;
     // warping
-    fn begin_restoration(&self, manifest: &Manifest);
+    /// Starting restoration of manifest that is already in progress keeps restored chunks.
+    fn begin_restoration(&self, manifest: &ManifestData) -> Result<(), SnapshotError>;
+    /// Discards chunks restored so far.
     fn abort_restoration(&self);
-    fn restore_chunk(&self, chunk: Vec<u8>, chunk_type: ChunkType);
+    /// Chunk is compressed data whose keccak hash is listed in manifest.
+    fn restore_chunk(
+        &self,
+        hash: H256,
+        chunk: Bytes,
+        chunk_type: ChunkType,
+    ) -> Result<(), SnapshotError>;
+}
 
-    // TODO just for now
-    fn manifest_status(&self) -> Manifest {
-        Manifest {
-            block_number: 0,
-            hash: H256::zero(),
-        }
-    }
+/// Storage for state that is downloaded with snap protocol. Data is already verified against
+/// state root when it gets here.
+pub trait StateWriter: Send + Sync {
+    /// Accounts by hashed address, values are rlp encoded as in state trie.
+    fn write_accounts(&self, accounts: &[(H256, Bytes)]);
+    /// Storage slots by hashed key, values are rlp encoded as in storage trie.
+    fn write_storage(&self, account: &H256, slots: &[(H256, Bytes)]);
+    fn write_code(&self, hash: &H256, code: &[u8]);
+    fn write_trie_node(&self, hash: &H256, node: &[u8]);
+    /// Trie nodes under root that are referenced but not present locally, with snap path set
+    /// (account path followed by storage path, compact encoded) that addresses each of them.
+    fn missing_trie_nodes(&self, root: &H256, limit: usize) -> Vec<(Vec<Bytes>, H256)>;
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ChunkType {
     Block,
     State,
 }
 
+/// Manifest of snapshot in OpenEthereum format. Keccak of its rlp is hash that is advertised to peers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestData {
+    pub version: u64,
+    pub state_hashes: Vec<H256>,
+    pub block_hashes: Vec<H256>,
+    pub state_root: H256,
+    pub block_number: BlockNumber,
+    pub block_hash: H256,
+}
+
+impl ManifestData {
+    pub fn chunk_count(&self) -> usize {
+        self.state_hashes.len() + self.block_hashes.len()
+    }
+}
+
+impl Encodable for ManifestData {
+    fn rlp_append(&self, stream: &mut RlpStream) {
+        stream.begin_list(6);
+        stream.append(&self.version);
+        stream.append_list(&self.state_hashes);
+        stream.append_list(&self.block_hashes);
+        stream.append(&self.state_root);
+        stream.append(&self.block_number);
+        stream.append(&self.block_hash);
+    }
+}
+
+/// Version 1 manifest does not contain version field.
+impl Decodable for ManifestData {
+    fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        let (version, start) = match rlp.item_count()? {
+            5 => (1, 0),
+            6 => (rlp.val_at(0)?, 1),
+            _ => return Err(DecoderError::RlpIncorrectListLen),
+        };
+        Ok(ManifestData {
+            version,
+            state_hashes: rlp.list_at(start)?,
+            block_hashes: rlp.list_at(start + 1)?,
+            state_root: rlp.val_at(start + 2)?,
+            block_number: rlp.val_at(start + 3)?,
+            block_hash: rlp.val_at(start + 4)?,
+        })
+    }
+}
+
+/// Snapshot that we advertise in status message.
 pub struct Manifest {
     pub block_number: BlockNumber,
     pub hash: H256,
@@ -39,3 +108,57 @@ impl Manifest {
         self.block_number == 0
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RestorationStatus {
+    Inactive,
+    Ongoing {
+        state_chunks: u32,
+        block_chunks: u32,
+        state_chunks_done: u32,
+        block_chunks_done: u32,
+    },
+    /// All chunks are restored and state is being committed.
+    Finalizing,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SnapshotError {
+    /// Restoration is not started or it was aborted.
+    RestorationInactive,
+    /// Chunk is not part of manifest or it is already restored.
+    UnknownChunk(H256),
+    /// Chunk could not be decompressed or decoded.
+    InvalidChunk(H256),
+    /// State root after restoration does not match the one in manifest.
+    StateRootMismatch { expected: H256, found: H256 },
+    /// Block that snapshot should be taken at is not available.
+    BlockNotFound(BlockNumber),
+    /// State data (code or trie node) with given hash is not available.
+    MissingState(H256),
+    /// Another snapshot is being created.
+    SnapshotInProgress,
+    Io(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RestorationInactive => write!(f, "Restoration is not active"),
+            Self::UnknownChunk(hash) => write!(f, "Unknown chunk {:?}", hash),
+            Self::InvalidChunk(hash) => write!(f, "Invalid chunk {:?}", hash),
+            Self::StateRootMismatch { expected, found } => write!(
+                f,
+                "State root mismatch. Expected:{:?} found:{:?}",
+                expected, found
+            ),
+            Self::BlockNotFound(number) => write!(f, "Block {} not found", number),
+            Self::MissingState(hash) => write!(f, "Missing state data {:?}", hash),
+            Self::SnapshotInProgress => write!(f, "Snapshot is already in progress"),
+            Self::Io(err) => write!(f, "IO error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
@@ -0,0 +1,11 @@
+// Copyright 2021 Gnosis Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+use core::{Address, U256, U64};
+
+/// Read access to accounts in latest state, used for validating transactions.
+pub trait StateProvider: Send + Sync {
+    /// Nonce of account, zero for account that does not exist.
+    fn account_nonce(&self, address: &Address) -> U64;
+    fn account_balance(&self, address: &Address) -> U256;
+}
@@ -1,29 +1,144 @@
 // Copyright 2021 Gnosis Ltd.
 // SPDX-License-Identifier: Apache-2.0
 
-pub trait TransactionPool {
+use core::{transaction::BlobSidecar, Address, Transaction, WireBlock, H256, U256, U64};
+use std::sync::{mpsc::Receiver, Arc};
 
+/// Reason why transaction is not accepted into pool.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Error {
+    AlreadyKnown,
+    /// Sender can't be recovered from signature.
+    InvalidSignature,
+    /// Gas price is lower than minimal gas price, or pool is full of better paying transactions.
+    Underpriced,
+    /// Transaction with same sender and nonce is present and new one does not pay enough more.
+    ReplacementUnderpriced,
+    /// Max priority fee is higher than max fee.
+    TipAboveFeeCap,
+    /// Gas limit of transaction is higher than block gas limit.
+    GasLimitExceeded,
+    /// Nonce is lower than nonce of sender account.
+    StaleNonce,
+    /// Sender has maximal number of transactions in pool.
+    SenderLimitReached,
+    /// Sender balance does not cover value and gas limit times max fee.
+    InsufficientFunds,
+    /// Pool is not configured for this transaction type.
+    TxTypeNotSupported,
+    /// Blob transaction is without sidecar or sidecar does not match its versioned hashes.
+    InvalidSidecar,
+    /// Sender has maximal number of blobs in pool.
+    BlobLimitReached,
+    /// Blob transactions are accepted only without nonce gap.
+    NonceGap,
+    /// Sender has transactions of other kind in pool, blob and regular transactions of one
+    /// sender are not mixed.
+    SenderReserved,
+    /// Sidecar could not be written to disk, blob transaction is not kept without it.
+    SidecarNotStored,
+}
 
-    /// Register new broadcaster that notifies when new transaction is added to pool
-    fn register_broadcaster(&self);
-    fn unregister_broadcaster(&self);
+/// Why transaction left pool without being mined.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DropReason {
+    /// Pool is full and transaction pays less than new one.
+    Evicted,
+    /// Minimal gas price is raised over transaction tip.
+    Underpriced,
+    GasLimitExceeded,
+    /// Transaction with the same nonce is mined from other source.
+    StaleNonce,
+    InsufficientFunds,
+    /// Explicitly removed from pool.
+    Removed,
+    /// Queued transaction was in pool longer than configured lifetime.
+    Expired,
+}
+
+/// Change of pool content that is sent to subscribers.
+#[derive(Debug, Clone)]
+pub enum PoolEvent {
+    /// Transaction can be included in next block.
+    AddedPending(Arc<Transaction>),
+    /// Transaction waits for nonce gap to be filled or for base fee to drop.
+    AddedQueued(Arc<Transaction>),
+    /// Transaction that was added as queued can be included in next block now.
+    Promoted(Arc<Transaction>),
+    Replaced {
+        old: Arc<Transaction>,
+        new: Arc<Transaction>,
+    },
+    Dropped(Arc<Transaction>, DropReason),
+    /// Transaction is included in block on canonical chain.
+    Mined(Arc<Transaction>),
+}
+
+/// Content of pool, transactions are counted by subpool.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PoolStatus {
+    /// Transactions that can be included in next block.
+    pub pending: usize,
+    /// Transactions without nonce gap that don't pay base fee of next block.
+    pub base_fee: usize,
+    /// Transactions that wait for transactions with lower nonce.
+    pub queued: usize,
+    /// Encoded size of all transactions.
+    pub bytes: usize,
+    /// Number of accounts with transactions in pool.
+    pub senders: usize,
+}
+
+pub trait TransactionPool: Send + Sync {
+    /// Receiver of pool events, subscription ends when receiver is dropped.
+    fn subscribe(&mut self) -> Receiver<PoolEvent>;
 
     // configs, updated when new block is mined.
-    fn raise_min_gas_price(&mut self);
-    fn raise_block_gas_limit(&mut self);
+    /// Transactions paying less are removed and not accepted.
+    fn raise_min_gas_price(&mut self, min_gas_price: U256);
+    fn raise_block_gas_limit(&mut self, block_gas_limit: U256);
+    /// Base fee of next block, transactions move between pending and base fee subpool.
+    fn set_base_fee(&mut self, base_fee: U256);
 
-    // standard function for insert/find/filter/remove 
-    fn insert(&mut self, tx: Vec<Tx>);
-    fn find(&self);
-    fn filter(&self);
-    fn remove(&mut self, tx_hash_list: Vec<Hash>);
+    // standard function for insert/find/filter/remove
+    /// Result for every transaction, in same order.
+    fn insert(&mut self, txs: Vec<Transaction>) -> Vec<Result<H256, Error>>;
+    fn find(&self, tx_hash: &H256) -> Option<Arc<Transaction>>;
+    /// Transactions of sender ordered by nonce.
+    fn filter(&self, sender: &Address) -> Vec<Arc<Transaction>>;
+    /// Returns transactions that were removed.
+    fn remove(&mut self, tx_hash_list: &[H256]) -> Vec<Arc<Transaction>>;
+    /// Pending transactions ordered by effective tip, transactions of one sender are in nonce order.
+    fn pending(&self, limit: usize) -> Vec<Arc<Transaction>>;
 
+    /// Blob transaction comes with its sidecar, which is kept until transaction leaves pool.
+    fn insert_blob(&mut self, tx: Transaction, sidecar: BlobSidecar) -> Result<H256, Error>;
+    fn blob_sidecar(&self, tx_hash: &H256) -> Option<BlobSidecar>;
+    /// Blob base fee of next block, blob transactions paying less are not pending.
+    fn set_blob_base_fee(&mut self, blob_base_fee: U256);
+    /// Pending blob transactions ordered like `pending`.
+    fn pending_blobs(&self, limit: usize) -> Vec<Arc<Transaction>>;
 
-    // there is support for local transaction that needs to be inside poll all the time 
-    // local transactions??? insert local
+    /// Local transaction is submitted over RPC. It is not evicted for low gas price and it is
+    /// kept across restarts until it is mined.
+    fn insert_local(&mut self, tx: Transaction) -> Result<H256, Error>;
+    /// Local transactions that are not mined and were not broadcast for a while.
+    fn local_to_rebroadcast(&mut self) -> Vec<Arc<Transaction>>;
+    /// Removes remote queued transactions older than lifetime, it should be called periodically.
+    fn evict_expired(&mut self) -> Vec<Arc<Transaction>>;
 
-    //???
-    fn worst_gas_price_tx(&self);
-    fn next_account_nonce(&self);
-    fn status(&self);
-}
\ No newline at end of file
+    /// Transaction that is evicted first when pool is full, local transactions are never evicted.
+    fn worst_gas_price_tx(&self) -> Option<Arc<Transaction>>;
+    /// Nonce that next transaction of sender needs to have, taking pending transactions into account.
+    fn next_account_nonce(&self, sender: &Address) -> U64;
+    /// Nonce of sender account in latest state. Transactions with lower nonce are removed.
+    fn set_account_nonce(&mut self, sender: &Address, nonce: U64);
+    /// Accounts of senders changed in committed block, their transactions are checked
+    /// against nonce and balance in new state.
+    fn revalidate(&mut self, senders: &[Address]);
+    /// Canonical chain changed, blocks are in ascending order. Transactions included in added
+    /// blocks are removed and transactions from removed blocks that are not included anymore
+    /// are inserted back.
+    fn on_canonical_state_change(&mut self, added: &[WireBlock], removed: &[WireBlock]);
+    fn status(&self) -> PoolStatus;
+}
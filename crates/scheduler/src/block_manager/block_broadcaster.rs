@@ -0,0 +1,160 @@
+// Copyright 2021 Gnosis Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    block_manager::rlp_en_de::{encode_new_block, encode_new_block_hashes},
+    common_types::{NewBlock, NewBlockHash},
+    scheduler::{
+        peer_organizer::{PeerId, Task},
+        propagation::{fan_out, full_count, KnownHashes},
+        protocol::{EthMessageId, MessageId},
+    },
+};
+use core::{WireBlock, H256, U256};
+use interfaces::devp2p::ProtocolId;
+use std::collections::HashMap;
+
+/// Number of block hashes remembered per peer.
+pub const MAX_KNOWN_BLOCKS: usize = 1024;
+
+/// Announces our new head to peers. Square root of peers get whole block with total difficulty
+/// so it spreads fast, others get only hash and fetch block if they need it.
+pub struct BlockBroadcaster {
+    /// Blocks that peer sent, announced or got from us.
+    known: HashMap<PeerId, KnownHashes>,
+}
+
+impl BlockBroadcaster {
+    pub fn new() -> Self {
+        BlockBroadcaster {
+            known: HashMap::new(),
+        }
+    }
+
+    pub fn mark_known(&mut self, peer: &PeerId, hash: H256) {
+        self.known
+            .entry(*peer)
+            .or_insert_with(|| KnownHashes::new(MAX_KNOWN_BLOCKS))
+            .insert(hash);
+    }
+
+    pub fn is_known(&self, peer: &PeerId, hash: &H256) -> bool {
+        self.known
+            .get(peer)
+            .map_or(false, |known| known.contains(hash))
+    }
+
+    pub fn remove_peer(&mut self, peer: &PeerId) {
+        self.known.remove(peer);
+    }
+
+    /// Tasks that send new head to peers that do not know it yet.
+    pub fn broadcast(
+        &mut self,
+        block: &WireBlock,
+        total_difficulty: U256,
+        peers: &[PeerId],
+    ) -> Vec<Task> {
+        let hash = block.header.hash();
+        let mut targets: Vec<PeerId> = peers
+            .iter()
+            .filter(|peer| !self.is_known(peer, &hash))
+            .cloned()
+            .collect();
+        if targets.is_empty() {
+            return Vec::new();
+        }
+        targets.sort();
+        info!(
+            "Broadcasting block {} {} to {} peers",
+            block.header.number,
+            hash,
+            targets.len()
+        );
+
+        let whole = fan_out(&hash, targets.len(), full_count(targets.len()));
+        let new_block = encode_new_block(&NewBlock {
+            header: block.header.clone(),
+            transactions: block.body.transactions.clone(),
+            ommers: block.body.ommers.clone(),
+            score: total_difficulty,
+        });
+        let new_block_hashes =
+            encode_new_block_hashes(&[NewBlockHash::new(hash, block.header.number)]);
+
+        let mut tasks = Vec::with_capacity(targets.len());
+        for (peer, whole) in targets.iter().zip(whole) {
+            let (message_id, data) = if whole {
+                (EthMessageId::NewBlock, new_block.clone())
+            } else {
+                (EthMessageId::NewBlockHashes, new_block_hashes.clone())
+            };
+            tasks.push(Task::Responde(
+                *peer,
+                ProtocolId::Eth,
+                MessageId::Eth(message_id),
+                data,
+            ));
+            self.mark_known(peer, hash);
+        }
+        tasks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_manager::rlp_en_de::{decode_new_block, decode_new_block_hashes};
+    use core::{BlockBody, BlockHeader};
+
+    fn block(number: u64) -> WireBlock {
+        WireBlock {
+            header: BlockHeader {
+                number,
+                ..Default::default()
+            },
+            body: BlockBody {
+                transactions: Vec::new(),
+                ommers: Vec::new(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_broadcast_new_head() {
+        let mut broadcaster = BlockBroadcaster::new();
+        let block = block(10);
+        let hash = block.header.hash();
+        broadcaster.mark_known(&0, hash);
+
+        let peers: Vec<PeerId> = (0..10).collect();
+        let mut full = Vec::new();
+        let mut announced = Vec::new();
+        for task in broadcaster.broadcast(&block, 100.into(), &peers) {
+            match task {
+                Task::Responde(peer, _, MessageId::Eth(EthMessageId::NewBlock), data) => {
+                    let new_block = decode_new_block(&data).unwrap();
+                    assert_eq!(new_block.header.number, 10);
+                    assert_eq!(new_block.score, 100.into());
+                    full.push(peer);
+                }
+                Task::Responde(peer, _, MessageId::Eth(EthMessageId::NewBlockHashes), data) => {
+                    assert_eq!(
+                        decode_new_block_hashes(&data).unwrap(),
+                        vec![NewBlockHash::new(hash, 10)]
+                    );
+                    announced.push(peer);
+                }
+                task => panic!("Unexpected task {:?}", task),
+            }
+        }
+        // peer that sent us block is skipped and square root of the others get whole block.
+        assert_eq!(full.len(), 3);
+        assert_eq!(announced.len(), 6);
+        assert!(!full.contains(&0) && !announced.contains(&0));
+
+        assert!(broadcaster.broadcast(&block, 100.into(), &peers).is_empty());
+        broadcaster.remove_peer(&1);
+        assert_eq!(broadcaster.broadcast(&block, 100.into(), &peers).len(), 1);
+    }
+}
@@ -7,17 +7,20 @@ use super::rlp_en_de::{
 };
 use crate::{
     block_manager::{
+        block_broadcaster::BlockBroadcaster,
         rlp_en_de::{decode_block_bodies, decode_get_block_bodies, decode_get_block_headers},
-        sync_buffer::{SyncBuffer, SyncWatcher},
+        sync_buffer::{RequestLimits, SyncBuffer, SyncWatcher},
+        sync_target::{PeerHeads, Pivot, SyncTarget},
     },
     common_types::GetBlockHeaders,
     scheduler::{
+        fork_filter::Head,
         peer_organizer::{ErrorAct, InitialRequest, PeerId, Task},
         protocol::{EthMessageId, MessageId},
-        PeerOrganizer,
+        PeerOrganizer, SchedulerState,
     },
 };
-use core::{BlockBody, BlockId, H256};
+use core::{BlockBody, BlockId, BlockNumber, WireBlock, H256, U256};
 use interfaces::{
     blockchain::BlockchainReadOnly,
     devp2p::{PeerPenal, ProtocolId},
@@ -27,18 +30,33 @@ use std::sync::{Arc, Mutex, MutexGuard};
 
 pub struct Devp2pHandler {
     chain: Arc<Mutex<BlockchainReadOnly>>,
+    heads: Arc<Mutex<PeerHeads>>,
+    broadcaster: Arc<Mutex<BlockBroadcaster>>,
 }
 
 impl Devp2pHandler {
-    pub fn new(chain: Arc<Mutex<BlockchainReadOnly>>) -> Self {
-        Devp2pHandler { chain }
+    pub fn new(
+        chain: Arc<Mutex<BlockchainReadOnly>>,
+        heads: Arc<Mutex<PeerHeads>>,
+        broadcaster: Arc<Mutex<BlockBroadcaster>>,
+    ) -> Self {
+        Devp2pHandler {
+            chain,
+            heads,
+            broadcaster,
+        }
     }
 
     pub fn new_block_hashes(&self, peer: &PeerId, data: &[u8]) -> Result<Task, ErrorAct> {
         match decode_new_block_hashes(data) {
             Ok(hashes) => {
                 info!("Blockhashes: {:?}", hashes);
-                Ok(Task::None) // Task::InsertPeer()
+                let mut broadcaster = self.broadcaster.lock().unwrap();
+                for block in hashes.iter() {
+                    broadcaster.mark_known(peer, block.hash);
+                }
+                self.heads.lock().unwrap().new_block_hashes(peer, &hashes);
+                Ok(Task::None)
             }
             Err(err) => {
                 ErrorAct::new_kick_generic(format!("Invalid NewBlockHashes request: {}", err))
@@ -95,11 +113,17 @@ impl Devp2pHandler {
         match decode_new_block(data) {
             Ok(new_block) => {
                 info!("NewBlock: {:?}", new_block);
+                let hash = new_block.header.hash();
+                self.broadcaster.lock().unwrap().mark_known(peer, hash);
+                self.heads.lock().unwrap().new_block(
+                    peer,
+                    hash,
+                    &new_block.header,
+                    new_block.score,
+                );
                 Ok(Task::None)
             }
-            Err(err) => {
-                ErrorAct::new_kick_generic(format!("Invalid NewBlockHashes request: {}", err))
-            }
+            Err(err) => ErrorAct::new_kick_generic(format!("Invalid NewBlock request: {}", err)),
         }
     }
 
@@ -109,41 +133,186 @@ impl Devp2pHandler {
 pub struct BlockchainSync {
     buffer: Arc<Mutex<SyncBuffer>>,
     watcher: Arc<Mutex<SyncWatcher>>,
+    heads: Arc<Mutex<PeerHeads>>,
+    broadcaster: Arc<Mutex<BlockBroadcaster>>,
     devp2p: Arc<Mutex<Devp2pHandler>>,
 }
 
 impl BlockchainSync {
-    pub fn new(chain: Arc<Mutex<dyn BlockchainReadOnly>>, importer: Arc<Mutex<dyn Importer>>) -> Self {
-        let buffer = Arc::new(Mutex::new(SyncBuffer::new(Arc::clone(&importer))));
+    pub fn new(
+        chain: Arc<Mutex<dyn BlockchainReadOnly>>,
+        importer: Arc<Mutex<dyn Importer>>,
+        checkpoint: Option<SyncTarget>,
+    ) -> Self {
+        let (head, head_hash) = importer.lock().unwrap().status().highest_block;
+        let buffer = Arc::new(Mutex::new(SyncBuffer::new(
+            Arc::clone(&importer),
+            head,
+            head_hash,
+        )));
         let watcher = Arc::new(Mutex::new(SyncWatcher::new(Arc::clone(&buffer))));
-        let devp2p = Arc::new(Mutex::new(Devp2pHandler::new(Arc::clone(&chain))));
-        BlockchainSync { buffer, watcher, devp2p }
+        let heads = Arc::new(Mutex::new(PeerHeads::new(checkpoint)));
+        let broadcaster = Arc::new(Mutex::new(BlockBroadcaster::new()));
+        let devp2p = Arc::new(Mutex::new(Devp2pHandler::new(
+            Arc::clone(&chain),
+            Arc::clone(&heads),
+            Arc::clone(&broadcaster),
+        )));
+        BlockchainSync {
+            buffer,
+            watcher,
+            heads,
+            broadcaster,
+            devp2p,
+        }
     }
 
     pub fn is_syncing(&self) -> bool {
-        self.watcher.lock().unwrap().is_syncing()
+        self.buffer.lock().unwrap().is_syncing()
     }
 
-    pub fn next_sync_task(&self) -> Option<InitialRequest> {
-        self.watcher.lock().unwrap().next_sync_task()
+    /// Best block that we imported.
+    pub fn head(&self) -> BlockNumber {
+        self.buffer.lock().unwrap().head()
     }
 
-    pub fn sync_task_started(&self) {
-        self.watcher.lock().unwrap().sync_task_started();
+    /// Best block with its timestamp, for fork id.
+    pub fn fork_head(&self) -> Head {
+        let buffer = self.buffer.lock().unwrap();
+        Head {
+            number: buffer.head(),
+            timestamp: buffer.head_timestamp(),
+        }
     }
 
-    pub fn process_block_headers(&self, data: &[u8]) {
+    /// After warp our best block is snapshot block, block sync continues from it. After snap
+    /// sync only state of pivot block is present, block sync imports pivot block first.
+    pub fn state_changed(&self, from: SchedulerState, to: SchedulerState, snapped: Option<Pivot>) {
+        if from == SchedulerState::Warping {
+            let mut buffer = self.buffer.lock().unwrap();
+            match snapped {
+                Some(pivot) => {
+                    info!("Sync: continue block sync from snap pivot {}", pivot.number);
+                    buffer.reset_to_pivot(pivot.number, pivot.hash);
+                }
+                None => {
+                    let (head, head_hash) = buffer.importer_head();
+                    info!("Sync: continue block sync from {} after warp", head);
+                    buffer.reset(head, head_hash);
+                }
+            }
+        }
+    }
+
+    /// Number of blocks between our head and sync target. None if we don't have target yet.
+    pub fn sync_distance(&self) -> Option<u64> {
+        let head = self.head();
+        self.heads.lock().unwrap().best_target(head)?;
+        Some(self.buffer.lock().unwrap().distance())
+    }
+
+    /// Best target with known state root, snap sync downloads its state.
+    pub fn pivot(&self) -> Option<Pivot> {
+        let head = self.head();
+        self.heads.lock().unwrap().pivot(head)
+    }
+
+    /// Moves sync target to best block of peers, or to checkpoint while we are below it. Target
+    /// can also go down when peer with best block disconnects.
+    pub fn update_target(&self) {
+        let head = self.head();
+        let heads = self.heads.lock().unwrap();
+        if let Some(target) = heads.best_target(head) {
+            let trusted = heads.checkpoint() == Some(target);
+            drop(heads);
+            self.buffer.lock().unwrap().set_target(&target, trusted);
+        }
+    }
+
+    pub fn insert_peer(&self, peer: &PeerId, best_hash: H256, total_difficulty: Option<U256>) {
+        self.heads
+            .lock()
+            .unwrap()
+            .insert_peer(peer, best_hash, total_difficulty);
+    }
+
+    pub fn remove_peer(&self, peer: &PeerId) {
+        self.heads.lock().unwrap().remove_peer(peer);
+        self.broadcaster.lock().unwrap().remove_peer(peer);
+        self.sync_task_failed(peer);
+    }
+
+    /// Next request for peer, sized by `limits`.
+    pub fn next_sync_task(&self, peer: &PeerId, limits: RequestLimits) -> Option<InitialRequest> {
+        if let Some(request) = self.heads.lock().unwrap().next_resolve_request(peer) {
+            info!("Sync: Resolving best block of peer {}", peer);
+            return Some(InitialRequest::new(
+                MessageId::Eth(EthMessageId::GetBlockHeaders),
+                encode_get_block_headers(&request),
+            ));
+        }
+        self.watcher.lock().unwrap().next_sync_task(peer, limits)
+    }
+
+    /// Sends our new canonical head to peers that do not know it.
+    pub fn broadcast_block(
+        &self,
+        block: &WireBlock,
+        total_difficulty: U256,
+        peers: &[PeerId],
+    ) -> Vec<Task> {
+        self.broadcaster
+            .lock()
+            .unwrap()
+            .broadcast(block, total_difficulty, peers)
+    }
+
+    pub fn sync_task_failed(&self, peer: &PeerId) {
+        self.heads.lock().unwrap().release(peer);
+        self.watcher.lock().unwrap().sync_task_failed(peer);
+    }
+
+    /// Timeouted request is retried with another peer. Best block of peer can be resolved
+    /// only by that peer, so those requests are not moved.
+    pub fn sync_task_reassigned(&self, from: &PeerId, to: &PeerId) -> bool {
+        if self.heads.lock().unwrap().is_resolving(from) {
+            return false;
+        }
+        self.watcher.lock().unwrap().sync_task_reassigned(from, to)
+    }
+
+    pub fn process_block_headers(&self, peer: &PeerId, data: &[u8]) -> Result<Task, ErrorAct> {
         match decode_block_headers_with_hash(&data) {
-            Ok(headers) => self.buffer.lock().unwrap().process_headers(&headers),
-            Err(err) => error!("Sync: Could not decode block header: {}", err),
+            Ok(headers) => {
+                let mut heads = self.heads.lock().unwrap();
+                if heads.is_resolving(peer) {
+                    heads.resolve(peer, &headers)?;
+                } else {
+                    drop(heads);
+                    self.buffer.lock().unwrap().process_headers(peer, headers)?;
+                }
+            }
+            Err(err) => {
+                self.sync_task_failed(peer);
+                ErrorAct::new_kick(format!("Could not decode block headers: {}", err))?
+            }
         }
+        Ok(Task::None)
     }
 
-    pub fn process_block_bodies(&self, data: &[u8]) {
+    pub fn process_block_bodies(&self, peer: &PeerId, data: &[u8]) -> Result<Task, ErrorAct> {
         match decode_block_bodies(&data) {
-            Ok(bodies) => self.buffer.lock().unwrap().process_block_bodies(&bodies),
-            Err(err) => error!("Sync: Could not decode block bodies: {}", err),
+            Ok(bodies) => self
+                .buffer
+                .lock()
+                .unwrap()
+                .process_block_bodies(peer, bodies)?,
+            Err(err) => {
+                self.sync_task_failed(peer);
+                ErrorAct::new_kick(format!("Could not decode block bodies: {}", err))?
+            }
         }
+        Ok(Task::None)
     }
 
     pub fn api_new_block_hashes(&self, peer: &PeerId, data: &[u8]) -> Result<Task, ErrorAct> {
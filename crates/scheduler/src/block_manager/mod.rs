@@ -1,9 +1,16 @@
 // Copyright 2020-2021 Gnosis Ltd.
 // SPDX-License-Identifier: Apache-2.0
 
+mod block_broadcaster;
 mod rlp_en_de;
+mod skeleton;
 mod sync_buffer;
+mod sync_target;
 
 pub mod block_manager;
 
+pub use block_broadcaster::{BlockBroadcaster, MAX_KNOWN_BLOCKS};
 pub use block_manager::BlockchainSync;
+pub use skeleton::{MAX_HEADER_FETCH, MAX_SKELETON_SIZE};
+pub use sync_buffer::{RequestLimits, MAX_BODIES_FETCH};
+pub use sync_target::{Pivot, SyncTarget};
@@ -5,7 +5,9 @@ use crate::common_types::{BlockHeaderAndHash, GetBlockHeaders, NewBlock, NewBloc
 use core::{BlockBody, BlockHeader, BlockId, BlockNumber, Transaction, H160, H256, U256};
 
 use keccak_hash::keccak;
+use keccak_hasher::KeccakHasher;
 use rlp::{DecoderError, Rlp, RlpStream};
+use triehash::ordered_trie_root;
 
 pub fn encode_new_block_hashes(request: &[NewBlockHash]) -> Vec<u8> {
     let mut stream = RlpStream::new_list(request.len());
@@ -27,7 +29,7 @@ pub fn decode_new_block_hashes(data: &[u8]) -> Result<Vec<NewBlockHash>, Decoder
     for ref encoded_hash in encoded_hashes.iter() {
         let hash_data = encoded_hash.at(0)?.data()?;
         if hash_data.len() != 32 {
-            panic!("ENCODED_HASH {:?}", encoded_hash);
+            return Err(DecoderError::RlpInvalidLength);
         }
         decoded_hashes.push(NewBlockHash {
             hash: H256::from_slice(encoded_hash.at(0)?.data()?),
@@ -73,29 +75,10 @@ pub fn decode_get_block_headers(data: &[u8]) -> Result<GetBlockHeaders, DecoderE
     Ok(GetBlockHeaders::new(block_id, max_headers, skip, reverse))
 }
 
-fn encode_block_header(stream: &mut RlpStream, header: &BlockHeader) {
-    stream.begin_list(15)
-        .append(&header.parent_hash)
-        .append(&header.ommers_hash)
-        .append(&header.beneficiary_address)
-        .append(&header.state_root)
-        .append(&header.transactions_root)
-        .append(&header.receipts_root)
-        .append(&header.logs_bloom)
-        .append(&header.difficulty)
-        .append(&header.number)
-        .append(&header.gas_limit)
-        .append(&header.gas_used)
-        .append(&header.timestamp)
-        .append(&header.extra_data)
-        .append(&header.mix_hash)
-        .append(&header.nonce);
-}
-
 pub fn encode_block_headers(headers: &[BlockHeader]) -> Vec<u8> {
     let mut stream = RlpStream::new_list(headers.len());
     for header in headers {
-        encode_block_header(&mut stream, &header);
+        stream.append(header);
     }
     stream.out().to_vec()
 }
@@ -164,9 +147,9 @@ pub fn decode_get_block_bodies(data: &[u8]) -> Result<Vec<H256>, DecoderError> {
 fn encode_block_body(stream: &mut RlpStream, block_body: &BlockBody) {
     let block_stream = stream.begin_list(2);
     Transaction::rlp_append_list(block_stream, &block_body.transactions);
-    let mut ommers_stream = block_stream.begin_list(block_body.ommers.len());
-    for ref ommer in &block_body.ommers {
-        encode_block_header(&mut ommers_stream, ommer);
+    let ommers_stream = block_stream.begin_list(block_body.ommers.len());
+    for ommer in &block_body.ommers {
+        ommers_stream.append(ommer);
     }
 }
 
@@ -178,6 +161,23 @@ pub fn encode_block_bodies(block_bodies: &[BlockBody]) -> Vec<u8> {
     stream.out().to_vec()
 }
 
+/// Keccak of rlp encoded ommers list. Needs to be same as `ommers_hash` in block header.
+pub fn ommers_hash(ommers: &[BlockHeader]) -> H256 {
+    let mut stream = RlpStream::new_list(ommers.len());
+    for ommer in ommers {
+        stream.append(ommer);
+    }
+    H256::from_slice(keccak(stream.out()).as_bytes())
+}
+
+/// Root of trie where key is rlp encoded index and value is encoded transaction.
+/// Needs to be same as `transactions_root` in block header.
+pub fn transactions_root(transactions: &[Transaction]) -> H256 {
+    H256::from(ordered_trie_root::<KeccakHasher, _>(
+        transactions.iter().map(|tx| tx.encode()),
+    ))
+}
+
 fn decode_block_body(body: &Rlp) -> Result<BlockBody, DecoderError> {
     let transactions = Transaction::rlp_decode_list(&body.at(0)?)?;
     let mut ommers = vec![];
@@ -201,14 +201,14 @@ pub fn decode_block_bodies(data: &[u8]) -> Result<Vec<BlockBody>, DecoderError>
 
 pub fn encode_new_block(new_block: &NewBlock) -> Vec<u8> {
     let mut stream = RlpStream::new_list(2);
-    let mut first_part = stream.begin_list(3);
+    let first_part = stream.begin_list(3);
 
-    encode_block_header(&mut first_part, &new_block.header);
+    first_part.append(&new_block.header);
     Transaction::rlp_append_list(first_part, &new_block.transactions);
 
-    let mut ommer_stream = first_part.begin_list(new_block.ommers.len());
-    for ref ommer in &new_block.ommers {
-        encode_block_header(&mut ommer_stream, ommer);
+    let ommer_stream = first_part.begin_list(new_block.ommers.len());
+    for ommer in &new_block.ommers {
+        ommer_stream.append(ommer);
     }
 
     stream.append(&new_block.score);
@@ -221,7 +221,7 @@ pub fn decode_new_block(data: &[u8]) -> Result<NewBlock, DecoderError> {
 
     let header = decode_block_header(&encoded.at(0)?.at(0)?)?;
 
-    let transactions = Transaction::rlp_decode_list(&encoded)?;
+    let transactions = Transaction::rlp_decode_list(&encoded.at(0)?.at(1)?)?;
 
     let mut ommers = vec![];
     for ref ommer in encoded.at(0)?.at(2)?.iter() {
@@ -241,6 +241,7 @@ pub fn decode_new_block(data: &[u8]) -> Result<NewBlock, DecoderError> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::str::FromStr;
 
     #[test]
     fn test_new_block_hashes_roundtrip() {
@@ -337,6 +338,20 @@ mod tests {
         //assert_eq!(block_body, decoded[0]);
     }
 
+    #[test]
+    fn test_empty_body_roots() {
+        assert_eq!(
+            transactions_root(&[]),
+            H256::from_str("56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421")
+                .unwrap()
+        );
+        assert_eq!(
+            ommers_hash(&[]),
+            H256::from_str("1dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347")
+                .unwrap()
+        );
+    }
+
     #[test]
     fn test_block_body_with_ommer_roundtrip() {
         let encoded = std::fs::read("src/block_manager/test_data/block_11_927_383").unwrap();
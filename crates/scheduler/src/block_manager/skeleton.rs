@@ -0,0 +1,482 @@
+// Copyright 2021 Gnosis Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    common_types::{BlockHeaderAndHash, GetBlockHeaders},
+    scheduler::peer_organizer::{ErrorAct, PeerId},
+};
+use core::{BlockId, BlockNumber, H256};
+use std::{cmp::min, collections::VecDeque};
+
+/// Number of headers in one filled segment. Skeleton anchors are this far apart.
+pub const MAX_HEADER_FETCH: u64 = 192;
+/// Maximal number of anchors requested in one skeleton request.
+pub const MAX_SKELETON_SIZE: u64 = 128;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RequestStatus {
+    Idle,
+    Requested(PeerId),
+    Received,
+}
+
+/// Continuous range of headers that ends with skeleton anchor.
+/// Tail segment (last one before target) does not have anchor.
+#[derive(Debug)]
+struct Segment {
+    from: BlockNumber,
+    count: u64,
+    anchor: Option<H256>,
+    status: RequestStatus,
+    headers: Vec<BlockHeaderAndHash>,
+}
+
+impl Segment {
+    fn new(from: BlockNumber, count: u64, anchor: Option<H256>) -> Self {
+        Segment {
+            from,
+            count,
+            anchor,
+            status: RequestStatus::Idle,
+            headers: vec![],
+        }
+    }
+
+    fn request(&self) -> GetBlockHeaders {
+        GetBlockHeaders::new(BlockId::Number(self.from), self.count, 0, false)
+    }
+}
+
+/// Header skeleton sync. First sparse headers (anchors) are fetched from one peer with `skip`,
+/// after that gaps between anchors are filled in parallel from different peers.
+/// Every filled segment needs to link to its parent and to end with the anchor we got from skeleton.
+pub struct Skeleton {
+    /// Last header that is verified and handed over for body download.
+    /// Hash is not known if we start syncing from arbitrary block.
+    head: (BlockNumber, Option<H256>),
+    /// Hash that first header after head needs to have. Used when we start from block whose
+    /// parent we don't know, as pivot of snap sync.
+    first_hash: Option<H256>,
+    target: BlockNumber,
+    /// Hash of target block, known only if target is trusted checkpoint.
+    target_hash: Option<H256>,
+    status: RequestStatus,
+    /// Number of anchors in pending skeleton request.
+    skeleton_size: u64,
+    segments: VecDeque<Segment>,
+}
+
+impl Skeleton {
+    pub fn new(head: BlockNumber, head_hash: Option<H256>, target: BlockNumber) -> Self {
+        Skeleton {
+            head: (head, head_hash),
+            first_hash: None,
+            target,
+            target_hash: None,
+            status: RequestStatus::Idle,
+            skeleton_size: 0,
+            segments: VecDeque::new(),
+        }
+    }
+
+    pub fn head(&self) -> BlockNumber {
+        self.head.0
+    }
+
+    pub fn target(&self) -> BlockNumber {
+        self.target
+    }
+
+    pub fn expect_first(&mut self, hash: H256) {
+        self.first_hash = Some(hash);
+    }
+
+    /// Target goes down when peer with best block disconnects. Segments above new target that
+    /// are not requested from peers are dropped, those already filled are kept.
+    pub fn set_target(&mut self, target: BlockNumber, target_hash: Option<H256>) {
+        self.target = target;
+        self.target_hash = target_hash;
+        while let Some(segment) = self.segments.back() {
+            if segment.from <= target || segment.status != RequestStatus::Idle {
+                break;
+            }
+            self.segments.pop_back();
+        }
+        if self.segments.is_empty() && self.status == RequestStatus::Received {
+            self.status = RequestStatus::Idle;
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.head.0 >= self.target
+    }
+
+    /// Returns next header request for peer, if there is something to request.
+    pub fn next_request(&mut self, peer: &PeerId) -> Option<GetBlockHeaders> {
+        self.next_request_sized(peer, MAX_SKELETON_SIZE)
+    }
+
+    /// Same as `next_request` but skeleton request has at most `max_anchors` anchors, so that
+    /// slow peer is not given whole skeleton. Segments always have `MAX_HEADER_FETCH` headers.
+    pub fn next_request_sized(
+        &mut self,
+        peer: &PeerId,
+        max_anchors: u64,
+    ) -> Option<GetBlockHeaders> {
+        if self.is_done() {
+            return None;
+        }
+        if self.status == RequestStatus::Idle && self.segments.is_empty() {
+            let remaining = self.target - self.head.0;
+            if remaining < MAX_HEADER_FETCH {
+                // skeleton is not needed, fetch tail directly.
+                self.segments
+                    .push_back(Segment::new(self.head.0 + 1, remaining, self.target_hash));
+            } else {
+                self.status = RequestStatus::Requested(*peer);
+                let max_anchors = max_anchors.max(1).min(MAX_SKELETON_SIZE);
+                let count = min(max_anchors, remaining / MAX_HEADER_FETCH);
+                self.skeleton_size = count;
+                return Some(GetBlockHeaders::new(
+                    BlockId::Number(self.head.0 + MAX_HEADER_FETCH),
+                    count,
+                    MAX_HEADER_FETCH - 1,
+                    false,
+                ));
+            }
+        }
+        let segment = self
+            .segments
+            .iter_mut()
+            .find(|segment| segment.status == RequestStatus::Idle)?;
+        segment.status = RequestStatus::Requested(*peer);
+        Some(segment.request())
+    }
+
+    /// Release all requests that are assigned to peer so that they can be rescheduled.
+    pub fn release(&mut self, peer: &PeerId) {
+        if self.status == RequestStatus::Requested(*peer) {
+            self.status = RequestStatus::Idle;
+        }
+        for segment in self.segments.iter_mut() {
+            if segment.status == RequestStatus::Requested(*peer) {
+                segment.status = RequestStatus::Idle;
+            }
+        }
+    }
+
+    /// Move pending request from one peer to another. Returns false if `from` has no request
+    /// or `to` already has one.
+    pub fn reassign(&mut self, from: &PeerId, to: &PeerId) -> bool {
+        if !self.is_requested_from(from) || self.is_requested_from(to) {
+            return false;
+        }
+        if self.status == RequestStatus::Requested(*from) {
+            self.status = RequestStatus::Requested(*to);
+        }
+        for segment in self.segments.iter_mut() {
+            if segment.status == RequestStatus::Requested(*from) {
+                segment.status = RequestStatus::Requested(*to);
+            }
+        }
+        true
+    }
+
+    /// Returns true if peer has pending skeleton or segment request.
+    pub fn is_requested_from(&self, peer: &PeerId) -> bool {
+        self.status == RequestStatus::Requested(*peer)
+            || self
+                .segments
+                .iter()
+                .any(|segment| segment.status == RequestStatus::Requested(*peer))
+    }
+
+    /// Process headers received from peer. Headers are either skeleton anchors or segment that fills the gap.
+    pub fn process_headers(
+        &mut self,
+        peer: &PeerId,
+        headers: Vec<BlockHeaderAndHash>,
+    ) -> Result<(), ErrorAct> {
+        if self.status == RequestStatus::Requested(*peer) {
+            return self.process_skeleton(peer, headers);
+        }
+        let index = match self
+            .segments
+            .iter()
+            .position(|segment| segment.status == RequestStatus::Requested(*peer))
+        {
+            Some(index) => index,
+            None => return ErrorAct::new_kick("Unrequested block headers".into()),
+        };
+        self.process_segment(index, headers)
+    }
+
+    fn process_skeleton(
+        &mut self,
+        peer: &PeerId,
+        headers: Vec<BlockHeaderAndHash>,
+    ) -> Result<(), ErrorAct> {
+        self.status = RequestStatus::Idle;
+        if headers.is_empty() {
+            info!("Sync: peer {} does not have skeleton headers", peer);
+            return Ok(());
+        }
+        if headers.len() as u64 > self.skeleton_size {
+            ErrorAct::new_kick("Too many skeleton headers".into())?
+        }
+        let mut expected = self.head.0 + MAX_HEADER_FETCH;
+        for header in headers.iter() {
+            if header.header.number != expected {
+                ErrorAct::new_kick(format!(
+                    "Skeleton header out of range. Expected:{} got:{}",
+                    expected, header.header.number
+                ))?
+            }
+            expected += MAX_HEADER_FETCH;
+        }
+        for header in headers {
+            let from = header.header.number + 1 - MAX_HEADER_FETCH;
+            self.segments
+                .push_back(Segment::new(from, MAX_HEADER_FETCH, Some(header.hash)));
+        }
+        self.status = RequestStatus::Received;
+        info!(
+            "Sync: skeleton with {} segments received",
+            self.segments.len()
+        );
+        Ok(())
+    }
+
+    fn process_segment(
+        &mut self,
+        index: usize,
+        headers: Vec<BlockHeaderAndHash>,
+    ) -> Result<(), ErrorAct> {
+        let parent_hash = if index == 0 {
+            self.head.1
+        } else {
+            self.segments[index - 1].anchor
+        };
+        let segment = &mut self.segments[index];
+        segment.status = RequestStatus::Idle;
+        if headers.is_empty() {
+            return Ok(());
+        }
+        if headers.len() as u64 != segment.count {
+            ErrorAct::new_kick(format!(
+                "Unexpected number of headers. Expected:{} got:{}",
+                segment.count,
+                headers.len()
+            ))?
+        }
+        verify_linkage(segment.from, parent_hash, &headers)?;
+        if let (0, Some(first_hash)) = (index, self.first_hash) {
+            if headers[0].hash != first_hash {
+                ErrorAct::new_kick("First header does not match expected hash".into())?
+            }
+        }
+        if let Some(anchor) = segment.anchor {
+            if headers.last().unwrap().hash != anchor {
+                ErrorAct::new_kick("Headers do not match skeleton anchor".into())?
+            }
+        }
+        segment.headers = headers;
+        segment.status = RequestStatus::Received;
+        Ok(())
+    }
+
+    /// Takes all verified headers from start of skeleton. Headers are in chain order.
+    pub fn take_filled(&mut self) -> Vec<BlockHeaderAndHash> {
+        let mut filled = vec![];
+        while let Some(segment) = self.segments.front() {
+            if segment.status != RequestStatus::Received {
+                break;
+            }
+            let segment = self.segments.pop_front().unwrap();
+            if let Some(last) = segment.headers.last() {
+                self.head = (last.header.number, Some(last.hash));
+                self.first_hash = None;
+            }
+            filled.extend(segment.headers);
+        }
+        if self.segments.is_empty() && self.status == RequestStatus::Received {
+            self.status = RequestStatus::Idle;
+        }
+        filled
+    }
+}
+
+/// Checks that headers are consecutive starting from `from` and that every header points to its parent.
+pub fn verify_linkage(
+    from: BlockNumber,
+    parent_hash: Option<H256>,
+    headers: &[BlockHeaderAndHash],
+) -> Result<(), ErrorAct> {
+    let mut parent_hash = parent_hash;
+    for (i, header) in headers.iter().enumerate() {
+        if header.header.number != from + i as u64 {
+            ErrorAct::new_kick(format!(
+                "Header out of range. Expected:{} got:{}",
+                from + i as u64,
+                header.header.number
+            ))?
+        }
+        if let Some(parent_hash) = parent_hash {
+            if header.header.parent_hash != parent_hash {
+                ErrorAct::new_kick(format!(
+                    "Header {} is not linked to its parent",
+                    header.header.number
+                ))?
+            }
+        }
+        parent_hash = Some(header.hash);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::{BlockHeader, H160};
+
+    fn header(number: BlockNumber) -> BlockHeaderAndHash {
+        BlockHeaderAndHash {
+            header: BlockHeader {
+                parent_hash: H256::from_low_u64_be(number - 1),
+                ommers_hash: H256::zero(),
+                beneficiary_address: H160::zero(),
+                state_root: H256::zero(),
+                transactions_root: H256::zero(),
+                receipts_root: H256::zero(),
+                logs_bloom: vec![],
+                difficulty: 0,
+                number,
+                gas_limit: 0,
+                gas_used: 0,
+                timestamp: 0,
+                extra_data: vec![],
+                mix_hash: H256::zero(),
+                nonce: 0,
+            },
+            hash: H256::from_low_u64_be(number),
+        }
+    }
+
+    fn headers(from: BlockNumber, count: u64, skip: u64) -> Vec<BlockHeaderAndHash> {
+        (0..count).map(|i| header(from + i * (skip + 1))).collect()
+    }
+
+    #[test]
+    fn test_skeleton_fill_in_parallel() {
+        let mut skeleton = Skeleton::new(
+            100,
+            Some(H256::from_low_u64_be(100)),
+            100 + 2 * MAX_HEADER_FETCH,
+        );
+        let request = skeleton.next_request(&1).unwrap();
+        assert_eq!(
+            request,
+            GetBlockHeaders::new(BlockId::Number(292), 2, 191, false)
+        );
+        assert!(skeleton.process_headers(&1, headers(292, 2, 191)).is_ok());
+
+        let first = skeleton.next_request(&2).unwrap();
+        let second = skeleton.next_request(&3).unwrap();
+        assert_eq!(first.block_id, BlockId::Number(101));
+        assert_eq!(second.block_id, BlockId::Number(293));
+        assert!(skeleton.next_request(&4).is_none());
+
+        assert!(skeleton
+            .process_headers(&3, headers(293, MAX_HEADER_FETCH, 0))
+            .is_ok());
+        assert!(skeleton.take_filled().is_empty());
+        assert!(skeleton
+            .process_headers(&2, headers(101, MAX_HEADER_FETCH, 0))
+            .is_ok());
+        assert_eq!(skeleton.take_filled().len(), 2 * MAX_HEADER_FETCH as usize);
+        assert!(skeleton.is_done());
+    }
+
+    #[test]
+    fn test_lower_target_drops_segments_above_it() {
+        let mut skeleton = Skeleton::new(
+            100,
+            Some(H256::from_low_u64_be(100)),
+            100 + 3 * MAX_HEADER_FETCH,
+        );
+        skeleton.next_request(&1).unwrap();
+        skeleton.process_headers(&1, headers(292, 3, 191)).unwrap();
+
+        skeleton.set_target(300, None);
+        assert_eq!(skeleton.target(), 300);
+        assert_eq!(
+            skeleton.next_request(&2).unwrap().block_id,
+            BlockId::Number(101)
+        );
+        assert_eq!(
+            skeleton.next_request(&3).unwrap().block_id,
+            BlockId::Number(293)
+        );
+        assert!(skeleton.next_request(&4).is_none());
+    }
+
+    #[test]
+    fn test_skeleton_out_of_range() {
+        let mut skeleton = Skeleton::new(100, None, 100 + 2 * MAX_HEADER_FETCH);
+        skeleton.next_request(&1).unwrap();
+        assert!(skeleton.process_headers(&1, headers(292, 2, 100)).is_err());
+        // skeleton can be requested again from another peer
+        assert!(skeleton.next_request(&2).is_some());
+    }
+
+    #[test]
+    fn test_segment_unlinked_and_anchor_mismatch() {
+        let mut skeleton = Skeleton::new(100, None, 100 + MAX_HEADER_FETCH);
+        skeleton.next_request(&1).unwrap();
+        skeleton.process_headers(&1, headers(292, 1, 191)).unwrap();
+
+        skeleton.next_request(&2).unwrap();
+        let mut unlinked = headers(101, MAX_HEADER_FETCH, 0);
+        unlinked[10].header.parent_hash = H256::repeat_byte(0xff);
+        assert!(skeleton.process_headers(&2, unlinked).is_err());
+
+        skeleton.next_request(&3).unwrap();
+        let mut wrong_anchor = headers(101, MAX_HEADER_FETCH, 0);
+        wrong_anchor.last_mut().unwrap().hash = H256::repeat_byte(0xff);
+        assert!(skeleton.process_headers(&3, wrong_anchor).is_err());
+
+        skeleton.next_request(&4).unwrap();
+        assert!(skeleton
+            .process_headers(&4, headers(101, MAX_HEADER_FETCH, 0))
+            .is_ok());
+        assert_eq!(skeleton.take_filled().len(), MAX_HEADER_FETCH as usize);
+    }
+
+    #[test]
+    fn test_tail_ends_with_checkpoint() {
+        let mut skeleton = Skeleton::new(100, None, 100);
+        skeleton.set_target(110, Some(H256::repeat_byte(0xff)));
+        skeleton.next_request(&1).unwrap();
+        assert!(skeleton.process_headers(&1, headers(101, 10, 0)).is_err());
+        skeleton.next_request(&1).unwrap();
+        let mut checkpoint = headers(101, 10, 0);
+        checkpoint.last_mut().unwrap().hash = H256::repeat_byte(0xff);
+        assert!(skeleton.process_headers(&1, checkpoint).is_ok());
+        assert!(skeleton.take_filled().len() == 10 && skeleton.is_done());
+    }
+
+    #[test]
+    fn test_tail_without_skeleton() {
+        let mut skeleton = Skeleton::new(100, Some(H256::from_low_u64_be(100)), 110);
+        let request = skeleton.next_request(&1).unwrap();
+        assert_eq!(
+            request,
+            GetBlockHeaders::new(BlockId::Number(101), 10, 0, false)
+        );
+        assert!(skeleton.process_headers(&1, headers(102, 10, 0)).is_err());
+        skeleton.next_request(&1).unwrap();
+        assert!(skeleton.process_headers(&1, headers(101, 10, 0)).is_ok());
+        assert_eq!(skeleton.take_filled().len(), 10);
+        assert!(skeleton.is_done());
+    }
+}
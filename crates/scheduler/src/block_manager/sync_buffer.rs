@@ -2,147 +2,571 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
-    block_manager::rlp_en_de::{encode_get_block_headers, encode_get_block_bodies},
+    block_manager::{
+        rlp_en_de::{
+            encode_get_block_bodies, encode_get_block_headers, ommers_hash, transactions_root,
+        },
+        skeleton::{Skeleton, MAX_HEADER_FETCH, MAX_SKELETON_SIZE},
+        sync_target::SyncTarget,
+    },
     common_types::{BlockHeaderAndHash, GetBlockHeaders},
-    scheduler::{peer_organizer::InitialRequest, protocol::EthMessageId},
+    scheduler::{
+        peer_organizer::{ErrorAct, InitialRequest, PeerId},
+        protocol::{EthMessageId, MessageId},
+    },
 };
-use core::{BlockBody, BlockHeader, BlockId, H256, WireBlock};
-use interfaces::{blockchain::BlockchainReadOnly, importer::Importer};
+use core::{BlockBody, BlockHeader, BlockNumber, WireBlock, H256};
+use interfaces::importer::Importer;
 
 use std::{
-    collections::HashMap,
-    iter::Iterator,
+    cmp::min,
+    collections::{BTreeMap, HashMap, VecDeque},
     sync::{Arc, Mutex},
 };
-use std::cmp::min;
 
+/// Maximal number of block bodies requested in one message.
+pub const MAX_BODIES_FETCH: usize = 128;
+/// Stop requesting new headers if there are this many headers waiting for bodies.
+const MAX_HEADERS_AWAITING_BODIES: usize = 4096;
+/// Body that peers skipped this many times is not served by anyone, most likely its header is
+/// not on canonical chain anymore. Headers are then downloaded again from our head.
+const MAX_BODY_RETRIES: usize = 8;
+
+/// How much data can be requested from peer in one message, based on its measured capacity.
 #[derive(Clone, Copy, Debug)]
-enum SyncSliceStatus {
-    Idle,
-    AwaitingHeaders,
-    ReceivedHeaders,
-    AwaitingBodies,
-    ReceivedBodies(usize),
+pub struct RequestLimits {
+    /// Maximal number of headers.
+    pub headers: u64,
+    pub bodies: usize,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        RequestLimits {
+            headers: MAX_SKELETON_SIZE * MAX_HEADER_FETCH,
+            bodies: MAX_BODIES_FETCH,
+        }
+    }
 }
 
 pub struct SyncBuffer {
+    skeleton: Skeleton,
     headers: HashMap<H256, BlockHeader>,
-    block_hashes: Vec<H256>,
+    /// Hashes of verified headers that are waiting for bodies, in chain order.
+    body_queue: VecDeque<H256>,
+    body_requests: HashMap<PeerId, Vec<H256>>,
+    /// Number of responses that skipped body of header.
+    body_retries: HashMap<H256, usize>,
+    /// Blocks with bodies that wait for their parents to be imported first.
+    blocks: BTreeMap<BlockNumber, (H256, WireBlock)>,
+    /// Next block number that is going to be sent to importer.
+    next_import: BlockNumber,
+    /// Hash of last block that we imported. Unknown after snap sync, until pivot is imported.
+    head_hash: Option<H256>,
+    /// Snap pivot that has to be imported next.
+    pivot_hash: Option<H256>,
+    /// Timestamp of last block that we imported, zero until sync imports one.
+    head_timestamp: u64,
     importer: Arc<Mutex<dyn Importer>>,
-    status: SyncSliceStatus,
 }
 
 impl SyncBuffer {
-    pub fn new(importer: Arc<Mutex<dyn Importer>>) -> Self {
+    pub fn new(importer: Arc<Mutex<dyn Importer>>, head: BlockNumber, head_hash: H256) -> Self {
         SyncBuffer {
+            skeleton: Skeleton::new(head, Some(head_hash), head),
             headers: HashMap::new(),
-            block_hashes: vec![],
+            body_queue: VecDeque::new(),
+            body_requests: HashMap::new(),
+            body_retries: HashMap::new(),
+            blocks: BTreeMap::new(),
+            next_import: head + 1,
+            head_hash: Some(head_hash),
+            pivot_hash: None,
+            head_timestamp: 0,
             importer,
-            status: SyncSliceStatus::Idle,
         }
     }
 
-    pub fn process_headers(&mut self, headers: &[BlockHeaderAndHash]) {
-        info!("Sync: processing {} headers", headers.len());
-        if headers.is_empty() {
-            self.status = SyncSliceStatus::Idle;
-            return;
+    /// Starts syncing from new head. Used after blocks are imported from outside of sync, as with warp.
+    pub fn reset(&mut self, head: BlockNumber, head_hash: H256) {
+        self.reset_from(head, Some(head_hash));
+    }
+
+    /// After snap sync we have state of pivot block but not the block itself. Sync continues
+    /// from its parent, so that pivot header and body are downloaded and imported first. Parent
+    /// hash is not known, so pivot is checked by its hash.
+    pub fn reset_to_pivot(&mut self, pivot: BlockNumber, pivot_hash: H256) {
+        self.reset_from(pivot - 1, None);
+        self.pivot_hash = Some(pivot_hash);
+        self.skeleton.expect_first(pivot_hash);
+    }
+
+    /// Drops everything that is not imported and downloads headers again from our head.
+    fn restart(&mut self) {
+        let head_timestamp = self.head_timestamp;
+        match self.pivot_hash {
+            Some(pivot_hash) => self.reset_to_pivot(self.next_import, pivot_hash),
+            None => self.reset_from(self.head(), self.head_hash),
         }
-        let mut new_block_hashes = vec![];
-        for header in headers {
-            info!("HEADER {:?}", header);
-            self.headers.insert(header.hash, header.header.clone());
-            new_block_hashes.push(header.hash);
+        self.head_timestamp = head_timestamp;
+    }
+
+    fn reset_from(&mut self, head: BlockNumber, head_hash: Option<H256>) {
+        let target = self.skeleton.target();
+        self.skeleton = Skeleton::new(head, head_hash, head);
+        self.skeleton.set_target(target, None);
+        self.headers.clear();
+        self.body_queue.clear();
+        self.body_requests.clear();
+        self.body_retries.clear();
+        self.blocks.clear();
+        self.next_import = head + 1;
+        self.head_hash = head_hash;
+        self.pivot_hash = None;
+        self.head_timestamp = 0;
+    }
+
+    pub fn importer_head(&self) -> (BlockNumber, H256) {
+        self.importer.lock().unwrap().status().highest_block
+    }
+
+    pub fn head(&self) -> BlockNumber {
+        self.next_import - 1
+    }
+
+    pub fn head_timestamp(&self) -> u64 {
+        self.head_timestamp
+    }
+
+    pub fn is_syncing(&self) -> bool {
+        self.next_import <= self.skeleton.target()
+    }
+
+    /// Number of blocks that we still need to import to reach target.
+    pub fn distance(&self) -> u64 {
+        (self.skeleton.target() + 1).saturating_sub(self.next_import)
+    }
+
+    pub fn set_target(&mut self, target: &SyncTarget, trusted: bool) {
+        let hash = if trusted { Some(target.hash) } else { None };
+        self.skeleton.set_target(target.number, hash);
+    }
+
+    /// Every skeleton anchor is followed by request for `MAX_HEADER_FETCH` headers of its
+    /// segment, so peer gets one anchor for each segment it can deliver.
+    pub fn next_header_request(
+        &mut self,
+        peer: &PeerId,
+        max_headers: u64,
+    ) -> Option<GetBlockHeaders> {
+        if self.body_queue.len() >= MAX_HEADERS_AWAITING_BODIES {
+            return None;
         }
-        self.block_hashes = new_block_hashes;
-        self.status = SyncSliceStatus::ReceivedHeaders;
+        self.skeleton
+            .next_request_sized(peer, max_headers / MAX_HEADER_FETCH)
     }
 
-    pub fn unmatched_headers(&self) -> Vec<H256> { self.block_hashes.clone() }
+    pub fn next_body_request(&mut self, peer: &PeerId, max: usize) -> Option<Vec<H256>> {
+        if self.body_queue.is_empty() {
+            return None;
+        }
+        let count = min(min(max.max(1), MAX_BODIES_FETCH), self.body_queue.len());
+        let hashes: Vec<H256> = self.body_queue.drain(..count).collect();
+        self.body_requests.insert(*peer, hashes.clone());
+        Some(hashes)
+    }
 
-    pub fn get_status(&self) -> SyncSliceStatus { self.status }
+    /// Return requests assigned to peer back to the queue. Called when request failed or peer disconnected.
+    pub fn release(&mut self, peer: &PeerId) {
+        self.skeleton.release(peer);
+        if let Some(hashes) = self.body_requests.remove(peer) {
+            for hash in hashes.into_iter().rev() {
+                self.body_queue.push_front(hash);
+            }
+        }
+    }
+
+    /// Move requests of timeouted peer to another peer.
+    pub fn reassign(&mut self, from: &PeerId, to: &PeerId) -> bool {
+        if self.body_requests.contains_key(to) {
+            return false;
+        }
+        if let Some(hashes) = self.body_requests.remove(from) {
+            self.body_requests.insert(*to, hashes);
+            return true;
+        }
+        self.skeleton.reassign(from, to)
+    }
+
+    pub fn process_headers(
+        &mut self,
+        peer: &PeerId,
+        headers: Vec<BlockHeaderAndHash>,
+    ) -> Result<(), ErrorAct> {
+        info!("Sync: processing {} headers from {}", headers.len(), peer);
+        if let Err(err) = self.skeleton.process_headers(peer, headers) {
+            self.release(peer);
+            return Err(err);
+        }
+        for header in self.skeleton.take_filled() {
+            self.body_queue.push_back(header.hash);
+            self.headers.insert(header.hash, header.header);
+        }
+        Ok(())
+    }
+
+    /// Bodies are matched to requested headers by transactions root and ommers hash.
+    /// Hashes that did not get their body are returned to queue and requested again, until
+    /// `MAX_BODY_RETRIES` responses skipped them.
+    pub fn process_block_bodies(
+        &mut self,
+        peer: &PeerId,
+        bodies: Vec<BlockBody>,
+    ) -> Result<(), ErrorAct> {
+        let hashes = match self.body_requests.get(peer) {
+            Some(hashes) => hashes,
+            None => return ErrorAct::new_kick("Unrequested block bodies".into()),
+        };
+        info!(
+            "Got {} block bodies for {} headers",
+            bodies.len(),
+            hashes.len()
+        );
+        let (matched, missing) = match match_bodies(&self.headers, hashes, bodies) {
+            Ok(result) => result,
+            Err(err) => {
+                self.release(peer);
+                return Err(err);
+            }
+        };
+        let hashes = self.body_requests.remove(peer).unwrap();
+        // response can end early because of its size, hashes after last body are not skipped.
+        let answered = match matched.last() {
+            Some((last, _)) => hashes.iter().position(|hash| hash == last).unwrap() + 1,
+            None => hashes.len(),
+        };
+        for (hash, body) in matched {
+            let header = self.headers.remove(&hash).unwrap();
+            self.body_retries.remove(&hash);
+            self.blocks
+                .insert(header.number, (hash, WireBlock { header, body }));
+        }
+        self.import_ready_blocks();
+        let mut exhausted = false;
+        for hash in missing
+            .iter()
+            .filter(|hash| hashes[..answered].contains(hash))
+        {
+            let retries = self.body_retries.entry(*hash).or_insert(0);
+            *retries += 1;
+            exhausted |= *retries >= MAX_BODY_RETRIES;
+        }
+        if exhausted {
+            info!(
+                "Sync: block bodies are not served, downloading headers again from {}",
+                self.head()
+            );
+            self.restart();
+            return Ok(());
+        }
+        for hash in missing.into_iter().rev() {
+            self.body_queue.push_front(hash);
+        }
+        Ok(())
+    }
 
-    pub fn set_status(&mut self, new_status: SyncSliceStatus) { self.status = new_status; }
+    fn import_ready_blocks(&mut self) {
+        let mut importer = self.importer.lock().unwrap();
+        while let Some((hash, block)) = self.blocks.remove(&self.next_import) {
+            importer.import_block(&block);
+            self.head_hash = Some(hash);
+            self.pivot_hash = None;
+            self.head_timestamp = block.header.timestamp;
+            self.next_import += 1;
+        }
+    }
+}
 
-    pub fn process_block_bodies(&mut self, bodies: &[BlockBody]) {
-        let n_hashes = self.block_hashes.len();
-        let n_bodies = bodies.len();
-        info!("Got {} block bodies for {} headers", n_bodies, n_hashes);
-        let n_blocks = min(n_hashes, n_bodies);
-        for i in 0..n_blocks {
-            let hash = &self.block_hashes[i];
-            if let Some(header) = self.headers.get(hash) {
-                let block = WireBlock { header: header.clone(), body: bodies[i].clone() };
-                self.importer.lock().unwrap().import_block(&block);
-            } else {
-                error!("No matching header found for {}", hash);
+/// Pairs every body with requested header that has the same transactions root and ommers hash.
+/// Bodies should come in the same order as requested but peer is allowed to skip the ones it doesn't have.
+/// Returns matched pairs and hashes that are still missing their bodies.
+fn match_bodies(
+    headers: &HashMap<H256, BlockHeader>,
+    requested: &[H256],
+    bodies: Vec<BlockBody>,
+) -> Result<(Vec<(H256, BlockBody)>, Vec<H256>), ErrorAct> {
+    if bodies.len() > requested.len() {
+        ErrorAct::new_kick(format!(
+            "Too many block bodies. Requested:{} got:{}",
+            requested.len(),
+            bodies.len()
+        ))?
+    }
+    let mut matched = Vec::with_capacity(bodies.len());
+    let mut missing = vec![];
+    let mut requested = requested.iter();
+    for body in bodies {
+        let transactions_root = transactions_root(&body.transactions);
+        let ommers_hash = ommers_hash(&body.ommers);
+        loop {
+            let hash = match requested.next() {
+                Some(hash) => hash,
+                None => ErrorAct::new_kick_generic(
+                    "Block body does not match any requested header".into(),
+                )?,
+            };
+            let header = match headers.get(hash) {
+                Some(header) => header,
+                None => {
+                    error!("No matching header found for {}", hash);
+                    missing.push(*hash);
+                    continue;
+                }
+            };
+            if header.transactions_root == transactions_root && header.ommers_hash == ommers_hash {
+                matched.push((*hash, body));
+                break;
             }
+            missing.push(*hash);
         }
-        self.block_hashes.clear();
-        self.status = SyncSliceStatus::ReceivedBodies(n_blocks);
     }
+    missing.extend(requested);
+    Ok((matched, missing))
 }
 
 pub struct SyncWatcher {
-    block_height: u64,
     buffer: Arc<Mutex<SyncBuffer>>,
-    status: SyncSliceStatus,
 }
 
-fn request_block_headers(block_height: u64) -> InitialRequest {
-    let request = GetBlockHeaders::new(BlockId::Number(block_height), 100, 0, false);
+fn request_block_headers(request: GetBlockHeaders) -> InitialRequest {
+    info!("Sync: Requesting headers {:?}", request);
     let data = encode_get_block_headers(&request);
-    info!("Sync: Requesting headers from {}", block_height);
-    InitialRequest::new(EthMessageId::GetBlockHeaders, data)
+    InitialRequest::new(MessageId::Eth(EthMessageId::GetBlockHeaders), data)
 }
 
 fn request_block_bodies(hashes: &[H256]) -> InitialRequest {
     let data = encode_get_block_bodies(hashes);
     info!("Sync: Requesting {} block bodies", hashes.len());
-    InitialRequest::new(EthMessageId::GetBlockBodies, data)
+    InitialRequest::new(MessageId::Eth(EthMessageId::GetBlockBodies), data)
 }
 
 impl SyncWatcher {
     pub fn new(buffer: Arc<Mutex<SyncBuffer>>) -> Self {
-        SyncWatcher { block_height: 10_000_000, buffer, status: SyncSliceStatus::Idle }
+        SyncWatcher { buffer }
     }
 
-    pub fn is_syncing(&self) -> bool {
-        self.block_height < 10_001_000
-    }
-
-    pub fn next_sync_task(&mut self) -> Option<InitialRequest> {
-        if self.is_syncing() {
-            let status = self.buffer.lock().unwrap().get_status();
-            info!("Sync status: {:?}", status);
-            match status {
-                SyncSliceStatus::Idle => {
-                    Some(request_block_headers(self.block_height))
-                },
-                SyncSliceStatus::ReceivedHeaders => {
-                    Some(request_block_bodies(&self.buffer.lock().unwrap().unmatched_headers()))
-                }
-                SyncSliceStatus::ReceivedBodies(n) => {
-                    self.block_height += n as u64;
-                    Some(request_block_headers(self.block_height))
+    /// Bodies are requested first so that verified headers do not pile up in buffer.
+    pub fn next_sync_task(
+        &mut self,
+        peer: &PeerId,
+        limits: RequestLimits,
+    ) -> Option<InitialRequest> {
+        let mut buffer = self.buffer.lock().unwrap();
+        if !buffer.is_syncing() {
+            return None;
+        }
+        if let Some(hashes) = buffer.next_body_request(peer, limits.bodies) {
+            return Some(request_block_bodies(&hashes));
+        }
+        buffer
+            .next_header_request(peer, limits.headers)
+            .map(request_block_headers)
+    }
+
+    pub fn sync_task_failed(&mut self, peer: &PeerId) {
+        self.buffer.lock().unwrap().release(peer);
+    }
+
+    pub fn sync_task_reassigned(&mut self, from: &PeerId, to: &PeerId) -> bool {
+        self.buffer.lock().unwrap().reassign(from, to)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client_adapter::headers_in_memory::HeadersInMemory;
+    use core::{BlockId, Transaction, H160};
+
+    fn header_for(body: &BlockBody, number: BlockNumber) -> BlockHeader {
+        BlockHeader {
+            parent_hash: H256::zero(),
+            ommers_hash: ommers_hash(&body.ommers),
+            beneficiary_address: H160::zero(),
+            state_root: H256::zero(),
+            transactions_root: transactions_root(&body.transactions),
+            receipts_root: H256::zero(),
+            logs_bloom: vec![],
+            difficulty: 0,
+            number,
+            gas_limit: 0,
+            gas_used: 0,
+            timestamp: 0,
+            extra_data: vec![],
+            mix_hash: H256::zero(),
+            nonce: 0,
+        }
+    }
+
+    fn setup() -> (HashMap<H256, BlockHeader>, Vec<H256>, Vec<BlockBody>) {
+        let mut tx = Transaction::default();
+        let bodies: Vec<BlockBody> = (0..3)
+            .map(|i| {
+                tx.nonce = i.into();
+                BlockBody {
+                    transactions: vec![tx.clone()],
+                    ommers: vec![],
                 }
-                _ => None,
-            }
-        } else {
-            None
+            })
+            .collect();
+        let mut headers = HashMap::new();
+        let mut hashes = vec![];
+        for (i, body) in bodies.iter().enumerate() {
+            let hash = H256::from_low_u64_be(i as u64);
+            headers.insert(hash, header_for(body, i as u64));
+            hashes.push(hash);
         }
+        (headers, hashes, bodies)
     }
 
-    pub fn sync_task_started(&mut self) {
-        let mut buffer = self.buffer.lock().unwrap();
-        match buffer.get_status() {
-            SyncSliceStatus::Idle | SyncSliceStatus::ReceivedBodies(_) => {
-                buffer.set_status(SyncSliceStatus::AwaitingHeaders);
-            }
-            SyncSliceStatus::ReceivedHeaders => {
-                buffer.set_status(SyncSliceStatus::AwaitingBodies);
-            },
-            _ => {}
+    #[test]
+    fn test_skipped_body_is_retried_until_limit() {
+        let importer = Arc::new(Mutex::new(HeadersInMemory::new()));
+        let mut buffer = SyncBuffer::new(importer, 0, H256::zero());
+        buffer.set_target(&SyncTarget::new(3, H256::from_low_u64_be(3)), false);
+        let (_, _, bodies) = setup();
+        let headers = bodies
+            .iter()
+            .enumerate()
+            .map(|(i, body)| {
+                let number = i as u64 + 1;
+                let mut header = header_for(body, number);
+                header.parent_hash = H256::from_low_u64_be(number - 1);
+                BlockHeaderAndHash {
+                    header,
+                    hash: H256::from_low_u64_be(number),
+                }
+            })
+            .collect();
+        let request = Some(GetBlockHeaders::new(BlockId::Number(1), 3, 0, false));
+        assert_eq!(buffer.next_header_request(&1, 1), request);
+        buffer.process_headers(&1, headers).unwrap();
+
+        let hash = H256::from_low_u64_be;
+        // first block is skipped, third is after end of response and it is not counted.
+        assert_eq!(
+            buffer.next_body_request(&1, 3),
+            Some(vec![hash(1), hash(2), hash(3)])
+        );
+        buffer
+            .process_block_bodies(&1, bodies[1..2].to_vec())
+            .unwrap();
+        assert_eq!(
+            buffer.next_body_request(&1, 3),
+            Some(vec![hash(1), hash(3)])
+        );
+        buffer
+            .process_block_bodies(&1, bodies[2..].to_vec())
+            .unwrap();
+        for _ in 2..MAX_BODY_RETRIES {
+            assert_eq!(buffer.next_body_request(&1, 3), Some(vec![hash(1)]));
+            buffer.process_block_bodies(&1, vec![]).unwrap();
         }
+
+        // downloaded blocks are dropped and headers are requested again.
+        assert_eq!(buffer.head(), 0);
+        assert_eq!(buffer.next_body_request(&1, 3), None);
+        assert_eq!(buffer.next_header_request(&1, 1), request);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_skeleton_size_follows_header_capacity() {
+        let importer = Arc::new(Mutex::new(HeadersInMemory::new()));
+        let mut buffer = SyncBuffer::new(importer, 0, H256::zero());
+        let target = 10 * MAX_HEADER_FETCH;
+        buffer.set_target(
+            &SyncTarget::new(target, H256::from_low_u64_be(target)),
+            false,
+        );
+        let skeleton = |count| {
+            Some(GetBlockHeaders::new(
+                BlockId::Number(MAX_HEADER_FETCH),
+                count,
+                MAX_HEADER_FETCH - 1,
+                false,
+            ))
+        };
+        assert_eq!(
+            buffer.next_header_request(&1, 3 * MAX_HEADER_FETCH + 10),
+            skeleton(3)
+        );
+        buffer.release(&1);
+        // slow peer still gets one anchor.
+        assert_eq!(buffer.next_header_request(&1, 10), skeleton(1));
+    }
+
+    #[test]
+    fn test_snap_pivot_is_downloaded_first() {
+        let importer = Arc::new(Mutex::new(HeadersInMemory::new()));
+        let mut buffer = SyncBuffer::new(importer, 0, H256::zero());
+        buffer.set_target(&SyncTarget::new(1002, H256::from_low_u64_be(1002)), false);
+        buffer.reset_to_pivot(1000, H256::from_low_u64_be(1000));
+        assert_eq!(buffer.head(), 999);
+
+        let headers = || -> Vec<BlockHeaderAndHash> {
+            (1000..=1002)
+                .map(|number| BlockHeaderAndHash {
+                    header: BlockHeader {
+                        parent_hash: H256::from_low_u64_be(number - 1),
+                        number,
+                        ..Default::default()
+                    },
+                    hash: H256::from_low_u64_be(number),
+                })
+                .collect()
+        };
+        assert_eq!(
+            buffer.next_header_request(&1, 1),
+            Some(GetBlockHeaders::new(BlockId::Number(1000), 3, 0, false))
+        );
+        let mut forged = headers();
+        forged[0].hash = H256::repeat_byte(0xff);
+        forged[1].header.parent_hash = forged[0].hash;
+        assert!(buffer.process_headers(&1, forged).is_err());
+
+        buffer.next_header_request(&1, 1).unwrap();
+        buffer.process_headers(&1, headers()).unwrap();
+        assert_eq!(
+            buffer.next_body_request(&1, 1),
+            Some(vec![H256::from_low_u64_be(1000)])
+        );
+    }
+
+    #[test]
+    fn test_match_bodies_partial_response() {
+        let (headers, hashes, mut bodies) = setup();
+        bodies.remove(1);
+        let (matched, missing) = match_bodies(&headers, &hashes, bodies).unwrap();
+        assert_eq!(matched.len(), 2);
+        assert_eq!(matched[0].0, hashes[0]);
+        assert_eq!(matched[1].0, hashes[2]);
+        assert_eq!(missing, vec![hashes[1]]);
+    }
+
+    #[test]
+    fn test_match_bodies_without_header() {
+        let (mut headers, hashes, mut bodies) = setup();
+        headers.remove(&hashes[1]);
+        bodies.remove(1);
+        let (matched, missing) = match_bodies(&headers, &hashes, bodies).unwrap();
+        assert_eq!(matched.len(), 2);
+        assert_eq!(missing, vec![hashes[1]]);
+    }
+
+    #[test]
+    fn test_match_bodies_mismatch() {
+        let (headers, hashes, mut bodies) = setup();
+        bodies[0].transactions.clear();
+        assert!(match_bodies(&headers, &hashes, bodies).is_err());
+    }
+}
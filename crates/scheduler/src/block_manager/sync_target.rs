@@ -0,0 +1,359 @@
+// Copyright 2021 Gnosis Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    common_types::{BlockHeaderAndHash, GetBlockHeaders, NewBlockHash},
+    scheduler::peer_organizer::{ErrorAct, PeerId},
+};
+use core::{BlockHeader, BlockId, BlockNumber, H256, U256};
+use std::collections::HashMap;
+
+/// Block we want to sync to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SyncTarget {
+    pub number: BlockNumber,
+    pub hash: H256,
+}
+
+impl SyncTarget {
+    pub fn new(number: BlockNumber, hash: H256) -> Self {
+        SyncTarget { number, hash }
+    }
+}
+
+/// Target block with known state root, snap sync downloads its state.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pivot {
+    pub number: BlockNumber,
+    pub hash: H256,
+    pub state_root: H256,
+}
+
+/// Best block that peer told us about. Only headers that we got are used as target, numbers
+/// from announcements are not trusted. Status message contains only hash, so its header is
+/// requested from peer.
+#[derive(Clone, Debug)]
+struct PeerHead {
+    /// Latest header of peer that we got, target is selected from these.
+    header: Option<ResolvedHead>,
+    total_difficulty: Option<U256>,
+    /// Announced head whose header we still need to get from peer.
+    pending: Option<H256>,
+    /// Hash of header that is requested from peer.
+    resolving: Option<H256>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct ResolvedHead {
+    hash: H256,
+    number: BlockNumber,
+    state_root: H256,
+}
+
+/// Tracks best blocks of our peers and selects the one we should sync to.
+pub struct PeerHeads {
+    peers: HashMap<PeerId, PeerHead>,
+    /// If set, we sync to this finalized block until we reach it, whatever peers advertise.
+    checkpoint: Option<SyncTarget>,
+}
+
+impl PeerHeads {
+    pub fn new(checkpoint: Option<SyncTarget>) -> Self {
+        PeerHeads {
+            peers: HashMap::new(),
+            checkpoint,
+        }
+    }
+
+    pub fn checkpoint(&self) -> Option<SyncTarget> {
+        self.checkpoint
+    }
+
+    pub fn insert_peer(&mut self, peer: &PeerId, hash: H256, total_difficulty: Option<U256>) {
+        self.peers.insert(
+            *peer,
+            PeerHead {
+                header: None,
+                total_difficulty,
+                pending: Some(hash),
+                resolving: None,
+            },
+        );
+    }
+
+    /// Head of disconnected peer is not a candidate for target anymore.
+    pub fn remove_peer(&mut self, peer: &PeerId) {
+        self.peers.remove(peer);
+    }
+
+    /// NewBlockHashes does not contain difficulty and its numbers are not trusted, header of
+    /// announced block is requested if it is ahead of what we know about peer.
+    pub fn new_block_hashes(&mut self, peer: &PeerId, hashes: &[NewBlockHash]) {
+        let head = match self.peers.get_mut(peer) {
+            Some(head) => head,
+            None => return,
+        };
+        if let Some(best) = hashes.iter().max_by_key(|block| block.number) {
+            if head
+                .header
+                .map_or(true, |header| best.number > header.number)
+            {
+                head.pending = Some(best.hash);
+            }
+        }
+    }
+
+    /// Block is taken as new head only if it is child of peer's head that we know, and its total
+    /// difficulty only if it adds up. Otherwise its header is requested as for announcement.
+    pub fn new_block(
+        &mut self,
+        peer: &PeerId,
+        hash: H256,
+        header: &BlockHeader,
+        total_difficulty: U256,
+    ) {
+        let head = match self.peers.get_mut(peer) {
+            Some(head) => head,
+            None => return,
+        };
+        let linked = head.header.map_or(false, |parent| {
+            parent.hash == header.parent_hash && parent.number + 1 == header.number
+        });
+        if !linked {
+            if head
+                .header
+                .map_or(true, |known| header.number > known.number)
+            {
+                head.pending = Some(hash);
+            }
+            return;
+        }
+        head.header = Some(ResolvedHead {
+            hash,
+            number: header.number,
+            state_root: header.state_root,
+        });
+        head.pending = None;
+        let expected = head
+            .total_difficulty
+            .map(|parent| parent.saturating_add(header.difficulty.into()));
+        head.total_difficulty = match expected {
+            Some(expected) if expected == total_difficulty => Some(total_difficulty),
+            _ => None,
+        };
+    }
+
+    /// Request for header of peers best block if we don't have it.
+    pub fn next_resolve_request(&mut self, peer: &PeerId) -> Option<GetBlockHeaders> {
+        let head = self.peers.get_mut(peer)?;
+        if head.resolving.is_some() {
+            return None;
+        }
+        let hash = head.pending?;
+        head.resolving = Some(hash);
+        Some(GetBlockHeaders::new(BlockId::Hash(hash), 1, 0, false))
+    }
+
+    pub fn is_resolving(&self, peer: &PeerId) -> bool {
+        self.peers
+            .get(peer)
+            .map_or(false, |head| head.resolving.is_some())
+    }
+
+    pub fn release(&mut self, peer: &PeerId) {
+        if let Some(head) = self.peers.get_mut(peer) {
+            head.resolving = None;
+        }
+    }
+
+    /// Peer needs to respond with header of block it advertised as best.
+    pub fn resolve(
+        &mut self,
+        peer: &PeerId,
+        headers: &[BlockHeaderAndHash],
+    ) -> Result<(), ErrorAct> {
+        let head = match self.peers.get_mut(peer) {
+            Some(head) => head,
+            None => return Ok(()),
+        };
+        let requested = head.resolving.take();
+        match headers {
+            [header] if Some(header.hash) == requested => {
+                // peer could announce newer block while we waited, that one is requested next.
+                if head.pending == requested {
+                    head.pending = None;
+                }
+                if head
+                    .header
+                    .map_or(true, |known| header.header.number > known.number)
+                {
+                    head.header = Some(ResolvedHead {
+                        hash: header.hash,
+                        number: header.header.number,
+                        state_root: header.header.state_root,
+                    });
+                }
+                Ok(())
+            }
+            _ => ErrorAct::new_kick("Peer did not return its best header".into()),
+        }
+    }
+
+    /// Configured checkpoint has priority while we are below it, otherwise we take head of peer
+    /// with highest total difficulty.
+    pub fn best_target(&self, head: BlockNumber) -> Option<SyncTarget> {
+        if let Some(checkpoint) = self.checkpoint {
+            if head < checkpoint.number {
+                return Some(checkpoint);
+            }
+        }
+        self.peers
+            .values()
+            .filter_map(|peer| Some((peer.total_difficulty, peer.header?)))
+            .max_by_key(|(total_difficulty, header)| {
+                (total_difficulty.unwrap_or_default(), header.number)
+            })
+            .map(|(_, header)| SyncTarget::new(header.number, header.hash))
+    }
+
+    /// Best target with its state root if we know its header.
+    pub fn pivot(&self, head: BlockNumber) -> Option<Pivot> {
+        let target = self.best_target(head)?;
+        self.peers
+            .values()
+            .filter_map(|peer| peer.header)
+            .find(|header| header.hash == target.hash)
+            .map(|header| Pivot {
+                number: header.number,
+                hash: header.hash,
+                state_root: header.state_root,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(number: BlockNumber, parent: u8, difficulty: u64) -> BlockHeader {
+        BlockHeader {
+            parent_hash: H256::repeat_byte(parent),
+            number,
+            difficulty,
+            state_root: H256::repeat_byte(0x55),
+            ..Default::default()
+        }
+    }
+
+    fn resolved(hash: u8, number: BlockNumber) -> Vec<BlockHeaderAndHash> {
+        vec![BlockHeaderAndHash {
+            header: header(number, 0, 0),
+            hash: H256::repeat_byte(hash),
+        }]
+    }
+
+    #[test]
+    fn test_target_is_highest_total_difficulty() {
+        let mut heads = PeerHeads::new(None);
+        heads.insert_peer(&1, H256::repeat_byte(1), Some(100.into()));
+        heads.insert_peer(&2, H256::repeat_byte(2), Some(200.into()));
+        assert_eq!(heads.best_target(0), None);
+
+        assert!(heads.next_resolve_request(&2).is_some());
+        assert!(heads.next_resolve_request(&2).is_none());
+        assert!(heads.resolve(&2, &resolved(9, 40)).is_err());
+        heads.next_resolve_request(&2).unwrap();
+        heads.resolve(&2, &resolved(2, 40)).unwrap();
+        heads.next_resolve_request(&1).unwrap();
+        heads.resolve(&1, &resolved(1, 50)).unwrap();
+        assert_eq!(
+            heads.best_target(0),
+            Some(SyncTarget::new(40, H256::repeat_byte(2)))
+        );
+
+        // child of known head with matching total difficulty is taken right away.
+        heads.new_block(&1, H256::repeat_byte(3), &header(51, 1, 150), 250.into());
+        assert_eq!(
+            heads.best_target(0),
+            Some(SyncTarget::new(51, H256::repeat_byte(3)))
+        );
+        assert_eq!(
+            heads.pivot(0),
+            Some(Pivot {
+                number: 51,
+                hash: H256::repeat_byte(3),
+                state_root: H256::repeat_byte(0x55),
+            })
+        );
+        assert!(heads.next_resolve_request(&1).is_none());
+
+        // head of disconnected peer is dropped and target can go down.
+        heads.remove_peer(&1);
+        assert_eq!(
+            heads.best_target(0),
+            Some(SyncTarget::new(40, H256::repeat_byte(2)))
+        );
+    }
+
+    #[test]
+    fn test_announced_head_is_taken_after_its_header() {
+        let mut heads = PeerHeads::new(None);
+        heads.insert_peer(&1, H256::repeat_byte(1), Some(100.into()));
+        heads.next_resolve_request(&1).unwrap();
+        heads.resolve(&1, &resolved(1, 50)).unwrap();
+
+        heads.new_block_hashes(&1, &[NewBlockHash::new(H256::repeat_byte(4), 1_000_000)]);
+        // unlinked block with huge difficulty is not trusted either.
+        heads.new_block(
+            &1,
+            H256::repeat_byte(5),
+            &header(2_000_000, 7, 1),
+            U256::MAX,
+        );
+        assert_eq!(
+            heads.best_target(0),
+            Some(SyncTarget::new(50, H256::repeat_byte(1)))
+        );
+        assert_eq!(
+            heads.next_resolve_request(&1),
+            Some(GetBlockHeaders::new(
+                BlockId::Hash(H256::repeat_byte(5)),
+                1,
+                0,
+                false
+            ))
+        );
+        heads.resolve(&1, &resolved(5, 52)).unwrap();
+        assert_eq!(
+            heads.best_target(0),
+            Some(SyncTarget::new(52, H256::repeat_byte(5)))
+        );
+
+        // child with total difficulty that does not add up loses its difficulty.
+        heads.new_block(&1, H256::repeat_byte(6), &header(53, 5, 1), 1_000.into());
+        heads.insert_peer(&2, H256::repeat_byte(2), Some(1.into()));
+        heads.next_resolve_request(&2).unwrap();
+        heads.resolve(&2, &resolved(2, 10)).unwrap();
+        assert_eq!(
+            heads.best_target(0),
+            Some(SyncTarget::new(10, H256::repeat_byte(2)))
+        );
+    }
+
+    #[test]
+    fn test_checkpoint_overrides_peers() {
+        let checkpoint = SyncTarget::new(10, H256::repeat_byte(10));
+        let mut heads = PeerHeads::new(Some(checkpoint));
+        heads.insert_peer(&1, H256::repeat_byte(1), Some(100.into()));
+        heads.next_resolve_request(&1).unwrap();
+        heads.resolve(&1, &resolved(1, 50)).unwrap();
+        assert_eq!(heads.best_target(0), Some(checkpoint));
+        assert_eq!(heads.best_target(9), Some(checkpoint));
+
+        // once we are at checkpoint, peers decide.
+        assert_eq!(
+            heads.best_target(10),
+            Some(SyncTarget::new(50, H256::repeat_byte(1)))
+        );
+    }
+}
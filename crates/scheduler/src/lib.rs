@@ -8,7 +8,6 @@ extern crate num;
 #[macro_use]
 extern crate num_derive;
 
-extern crate ethereum_forkid;
 extern crate interfaces;
 
 #[macro_use]
@@ -0,0 +1,204 @@
+// Copyright 2021 Gnosis Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+use core::{BlockNumber, ForkHash, ForkId, H256};
+use crc::crc32;
+
+/// Fork `next` values above this are timestamps, block numbers never get this high. It is
+/// mainnet genesis timestamp, same threshold is used by other clients.
+const TIMESTAMP_THRESHOLD: u64 = 1_438_269_973;
+
+/// Chain position that fork id is calculated for. Forks up to Paris activate at block number,
+/// from Shanghai on at block timestamp, see EIP-6122.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Head {
+    pub number: BlockNumber,
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Fork {
+    Block(BlockNumber),
+    Time(u64),
+}
+
+impl Fork {
+    fn value(&self) -> u64 {
+        match *self {
+            Fork::Block(value) | Fork::Time(value) => value,
+        }
+    }
+
+    fn is_passed(&self, head: &Head) -> bool {
+        match *self {
+            Fork::Block(number) => head.number >= number,
+            Fork::Time(timestamp) => head.timestamp >= timestamp,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ValidationError {
+    /// Peer is on our chain but did not learn about fork that we already passed.
+    RemoteStale,
+    /// Peer is on different chain, or we are stale ourselves.
+    LocalIncompatibleOrStale,
+}
+
+/// Calculates our fork id and validates fork ids of peers, see EIP-2124.
+#[derive(Clone, Debug)]
+pub struct ForkFilter {
+    /// Block forks followed by timestamp forks, without duplicates and forks active at genesis.
+    forks: Vec<Fork>,
+    /// Hash of genesis followed by hash after every fork.
+    hashes: Vec<ForkHash>,
+    head: Head,
+}
+
+impl ForkFilter {
+    pub fn new(head: Head, genesis: H256, block_forks: &[BlockNumber], time_forks: &[u64]) -> Self {
+        let forks: Vec<Fork> = schedule(block_forks)
+            .map(Fork::Block)
+            .chain(schedule(time_forks).map(Fork::Time))
+            .collect();
+        let mut checksum = crc32::checksum_ieee(genesis.as_bytes());
+        let mut hashes = vec![ForkHash(checksum.to_be_bytes())];
+        for fork in forks.iter() {
+            checksum = crc32::update(checksum, &crc32::IEEE_TABLE, &fork.value().to_be_bytes());
+            hashes.push(ForkHash(checksum.to_be_bytes()));
+        }
+        ForkFilter {
+            forks,
+            hashes,
+            head,
+        }
+    }
+
+    pub fn set_head(&mut self, head: Head) {
+        self.head = head;
+    }
+
+    /// Number of forks that our head passed.
+    fn passed(&self) -> usize {
+        self.forks
+            .iter()
+            .take_while(|fork| fork.is_passed(&self.head))
+            .count()
+    }
+
+    pub fn current(&self) -> ForkId {
+        let passed = self.passed();
+        ForkId {
+            hash: self.hashes[passed],
+            next: self.forks.get(passed).map_or(0, Fork::value),
+        }
+    }
+
+    /// Peer is compatible if it is at the same fork as we are and we did not pass its next fork,
+    /// if it is behind us and knows about the fork that it needs to pass next, or if it is ahead
+    /// of us on our fork schedule.
+    pub fn validate(&self, fork_id: ForkId) -> Result<(), ValidationError> {
+        let passed = self.passed();
+        if self.hashes[passed] == fork_id.hash {
+            let next_passed = fork_id.next > 0
+                && (self.head.number >= fork_id.next
+                    || (fork_id.next > TIMESTAMP_THRESHOLD && self.head.timestamp >= fork_id.next));
+            if next_passed {
+                return Err(ValidationError::LocalIncompatibleOrStale);
+            }
+            return Ok(());
+        }
+        if let Some(behind) = self.hashes[..passed]
+            .iter()
+            .position(|hash| *hash == fork_id.hash)
+        {
+            if self.forks[behind].value() != fork_id.next {
+                return Err(ValidationError::RemoteStale);
+            }
+            return Ok(());
+        }
+        if !self.hashes[passed + 1..].contains(&fork_id.hash) {
+            return Err(ValidationError::LocalIncompatibleOrStale);
+        }
+        Ok(())
+    }
+}
+
+/// Forks in activation order, forks active at genesis and ones at the same block (as
+/// Constantinople and Petersburg) do not change fork hash.
+fn schedule(forks: &[u64]) -> impl Iterator<Item = u64> {
+    let mut forks = forks.to_vec();
+    forks.sort_unstable();
+    forks.dedup();
+    forks.into_iter().filter(|fork| *fork != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::network::NetworkConfig;
+
+    fn fork_id(hash: u32, next: u64) -> ForkId {
+        ForkId {
+            hash: ForkHash(hash.to_be_bytes()),
+            next,
+        }
+    }
+
+    fn at(number: BlockNumber, timestamp: u64) -> Head {
+        Head { number, timestamp }
+    }
+
+    #[test]
+    fn test_validation_rules() {
+        let mut filter = NetworkConfig::mainnet().fork_filter(at(15_050_000, 1_681_338_455));
+        assert_eq!(filter.current(), fork_id(0xdce96c2d, 1_710_338_135));
+
+        // same fork, next fork of peer is unknown to us but not passed yet.
+        assert!(filter.validate(fork_id(0xdce96c2d, 1_800_000_000)).is_ok());
+        assert!(filter.validate(fork_id(0xdce96c2d, 0)).is_ok());
+        // peer announces fork at timestamp that we already passed without it.
+        assert_eq!(
+            filter.validate(fork_id(0xdce96c2d, 1_681_338_455)),
+            Err(ValidationError::LocalIncompatibleOrStale)
+        );
+
+        // peer is syncing and knows about its next fork, or it does not.
+        assert!(filter.validate(fork_id(0xf0afd0e3, 1_681_338_455)).is_ok());
+        assert!(filter.validate(fork_id(0xfc64ec04, 1_150_000)).is_ok());
+        assert_eq!(
+            filter.validate(fork_id(0xf0afd0e3, 0)),
+            Err(ValidationError::RemoteStale)
+        );
+
+        // peer is ahead of us, we did not sync yet.
+        assert!(filter.validate(fork_id(0xc376cf8b, 1_764_798_551)).is_ok());
+        assert_eq!(
+            filter.validate(fork_id(0xdeadbeef, 0)),
+            Err(ValidationError::LocalIncompatibleOrStale)
+        );
+
+        filter.set_head(at(25_000_000, 1_800_000_000));
+        assert_eq!(filter.current(), fork_id(0x07c9462e, 0));
+        assert!(filter.validate(fork_id(0x07c9462e, 0)).is_ok());
+    }
+
+    /// Encoding examples from EIP-2124.
+    #[test]
+    fn test_fork_id_rlp() {
+        let cases = [
+            (fork_id(0, 0), vec![0xc6, 0x84, 0, 0, 0, 0, 0x80]),
+            (
+                fork_id(0xdeadbeef, 0xbaddcafe),
+                vec![
+                    0xca, 0x84, 0xde, 0xad, 0xbe, 0xef, 0x84, 0xba, 0xdd, 0xca, 0xfe,
+                ],
+            ),
+        ];
+        for (fork_id, encoded) in cases.iter() {
+            let data = rlp::encode(fork_id);
+            assert_eq!(data.as_ref(), &encoded[..]);
+            assert_eq!(rlp::decode::<ForkId>(&data).unwrap(), *fork_id);
+        }
+    }
+}
@@ -9,22 +9,23 @@ use interfaces::{
 };
 
 use super::{
+    fork_filter::{ForkFilter, Head},
+    network::NetworkConfig,
     peer_organizer::{ErrorAct, PeerCapability, PeerId, Task, TaskId},
     protocol::{EthProtocolVersion, ParityProtocolVersion},
 };
 use rlp::{DecoderError, Rlp, RlpStream};
-use std::{collections::HashMap, str::FromStr};
+use std::collections::HashMap;
 
-use core::{H256, U256};
-use ethereum_forkid::{ForkFilter, ForkId};
+use core::{ForkId, H256, U256};
 
 #[derive(Debug, Clone)]
 pub struct Handshake {
     pub peers: HashMap<PeerId, (TaskId, PeerCapability)>,
     // field bellow are needed for creating and verifying status msg
-    pub network_id: u64, //it is hard coded in start
-    pub genesis_hash: H256,
-    //pub fork_filter: ForkFilter,
+    pub network: NetworkConfig,
+    /// Validates fork id of peers against our fork schedule and head, see EIP-2124 and EIP-6122.
+    pub fork_filter: ForkFilter,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -40,19 +41,25 @@ pub struct HandshakeInfo {
 }
 
 impl Handshake {
-    pub fn new() -> Handshake {
+    pub fn new(network: NetworkConfig) -> Handshake {
         Handshake {
             peers: HashMap::new(),
-            network_id: 1,
-            genesis_hash: H256::from_str(
-                "d4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3",
-            )
-            .unwrap(),
-            //ForkFilter:: ForkFilter::fork_filter()
+            fork_filter: network.fork_filter(Head::default()),
+            network,
         }
     }
 
+    /// Fork id changes when our head passes fork block or fork timestamp.
+    pub fn set_head(&mut self, head: Head) {
+        self.fork_filter.set_head(head);
+    }
+
+    pub fn fork_id(&self) -> ForkId {
+        self.fork_filter.current()
+    }
+
     fn encode_rlp_status_msg(
+        &self,
         status: &ImporterStatus,
         protocol_version: u32,
         fork_ids: Option<ForkId>,
@@ -61,20 +68,20 @@ impl Handshake {
         let mut rlp = RlpStream::new();
         rlp.begin_unbounded_list();
         rlp.append(&protocol_version); //send protocol
-        rlp.append(&status.network_id); //network ID
+        rlp.append(&self.network.network_id); //network ID
         rlp.append(&status.total_difficulty);
         rlp.append(&status.highest_block.1);
-        rlp.append(&status.genesis_block_hash);
+        rlp.append(&self.network.genesis_hash);
 
         if let Some(fork_id) = fork_ids {
             //protocol
             rlp.append(&fork_id);
         }
 
-        if let Some(_snapshot_ms) = snapshot_manifest {
-            //TODO for now send empty
-            //rlp.append(&H256::zero());//&snapshot_ms.hash);
-            //rlp.append(&(0 as u64));//&snapshot_ms.block_number);
+        if let Some(snapshot_ms) = snapshot_manifest {
+            // zero hash and number if we don't have snapshot
+            rlp.append(&snapshot_ms.hash);
+            rlp.append(&snapshot_ms.block_number);
         }
         rlp.finalize_unbounded_list();
         rlp.out().to_vec()
@@ -128,16 +135,15 @@ impl Handshake {
         snapshot_manifest: Manifest,
     ) -> Vec<u8> {
         self.peers.insert(*peer, (id, capability.clone()));
+        // devp2p connects only peers with common eth version, fallback is never expected.
+        let eth_version = capability
+            .get(&ProtocolId::Eth)
+            .and_then(|versions| self.network.common_eth_version(versions))
+            .unwrap_or(EthProtocolVersion::VERSION_64.to_number());
         let mut fork_id = None;
-        if let Some(eth_ver) = capability.get(&ProtocolId::Eth) {
-            if eth_ver
-                .iter()
-                .find(|&ver| *ver >= EthProtocolVersion::VERSION_64.to_number())
-                .is_some()
-            {
-                fork_id = Some(status.fork);
-            }
-        };
+        if eth_version >= EthProtocolVersion::VERSION_64.to_number() {
+            fork_id = Some(self.fork_id());
+        }
         let mut snap_manifest = None;
 
         if let Some(par_ver) = capability.get(&ProtocolId::Parity) {
@@ -146,25 +152,38 @@ impl Handshake {
             }
         }
 
-        Self::encode_rlp_status_msg(
-            status,
-            EthProtocolVersion::VERSION_64.to_number() as u32,
-            fork_id,
-            snap_manifest,
-        )
+        self.encode_rlp_status_msg(status, eth_version as u32, fork_id, snap_manifest)
     }
 
     pub fn verify_status(&self, hi: &HandshakeInfo) -> Result<(), ErrorAct> {
-        if hi.eth_protocol_version < EthProtocolVersion::VERSION_64.to_version_byte() {
-            ErrorAct::new_kick("Unsupported Eth version".into())?
+        if !self.network.eth_versions.contains(&hi.eth_protocol_version) {
+            ErrorAct::new_kick(format!(
+                "Unsupported Eth version {}",
+                hi.eth_protocol_version
+            ))?
         }
-        if hi.genesis_hash != self.genesis_hash {
-            ErrorAct::new_kick("Genesis hash is different".into())?
+        if hi.genesis_hash != self.network.genesis_hash {
+            ErrorAct::new_kick(format!(
+                "Genesis hash is different, expected {} network",
+                self.network.name
+            ))?
         }
-        if hi.network_id != self.network_id {
-            ErrorAct::new_kick("Network id is different".into())?
+        if hi.network_id != self.network.network_id {
+            ErrorAct::new_kick(format!(
+                "Network id {} is different, expected {}",
+                hi.network_id, self.network.network_id
+            ))?
+        }
+        if let Some(fork_id) = hi.fork_id {
+            if let Err(err) = self.fork_filter.validate(fork_id) {
+                ErrorAct::new_kick(format!(
+                    "Incompatible fork id {:?}, local {:?}: {:?}",
+                    fork_id,
+                    self.fork_id(),
+                    err
+                ))?
+            }
         }
-        //TODO self.fork_filter.is_compatible(hi.fork_id)?
 
         Ok(())
     }
@@ -189,3 +208,123 @@ impl Handshake {
             .and_then(|(task_id, _)| Some(task_id))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::BlockNumber;
+
+    fn head(number: BlockNumber) -> Head {
+        Head {
+            number,
+            timestamp: 0,
+        }
+    }
+
+    fn handshake_info(network: &NetworkConfig, number: BlockNumber) -> HandshakeInfo {
+        HandshakeInfo {
+            peer_id: 1,
+            eth_protocol_version: 65,
+            genesis_hash: network.genesis_hash,
+            network_id: network.network_id,
+            latest_hash: H256::zero(),
+            total_difficulty: None,
+            fork_id: Some(network.fork_filter(head(number)).current()),
+            snapshot: None,
+        }
+    }
+
+    fn rejection(handshake: &Handshake, hi: &HandshakeInfo) -> String {
+        let err = handshake.verify_status(hi).unwrap_err();
+        assert_eq!(err.penal(), PeerPenal::Kick);
+        err.reason()
+    }
+
+    #[test]
+    fn test_fork_id_validation() {
+        let mainnet = NetworkConfig::mainnet();
+        let mut handshake = Handshake::new(mainnet.clone());
+        handshake.set_head(head(13_000_000));
+        assert_eq!(handshake.fork_id().next, 13_773_000);
+        assert!(handshake
+            .verify_status(&handshake_info(&mainnet, 13_000_000))
+            .is_ok());
+
+        // peer that did not pass London yet is fine while it can still catch up.
+        assert!(handshake
+            .verify_status(&handshake_info(&mainnet, 12_500_000))
+            .is_ok());
+
+        // peer on chain that did not fork at DAO block is rejected.
+        let mut no_dao = mainnet.clone();
+        no_dao.forks.retain(|fork| *fork != 1_920_000);
+        assert!(rejection(&handshake, &handshake_info(&no_dao, 13_000_000))
+            .starts_with("Incompatible fork id"));
+
+        // after Shanghai, peer that never scheduled it is stale.
+        handshake.set_head(Head {
+            number: 17_000_000,
+            timestamp: 1_681_338_455,
+        });
+        let mut hi = handshake_info(&mainnet, 15_050_000);
+        assert!(handshake.verify_status(&hi).is_ok());
+        let mut no_shanghai = mainnet.clone();
+        no_shanghai.time_forks.clear();
+        hi.fork_id = Some(no_shanghai.fork_filter(head(17_000_000)).current());
+        assert!(rejection(&handshake, &hi).starts_with("Incompatible fork id"));
+    }
+
+    #[test]
+    fn test_networks_accept_only_own_peers() {
+        let gnosis = NetworkConfig::gnosis();
+        let mut handshake = Handshake::new(gnosis.clone());
+        handshake.set_head(head(20_000_000));
+        assert!(handshake
+            .verify_status(&handshake_info(&gnosis, 20_000_000))
+            .is_ok());
+
+        let reason = rejection(&handshake, &handshake_info(&NetworkConfig::mainnet(), 0));
+        assert!(reason.starts_with("Genesis hash is different"));
+
+        // chiado peer that claims gnosis genesis is caught by network id.
+        let mut hi = handshake_info(&gnosis, 20_000_000);
+        hi.network_id = NetworkConfig::chiado().network_id;
+        assert!(rejection(&handshake, &hi).starts_with("Network id"));
+
+        hi = handshake_info(&gnosis, 20_000_000);
+        hi.eth_protocol_version = 63;
+        assert!(rejection(&handshake, &hi).starts_with("Unsupported Eth version"));
+
+        let chiado = NetworkConfig::chiado();
+        let handshake = Handshake::new(chiado.clone());
+        assert!(handshake.verify_status(&handshake_info(&chiado, 0)).is_ok());
+        assert!(handshake
+            .verify_status(&handshake_info(&gnosis, 0))
+            .is_err());
+    }
+
+    #[test]
+    fn test_status_message_uses_network() {
+        let mut handshake = Handshake::new(NetworkConfig::gnosis());
+        let mut capability = PeerCapability::new();
+        capability.insert(ProtocolId::Eth, [64, 65, 66].iter().copied().collect());
+        let status = ImporterStatus {
+            total_difficulty: U256::from(1),
+            highest_block: (0, H256::zero()),
+            genesis_block_hash: H256::zero(),
+            network_id: 1,
+            fork: handshake.fork_id(),
+        };
+        let manifest = Manifest {
+            block_number: 0,
+            hash: H256::zero(),
+        };
+        let data =
+            handshake.connect_and_create_status_message(&1, 1, &capability, &status, manifest);
+        let hi = Handshake::decode_rlp_status_msg(&data, false).unwrap();
+        assert_eq!(hi.eth_protocol_version, 65);
+        assert_eq!(hi.network_id, 100);
+        assert_eq!(hi.genesis_hash, handshake.network.genesis_hash);
+        assert!(handshake.verify_status(&hi).is_ok());
+    }
+}
@@ -1,10 +1,19 @@
 // Copyright 2020-2021 Gnosis Ltd.
 // SPDX-License-Identifier: Apache-2.0
 
+pub mod fork_filter;
 mod handshake;
+pub mod msgrate;
+pub mod network;
 pub mod peer_organizer;
+pub mod propagation;
 pub mod protocol;
+pub mod reputation;
 pub mod scheduler;
+pub mod state;
 
+pub use network::NetworkConfig;
 pub use peer_organizer::PeerOrganizer;
+pub use reputation::{PeerScore, ReputationConfig};
 pub use scheduler::Scheduler;
+pub use state::{SchedulerConfig, SchedulerState, StateChange};
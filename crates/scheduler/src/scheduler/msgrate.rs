@@ -0,0 +1,201 @@
+// Copyright 2021 Gnosis Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Measures how fast peers deliver data, modeled after geth's msgrate. Capacity of peer is number
+//! of items it delivers per second, requests are sized so that peer can answer them in target
+//! roundtrip time. Slow peers get small requests and stay busy instead of holding up big ones.
+
+use super::{
+    peer_organizer::PeerId,
+    protocol::{EthMessageId, MessageId},
+};
+use std::{collections::HashMap, time::Duration};
+
+/// Weight of new measurement in capacity and roundtrip estimate.
+const MEASUREMENT_IMPACT: f64 = 0.1;
+/// Bounds of target roundtrip time, it is median of peers roundtrip estimates.
+const RTT_MIN: Duration = Duration::from_secs(2);
+const RTT_MAX: Duration = Duration::from_secs(20);
+/// Roundtrip estimate of peer before its first response.
+const RTT_INITIAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RequestKind {
+    Headers,
+    Bodies,
+    Receipts,
+    /// Parity snapshot chunks and snap requests, both are sized by bytes and not by items.
+    Snapshot,
+}
+
+impl RequestKind {
+    pub fn from_request(message_id: MessageId) -> Option<Self> {
+        match message_id {
+            MessageId::Eth(EthMessageId::GetBlockHeaders) => Some(Self::Headers),
+            MessageId::Eth(EthMessageId::GetBlockBodies) => Some(Self::Bodies),
+            MessageId::Eth(EthMessageId::GetReceipts) => Some(Self::Receipts),
+            MessageId::Parity(_) | MessageId::Snap(_) => Some(Self::Snapshot),
+            _ => None,
+        }
+    }
+}
+
+/// Number of items in response. Eth responses are plain lists, other responses count as one item.
+pub fn response_items(message_id: MessageId, data: &[u8]) -> usize {
+    match message_id {
+        MessageId::Eth(_) => rlp::Rlp::new(data).item_count().unwrap_or(0),
+        _ => 1,
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Tracker {
+    /// Items per second for every kind of request.
+    capacity: HashMap<RequestKind, f64>,
+    roundtrip: Duration,
+}
+
+/// Throughput and latency of all peers.
+#[derive(Default)]
+pub struct Trackers {
+    trackers: HashMap<PeerId, Tracker>,
+}
+
+impl Trackers {
+    /// New peer starts with mean capacity of existing peers, so it gets its fair share right away.
+    pub fn insert_peer(&mut self, peer: &PeerId) {
+        let mut capacity: HashMap<RequestKind, f64> = HashMap::new();
+        for tracker in self.trackers.values() {
+            for (kind, value) in tracker.capacity.iter() {
+                *capacity.entry(*kind).or_insert(0.0) += value;
+            }
+        }
+        let count = self.trackers.len().max(1) as f64;
+        for value in capacity.values_mut() {
+            *value /= count;
+        }
+        let roundtrip = match self.trackers.is_empty() {
+            true => RTT_INITIAL,
+            false => self.median_roundtrip(),
+        };
+        self.trackers.insert(
+            *peer,
+            Tracker {
+                capacity,
+                roundtrip,
+            },
+        );
+    }
+
+    pub fn remove_peer(&mut self, peer: &PeerId) {
+        self.trackers.remove(peer);
+    }
+
+    /// Records response (or timeout with zero items) to request of given kind.
+    pub fn update(&mut self, peer: &PeerId, kind: RequestKind, elapsed: Duration, items: usize) {
+        let tracker = match self.trackers.get_mut(peer) {
+            Some(tracker) => tracker,
+            None => return,
+        };
+        let elapsed = elapsed.max(Duration::from_millis(1));
+        let measured = items as f64 / elapsed.as_secs_f64();
+        let capacity = tracker.capacity.entry(kind).or_insert(0.0);
+        *capacity = (1.0 - MEASUREMENT_IMPACT) * *capacity + MEASUREMENT_IMPACT * measured;
+        if items > 0 {
+            tracker.roundtrip = tracker.roundtrip.mul_f64(1.0 - MEASUREMENT_IMPACT)
+                + elapsed.mul_f64(MEASUREMENT_IMPACT);
+        }
+    }
+
+    fn median_roundtrip(&self) -> Duration {
+        let mut roundtrips: Vec<Duration> = self
+            .trackers
+            .values()
+            .map(|tracker| tracker.roundtrip)
+            .collect();
+        roundtrips.sort();
+        roundtrips
+            .get(roundtrips.len() / 2)
+            .copied()
+            .unwrap_or(RTT_INITIAL)
+    }
+
+    /// Time in which we expect peers to answer requests.
+    pub fn target_roundtrip(&self) -> Duration {
+        self.median_roundtrip().max(RTT_MIN).min(RTT_MAX)
+    }
+
+    /// Number of items peer can deliver in target roundtrip time. It is at least one, and one more
+    /// than measured so that capacity of peer can grow.
+    pub fn capacity(&self, peer: &PeerId, kind: RequestKind, max: usize) -> usize {
+        let capacity = self
+            .trackers
+            .get(peer)
+            .and_then(|tracker| tracker.capacity.get(&kind))
+            .copied()
+            .unwrap_or(0.0);
+        let items = 1 + (capacity * self.target_roundtrip().as_secs_f64()) as usize;
+        items.min(max)
+    }
+
+    /// Peers ordered from the one with highest capacity.
+    pub fn by_capacity(&self, mut peers: Vec<PeerId>, kind: RequestKind) -> Vec<PeerId> {
+        let capacity = |peer: &PeerId| {
+            self.trackers
+                .get(peer)
+                .and_then(|tracker| tracker.capacity.get(&kind))
+                .copied()
+                .unwrap_or(0.0)
+        };
+        peers.sort_by(|a, b| capacity(b).partial_cmp(&capacity(a)).unwrap());
+        peers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capacity_follows_measurements() {
+        let mut trackers = Trackers::default();
+        trackers.insert_peer(&1);
+        trackers.insert_peer(&2);
+        assert_eq!(trackers.capacity(&1, RequestKind::Bodies, 128), 1);
+
+        for _ in 0..50 {
+            trackers.update(&1, RequestKind::Bodies, Duration::from_millis(500), 100);
+            trackers.update(&2, RequestKind::Bodies, Duration::from_secs(4), 4);
+        }
+        let fast = trackers.capacity(&1, RequestKind::Bodies, 1000);
+        let slow = trackers.capacity(&2, RequestKind::Bodies, 1000);
+        assert!(fast > 100 && slow < 10, "fast:{} slow:{}", fast, slow);
+        assert_eq!(trackers.capacity(&1, RequestKind::Bodies, 128), 128);
+        assert_eq!(trackers.capacity(&1, RequestKind::Headers, 128), 1);
+        assert_eq!(
+            trackers.by_capacity(vec![2, 1], RequestKind::Bodies),
+            vec![1, 2]
+        );
+
+        // timeouts bring capacity down.
+        for _ in 0..50 {
+            trackers.update(&1, RequestKind::Bodies, Duration::from_secs(10), 0);
+        }
+        assert!(trackers.capacity(&1, RequestKind::Bodies, 1000) < fast / 10);
+    }
+
+    #[test]
+    fn test_new_peer_gets_mean_capacity() {
+        let mut trackers = Trackers::default();
+        trackers.insert_peer(&1);
+        trackers.insert_peer(&2);
+        for _ in 0..50 {
+            trackers.update(&1, RequestKind::Headers, Duration::from_secs(1), 100);
+        }
+        trackers.insert_peer(&3);
+        let mean = trackers.capacity(&3, RequestKind::Headers, 10_000);
+        let best = trackers.capacity(&1, RequestKind::Headers, 10_000);
+        assert!(mean > 1 && mean < best);
+        assert!(trackers.target_roundtrip() >= RTT_MIN);
+    }
+}
@@ -0,0 +1,270 @@
+// Copyright 2021 Gnosis Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::fork_filter::{ForkFilter, Head};
+use core::{BlockNumber, H256};
+use std::str::FromStr;
+
+/// Eth protocol versions that scheduler can talk.
+pub const ETH_VERSIONS: &[u8] = &[64, 65];
+
+/// Chain we are peering on. Status message is built from it and status of peers is checked against it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkConfig {
+    pub name: String,
+    pub network_id: u64,
+    pub genesis_hash: H256,
+    /// Blocks at which forks activated, they define EIP-2124 fork id.
+    pub forks: Vec<BlockNumber>,
+    /// Timestamps at which forks activated from Shanghai on, they follow block forks in fork id.
+    pub time_forks: Vec<u64>,
+    /// Eth protocol versions that we accept, highest common one is used in status message.
+    pub eth_versions: Vec<u8>,
+}
+
+impl NetworkConfig {
+    pub fn new(
+        name: &str,
+        network_id: u64,
+        genesis_hash: &str,
+        forks: &[BlockNumber],
+        time_forks: &[u64],
+    ) -> Self {
+        NetworkConfig {
+            name: name.into(),
+            network_id,
+            genesis_hash: H256::from_str(genesis_hash).expect("Genesis hash should be valid"),
+            forks: forks.to_vec(),
+            time_forks: time_forks.to_vec(),
+            eth_versions: ETH_VERSIONS.to_vec(),
+        }
+    }
+
+    pub fn mainnet() -> Self {
+        Self::new(
+            "mainnet",
+            1,
+            "d4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3",
+            &[
+                1_150_000, 1_920_000, 2_463_000, 2_675_000, 4_370_000, 7_280_000, 9_069_000,
+                9_200_000, 12_244_000, 12_965_000, 13_773_000, 15_050_000,
+            ],
+            // Shanghai, Cancun, Prague, Osaka, BPO1, BPO2
+            &[
+                1_681_338_455,
+                1_710_338_135,
+                1_746_612_311,
+                1_764_798_551,
+                1_765_290_071,
+                1_767_747_671,
+            ],
+        )
+    }
+
+    pub fn goerli() -> Self {
+        Self::new(
+            "goerli",
+            5,
+            "bf7e331f7f7c1dd2e05159666b3bf8bc7a8a3a9eb1d518969eab529dd9b88c1a",
+            &[1_561_651, 4_460_644, 5_062_605],
+            // Shanghai, Cancun. Goerli is deprecated and did not get later forks.
+            &[1_678_832_736, 1_705_473_120],
+        )
+    }
+
+    pub fn sepolia() -> Self {
+        Self::new(
+            "sepolia",
+            11_155_111,
+            "25a5cc106eea7138acab33231d7160d69cb777ee0c2c553fcddf5138993e6dd9",
+            &[1_735_371],
+            // Shanghai, Cancun, Prague, Osaka, BPO1, BPO2
+            &[
+                1_677_557_088,
+                1_706_655_072,
+                1_741_159_776,
+                1_760_427_360,
+                1_761_017_184,
+                1_761_607_008,
+            ],
+        )
+    }
+
+    /// Gnosis Chain, previously xDai.
+    pub fn gnosis() -> Self {
+        Self::new(
+            "gnosis",
+            100,
+            "4f1dd23188aab3a76b463e4af801b52b1248ef073c648cbdc4c9333d3da79756",
+            &[
+                1_604_400, 2_508_800, 7_298_030, 9_186_425, 16_101_500, 19_040_000,
+            ],
+            // Shanghai, Cancun, Prague
+            &[1_690_889_660, 1_710_181_820, 1_746_021_820],
+        )
+    }
+
+    /// Gnosis Chain testnet, all block forks are active from genesis.
+    pub fn chiado() -> Self {
+        Self::new(
+            "chiado",
+            10_200,
+            "ada44fd8d2ecab8b08f256af07ad3e777f17fb434f8f8e678b312f576212ba9a",
+            &[],
+            // Shanghai, Cancun, Prague
+            &[1_684_934_220, 1_706_724_940, 1_741_254_220],
+        )
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "mainnet" => Some(Self::mainnet()),
+            "goerli" => Some(Self::goerli()),
+            "sepolia" => Some(Self::sepolia()),
+            "gnosis" | "xdai" => Some(Self::gnosis()),
+            "chiado" => Some(Self::chiado()),
+            _ => None,
+        }
+    }
+
+    pub fn fork_filter(&self, head: Head) -> ForkFilter {
+        ForkFilter::new(head, self.genesis_hash, &self.forks, &self.time_forks)
+    }
+
+    /// Highest eth version that both we and peer support.
+    pub fn common_eth_version<'a, I>(&self, peer_versions: I) -> Option<u8>
+    where
+        I: IntoIterator<Item = &'a u8>,
+    {
+        peer_versions
+            .into_iter()
+            .filter(|version| self.eth_versions.contains(version))
+            .max()
+            .copied()
+    }
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self::mainnet()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::{ForkHash, ForkId};
+
+    fn fork_id(network: &NetworkConfig, number: BlockNumber, timestamp: u64) -> ForkId {
+        network.fork_filter(Head { number, timestamp }).current()
+    }
+
+    fn expected(hash: u32, next: u64) -> ForkId {
+        ForkId {
+            hash: ForkHash(hash.to_be_bytes()),
+            next,
+        }
+    }
+
+    #[test]
+    fn test_genesis_fork_hashes() {
+        let mainnet = NetworkConfig::mainnet();
+        assert_eq!(fork_id(&mainnet, 0, 0), expected(0xfc64ec04, 1_150_000));
+
+        let gnosis = NetworkConfig::gnosis();
+        assert_eq!(
+            fork_id(&gnosis, 0, 0).hash,
+            ForkHash(4131981745u32.to_be_bytes())
+        );
+        let london = fork_id(&gnosis, 19_040_000, 0);
+        assert_eq!(london, expected(25459155, 1_690_889_660));
+
+        assert_eq!(NetworkConfig::from_name("xdai"), Some(gnosis));
+        assert!(NetworkConfig::from_name("ropsten").is_none());
+    }
+
+    /// Hashes from EIP-6122 and other clients, at last block fork and at every timestamp fork.
+    #[test]
+    fn test_timestamp_fork_hashes() {
+        let mainnet = NetworkConfig::mainnet();
+        let gray_glacier = 15_050_000;
+        assert_eq!(
+            fork_id(&mainnet, gray_glacier, 1_681_338_454),
+            expected(0xf0afd0e3, 1_681_338_455)
+        );
+        assert_eq!(
+            fork_id(&mainnet, gray_glacier, 1_681_338_455),
+            expected(0xdce96c2d, 1_710_338_135)
+        );
+        assert_eq!(
+            fork_id(&mainnet, gray_glacier, 1_710_338_135),
+            expected(0x9f3d2254, 1_746_612_311)
+        );
+        assert_eq!(
+            fork_id(&mainnet, gray_glacier, 1_746_612_311),
+            expected(0xc376cf8b, 1_764_798_551)
+        );
+        assert_eq!(
+            fork_id(&mainnet, gray_glacier, 1_764_798_551),
+            expected(0x5167e2a6, 1_765_290_071)
+        );
+        assert_eq!(
+            fork_id(&mainnet, gray_glacier, 1_767_747_671),
+            expected(0x07c9462e, 0)
+        );
+
+        let sepolia = NetworkConfig::sepolia();
+        assert_eq!(fork_id(&sepolia, 0, 0), expected(0xfe3366e7, 1_735_371));
+        assert_eq!(
+            fork_id(&sepolia, 1_735_371, 0),
+            expected(0xb96cbd13, 1_677_557_088)
+        );
+        assert_eq!(
+            fork_id(&sepolia, 1_735_371, 1_741_159_776),
+            expected(0xed88b5fd, 1_760_427_360)
+        );
+        assert_eq!(
+            fork_id(&sepolia, 1_735_371, 1_761_607_008),
+            expected(0x268956b6, 0)
+        );
+
+        let goerli = NetworkConfig::goerli();
+        assert_eq!(
+            fork_id(&goerli, 5_062_605, 1_678_832_736),
+            expected(0xf9843abf, 1_705_473_120)
+        );
+        assert_eq!(
+            fork_id(&goerli, 5_062_605, 1_705_473_120),
+            expected(0x70cc14e2, 0)
+        );
+
+        let gnosis = NetworkConfig::gnosis();
+        assert_eq!(
+            fork_id(&gnosis, 19_040_000, 1_690_889_660),
+            expected(0x2efe91ba, 1_710_181_820)
+        );
+        assert_eq!(
+            fork_id(&gnosis, 19_040_000, 1_710_181_820),
+            expected(0x1384dfc1, 1_746_021_820)
+        );
+        assert_eq!(
+            fork_id(&gnosis, 19_040_000, 1_746_021_820),
+            expected(0x2f095d4a, 0)
+        );
+
+        let chiado = NetworkConfig::chiado();
+        assert_eq!(fork_id(&chiado, 0, 0), expected(0x50d39d7b, 1_684_934_220));
+        assert_eq!(
+            fork_id(&chiado, 0, 1_706_724_940),
+            expected(0x5fbc16bc, 1_741_254_220)
+        );
+        assert_eq!(fork_id(&chiado, 0, 1_741_254_220), expected(0x8ba51786, 0));
+    }
+
+    #[test]
+    fn test_common_eth_version() {
+        let network = NetworkConfig::mainnet();
+        assert_eq!(network.common_eth_version(&[63, 64, 65, 66]), Some(65));
+        assert_eq!(network.common_eth_version(&[63]), None);
+    }
+}
@@ -3,7 +3,9 @@
 
 use super::{
     handshake::HandshakeInfo,
-    protocol::{EthMessageId, MessageId},
+    msgrate::{response_items, RequestKind, Trackers},
+    protocol::{EthMessageId, MessageId, ParityMessageId},
+    reputation::{PeerEvent, PeerScore, Reputation, ReputationConfig},
 };
 use std::{
     collections::{HashMap, HashSet},
@@ -11,7 +13,10 @@ use std::{
     time::{Duration, Instant},
 };
 
-use interfaces::devp2p::{Adapter as Devp2pAdapter, PeerPenal, ProtocolId};
+use interfaces::devp2p::{Adapter as Devp2pAdapter, NodeId, PeerPenal, ProtocolId};
+
+/// How many times timeouted request is sent to another peer before its manager is told it failed.
+pub const MAX_REQUEST_RETRIES: usize = 2;
 
 #[derive(Debug)]
 pub struct CustomError {
@@ -34,12 +39,12 @@ impl std::fmt::Display for CustomError {
 
 #[derive(Debug)]
 pub struct InitialRequest {
-    pub message_id: EthMessageId,
+    pub message_id: MessageId,
     pub data: MessageData,
 }
 
 impl InitialRequest {
-    pub fn new(message_id: EthMessageId, data: MessageData) -> Self {
+    pub fn new(message_id: MessageId, data: MessageData) -> Self {
         InitialRequest { message_id, data }
     }
 }
@@ -49,7 +54,7 @@ pub enum Task {
     InsertPeer(HandshakeInfo),
     PenalPeer(PeerId, PeerPenal, String), //last is reason
     WaitForStatus(PeerId, MessageData),
-    InitialRequest(PeerId, EthMessageId, MessageData),
+    InitialRequest(PeerId, MessageId, MessageData),
     Responde(PeerId, ProtocolId, MessageId, Vec<u8>),
     None,
 }
@@ -133,7 +138,7 @@ impl Task {
             Self::InsertPeer(_) => 0,
             Self::PenalPeer(_, _, _) => 0,
             Self::WaitForStatus(_, _) => 0,
-            Self::InitialRequest(_, _, _) => 0,
+            Self::InitialRequest(_, _, _) => MAX_REQUEST_RETRIES,
             Self::Responde(_, _, _, _) => 0,
             Self::None => 0,
         }
@@ -143,7 +148,7 @@ impl Task {
         match self {
             Self::InsertPeer(_) => None,
             Self::PenalPeer(_, _, _) => None,
-            Self::InitialRequest(_, _, _) => None,
+            Self::InitialRequest(_, message_id, _) => Some(request_timeout(*message_id)),
             Self::Responde(_, _, _, _) => None,
             Self::WaitForStatus(_, _) => Some(Duration::from_millis(3000)), //timeout after not receiving status msg from peer
             Self::None => None,
@@ -154,6 +159,24 @@ impl Task {
         GLOBAL_TASK_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
     }
 }
+
+/// Soft limit on size of broadcast and response, more data is split in more messages.
+pub const MAX_MESSAGE_SIZE: usize = 100 * 1024;
+
+/// Maximal number of hashes in one announcement or request for transactions.
+pub const MAX_MESSAGE_HASHES: usize = 4096;
+
+/// Time in which peer needs to answer request. Requests for bigger data get more time.
+fn request_timeout(message_id: MessageId) -> Duration {
+    match message_id {
+        MessageId::Eth(EthMessageId::GetBlockHeaders) => Duration::from_secs(10),
+        MessageId::Eth(EthMessageId::GetBlockBodies) => Duration::from_secs(15),
+        MessageId::Eth(EthMessageId::GetReceipts) => Duration::from_secs(15),
+        MessageId::Parity(ParityMessageId::GetSnapshotData) => Duration::from_secs(30),
+        MessageId::Snap(_) => Duration::from_secs(20),
+        _ => Duration::from_secs(10),
+    }
+}
 #[derive(Debug)]
 pub struct TaskWrapper {
     task: Task,
@@ -200,12 +223,16 @@ pub struct Peer {
     peer_id: PeerId,
     info: PeerInfo,
     tasks: HashSet<TaskId>,
+    /// Requests that timeouted. Messages have no request id, so peer does not get new request
+    /// until their late response arrives, otherwise it would be taken as answer to the new one.
+    late: Vec<MessageId>,
 }
 
 // TODO expend this to cover all needed information fields
 // all field here should be one that are persistent.
 pub struct PeerInfo {
     network_id: u64,
+    eth_version: u8,
 }
 
 impl From<HandshakeInfo> for Peer {
@@ -213,8 +240,10 @@ impl From<HandshakeInfo> for Peer {
         Peer {
             peer_id: hi.peer_id,
             tasks: HashSet::new(),
+            late: Vec::new(),
             info: PeerInfo {
                 network_id: hi.network_id,
+                eth_version: hi.eth_protocol_version,
             },
         }
     }
@@ -223,6 +252,11 @@ pub struct PeerOrganizer {
     peers: HashMap<PeerId, Peer>,
     pending_tasks: HashMap<TaskId, TaskWrapper>,
     devp2p: Arc<Box<dyn Devp2pAdapter>>,
+    reputation: Reputation,
+    /// Node ids of connected peers, reputation is kept per node and outlives session.
+    nodes: HashMap<PeerId, NodeId>,
+    /// Measured throughput of peers, used to pick peers and size requests.
+    rates: Trackers,
 }
 
 impl PeerOrganizer {
@@ -230,36 +264,39 @@ impl PeerOrganizer {
         &self.peers
     }
 
-    fn free_peer(&self) -> Option<PeerId> {
-        for peer in self.peers.keys() {
-            let peer_tasks = self.peers.get(peer).unwrap().tasks.len();
-            if peer_tasks == 0 {
-                return Some(*peer);
-            }
-        }
-        None
+    pub fn free_peers(&self) -> Vec<PeerId> {
+        self.peers
+            .values()
+            .filter(|peer| peer.tasks.is_empty() && peer.late.is_empty())
+            .map(|peer| peer.peer_id)
+            .collect()
     }
 
-    pub fn schedule_to_free_peer(&mut self, request: InitialRequest) -> bool {
-        if let Some(ref peer_id) = self.free_peer() {
-            info!("Scheduling task {:?} to peer {}", &request, peer_id);
-            let task = Task::InitialRequest(*peer_id, request.message_id, request.data);
-            let task_id = Task::new_id();
-            let peers_tasks = &mut self.peers.get_mut(peer_id).unwrap().tasks;
-            peers_tasks.insert(task_id);
-            self.push_task(task, Some(task_id));
-            true
-        } else {
-            info!("No free peer to schedule task {:?} to", &request);
-            false
-        }
+    /// Free peers ordered from the one that delivers given kind of data fastest.
+    pub fn free_peers_by_capacity(&self, kind: RequestKind) -> Vec<PeerId> {
+        self.rates.by_capacity(self.free_peers(), kind)
     }
 
-    pub fn random_peer(&self) -> Option<PeerId> {
-        match self.peers.keys().next() {
-            Some(peer) => Some(*peer),
-            None => None,
-        }
+    /// Connected peers with eth protocol version they talk.
+    pub fn eth_versions(&self) -> Vec<(PeerId, u8)> {
+        self.peers
+            .values()
+            .map(|peer| (peer.peer_id, peer.info.eth_version))
+            .collect()
+    }
+
+    /// Number of items that peer can deliver in target roundtrip time, at most `max`.
+    pub fn request_limit(&self, peer: &PeerId, kind: RequestKind, max: usize) -> usize {
+        self.rates.capacity(peer, kind, max)
+    }
+
+    pub fn schedule_request(&mut self, peer_id: &PeerId, request: InitialRequest) {
+        info!("Scheduling task {:?} to peer {}", &request, peer_id);
+        let task = Task::InitialRequest(*peer_id, request.message_id, request.data);
+        let task_id = Task::new_id();
+        let peers_tasks = &mut self.peers.get_mut(peer_id).unwrap().tasks;
+        peers_tasks.insert(task_id);
+        self.push_task(task, Some(task_id));
     }
 
     pub fn schedule(&mut self, task: Task) {
@@ -272,14 +309,16 @@ impl PeerOrganizer {
         self.push_task(task, Some(task_id));
     }
 
-    pub fn new(devp2p: Arc<Box<dyn Devp2pAdapter>>) -> Arc<Mutex<PeerOrganizer>> {
-        let peer_org = Arc::new(Mutex::new(PeerOrganizer {
+    /// Organizer is owned by scheduler event loop, so it is not behind mutex.
+    pub fn new(devp2p: Arc<Box<dyn Devp2pAdapter>>, reputation: ReputationConfig) -> PeerOrganizer {
+        PeerOrganizer {
             peers: HashMap::new(),
             pending_tasks: HashMap::new(),
             devp2p,
-        }));
-
-        peer_org
+            reputation: Reputation::new(reputation),
+            nodes: HashMap::new(),
+            rates: Trackers::default(),
+        }
     }
 
     pub fn start(&self) {
@@ -290,49 +329,176 @@ impl PeerOrganizer {
         self.devp2p.stop();
     }
 
-    pub fn tick(&mut self) -> Vec<Task> {
+    /// Removes timeouted tasks. Requests with retries left are sent to another free peer, `reassign`
+    /// is called with (from, to, message_id) so that originating manager can move request to new
+    /// peer, it returns false if new peer can't serve it. Tasks that could not be retried are returned.
+    pub fn tick<F>(&mut self, mut reassign: F) -> Vec<Task>
+    where
+        F: FnMut(&PeerId, &PeerId, MessageId) -> bool,
+    {
         let now = Instant::now();
-        let mut timeouted_tasks = Vec::new();
-        let mut rem_ids = Vec::new();
-        for (id, task) in self.pending_tasks.iter_mut() {
-            //check timeout, and if timeouted call organizer to notify managers that request task was not successfull
-            if task.timeouted(&now) {
-                //disconnect particular peer, nulify all its pending tasks and retry all his pending tasks.
-
-                // if we cant retry tasks send them to failed_task array.
-                timeouted_tasks.push(task.task.clone());
-                rem_ids.push(id.clone());
+        self.reputation.prune(now);
+        let rem_ids: Vec<TaskId> = self
+            .pending_tasks
+            .iter()
+            .filter(|(_, task)| task.timeouted(&now))
+            .map(|(id, _)| *id)
+            .collect();
+
+        let mut failed_tasks = Vec::new();
+        for rem_id in rem_ids {
+            let mut wrapper = match self.pending_tasks.remove(&rem_id) {
+                Some(wrapper) => wrapper,
+                None => continue,
+            };
+            // tasks of disconnected peers are timeouted on purpose, they don't count.
+            if let Some(peer) = wrapper.task.peer_id() {
+                if let Some(peer_info) = self.peers.get_mut(&peer) {
+                    peer_info.tasks.remove(&rem_id);
+                    if let Task::InitialRequest(_, message_id, _) = wrapper.task {
+                        peer_info.late.push(message_id);
+                        if let Some(kind) = RequestKind::from_request(message_id) {
+                            let timeout = wrapper.task.timelimit().unwrap_or_default();
+                            self.rates.update(&peer, kind, timeout, 0);
+                        }
+                    }
+                    self.record(&peer, PeerEvent::Timeout);
+                }
+            }
+            if !self.reassign_task(&mut wrapper, &mut reassign) {
+                failed_tasks.push(wrapper.task);
             }
         }
+        failed_tasks
+    }
 
-        for rem_id in rem_ids {
-            self.pending_tasks.remove(&rem_id);
+    /// Sends timeouted request to fastest free peer that its manager accepts.
+    fn reassign_task<F>(&mut self, wrapper: &mut TaskWrapper, reassign: &mut F) -> bool
+    where
+        F: FnMut(&PeerId, &PeerId, MessageId) -> bool,
+    {
+        let (from, message_id, data) = match wrapper.task {
+            Task::InitialRequest(from, message_id, ref data) => (from, message_id, data.clone()),
+            _ => return false,
+        };
+        let kind = match RequestKind::from_request(message_id) {
+            Some(kind) => kind,
+            None => return false,
+        };
+        if !wrapper.retry() {
+            return false;
+        }
+        for to in self.free_peers_by_capacity(kind) {
+            if to == from || !reassign(&from, &to, message_id) {
+                continue;
+            }
+            info!(
+                "Request {:?} timeouted on peer {}, retrying with peer {}",
+                message_id, from, to
+            );
+            let task_id = Task::new_id();
+            self.peers.get_mut(&to).unwrap().tasks.insert(task_id);
+            self.push_task(Task::InitialRequest(to, message_id, data), Some(task_id));
+            if let Some(retried) = self.pending_tasks.get_mut(&task_id) {
+                retried.retries = wrapper.retries;
+            }
+            return true;
         }
-        timeouted_tasks
+        false
+    }
+
+    /// Node id of peer, asked from devp2p when peer is first seen.
+    fn node(&mut self, peer: &PeerId) -> Option<NodeId> {
+        if let Some(node) = self.nodes.get(peer) {
+            return Some(*node);
+        }
+        let node = self.devp2p.node_id(peer)?;
+        self.reputation.connected(&node);
+        self.nodes.insert(*peer, node);
+        Some(node)
+    }
+
+    /// Updates peer reputation and returns penalty if its score got too low.
+    fn penalty(&mut self, peer: &PeerId, event: PeerEvent) -> Option<PeerPenal> {
+        let node = self.node(peer)?;
+        let penal = self.reputation.record(&node, event, Instant::now())?;
+        info!("Peer {} reputation too low after {:?}", peer, event);
+        Some(penal)
+    }
+
+    /// Updates peer reputation and disconnects it if its score got too low.
+    pub fn record(&mut self, peer: &PeerId, event: PeerEvent) {
+        if let Some(penal) = self.penalty(peer, event) {
+            self.disconnect_with(peer, penal);
+        }
+    }
+
+    pub fn is_banned(&mut self, peer: &PeerId) -> bool {
+        match self.node(peer) {
+            Some(node) => self.reputation.is_banned(&node, Instant::now()),
+            None => false,
+        }
+    }
+
+    pub fn peer_scores(&self) -> HashMap<NodeId, PeerScore> {
+        self.reputation.scores().clone()
+    }
+
+    /// Banned nodes with time until their ban expires.
+    pub fn banned_peers(&self) -> HashMap<NodeId, Duration> {
+        let now = Instant::now();
+        self.reputation
+            .banned()
+            .iter()
+            .filter(|(_, expiry)| **expiry > now)
+            .map(|(peer, expiry)| (*peer, *expiry - now))
+            .collect()
     }
 
     // Checks if response is expected. This related to older <eth/65 protocols without requests_id,
-    // It is expected for peer to have only one pending task
-    pub fn check_response(&mut self, peer: &PeerId, _message_id: MessageId) -> bool {
+    // It is expected for peer to have only one pending task. Late response to timeouted request
+    // is dropped, its request was already sent to another peer.
+    pub fn check_response(&mut self, peer: &PeerId, message_id: MessageId, data: &[u8]) -> bool {
         let task_id = match self.peers.get_mut(peer) {
             Some(peer) => {
+                if let Some(index) = peer
+                    .late
+                    .iter()
+                    .position(|request| message_id.is_response_to(*request))
+                {
+                    peer.late.remove(index);
+                    return false;
+                }
                 // expects only one task for older protocol
                 if peer.tasks.len() != 1 {
                     //disconnect
                     return false;
                 }
-                peer.tasks.drain().next().unwrap()
+                *peer.tasks.iter().next().unwrap()
             }
             None => {
                 return false;
             }
         };
 
+        match self.pending_tasks.get(&task_id).map(|task| &task.task) {
+            Some(Task::InitialRequest(_, request, _)) if !message_id.is_response_to(*request) => {
+                return false
+            }
+            _ => (),
+        }
+        self.peers.get_mut(peer).unwrap().tasks.remove(&task_id);
         trace!("peers:{} task_id:{} removed", peer, task_id);
         match self.pending_tasks.remove(&task_id) {
-            Some(_) => {
-                // TODO check if task has same message_id that is expected
-                // with message_id
+            Some(task) => {
+                let elapsed = task.timestamp.elapsed();
+                if let Task::InitialRequest(_, request_id, _) = task.task {
+                    if let Some(kind) = RequestKind::from_request(request_id) {
+                        let items = response_items(message_id, data);
+                        self.rates.update(peer, kind, elapsed, items);
+                    }
+                }
+                self.record(peer, PeerEvent::Response(elapsed));
                 return true;
             }
             None => {
@@ -385,14 +551,32 @@ impl PeerOrganizer {
     pub fn push_task(&mut self, mut task: Task, task_id: Option<TaskId>) -> Option<TaskId> {
         let task_id = match task {
             Task::InsertPeer(hi) => {
+                if self.is_banned(&hi.peer_id) {
+                    self.devp2p.penalize_peer(&hi.peer_id, PeerPenal::Ban);
+                    return None;
+                }
                 info!("Peer inserted: {:?}", task);
+                self.rates.insert_peer(&hi.peer_id);
                 self.peers.insert(hi.peer_id, Peer::from(hi));
                 None
             }
-            Task::PenalPeer(peer, _penal, ref reason) => {
+            // Kick becomes ban if reputation drops too low, Ban is applied right away.
+            Task::PenalPeer(peer, penal, ref reason) => {
                 debug!("Peer penalized. Reason:{}", reason);
-                self.disconnect(&peer); // for now just disconnect all peers
-
+                match penal {
+                    PeerPenal::Kick => {
+                        let penal = self
+                            .penalty(&peer, PeerEvent::InvalidData)
+                            .unwrap_or(PeerPenal::Kick);
+                        self.disconnect_with(&peer, penal);
+                    }
+                    PeerPenal::Ban => {
+                        if let Some(node) = self.node(&peer) {
+                            self.reputation.ban(&node, Instant::now());
+                        }
+                        self.disconnect_with(&peer, PeerPenal::Ban);
+                    }
+                }
                 None
             }
             Task::WaitForStatus(ref peer, ref mut data) => {
@@ -404,10 +588,10 @@ impl PeerOrganizer {
                 }
                 task_id
             }
-            Task::InitialRequest(ref peer, ref message_id, ref mut data) => {
+            // data is kept so that request can be sent to another peer if this one timeouts.
+            Task::InitialRequest(ref peer, ref message_id, ref data) => {
                 self.devp2p
-                    .send_mesage(ProtocolId::Eth, peer, *message_id as u8, &data);
-                data.clear();
+                    .send_mesage(message_id.protocol(), peer, message_id.to_u8(), &data);
                 if task_id.is_none() {
                     panic!("Task id should be set for InitialRequest msg");
                 }
@@ -437,6 +621,24 @@ impl PeerOrganizer {
 
     //disconnect peer.
     pub fn disconnect(&mut self, peer_id: &PeerId) {
+        self.disconnect_with(peer_id, PeerPenal::Kick);
+    }
+
+    /// Called when devp2p tells that peer disconnected.
+    pub fn disconnected(&mut self, peer_id: &PeerId) {
+        self.remove_peer(peer_id);
+    }
+
+    fn disconnect_with(&mut self, peer_id: &PeerId, penal: PeerPenal) {
+        self.remove_peer(peer_id);
+        self.devp2p.penalize_peer(peer_id, penal);
+    }
+
+    fn remove_peer(&mut self, peer_id: &PeerId) {
+        if let Some(node) = self.nodes.remove(peer_id) {
+            self.reputation.disconnected(&node, Instant::now());
+        }
+        self.rates.remove_peer(peer_id);
         if let Some(peer) = self.peers.remove(peer_id) {
             for task_id in peer.tasks {
                 // should we remove task, or do retrasmision. Best way is to naturally timeout it! TODO.
@@ -447,6 +649,178 @@ impl PeerOrganizer {
                 }
             }
         }
-        self.devp2p.penalize_peer(peer_id, PeerPenal::Kick);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::H256;
+    use interfaces::devp2p::Inbound;
+
+    #[derive(Clone, Default)]
+    struct TestDevp2p {
+        sent: Arc<Mutex<Vec<(PeerId, u8)>>>,
+        penalized: Arc<Mutex<Vec<(PeerId, PeerPenal)>>>,
+    }
+
+    impl Devp2pAdapter for TestDevp2p {
+        fn start(&self) {}
+        fn stop(&self) {}
+        fn register_handler(&self, _handle: Arc<dyn Inbound>) {}
+        fn send_mesage(&self, _protocol: ProtocolId, peer: &PeerId, mesage_id: u8, _data: &[u8]) {
+            self.sent.lock().unwrap().push((*peer, mesage_id));
+        }
+        fn penalize_peer(&self, peer: &PeerId, penal: PeerPenal) {
+            self.penalized.lock().unwrap().push((*peer, penal));
+        }
+        fn node_id(&self, peer: &PeerId) -> Option<NodeId> {
+            Some(NodeId::from_low_u64_be(*peer as u64 % 100))
+        }
+    }
+
+    fn insert_peer(org: &mut PeerOrganizer, peer_id: PeerId) {
+        org.push_task(
+            Task::InsertPeer(HandshakeInfo {
+                peer_id,
+                eth_protocol_version: 65,
+                genesis_hash: H256::zero(),
+                network_id: 1,
+                latest_hash: H256::zero(),
+                total_difficulty: None,
+                fork_id: None,
+                snapshot: None,
+            }),
+            None,
+        );
+    }
+
+    fn expire_all(org: &mut PeerOrganizer) {
+        for task in org.pending_tasks.values_mut() {
+            task.timestamp = task.timestamp - Duration::from_secs(1000);
+        }
+    }
+
+    #[test]
+    fn test_timeouted_request_is_reassigned_until_retries_run_out() {
+        let mut org = PeerOrganizer::new(
+            Arc::new(Box::new(TestDevp2p::default())),
+            ReputationConfig::default(),
+        );
+        for peer in 1..=4 {
+            insert_peer(&mut org, peer);
+        }
+        let message_id = MessageId::Eth(EthMessageId::GetBlockBodies);
+        org.schedule_request(&1, InitialRequest::new(message_id, vec![0xc0]));
+
+        let mut moves = Vec::new();
+        for _ in 0..MAX_REQUEST_RETRIES {
+            expire_all(&mut org);
+            let failed = org.tick(|from, to, _| {
+                moves.push((*from, *to));
+                true
+            });
+            assert!(failed.is_empty());
+        }
+        assert_eq!(moves.len(), MAX_REQUEST_RETRIES);
+        assert_eq!(moves[0].0, 1);
+        assert_eq!(moves[1].0, moves[0].1);
+        // peers that timeouted wait for their late responses.
+        assert_eq!(org.free_peers().len(), 1);
+
+        expire_all(&mut org);
+        let failed = org.tick(|_, _, _| true);
+        assert_eq!(failed.len(), 1);
+        match &failed[0] {
+            Task::InitialRequest(peer, MessageId::Eth(EthMessageId::GetBlockBodies), data) => {
+                assert_eq!(*peer, moves[1].1);
+                assert_eq!(data, &vec![0xc0]);
+            }
+            task => panic!("unexpected task {:?}", task),
+        }
+        assert_eq!(org.free_peers().len(), 1);
+        let response = MessageId::Eth(EthMessageId::BlockBodies);
+        for peer in [1, moves[1].0, moves[1].1].iter() {
+            assert!(!org.check_response(peer, response, &[0xc0]));
+        }
+        assert_eq!(org.free_peers().len(), 4);
+    }
+
+    #[test]
+    fn test_kick_disconnects_and_reputation_follows_node() {
+        let devp2p = TestDevp2p::default();
+        let mut org = PeerOrganizer::new(
+            Arc::new(Box::new(devp2p.clone())),
+            ReputationConfig::default(),
+        );
+        insert_peer(&mut org, 1);
+        org.push_task(Task::new_kick(&1, "invalid".into()), None);
+        assert_eq!(
+            *devp2p.penalized.lock().unwrap(),
+            vec![(1, PeerPenal::Kick)]
+        );
+        assert!(org.peers().is_empty());
+        let node = NodeId::from_low_u64_be(1);
+        assert_eq!(org.peer_scores()[&node].score, -50);
+
+        // same node reconnects with new session id.
+        insert_peer(&mut org, 101);
+        for _ in 0..3 {
+            org.push_task(Task::new_kick(&101, "invalid".into()), None);
+        }
+        assert_eq!(
+            devp2p.penalized.lock().unwrap().last(),
+            Some(&(101, PeerPenal::Ban))
+        );
+        assert!(org.banned_peers().contains_key(&node));
+        assert!(org.is_banned(&201));
+    }
+
+    #[test]
+    fn test_late_response_is_not_taken_for_new_request() {
+        let mut org = PeerOrganizer::new(
+            Arc::new(Box::new(TestDevp2p::default())),
+            ReputationConfig::default(),
+        );
+        insert_peer(&mut org, 1);
+        let headers = MessageId::Eth(EthMessageId::GetBlockHeaders);
+        org.schedule_request(&1, InitialRequest::new(headers, vec![]));
+        expire_all(&mut org);
+        assert_eq!(org.tick(|_, _, _| false).len(), 1);
+        assert!(org.free_peers().is_empty());
+
+        // response of other kind does not free peer.
+        let bodies = MessageId::Eth(EthMessageId::BlockBodies);
+        assert!(!org.check_response(&1, bodies, &[0xc0]));
+        assert!(org.free_peers().is_empty());
+
+        let late = MessageId::Eth(EthMessageId::BlockHeaders);
+        assert!(!org.check_response(&1, late, &[0xc0]));
+        assert_eq!(org.free_peers(), vec![1]);
+
+        org.schedule_request(&1, InitialRequest::new(headers, vec![]));
+        assert!(!org.check_response(&1, bodies, &[0xc0]));
+        assert!(org.check_response(&1, late, &[0xc0]));
+        assert_eq!(org.free_peers(), vec![1]);
+    }
+
+    #[test]
+    fn test_refused_reassignment_fails_request() {
+        let mut org = PeerOrganizer::new(
+            Arc::new(Box::new(TestDevp2p::default())),
+            ReputationConfig::default(),
+        );
+        insert_peer(&mut org, 1);
+        insert_peer(&mut org, 2);
+        let message_id = MessageId::Eth(EthMessageId::GetBlockHeaders);
+        org.schedule_request(&1, InitialRequest::new(message_id, vec![]));
+        assert!(org.tick(|_, _, _| true).is_empty());
+
+        expire_all(&mut org);
+        assert_eq!(org.tick(|_, _, _| false).len(), 1);
+        assert_eq!(
+            Task::InitialRequest(1, message_id, vec![]).timelimit(),
+            Some(Duration::from_secs(10))
+        );
     }
 }
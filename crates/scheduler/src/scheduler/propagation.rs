@@ -0,0 +1,106 @@
+// Copyright 2021 Gnosis Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+use core::H256;
+use std::collections::{BTreeMap, HashMap};
+
+/// Bounded set of block or transaction hashes that peer knows about. When it is full, hash that was
+/// least recently seen is forgotten.
+pub struct KnownHashes {
+    capacity: usize,
+    /// Incremented on every insert, hash with the lowest value is the least recently seen.
+    counter: u64,
+    hashes: HashMap<H256, u64>,
+    order: BTreeMap<u64, H256>,
+}
+
+impl KnownHashes {
+    pub fn new(capacity: usize) -> Self {
+        KnownHashes {
+            capacity,
+            counter: 0,
+            hashes: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    pub fn contains(&self, hash: &H256) -> bool {
+        self.hashes.contains_key(hash)
+    }
+
+    pub fn insert(&mut self, hash: H256) {
+        self.counter += 1;
+        if let Some(seen) = self.hashes.insert(hash, self.counter) {
+            self.order.remove(&seen);
+        }
+        self.order.insert(self.counter, hash);
+        while self.hashes.len() > self.capacity {
+            let oldest = *self
+                .order
+                .keys()
+                .next()
+                .expect("Order has entry for every hash");
+            if let Some(hash) = self.order.remove(&oldest) {
+                self.hashes.remove(&hash);
+            }
+        }
+    }
+}
+
+/// Number of peers that get whole block or transaction so that it spreads fast, square root
+/// of `targets` but at least one. The rest get only its hash.
+pub fn full_count(targets: usize) -> usize {
+    ((targets as f64).sqrt() as usize).max(1)
+}
+
+/// Tells for every of `targets` sorted peers whether it is one of `full_count` peers that get
+/// whole item. Every item starts at different peer picked by its hash, so whole items are
+/// spread evenly.
+pub fn fan_out(hash: &H256, targets: usize, full_count: usize) -> impl Iterator<Item = bool> {
+    let offset = match targets {
+        0 => 0,
+        _ => hash.to_low_u64_be() as usize % targets,
+    };
+    (0..targets).map(move |index| (index + targets - offset) % targets < full_count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_least_recently_seen_is_forgotten() {
+        let mut known = KnownHashes::new(2);
+        let (a, b, c) = (
+            H256::from_low_u64_be(1),
+            H256::from_low_u64_be(2),
+            H256::from_low_u64_be(3),
+        );
+        known.insert(a);
+        known.insert(b);
+        known.insert(a);
+        known.insert(c);
+        assert_eq!(known.len(), 2);
+        assert!(known.contains(&a) && known.contains(&c));
+        assert!(!known.contains(&b));
+    }
+
+    #[test]
+    fn test_fan_out_starts_at_hash() {
+        assert_eq!(full_count(1), 1);
+        assert_eq!(full_count(9), 3);
+        let full: Vec<bool> = fan_out(&H256::from_low_u64_be(7), 9, 3).collect();
+        // peers 7 and 8 come first and it wraps around to peer 0.
+        assert_eq!(
+            full,
+            vec![true, false, false, false, false, false, false, true, true]
+        );
+        let full: Vec<bool> = fan_out(&H256::from_low_u64_be(2), 4, 1).collect();
+        assert_eq!(full, vec![false, false, true, false]);
+        assert_eq!(fan_out(&H256::zero(), 0, 1).count(), 0);
+    }
+}
@@ -1,6 +1,8 @@
 // Copyright 2020-2021 Gnosis Ltd.
 // SPDX-License-Identifier: Apache-2.0
 
+use interfaces::devp2p::ProtocolId;
+
 /// ETH protocol version related protocol
 #[allow(non_camel_case_types)]
 #[derive(PartialEq)]
@@ -77,9 +79,9 @@ pub enum EthMessageId {
     GetBlockBodies = 0x05,
     BlockBodies = 0x06,
     NewBlock = 0x07,
-    // NewPooledTransactionHashes = 0x08, // eth/65 protocol
-    // GetPooledTransactions = 0x09, // eth/65 protocol
-    // PooledTransactions  = 0x0a, // eth/65 protocol
+    NewPooledTransactionHashes = 0x08, // eth/65 protocol
+    GetPooledTransactions = 0x09,      // eth/65 protocol
+    PooledTransactions = 0x0a,         // eth/65 protocol
     //GetNodeData = 0x0d, // ommited it can overburder client.
     //NodeData = 0x0e,    // ommited it can overburder client
     GetReceipts = 0x0f,
@@ -89,7 +91,9 @@ pub enum EthMessageId {
 impl EthMessageId {
     pub fn is_response(&self) -> bool {
         match self {
-            Self::BlockHeaders | Self::BlockBodies | Self::Receipts => true,
+            Self::BlockHeaders | Self::BlockBodies | Self::Receipts | Self::PooledTransactions => {
+                true
+            }
             _ => false,
         }
     }
@@ -114,10 +118,36 @@ impl ParityMessageId {
     }
 }
 
+/// snap/1 version byte sent in capability.
+pub const SNAP_PROTOCOL_VERSION: u8 = 1;
+
+/// snap/1 messages. Every message starts with request id that response echoes back.
+#[derive(FromPrimitive, Debug, Copy, Clone, PartialEq)]
+pub enum SnapMessageId {
+    GetAccountRange = 0x00,
+    AccountRange = 0x01,
+    GetStorageRanges = 0x02,
+    StorageRanges = 0x03,
+    GetByteCodes = 0x04,
+    ByteCodes = 0x05,
+    GetTrieNodes = 0x06,
+    TrieNodes = 0x07,
+}
+
+impl SnapMessageId {
+    pub fn is_response(&self) -> bool {
+        match self {
+            Self::AccountRange | Self::StorageRanges | Self::ByteCodes | Self::TrieNodes => true,
+            _ => false,
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum MessageId {
     Eth(EthMessageId),
     Parity(ParityMessageId),
+    Snap(SnapMessageId),
 }
 
 impl MessageId {
@@ -125,6 +155,20 @@ impl MessageId {
         match self {
             Self::Eth(msg_id) => *msg_id as u8,
             Self::Parity(msg_id) => *msg_id as u8,
+            Self::Snap(msg_id) => *msg_id as u8,
+        }
+    }
+
+    pub fn protocol(&self) -> ProtocolId {
+        match self {
+            Self::Eth(_) => ProtocolId::Eth,
+            Self::Parity(_) => ProtocolId::Parity,
+            Self::Snap(_) => ProtocolId::Snap,
         }
     }
+
+    /// In all supported protocols response id directly follows id of its request.
+    pub fn is_response_to(&self, request: MessageId) -> bool {
+        self.protocol() == request.protocol() && self.to_u8() == request.to_u8() + 1
+    }
 }
@@ -0,0 +1,282 @@
+// Copyright 2021 Gnosis Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+use interfaces::devp2p::{NodeId, PeerPenal};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// What peer did that changes its reputation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PeerEvent {
+    /// Peer answered our request after given time.
+    Response(Duration),
+    /// Request or status message was not answered in time.
+    Timeout,
+    /// Peer sent data that we could not decode or verify.
+    InvalidData,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReputationConfig {
+    /// Reward for response that came faster than `slow_response`.
+    pub response_reward: i32,
+    /// Penalty for response that came slower than `slow_response`.
+    pub slow_response_penalty: i32,
+    pub slow_response: Duration,
+    pub timeout_penalty: i32,
+    pub invalid_data_penalty: i32,
+    /// Scores are capped so that long good behaviour can't hide misbehaviour.
+    pub max_score: i32,
+    /// Peer with score at or below this is disconnected.
+    pub kick_threshold: i32,
+    /// Peer with score at or below this is disconnected and not accepted until ban expires.
+    pub ban_threshold: i32,
+    pub ban_duration: Duration,
+    /// How long bad score of disconnected node is remembered.
+    pub score_ttl: Duration,
+}
+
+impl Default for ReputationConfig {
+    fn default() -> Self {
+        ReputationConfig {
+            response_reward: 1,
+            slow_response_penalty: 2,
+            slow_response: Duration::from_secs(5),
+            timeout_penalty: 10,
+            invalid_data_penalty: 50,
+            max_score: 100,
+            kick_threshold: -50,
+            ban_threshold: -200,
+            ban_duration: Duration::from_secs(30 * 60),
+            score_ttl: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+/// Reputation of one peer, exposed for diagnostics.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PeerScore {
+    pub score: i32,
+    pub responses: u32,
+    pub timeouts: u32,
+    pub invalid: u32,
+    /// Average response latency.
+    pub latency: Option<Duration>,
+}
+
+/// Keeps scores and temporary bans of nodes. Negative scores of disconnected nodes are kept
+/// for `score_ttl`, so that node that keeps reconnecting and misbehaving ends up banned.
+pub struct Reputation {
+    config: ReputationConfig,
+    scores: HashMap<NodeId, PeerScore>,
+    /// Ban expiry of banned nodes.
+    banned: HashMap<NodeId, Instant>,
+    /// Disconnect time of nodes whose score is kept.
+    disconnected: HashMap<NodeId, Instant>,
+}
+
+impl Reputation {
+    pub fn new(config: ReputationConfig) -> Self {
+        Reputation {
+            config,
+            scores: HashMap::new(),
+            banned: HashMap::new(),
+            disconnected: HashMap::new(),
+        }
+    }
+
+    /// Records event and returns penalty that node deserves with its new score.
+    pub fn record(&mut self, node: &NodeId, event: PeerEvent, now: Instant) -> Option<PeerPenal> {
+        let config = &self.config;
+        let score = self.scores.entry(*node).or_default();
+        match event {
+            PeerEvent::Response(latency) => {
+                score.latency = Some(match score.latency {
+                    Some(average) => (average * score.responses + latency) / (score.responses + 1),
+                    None => latency,
+                });
+                score.responses += 1;
+                score.score += match latency > config.slow_response {
+                    true => -config.slow_response_penalty,
+                    false => config.response_reward,
+                };
+            }
+            PeerEvent::Timeout => {
+                score.timeouts += 1;
+                score.score -= config.timeout_penalty;
+            }
+            PeerEvent::InvalidData => {
+                score.invalid += 1;
+                score.score -= config.invalid_data_penalty;
+            }
+        }
+        score.score = score.score.min(config.max_score);
+        let penal = if score.score <= config.ban_threshold {
+            Some(PeerPenal::Ban)
+        } else if score.score <= config.kick_threshold {
+            Some(PeerPenal::Kick)
+        } else {
+            None
+        };
+        if let Some(PeerPenal::Ban) = penal {
+            self.ban(node, now);
+        }
+        penal
+    }
+
+    pub fn ban(&mut self, node: &NodeId, now: Instant) {
+        info!("Node {:?} banned for {:?}", node, self.config.ban_duration);
+        self.banned.insert(*node, now + self.config.ban_duration);
+    }
+
+    /// Expired ban is lifted and node starts with clean score.
+    pub fn is_banned(&mut self, node: &NodeId, now: Instant) -> bool {
+        match self.banned.get(node) {
+            Some(expiry) if *expiry > now => true,
+            Some(_) => {
+                self.banned.remove(node);
+                self.scores.remove(node);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Node that reconnected keeps its score.
+    pub fn connected(&mut self, node: &NodeId) {
+        self.disconnected.remove(node);
+    }
+
+    /// Only bad reputation is remembered after disconnect.
+    pub fn disconnected(&mut self, node: &NodeId, now: Instant) {
+        if self
+            .scores
+            .get(node)
+            .map_or(false, |score| score.score >= 0)
+        {
+            self.scores.remove(node);
+        } else {
+            self.disconnected.insert(*node, now);
+        }
+    }
+
+    /// Lifts expired bans and forgets scores of nodes that are disconnected longer than
+    /// `score_ttl`.
+    pub fn prune(&mut self, now: Instant) {
+        let scores = &mut self.scores;
+        self.banned.retain(|node, expiry| {
+            if *expiry > now {
+                return true;
+            }
+            scores.remove(node);
+            false
+        });
+        let ttl = self.config.score_ttl;
+        self.disconnected.retain(|node, at| {
+            if *at + ttl > now {
+                return true;
+            }
+            scores.remove(node);
+            false
+        });
+    }
+
+    pub fn score(&self, node: &NodeId) -> Option<&PeerScore> {
+        self.scores.get(node)
+    }
+
+    pub fn scores(&self) -> &HashMap<NodeId, PeerScore> {
+        &self.scores
+    }
+
+    /// Banned nodes with ban expiry.
+    pub fn banned(&self) -> &HashMap<NodeId, Instant> {
+        &self.banned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(id: u64) -> NodeId {
+        NodeId::from_low_u64_be(id)
+    }
+
+    #[test]
+    fn test_scores_turn_into_penalties() {
+        let mut reputation = Reputation::new(ReputationConfig::default());
+        let now = Instant::now();
+        for _ in 0..200 {
+            reputation.record(
+                &node(1),
+                PeerEvent::Response(Duration::from_millis(100)),
+                now,
+            );
+        }
+        assert_eq!(reputation.score(&node(1)).unwrap().score, 100);
+        assert_eq!(
+            reputation.score(&node(1)).unwrap().latency,
+            Some(Duration::from_millis(100))
+        );
+
+        assert_eq!(reputation.record(&node(2), PeerEvent::Timeout, now), None);
+        assert_eq!(
+            reputation.record(&node(2), PeerEvent::InvalidData, now),
+            Some(PeerPenal::Kick)
+        );
+        reputation.disconnected(&node(2), now);
+        assert!(reputation.score(&node(2)).is_some());
+        reputation.connected(&node(2));
+        reputation.record(&node(2), PeerEvent::InvalidData, now);
+        reputation.record(&node(2), PeerEvent::InvalidData, now);
+        assert_eq!(
+            reputation.record(&node(2), PeerEvent::InvalidData, now),
+            Some(PeerPenal::Ban)
+        );
+        assert!(reputation.is_banned(&node(2), now));
+        assert!(!reputation.is_banned(&node(1), now));
+    }
+
+    #[test]
+    fn test_ban_expires_with_clean_score() {
+        let config = ReputationConfig::default();
+        let ban_duration = config.ban_duration;
+        let mut reputation = Reputation::new(config);
+        let now = Instant::now();
+        reputation.record(&node(1), PeerEvent::InvalidData, now);
+        reputation.ban(&node(1), now);
+        assert!(reputation.is_banned(&node(1), now + ban_duration / 2));
+        assert!(!reputation.is_banned(&node(1), now + ban_duration));
+        assert!(reputation.score(&node(1)).is_none());
+
+        reputation.record(&node(2), PeerEvent::Response(Duration::from_secs(1)), now);
+        reputation.disconnected(&node(2), now);
+        assert!(reputation.score(&node(2)).is_none());
+    }
+
+    #[test]
+    fn test_prune_expired_bans_and_scores() {
+        let config = ReputationConfig::default();
+        let (ban_duration, score_ttl) = (config.ban_duration, config.score_ttl);
+        let mut reputation = Reputation::new(config);
+        let now = Instant::now();
+        reputation.ban(&node(1), now);
+        reputation.record(&node(2), PeerEvent::InvalidData, now);
+        reputation.disconnected(&node(2), now);
+        reputation.record(&node(3), PeerEvent::InvalidData, now);
+        reputation.disconnected(&node(3), now);
+        reputation.connected(&node(3));
+
+        reputation.prune(now + ban_duration);
+        assert!(reputation.banned().is_empty());
+        assert!(reputation.score(&node(2)).is_some());
+
+        reputation.prune(now + score_ttl);
+        assert!(reputation.score(&node(2)).is_none());
+        // node that reconnected keeps its score.
+        assert!(reputation.score(&node(3)).is_some());
+    }
+}
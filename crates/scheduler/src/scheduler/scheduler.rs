@@ -3,137 +3,312 @@
 
 use super::{
     handshake::Handshake,
+    msgrate::RequestKind,
     peer_organizer::{ErrorAct, PeerCapability, PeerId, PeerOrganizer, Task, TaskType},
-    protocol::{EthMessageId, MessageId, ParityMessageId},
+    protocol::{EthMessageId, MessageId, ParityMessageId, SnapMessageId},
+    reputation::PeerScore,
+    state::{SchedulerConfig, SchedulerState, StateChange, StateInput, StateMachine},
+};
+use crate::{
+    block_manager::{
+        BlockchainSync, RequestLimits, MAX_BODIES_FETCH, MAX_HEADER_FETCH, MAX_SKELETON_SIZE,
+    },
+    client_adapter::headers_in_memory::HeadersInMemory,
+    snapshot_manager::SnapshotManager,
+    transaction_manager::TransactionManager,
 };
-use crate::{block_manager::BlockchainSync, client_adapter::headers_in_memory::HeadersInMemory};
 
+use core::{WireBlock, U256};
 use interfaces::{
     blockchain::BlockchainReadOnly,
-    devp2p::{Adapter as Devp2pAdapter, Inbound as Devp2pInbound, PeerPenal, ProtocolId},
+    devp2p::{Adapter as Devp2pAdapter, Inbound as Devp2pInbound, NodeId, PeerPenal, ProtocolId},
     importer::{Importer, ImporterStatus},
-    snapshot::Snapshot,
+    snapshot::{Snapshot, StateWriter},
+    transaction_pool::TransactionPool,
 };
 
 use log::*;
 use std::{
+    collections::HashMap,
     sync::{
-        mpsc::{channel, Sender},
+        mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender},
         Arc, Mutex,
     },
-    thread,
-    time::Duration,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
+use tokio::{sync::oneshot, task};
 
-pub enum SchedulerState {
-    WaitingPeer,
-    Warping,
-    ActiveSync,
-    PassiveSync,
-}
+/// How often pending requests are checked for timeouts. Requests are scheduled after every event.
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+/// Number of events that can wait for event loop. When queue is full devp2p callbacks block,
+/// so network can't get ahead of block import.
+const EVENT_QUEUE_SIZE: usize = 1024;
 
+/// Handle to scheduler. Devp2p callbacks and API calls are sent as events to `SchedulerCore`,
+/// it owns all scheduler state, so there are no locks to order. Event loop runs on its own
+/// thread because block import and snapshot restoration block.
 pub struct Scheduler {
-    handshake: Mutex<Handshake>,
-    state: Mutex<SchedulerState>,
+    devp2p: Arc<Box<dyn Devp2pAdapter>>,
+    events: SyncSender<SchedulerEvent>,
+    handle: Mutex<Option<JoinHandle<()>>>,
+}
+
+pub enum SchedulerEvent {
+    Message(PeerId, ProtocolId, u8, Vec<u8>),
+    Connected(PeerId, PeerCapability),
+    Disconnected(PeerId),
+    NewHead(WireBlock, U256),
+    State(oneshot::Sender<SchedulerState>),
+    SubscribeState(oneshot::Sender<Receiver<StateChange>>),
+    PeerScores(oneshot::Sender<HashMap<NodeId, PeerScore>>),
+    BannedPeers(oneshot::Sender<HashMap<NodeId, Duration>>),
+    Stop,
+}
 
-    peer_organizer: Arc<Mutex<PeerOrganizer>>,
+struct SchedulerCore {
+    config: SchedulerConfig,
+    handshake: Handshake,
+    state: StateMachine,
+
+    peer_organizer: PeerOrganizer,
     importer: Arc<Mutex<dyn Importer>>,
     snapshot: Arc<dyn Snapshot>,
 
     blockchain_sync: BlockchainSync,
-    //pending_packages: u32,
-    /*
-    block_manager,
-    snapshot_manager
-    transaction_manager,
-    brodcaster,
-    PendingMessages
-    */
-    // peer org thread,
-    // organizer thread.
-    main_loop_trigger: Mutex<Sender<LoopMsg>>,
-    thread_handle: Mutex<Option<thread::JoinHandle<()>>>,
-}
-
-pub enum LoopMsg {
-    TrigerLoop,
-    EndLoop,
+    snapshot_manager: SnapshotManager,
+    transaction_manager: TransactionManager,
 }
 
 impl Scheduler {
+    /// Starts event loop thread. Async API needs to be called from within tokio runtime.
     pub fn new(
         devp2p: Box<dyn Devp2pAdapter>,
         blockchain: Arc<dyn BlockchainReadOnly>,
         importer: Arc<dyn Importer>,
         snapshot: Arc<dyn Snapshot>,
+        state_writer: Option<Arc<dyn StateWriter>>,
+        txpool: Arc<Mutex<dyn TransactionPool>>,
+        config: SchedulerConfig,
     ) -> Arc<Scheduler> {
         let devp2p = Arc::new(devp2p);
-        let (tx, rx) = channel::<LoopMsg>();
+        let (events, rx) = sync_channel(EVENT_QUEUE_SIZE);
         let chain = Arc::new(Mutex::new(HeadersInMemory::new()));
         let importer = Arc::clone(&chain);
-        let peer_organizer = PeerOrganizer::new(devp2p.clone());
-        let blockchain_sync = BlockchainSync::new(chain, importer.clone());
-        let org = Arc::new(Scheduler {
+        let peer_organizer = PeerOrganizer::new(devp2p.clone(), config.reputation.clone());
+        let blockchain_sync = BlockchainSync::new(chain, importer.clone(), config.checkpoint);
+        let snapshot_manager = SnapshotManager::new(
+            Arc::clone(&snapshot),
+            config.min_snapshot_peers,
+            config.warp_distance,
+            config.snapshot_dir.clone(),
+            state_writer,
+        );
+        let core = SchedulerCore {
             peer_organizer: peer_organizer,
-            state: Mutex::new(SchedulerState::WaitingPeer),
-            handshake: Mutex::new(Handshake::new()),
+            state: StateMachine::new(&config),
+            handshake: Handshake::new(config.network.clone()),
+            config,
             blockchain_sync: blockchain_sync,
-            main_loop_trigger: Mutex::new(tx),
-            thread_handle: Mutex::new(None),
+            snapshot_manager: snapshot_manager,
+            transaction_manager: TransactionManager::new(txpool),
             importer,
             snapshot,
+        };
+        let handle = thread::Builder::new()
+            .name("scheduler".into())
+            .spawn(move || core.run(rx))
+            .expect("Expect scheduler thread to start");
+        let scheduler = Arc::new(Scheduler {
+            devp2p: devp2p.clone(),
+            events,
+            handle: Mutex::new(Some(handle)),
         });
-        let org_exec = org.clone();
-        *(org.thread_handle.lock().unwrap()) = Some(
-            thread::Builder::new()
-                .name("Scheduler".to_string())
-                .spawn(move || loop {
-                    {
-                        match rx.recv_timeout(Duration::from_secs(1)) {
-                            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => (),
-                            Ok(LoopMsg::TrigerLoop) => (),
-                            Ok(LoopMsg::EndLoop) => break,
-                            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
-                        }
-                        org_exec.main_loop();
-                    }
-                })
-                .expect("Expect to run thread"),
-        );
-        let org_handler = org.clone();
-        devp2p.register_handler(org_handler);
-        org
+        devp2p.register_handler(scheduler.clone());
+        scheduler
     }
 
     pub fn start(&self) {
-        self.peer_organizer.lock().unwrap().start();
+        self.devp2p.start();
     }
 
-    pub fn stop(&self) {
-        let handle = {
-            self.main_loop_trigger
-                .lock()
-                .unwrap()
-                .send(LoopMsg::EndLoop)
-                .unwrap();
-            self.thread_handle.lock().unwrap().take()
-        };
-        self.peer_organizer.lock().unwrap().stop();
+    /// Stops event loop and waits for it to finish.
+    pub async fn stop(&self) {
+        self.send_async(SchedulerEvent::Stop).await;
+        let handle = self.handle.lock().unwrap().take();
         if let Some(handle) = handle {
-            handle.join().expect("Expect for thread to end gracefully.");
+            task::spawn_blocking(move || handle.join())
+                .await
+                .expect("Expect join task not to panic")
+                .expect("Expect for scheduler to end gracefully.");
         }
         //TODO clean all states
     }
 
-    pub fn main_loop(&self) {
-        let mut org = self.peer_organizer.lock().unwrap();
-        if let Some(task) = self.blockchain_sync.next_sync_task() {
-            let success = org.schedule_to_free_peer(task);
-            if success {
-                self.blockchain_sync.sync_task_started();
+    /// Sends query to event loop and waits for answer. Returns None if scheduler is stopped.
+    async fn query<T, F>(&self, event: F) -> Option<T>
+    where
+        F: FnOnce(oneshot::Sender<T>) -> SchedulerEvent,
+    {
+        let (tx, rx) = oneshot::channel();
+        if !self.send_async(event(tx)).await {
+            return None;
+        }
+        rx.await.ok()
+    }
+
+    /// Full queue blocks sender, so from async code event is sent on blocking thread.
+    /// Returns false if scheduler is stopped.
+    async fn send_async(&self, event: SchedulerEvent) -> bool {
+        let events = self.events.clone();
+        task::spawn_blocking(move || events.send(event).is_ok())
+            .await
+            .unwrap_or(false)
+    }
+
+    pub async fn state(&self) -> Option<SchedulerState> {
+        self.query(SchedulerEvent::State).await
+    }
+
+    /// Receiver of all future state changes. Used by RPC and metrics.
+    pub async fn subscribe_state(&self) -> Option<Receiver<StateChange>> {
+        self.query(SchedulerEvent::SubscribeState).await
+    }
+
+    /// Reputation of connected nodes and of disconnected nodes that misbehaved.
+    pub async fn peer_scores(&self) -> Option<HashMap<NodeId, PeerScore>> {
+        self.query(SchedulerEvent::PeerScores).await
+    }
+
+    /// Banned nodes with remaining ban time.
+    pub async fn banned_peers(&self) -> Option<HashMap<NodeId, Duration>> {
+        self.query(SchedulerEvent::BannedPeers).await
+    }
+
+    /// Called after block is imported as new canonical head, it is broadcast to peers.
+    /// Blocks while event queue is full.
+    ///
+    /// Client has to call it for blocks that block sync imported too, sync does not do it by
+    /// itself: `Importer::import_block` does not tell whether block is valid and what its total
+    /// difficulty is, and block must not be propagated before it is validated.
+    pub fn new_head(&self, block: WireBlock, total_difficulty: U256) {
+        self.send(SchedulerEvent::NewHead(block, total_difficulty));
+    }
+
+    fn send(&self, event: SchedulerEvent) {
+        if self.events.send(event).is_err() {
+            debug!("Scheduler is stopped, event dropped");
+        }
+    }
+}
+
+impl SchedulerCore {
+    /// Event loop. Requests are scheduled right after every event, timer only drives timeouts.
+    /// Timer is checked before every event so that busy queue does not delay it.
+    fn run(mut self, events: Receiver<SchedulerEvent>) {
+        let mut next_tick = Instant::now();
+        loop {
+            if Instant::now() >= next_tick {
+                next_tick = Instant::now() + TICK_INTERVAL;
+                self.tick();
+            }
+            match events.recv_timeout(next_tick.saturating_duration_since(Instant::now())) {
+                Ok(SchedulerEvent::Stop) | Err(RecvTimeoutError::Disconnected) => break,
+                Ok(event) => self.handle_event(event),
+                Err(RecvTimeoutError::Timeout) => (),
             }
+            self.main_loop();
+        }
+        self.peer_organizer.stop();
+    }
+
+    fn tick(&mut self) {
+        if self.peer_organizer.peers().len() != 0 {
+            info!("Current peer number:{}", self.peer_organizer.peers().len());
         }
-        let failed_tasks = org.tick();
+        self.transaction_manager.evict_expired();
+        self.transaction_manager.rebroadcast_locals();
+    }
+
+    fn handle_event(&mut self, event: SchedulerEvent) {
+        match event {
+            SchedulerEvent::Message(peer, protocol_id, message_id, data) => {
+                self.receive_message(&peer, protocol_id, message_id, &data)
+            }
+            SchedulerEvent::Connected(peer, capability) => self.connected(&peer, &capability),
+            SchedulerEvent::Disconnected(peer) => self.disconnected(&peer),
+            SchedulerEvent::NewHead(block, total_difficulty) => {
+                self.new_head(&block, total_difficulty)
+            }
+            // receiver can be dropped if caller is not waiting anymore.
+            SchedulerEvent::State(tx) => {
+                let _ = tx.send(self.state.state());
+            }
+            SchedulerEvent::SubscribeState(tx) => {
+                let _ = tx.send(self.state.subscribe());
+            }
+            SchedulerEvent::PeerScores(tx) => {
+                let _ = tx.send(self.peer_organizer.peer_scores());
+            }
+            SchedulerEvent::BannedPeers(tx) => {
+                let _ = tx.send(self.peer_organizer.banned_peers());
+            }
+            SchedulerEvent::Stop => (),
+        }
+    }
+
+    fn state(&self) -> SchedulerState {
+        self.state.state()
+    }
+
+    fn update_state(&mut self, peers: usize) {
+        let best_block = self.blockchain_sync.head();
+        let (warp_available, warp_finished) = {
+            let snapshot_manager = &mut self.snapshot_manager;
+            snapshot_manager.set_pivot(self.blockchain_sync.pivot());
+            (
+                self.config.warp && snapshot_manager.warp_available(best_block),
+                snapshot_manager.is_finished(),
+            )
+        };
+        let input = StateInput {
+            peers,
+            warp_available,
+            warp_finished,
+            sync_distance: self.blockchain_sync.sync_distance(),
+            now: Instant::now(),
+        };
+        let change = self.state.update(&input);
+        if let Some(change) = change {
+            let snapped = self.snapshot_manager.snapped_block();
+            self.blockchain_sync
+                .state_changed(change.from, change.to, snapped);
+            self.snapshot_manager.state_changed(change.to, best_block);
+            self.transaction_manager.state_changed(change.to);
+        }
+    }
+
+    fn main_loop(&mut self) {
+        self.blockchain_sync.update_target();
+        self.handshake.set_head(self.blockchain_sync.fork_head());
+        let peers = self.peer_organizer.peers().len();
+        self.update_state(peers);
+        self.transaction_manager.process_pool_events();
+        let state = self.state();
+        let org = &mut self.peer_organizer;
+        let snapshot_manager = &mut self.snapshot_manager;
+        let blockchain_sync = &self.blockchain_sync;
+        let transaction_manager = &mut self.transaction_manager;
+        for task in transaction_manager.propagate(&org.eth_versions()) {
+            org.push_task(task, None);
+        }
+        // timeouted requests are retried first, so that they get free peers before new requests.
+        let failed_tasks = org.tick(|from, to, message_id| match message_id {
+            MessageId::Parity(_) | MessageId::Snap(_) => {
+                snapshot_manager.request_reassigned(from, to)
+            }
+            _ => blockchain_sync.sync_task_reassigned(from, to),
+        });
         if failed_tasks.len() != 0 {
             info!("Failed tasks: {:?}", failed_tasks);
         }
@@ -145,49 +320,85 @@ impl Scheduler {
                         None,
                     );
                 }
+                Task::InitialRequest(peer, MessageId::Parity(_), _)
+                | Task::InitialRequest(peer, MessageId::Snap(_), _) => {
+                    snapshot_manager.request_failed(peer);
+                }
+                // announced transactions are fetched again if someone announces them.
+                Task::InitialRequest(_, MessageId::Eth(EthMessageId::GetPooledTransactions), _) => {
+                }
+                Task::InitialRequest(peer, _, _) => {
+                    blockchain_sync.sync_task_failed(peer);
+                }
                 _ => (),
             }
         }
-        if org.peers().len() != 0 {
-            info!("Current peer number:{}", org.peers().len());
-        }
-        //wait for n number of peer
-        /*
-            match self.state {
-            WaitingPeer => //check timeout or N number of peers,
-            Warping => self.snapshot_manager.i_want(),
-            ActiveSync => self.block_manager.i_want(),
-            PassiveSync => {
-                self.block_manager.i_want()
-                self.transaction_manager.i_want()
+        match state {
+            SchedulerState::WaitingPeer => (),
+            SchedulerState::Warping => {
+                // only peers that advertised target snapshot (or support snap) get requests
+                for peer in org.free_peers_by_capacity(RequestKind::Snapshot) {
+                    if let Some(request) = snapshot_manager.next_request(&peer) {
+                        org.schedule_request(&peer, request);
+                    }
+                }
+            }
+            SchedulerState::ActiveSync | SchedulerState::PassiveSync => {
+                // fastest peers get requests first, every request is sized by peer capacity.
+                for peer in org.free_peers_by_capacity(RequestKind::Bodies) {
+                    let limits = RequestLimits {
+                        headers: org.request_limit(
+                            &peer,
+                            RequestKind::Headers,
+                            (MAX_SKELETON_SIZE * MAX_HEADER_FETCH) as usize,
+                        ) as u64,
+                        bodies: org.request_limit(&peer, RequestKind::Bodies, MAX_BODIES_FETCH),
+                    };
+                    match blockchain_sync.next_sync_task(&peer, limits) {
+                        Some(request) => org.schedule_request(&peer, request),
+                        None => break,
+                    }
+                }
+                // peers that are left free fetch transactions they announced.
+                if transaction_manager.is_enabled() {
+                    for peer in org.free_peers() {
+                        if let Some(request) = transaction_manager.next_request(&peer) {
+                            org.schedule_request(&peer, request);
+                        }
+                    }
+                }
             }
         }
-        */
     }
 
     fn process_eth_message(
-        &self,
+        &mut self,
         id: EthMessageId,
         peer: &PeerId,
         data: &[u8],
     ) -> Result<Task, ErrorAct> {
         match id {
             EthMessageId::Status => {
-                let mut handshake = self.handshake.lock().unwrap();
-                let peer_handshake = handshake.peers.get(peer).clone();
-                if let Some((task_id, _)) = peer_handshake {
+                let peer_handshake = self.handshake.peers.get(peer).map(|(id, _)| *id);
+                if let Some(task_id) = peer_handshake {
                     // handshake has specific flow
-                    // this should be only place where we interlock handshake and peer_organizer
-                    let mut org = self.peer_organizer.lock().unwrap();
-                    if org.check_response_with_task_id(peer, TaskType::StatusMsg, task_id) {
-                        org.push_task(
-                            handshake
-                                .handle_status_message(peer, data)
-                                .unwrap_or_else(|act| {
-                                    Task::PenalPeer(*peer, act.penal(), act.reason())
-                                }),
-                            None,
-                        );
+                    let org = &mut self.peer_organizer;
+                    if org.check_response_with_task_id(peer, TaskType::StatusMsg, &task_id) {
+                        let task = self
+                            .handshake
+                            .handle_status_message(peer, data)
+                            .unwrap_or_else(|act| {
+                                Task::PenalPeer(*peer, act.penal(), act.reason())
+                            });
+                        if let Task::InsertPeer(ref hi) = task {
+                            self.blockchain_sync.insert_peer(
+                                peer,
+                                hi.latest_hash,
+                                hi.total_difficulty,
+                            );
+                            self.snapshot_manager.insert_peer(peer, hi.snapshot);
+                        }
+                        org.push_task(task, None);
                     };
                 };
             }
@@ -195,14 +406,17 @@ impl Scheduler {
                 info!("Got NewBlockHashes message from {}", peer);
                 self.blockchain_sync.api_new_block_hashes(peer, data)?;
             }
-            EthMessageId::Transactions => {}
+            EthMessageId::Transactions => {
+                debug!("Got Transactions message from {}", peer);
+                return self.transaction_manager.process_transactions(peer, data);
+            }
             EthMessageId::GetBlockHeaders => {
                 info!("Responding peer {} with dummy BlockHeaders message", peer);
                 return self.blockchain_sync.api_get_block_headers(peer, &data);
             }
             EthMessageId::BlockHeaders => {
                 info!("Got BlockHeaders message from {}", peer);
-                self.blockchain_sync.process_block_headers(&data);
+                return self.blockchain_sync.process_block_headers(peer, &data);
             }
             EthMessageId::GetBlockBodies => {
                 info!("Responding peer {} with dummy BlockBodies message", peer);
@@ -214,7 +428,7 @@ impl Scheduler {
                     peer,
                     data.len()
                 );
-                self.blockchain_sync.process_block_bodies(&data);
+                return self.blockchain_sync.process_block_bodies(peer, &data);
             }
             EthMessageId::NewBlock => {
                 info!(
@@ -224,9 +438,22 @@ impl Scheduler {
                 );
                 return self.blockchain_sync.api_new_block(peer, data);
             }
-            // NewPooledTransactionHashes = 0x08, // eth/65 protocol
-            // GetPooledTransactions = 0x09, // eth/65 protocol
-            // PooledTransactions  = 0x0a, // eth/65 protocol
+            EthMessageId::NewPooledTransactionHashes => {
+                debug!("Got NewPooledTransactionHashes message from {}", peer);
+                return self.transaction_manager.process_pooled_hashes(peer, data);
+            }
+            EthMessageId::GetPooledTransactions => {
+                debug!("Responding peer {} with PooledTransactions message", peer);
+                return self
+                    .transaction_manager
+                    .api_get_pooled_transactions(peer, data);
+            }
+            EthMessageId::PooledTransactions => {
+                debug!("Got PooledTransactions message from {}", peer);
+                return self
+                    .transaction_manager
+                    .process_pooled_transactions(peer, data);
+            }
             //EthMessageId::GetNodeData => {} // ommited it can overburder client.
             //EthMessageId::NodeData => {}    // ommited it can overburder client
             EthMessageId::GetReceipts => {}
@@ -234,11 +461,61 @@ impl Scheduler {
         }
         Ok(Task::None)
     }
-}
 
-impl Devp2pInbound for Scheduler {
-    /// Called when new network packet received.
-    fn receive_message(&self, peer: &PeerId, protocol_id: ProtocolId, message_id: u8, data: &[u8]) {
+    fn process_parity_message(
+        &mut self,
+        id: ParityMessageId,
+        peer: &PeerId,
+        data: &[u8],
+    ) -> Result<Task, ErrorAct> {
+        match id {
+            ParityMessageId::GetSnapshotManifest => {
+                info!("Responding peer {} with SnapshotManifest message", peer);
+                return self.snapshot_manager.api_get_snapshot_manifest(peer);
+            }
+            ParityMessageId::SnapshotManifest => {
+                info!("Got SnapshotManifest message from {}", peer);
+                return self.snapshot_manager.process_manifest(peer, data);
+            }
+            ParityMessageId::GetSnapshotData => {
+                info!("Responding peer {} with SnapshotData message", peer);
+                return self.snapshot_manager.api_get_snapshot_data(peer, data);
+            }
+            ParityMessageId::SnapshotData => {
+                info!(
+                    "Got SnapshotData message from {} with {} bytes",
+                    peer,
+                    data.len()
+                );
+                return self.snapshot_manager.process_chunk(peer, data);
+            }
+            ParityMessageId::ConsensusData => {}
+        }
+        Ok(Task::None)
+    }
+
+    fn process_snap_message(
+        &mut self,
+        id: SnapMessageId,
+        peer: &PeerId,
+        data: &[u8],
+    ) -> Result<Task, ErrorAct> {
+        info!(
+            "Got snap {:?} message from {} with {} bytes",
+            id,
+            peer,
+            data.len()
+        );
+        self.snapshot_manager.process_snap_message(id, peer, data)
+    }
+
+    fn receive_message(
+        &mut self,
+        peer: &PeerId,
+        protocol_id: ProtocolId,
+        message_id: u8,
+        data: &[u8],
+    ) {
         info!(
             "recv msg: peer:{} msg:{}, ver:{:?}",
             peer, message_id, protocol_id
@@ -255,19 +532,16 @@ impl Devp2pInbound for Scheduler {
                 if message_id.is_response() {
                     if !self
                         .peer_organizer
-                        .lock()
-                        .unwrap()
-                        .check_response(peer, MessageId::Eth(message_id))
+                        .check_response(peer, MessageId::Eth(message_id), data)
                     {
                         return;
                     }
                 }
 
-                let tasks = self.process_eth_message(message_id, peer, data);
-                let mut peer_org = self.peer_organizer.lock().unwrap();
-                for task in tasks {
-                    peer_org.push_task(task, None);
-                }
+                let task = self
+                    .process_eth_message(message_id, peer, data)
+                    .unwrap_or_else(|act| Task::PenalPeer(*peer, act.penal(), act.reason()));
+                self.peer_organizer.push_task(task, None);
             }
             ProtocolId::Parity => {
                 // transform message id
@@ -277,59 +551,257 @@ impl Devp2pInbound for Scheduler {
                     None => return, //TODO disconnect peer. but for now just ignore it.
                 };
 
+                if message_id.is_response() {
+                    if !self.peer_organizer.check_response(
+                        peer,
+                        MessageId::Parity(message_id),
+                        data,
+                    ) {
+                        return;
+                    }
+                }
+
+                let task = self
+                    .process_parity_message(message_id, peer, data)
+                    .unwrap_or_else(|act| Task::PenalPeer(*peer, act.penal(), act.reason()));
+                self.peer_organizer.push_task(task, None);
+            }
+            ProtocolId::Snap => {
+                let message_id: Option<SnapMessageId> = num::FromPrimitive::from_u8(message_id);
+                let message_id = match message_id {
+                    Some(id) => id,
+                    None => return,
+                };
+
                 if message_id.is_response() {
                     if !self
                         .peer_organizer
-                        .lock()
-                        .unwrap()
-                        .check_response(peer, MessageId::Parity(message_id))
+                        .check_response(peer, MessageId::Snap(message_id), data)
                     {
                         return;
                     }
                 }
 
-                match message_id {
-                    ParityMessageId::GetSnapshotManifest => {}
-                    ParityMessageId::SnapshotManifest => {}
-                    ParityMessageId::GetSnapshotData => {}
-                    ParityMessageId::SnapshotData => {}
-                    ParityMessageId::ConsensusData => {}
-                }
+                let task = self
+                    .process_snap_message(message_id, peer, data)
+                    .unwrap_or_else(|act| Task::PenalPeer(*peer, act.penal(), act.reason()));
+                self.peer_organizer.push_task(task, None);
             }
         }
     }
-    /// Called when new peer is connected. Only called when peer supports the same protocol.
-    fn connected(&self, peer: &PeerId, capability: &PeerCapability) {
+
+    fn connected(&mut self, peer: &PeerId, capability: &PeerCapability) {
+        if self.peer_organizer.is_banned(peer) {
+            info!("Banned peer {} connected, disconnecting", peer);
+            self.peer_organizer.disconnect(peer);
+            return;
+        }
         let client_status = self.importer.lock().unwrap().status();
-        let snapshot_manifest_status = self.snapshot.manifest_status();
+        let snapshot_manifest_status = self.snapshot_manager.local_manifest();
         let task_id = Task::new_id();
         info!("Peer connected with capa:{:?}", capability);
-        let data = self
-            .handshake
-            .lock()
-            .unwrap()
-            .connect_and_create_status_message(
-                peer,
-                task_id,
-                capability,
-                &client_status,
-                snapshot_manifest_status,
-            );
+        if capability.contains_key(&ProtocolId::Snap) {
+            self.snapshot_manager.insert_snap_peer(peer);
+        }
+        let data = self.handshake.connect_and_create_status_message(
+            peer,
+            task_id,
+            capability,
+            &client_status,
+            snapshot_manifest_status,
+        );
         self.peer_organizer
-            .lock()
-            .unwrap()
             .push_task(Task::WaitForStatus(*peer, data), Some(task_id));
     }
 
+    /// Blocks are broadcast only at the head of the chain, while syncing peers don't need them.
+    fn new_head(&mut self, block: &WireBlock, total_difficulty: U256) {
+        if self.state() != SchedulerState::PassiveSync {
+            return;
+        }
+        let peers: Vec<PeerId> = self.peer_organizer.peers().keys().cloned().collect();
+        let tasks = self
+            .blockchain_sync
+            .broadcast_block(block, total_difficulty, &peers);
+        for task in tasks {
+            self.peer_organizer.push_task(task, None);
+        }
+    }
+
+    fn disconnected(&mut self, peer: &PeerId) {
+        info!("disconnected:{}", peer);
+        let task_id = self.handshake.disconnect(peer);
+
+        self.blockchain_sync.remove_peer(peer);
+        self.snapshot_manager.remove_peer(peer);
+        self.transaction_manager.peer_disconnected(peer);
+        let peer_org = &mut self.peer_organizer;
+        if let Some(task_id) = task_id {
+            peer_org.remove_task(&task_id);
+        }
+        peer_org.disconnected(peer);
+    }
+}
+
+impl Devp2pInbound for Scheduler {
+    /// Called when new network packet received.
+    fn receive_message(&self, peer: &PeerId, protocol_id: ProtocolId, message_id: u8, data: &[u8]) {
+        self.send(SchedulerEvent::Message(
+            *peer,
+            protocol_id,
+            message_id,
+            data.to_vec(),
+        ));
+    }
+
+    /// Called when new peer is connected. Only called when peer supports the same protocol.
+    fn connected(&self, peer: &PeerId, capability: &PeerCapability) {
+        self.send(SchedulerEvent::Connected(*peer, capability.clone()));
+    }
+
     /// Called when a previously connected peer disconnects.
     fn disconnected(&self, peer: &PeerId) {
-        info!("disconnected:{}", peer);
-        let task_id = self.handshake.lock().unwrap().disconnect(peer);
+        self.send(SchedulerEvent::Disconnected(*peer));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::{Address, BlockNumber, Bytes, H256, U64};
+    use interfaces::{
+        devp2p::Inbound,
+        snapshot::{ChunkType, ManifestData, RestorationStatus, SnapshotError},
+        state::StateProvider,
+    };
+    use rlp::RlpStream;
+    use txpool::{Pool, PoolConfig};
+
+    #[derive(Clone, Default)]
+    struct TestDevp2p {
+        sent: Arc<Mutex<Vec<(PeerId, u8)>>>,
+        stopped: Arc<Mutex<bool>>,
+    }
+
+    impl Devp2pAdapter for TestDevp2p {
+        fn start(&self) {}
+        fn stop(&self) {
+            *self.stopped.lock().unwrap() = true;
+        }
+        fn register_handler(&self, _handle: Arc<dyn Inbound>) {}
+        fn send_mesage(&self, _protocol: ProtocolId, peer: &PeerId, mesage_id: u8, _data: &[u8]) {
+            self.sent.lock().unwrap().push((*peer, mesage_id));
+        }
+        fn penalize_peer(&self, _peer: &PeerId, _penal: PeerPenal) {}
+        fn node_id(&self, peer: &PeerId) -> Option<NodeId> {
+            Some(NodeId::from_low_u64_be(*peer as u64))
+        }
+    }
 
-        let mut peer_org = self.peer_organizer.lock().unwrap();
-        match task_id {
-            Some(task_id) => peer_org.remove_task(&task_id),
-            None => peer_org.disconnect(peer),
+    struct TestSnapshot;
+
+    impl Snapshot for TestSnapshot {
+        fn create_snapshot(
+            &self,
+            block_number: BlockNumber,
+        ) -> Result<ManifestData, SnapshotError> {
+            Err(SnapshotError::BlockNotFound(block_number))
+        }
+        fn manifest(&self) -> Option<ManifestData> {
+            None
+        }
+        fn status(&self) -> RestorationStatus {
+            RestorationStatus::Inactive
+        }
+        fn chunk(&self, _hash: H256) -> Option<Bytes> {
+            None
+        }
+        fn begin_restoration(&self, _manifest: &ManifestData) -> Result<(), SnapshotError> {
+            Ok(())
+        }
+        fn abort_restoration(&self) {}
+        fn restore_chunk(
+            &self,
+            _hash: H256,
+            _chunk: Bytes,
+            _chunk_type: ChunkType,
+        ) -> Result<(), SnapshotError> {
+            Ok(())
+        }
+    }
+
+    struct TestState;
+
+    impl StateProvider for TestState {
+        fn account_nonce(&self, _address: &Address) -> U64 {
+            U64::zero()
+        }
+
+        fn account_balance(&self, _address: &Address) -> U256 {
+            U256::zero()
+        }
+    }
+
+    fn core(devp2p: TestDevp2p) -> SchedulerCore {
+        let config = SchedulerConfig::default();
+        let devp2p: Arc<Box<dyn Devp2pAdapter>> = Arc::new(Box::new(devp2p));
+        let chain = Arc::new(Mutex::new(HeadersInMemory::new()));
+        let snapshot: Arc<dyn Snapshot> = Arc::new(TestSnapshot);
+        let pool = Pool::new(PoolConfig::default(), Arc::new(TestState));
+        SchedulerCore {
+            peer_organizer: PeerOrganizer::new(devp2p, config.reputation.clone()),
+            state: StateMachine::new(&config),
+            handshake: Handshake::new(config.network.clone()),
+            blockchain_sync: BlockchainSync::new(chain.clone(), chain.clone(), None),
+            snapshot_manager: SnapshotManager::new(snapshot.clone(), 3, 100, None, None),
+            transaction_manager: TransactionManager::new(Arc::new(Mutex::new(pool))),
+            config,
+            importer: chain,
+            snapshot,
         }
     }
+
+    #[test]
+    fn test_event_loop_handles_events_until_stop() {
+        let devp2p = TestDevp2p::default();
+        let (events, rx) = sync_channel(1);
+        let core = core(devp2p.clone());
+        let handle = thread::spawn(move || core.run(rx));
+
+        let mut capability = PeerCapability::new();
+        capability.insert(ProtocolId::Eth, vec![66].into_iter().collect());
+        events
+            .send(SchedulerEvent::Connected(1, capability))
+            .unwrap();
+        let mut request = RlpStream::new_list(4);
+        request
+            .append(&0u64)
+            .append(&1u64)
+            .append(&0u64)
+            .append(&false);
+        events
+            .send(SchedulerEvent::Message(
+                1,
+                ProtocolId::Eth,
+                EthMessageId::GetBlockHeaders as u8,
+                request.out().to_vec(),
+            ))
+            .unwrap();
+        let (tx, mut state) = oneshot::channel();
+        events.send(SchedulerEvent::State(tx)).unwrap();
+        events.send(SchedulerEvent::Stop).unwrap();
+        handle.join().unwrap();
+
+        assert_eq!(state.try_recv(), Ok(SchedulerState::WaitingPeer));
+        assert_eq!(
+            *devp2p.sent.lock().unwrap(),
+            vec![
+                (1, EthMessageId::Status as u8),
+                (1, EthMessageId::BlockHeaders as u8)
+            ]
+        );
+        assert!(*devp2p.stopped.lock().unwrap());
+        // events sent after stop are refused.
+        assert!(events.send(SchedulerEvent::Stop).is_err());
+    }
 }
@@ -0,0 +1,230 @@
+// Copyright 2021 Gnosis Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::{network::NetworkConfig, reputation::ReputationConfig};
+use crate::block_manager::SyncTarget;
+use std::{
+    path::PathBuf,
+    sync::mpsc::{channel, Receiver, Sender},
+    time::{Duration, Instant},
+};
+
+/// If we are further than this from sync target we are in active sync.
+pub const PASSIVE_SYNC_DISTANCE: u64 = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SchedulerState {
+    WaitingPeer,
+    Warping,
+    ActiveSync,
+    PassiveSync,
+}
+
+#[derive(Debug, Clone)]
+pub struct SchedulerConfig {
+    /// Number of peers we wait for before we start syncing.
+    pub min_peers: usize,
+    /// After this time we start syncing with any number of peers that we have.
+    pub wait_peers_timeout: Duration,
+    /// Enables warp sync from snapshots advertised by peers.
+    pub warp: bool,
+    /// Number of peers that need to advertise same snapshot so we can warp to it.
+    pub min_snapshot_peers: usize,
+    /// Snapshot needs to be at least this many blocks ahead of our best block.
+    pub warp_distance: u64,
+    /// Directory where warp progress is kept so that restoration can be resumed after restart.
+    pub snapshot_dir: Option<PathBuf>,
+    /// Trusted finalized block that we sync to instead of following peers best block.
+    pub checkpoint: Option<SyncTarget>,
+    /// Scores that decide when misbehaving peers are kicked or banned.
+    pub reputation: ReputationConfig,
+    /// Chain that we peer on.
+    pub network: NetworkConfig,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        SchedulerConfig {
+            min_peers: 3,
+            wait_peers_timeout: Duration::from_secs(5),
+            warp: true,
+            min_snapshot_peers: 3,
+            warp_distance: 30_000,
+            snapshot_dir: None,
+            checkpoint: None,
+            reputation: ReputationConfig::default(),
+            network: NetworkConfig::default(),
+        }
+    }
+}
+
+/// Event that is sent to subscribers when scheduler state changes.
+#[derive(Debug, Clone, Copy)]
+pub struct StateChange {
+    pub from: SchedulerState,
+    pub to: SchedulerState,
+    pub at: Instant,
+}
+
+/// Information from managers needed to decide state transition.
+#[derive(Debug, Clone, Copy)]
+pub struct StateInput {
+    pub peers: usize,
+    /// Snapshot advertised by enough peers is available.
+    pub warp_available: bool,
+    /// Snapshot manager finished (or aborted) restoration.
+    pub warp_finished: bool,
+    /// Distance to sync target, None if target is not known.
+    pub sync_distance: Option<u64>,
+    pub now: Instant,
+}
+
+pub struct StateMachine {
+    state: SchedulerState,
+    waiting_since: Instant,
+    min_peers: usize,
+    wait_peers_timeout: Duration,
+    subscribers: Vec<Sender<StateChange>>,
+}
+
+impl StateMachine {
+    pub fn new(config: &SchedulerConfig) -> Self {
+        StateMachine {
+            state: SchedulerState::WaitingPeer,
+            waiting_since: Instant::now(),
+            min_peers: config.min_peers,
+            wait_peers_timeout: config.wait_peers_timeout,
+            subscribers: vec![],
+        }
+    }
+
+    pub fn state(&self) -> SchedulerState {
+        self.state
+    }
+
+    pub fn subscribe(&mut self) -> Receiver<StateChange> {
+        let (tx, rx) = channel();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    fn sync_state(sync_distance: Option<u64>) -> SchedulerState {
+        match sync_distance {
+            Some(distance) if distance <= PASSIVE_SYNC_DISTANCE => SchedulerState::PassiveSync,
+            _ => SchedulerState::ActiveSync,
+        }
+    }
+
+    fn next_state(&self, input: &StateInput) -> SchedulerState {
+        match self.state {
+            SchedulerState::WaitingPeer => {
+                let enough_peers = input.peers >= self.min_peers
+                    || (input.peers > 0
+                        && input.now >= self.waiting_since + self.wait_peers_timeout);
+                if !enough_peers {
+                    SchedulerState::WaitingPeer
+                } else if input.warp_available {
+                    SchedulerState::Warping
+                } else {
+                    Self::sync_state(input.sync_distance)
+                }
+            }
+            SchedulerState::Warping => {
+                if input.warp_finished {
+                    SchedulerState::ActiveSync
+                } else {
+                    SchedulerState::Warping
+                }
+            }
+            SchedulerState::ActiveSync | SchedulerState::PassiveSync => {
+                if input.peers == 0 {
+                    SchedulerState::WaitingPeer
+                } else {
+                    Self::sync_state(input.sync_distance)
+                }
+            }
+        }
+    }
+
+    /// Moves to next state and notifies subscribers. Returns change if state is changed.
+    pub fn update(&mut self, input: &StateInput) -> Option<StateChange> {
+        let to = self.next_state(input);
+        if to == self.state {
+            return None;
+        }
+        let change = StateChange {
+            from: self.state,
+            to,
+            at: input.now,
+        };
+        info!(
+            "Scheduler state changed {:?} -> {:?}",
+            change.from, change.to
+        );
+        self.state = to;
+        if to == SchedulerState::WaitingPeer {
+            self.waiting_since = input.now;
+        }
+        self.subscribers.retain(|sub| sub.send(change).is_ok());
+        Some(change)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(peers: usize, now: Instant) -> StateInput {
+        StateInput {
+            peers,
+            warp_available: false,
+            warp_finished: false,
+            sync_distance: None,
+            now,
+        }
+    }
+
+    #[test]
+    fn test_wait_for_peers_or_timeout() {
+        let mut machine = StateMachine::new(&SchedulerConfig::default());
+        let now = Instant::now();
+        assert!(machine.update(&input(2, now)).is_none());
+        let later = now + Duration::from_secs(6);
+        let change = machine.update(&input(1, later)).unwrap();
+        assert_eq!(change.to, SchedulerState::ActiveSync);
+    }
+
+    #[test]
+    fn test_warp_then_sync() {
+        let mut machine = StateMachine::new(&SchedulerConfig::default());
+        let events = machine.subscribe();
+        let now = Instant::now();
+        let mut state_input = input(3, now);
+        state_input.warp_available = true;
+        machine.update(&state_input);
+        assert_eq!(machine.state(), SchedulerState::Warping);
+        machine.update(&state_input);
+        assert_eq!(machine.state(), SchedulerState::Warping);
+
+        state_input.warp_finished = true;
+        state_input.sync_distance = Some(10);
+        machine.update(&state_input);
+        assert_eq!(machine.state(), SchedulerState::ActiveSync);
+        machine.update(&state_input);
+        assert_eq!(machine.state(), SchedulerState::PassiveSync);
+
+        machine.update(&input(0, now));
+        assert_eq!(machine.state(), SchedulerState::WaitingPeer);
+
+        let states: Vec<SchedulerState> = events.try_iter().map(|change| change.to).collect();
+        assert_eq!(
+            states,
+            vec![
+                SchedulerState::Warping,
+                SchedulerState::ActiveSync,
+                SchedulerState::PassiveSync,
+                SchedulerState::WaitingPeer
+            ]
+        );
+    }
+}
@@ -1,4 +1,9 @@
 // Copyright 2020-2021 Gnosis Ltd.
 // SPDX-License-Identifier: Apache-2.0
 
+mod range_proof;
+mod rlp_en_de;
+mod snap_sync;
 mod snapshot_manager;
+
+pub use snapshot_manager::SnapshotManager;
@@ -0,0 +1,557 @@
+// Copyright 2021 Gnosis Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Verification of snap range proofs. Proof contains trie nodes on paths to first and last key of
+//! range. From them we build partial trie, replace everything between the two paths with received
+//! entries and check that root hash did not change.
+
+use crate::snapshot_manager::rlp_en_de::hash;
+use core::H256;
+use rlp::{DecoderError, Rlp, RlpStream};
+use std::{cmp::Ordering, collections::HashMap};
+
+#[derive(Debug, PartialEq)]
+pub enum ProofError {
+    /// Node on path to range edge is not in proof.
+    MissingNode(H256),
+    InvalidNode,
+    /// Keys are not ordered, are before origin or don't match values.
+    InvalidKeys,
+    RootMismatch,
+}
+
+impl From<DecoderError> for ProofError {
+    fn from(_: DecoderError) -> Self {
+        ProofError::InvalidNode
+    }
+}
+
+/// Root of empty trie, keccak of empty rlp string.
+pub fn empty_root() -> H256 {
+    hash(&[0x80])
+}
+
+fn nibbles(key: &H256) -> Vec<u8> {
+    key.as_bytes()
+        .iter()
+        .flat_map(|byte| vec![byte >> 4, byte & 0x0f])
+        .collect()
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Empty,
+    Leaf(Vec<u8>, Vec<u8>),
+    Extension(Vec<u8>, Box<Node>),
+    /// Always has 16 children, branch values are not used in secure tries.
+    Branch(Vec<Node>),
+    /// Subtree that is not in proof, only its hash is known.
+    Hash(H256),
+}
+
+fn empty_children() -> Vec<Node> {
+    vec![Node::Empty; 16]
+}
+
+/// Hex prefix encoding of node path.
+fn encode_path(path: &[u8], leaf: bool) -> Vec<u8> {
+    let flag = if leaf { 2 } else { 0 };
+    let mut out = Vec::with_capacity(path.len() / 2 + 1);
+    let rest = if path.len() % 2 == 1 {
+        out.push(((flag + 1) << 4) | path[0]);
+        &path[1..]
+    } else {
+        out.push(flag << 4);
+        path
+    };
+    for pair in rest.chunks(2) {
+        out.push((pair[0] << 4) | pair[1]);
+    }
+    out
+}
+
+fn decode_path(encoded: &[u8]) -> Result<(Vec<u8>, bool), ProofError> {
+    let first = *encoded.first().ok_or(ProofError::InvalidNode)?;
+    let flag = first >> 4;
+    if flag > 3 {
+        return Err(ProofError::InvalidNode);
+    }
+    let mut path = Vec::with_capacity(encoded.len() * 2);
+    if flag & 1 == 1 {
+        path.push(first & 0x0f);
+    }
+    for byte in &encoded[1..] {
+        path.push(byte >> 4);
+        path.push(byte & 0x0f);
+    }
+    Ok((path, flag & 2 != 0))
+}
+
+impl Node {
+    fn encode(&self) -> Vec<u8> {
+        let mut stream = RlpStream::new();
+        match self {
+            Node::Empty => {
+                stream.append_empty_data();
+            }
+            Node::Leaf(path, value) => {
+                stream.begin_list(2);
+                stream.append(&encode_path(path, true));
+                stream.append(value);
+            }
+            Node::Extension(path, child) => {
+                stream.begin_list(2);
+                stream.append(&encode_path(path, false));
+                child.append_reference(&mut stream);
+            }
+            Node::Branch(children) => {
+                stream.begin_list(17);
+                for child in children.iter() {
+                    child.append_reference(&mut stream);
+                }
+                stream.append_empty_data();
+            }
+            Node::Hash(hash) => {
+                stream.append(hash);
+            }
+        }
+        stream.out().to_vec()
+    }
+
+    /// Children shorter than 32 bytes are embedded in parent, others are referenced by hash.
+    fn append_reference(&self, stream: &mut RlpStream) {
+        match self {
+            Node::Empty => {
+                stream.append_empty_data();
+            }
+            Node::Hash(hash) => {
+                stream.append(hash);
+            }
+            _ => {
+                let encoded = self.encode();
+                if encoded.len() < 32 {
+                    stream.append_raw(&encoded, 1);
+                } else {
+                    stream.append(&hash(&encoded));
+                }
+            }
+        }
+    }
+
+    fn hash(&self) -> H256 {
+        match self {
+            Node::Empty => empty_root(),
+            Node::Hash(hash) => *hash,
+            _ => hash(&self.encode()),
+        }
+    }
+
+    fn decode(rlp: &Rlp) -> Result<Node, ProofError> {
+        match rlp.item_count()? {
+            2 => {
+                let (path, leaf) = decode_path(rlp.at(0)?.data()?)?;
+                if leaf {
+                    Ok(Node::Leaf(path, rlp.at(1)?.data()?.to_vec()))
+                } else {
+                    let child = Node::decode_reference(&rlp.at(1)?)?;
+                    Ok(Node::Extension(path, Box::new(child)))
+                }
+            }
+            17 => {
+                let children = (0..16)
+                    .map(|index| Node::decode_reference(&rlp.at(index)?))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Node::Branch(children))
+            }
+            _ => Err(ProofError::InvalidNode),
+        }
+    }
+
+    fn decode_reference(rlp: &Rlp) -> Result<Node, ProofError> {
+        if rlp.is_list() {
+            return Node::decode(rlp);
+        }
+        match rlp.data()? {
+            [] => Ok(Node::Empty),
+            hash if hash.len() == 32 => Ok(Node::Hash(H256::from_slice(hash))),
+            _ => Err(ProofError::InvalidNode),
+        }
+    }
+}
+
+/// Replaces hash references on path to key with nodes from proof.
+fn resolve(node: &mut Node, key: &[u8], proof: &HashMap<H256, Vec<u8>>) -> Result<(), ProofError> {
+    if let Node::Hash(node_hash) = *node {
+        let data = proof
+            .get(&node_hash)
+            .ok_or(ProofError::MissingNode(node_hash))?;
+        *node = Node::decode(&Rlp::new(data))?;
+    }
+    match node {
+        Node::Branch(children) if !key.is_empty() => {
+            resolve(&mut children[key[0] as usize], &key[1..], proof)
+        }
+        Node::Extension(path, child) if key.starts_with(path) => {
+            resolve(child, &key[path.len()..], proof)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Removes all entries between `left` and `right` (inclusive). Missing bound means that subtree
+/// is entirely on inner side of it.
+fn unset(node: &mut Node, left: Option<&[u8]>, right: Option<&[u8]>) -> Result<(), ProofError> {
+    if left.is_none() && right.is_none() {
+        *node = Node::Empty;
+        return Ok(());
+    }
+    match node {
+        Node::Empty => {}
+        // subtree that is only partially in range needs to be in proof.
+        Node::Hash(hash) => return Err(ProofError::MissingNode(*hash)),
+        Node::Leaf(path, _) => {
+            let after_left = left.map_or(true, |left| path.as_slice() >= left);
+            let before_right = right.map_or(true, |right| path.as_slice() <= right);
+            if after_left && before_right {
+                *node = Node::Empty;
+            }
+        }
+        Node::Extension(path, child) => {
+            let len = path.len();
+            if left.map_or(false, |left| left.len() < len)
+                || right.map_or(false, |right| right.len() < len)
+            {
+                return Err(ProofError::InvalidNode);
+            }
+            let left = match left {
+                None => None,
+                Some(left) => match path.as_slice().cmp(&left[..len]) {
+                    Ordering::Less => return Ok(()),
+                    Ordering::Equal => Some(&left[len..]),
+                    Ordering::Greater => None,
+                },
+            };
+            let right = match right {
+                None => None,
+                Some(right) => match path.as_slice().cmp(&right[..len]) {
+                    Ordering::Greater => return Ok(()),
+                    Ordering::Equal => Some(&right[len..]),
+                    Ordering::Less => None,
+                },
+            };
+            unset(child, left, right)?;
+            if let Node::Empty = **child {
+                *node = Node::Empty;
+            }
+        }
+        Node::Branch(children) => {
+            if left.map_or(false, |left| left.is_empty())
+                || right.map_or(false, |right| right.is_empty())
+            {
+                return Err(ProofError::InvalidNode);
+            }
+            for (index, child) in children.iter_mut().enumerate() {
+                let index = index as u8;
+                let left = match left {
+                    Some(left) if index < left[0] => continue,
+                    Some(left) if index == left[0] => Some(&left[1..]),
+                    _ => None,
+                };
+                let right = match right {
+                    Some(right) if index > right[0] => continue,
+                    Some(right) if index == right[0] => Some(&right[1..]),
+                    _ => None,
+                };
+                unset(child, left, right)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn insert(node: Node, path: &[u8], value: Vec<u8>) -> Result<Node, ProofError> {
+    let wrap = |prefix: &[u8], node: Node| {
+        if prefix.is_empty() {
+            node
+        } else {
+            Node::Extension(prefix.to_vec(), Box::new(node))
+        }
+    };
+    Ok(match node {
+        Node::Empty => Node::Leaf(path.to_vec(), value),
+        Node::Leaf(leaf_path, leaf_value) => {
+            let common = common_prefix(&leaf_path, path);
+            if common == leaf_path.len() || common == path.len() {
+                if leaf_path.len() != path.len() {
+                    return Err(ProofError::InvalidNode);
+                }
+                return Ok(Node::Leaf(leaf_path, value));
+            }
+            let mut children = empty_children();
+            children[leaf_path[common] as usize] =
+                Node::Leaf(leaf_path[common + 1..].to_vec(), leaf_value);
+            children[path[common] as usize] = Node::Leaf(path[common + 1..].to_vec(), value);
+            wrap(&path[..common], Node::Branch(children))
+        }
+        Node::Extension(ext_path, child) => {
+            let common = common_prefix(&ext_path, path);
+            if common == ext_path.len() {
+                let child = insert(*child, &path[common..], value)?;
+                Node::Extension(ext_path, Box::new(child))
+            } else if common == path.len() {
+                return Err(ProofError::InvalidNode);
+            } else {
+                let mut children = empty_children();
+                children[ext_path[common] as usize] = wrap(&ext_path[common + 1..], *child);
+                children[path[common] as usize] = Node::Leaf(path[common + 1..].to_vec(), value);
+                wrap(&path[..common], Node::Branch(children))
+            }
+        }
+        Node::Branch(mut children) => {
+            let index = *path.first().ok_or(ProofError::InvalidNode)? as usize;
+            let child = std::mem::replace(&mut children[index], Node::Empty);
+            children[index] = insert(child, &path[1..], value)?;
+            Node::Branch(children)
+        }
+        Node::Hash(hash) => return Err(ProofError::MissingNode(hash)),
+    })
+}
+
+fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b.iter()).take_while(|(a, b)| a == b).count()
+}
+
+/// Checks if there is any entry after `key`. Nodes that are not resolved are on the right side
+/// of the path, otherwise they would be removed by `unset`.
+fn has_right_element(node: &Node, key: &[u8]) -> bool {
+    match node {
+        Node::Empty => false,
+        Node::Hash(_) => true,
+        Node::Leaf(path, _) => path.as_slice() > key,
+        Node::Extension(path, child) => {
+            let len = path.len().min(key.len());
+            match path[..len].cmp(&key[..len]) {
+                Ordering::Greater => true,
+                Ordering::Less => false,
+                Ordering::Equal => has_right_element(child, &key[len..]),
+            }
+        }
+        Node::Branch(children) => match key.first() {
+            Some(first) => {
+                let index = *first as usize;
+                children[index + 1..]
+                    .iter()
+                    .any(|child| !matches!(child, Node::Empty))
+                    || has_right_element(&children[index], &key[1..])
+            }
+            None => false,
+        },
+    }
+}
+
+/// Root of trie that contains only given entries.
+pub fn trie_root(keys: &[H256], values: &[Vec<u8>]) -> Result<H256, ProofError> {
+    let mut trie = Node::Empty;
+    for (key, value) in keys.iter().zip(values) {
+        trie = insert(trie, &nibbles(key), value.clone())?;
+    }
+    Ok(trie.hash())
+}
+
+/// Verifies that `keys` and `values` are all entries of trie with given root starting from `origin`.
+/// Without proof, entries need to be the whole trie. Returns true if trie has more entries after last key.
+pub fn verify_range_proof(
+    root: &H256,
+    origin: &H256,
+    keys: &[H256],
+    values: &[Vec<u8>],
+    proof: &[Vec<u8>],
+) -> Result<bool, ProofError> {
+    if keys.len() != values.len()
+        || keys.windows(2).any(|pair| pair[0] >= pair[1])
+        || keys.first().map_or(false, |first| first < origin)
+    {
+        return Err(ProofError::InvalidKeys);
+    }
+    if proof.is_empty() {
+        return match trie_root(keys, values)? == *root {
+            true => Ok(false),
+            false => Err(ProofError::RootMismatch),
+        };
+    }
+
+    let proof: HashMap<H256, Vec<u8>> = proof
+        .iter()
+        .map(|node| (hash(node), node.clone()))
+        .collect();
+    let left = nibbles(origin);
+    let mut trie = Node::Hash(*root);
+    resolve(&mut trie, &left, &proof)?;
+    let right = match keys.last() {
+        Some(last) => nibbles(last),
+        None => {
+            // proof of absence, there is nothing at or after origin.
+            unset(&mut trie, Some(&left), None)?;
+            return match trie.hash() == *root {
+                true => Ok(false),
+                false => Err(ProofError::RootMismatch),
+            };
+        }
+    };
+    resolve(&mut trie, &right, &proof)?;
+    unset(&mut trie, Some(&left), Some(&right))?;
+    for (key, value) in keys.iter().zip(values) {
+        trie = insert(trie, &nibbles(key), value.clone())?;
+    }
+    if trie.hash() != *root {
+        return Err(ProofError::RootMismatch);
+    }
+    Ok(has_right_element(&trie, &right))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(count: u64) -> (Vec<H256>, Vec<Vec<u8>>) {
+        let mut keys: Vec<H256> = (0..count).map(|i| hash(&i.to_be_bytes())).collect();
+        keys.sort();
+        let values = keys
+            .iter()
+            .map(|key| key.as_bytes()[..4].to_vec())
+            .collect();
+        (keys, values)
+    }
+
+    fn build(keys: &[H256], values: &[Vec<u8>]) -> Node {
+        let mut trie = Node::Empty;
+        for (key, value) in keys.iter().zip(values) {
+            trie = insert(trie, &nibbles(key), value.clone()).unwrap();
+        }
+        trie
+    }
+
+    /// Nodes on path to key that are referenced by hash.
+    fn prove(node: &Node, key: &[u8], root: bool, proof: &mut Vec<Vec<u8>>) {
+        let encoded = node.encode();
+        if root || encoded.len() >= 32 {
+            proof.push(encoded);
+        }
+        match node {
+            Node::Branch(children) => prove(&children[key[0] as usize], &key[1..], false, proof),
+            Node::Extension(path, child) if key.starts_with(path) => {
+                prove(child, &key[path.len()..], false, proof)
+            }
+            _ => (),
+        }
+    }
+
+    fn range_proof(trie: &Node, origin: &H256, last: Option<&H256>) -> Vec<Vec<u8>> {
+        let mut proof = Vec::new();
+        prove(trie, &nibbles(origin), true, &mut proof);
+        if let Some(last) = last {
+            prove(trie, &nibbles(last), true, &mut proof);
+        }
+        proof
+    }
+
+    #[test]
+    fn test_empty_root() {
+        assert_eq!(Node::Empty.hash(), empty_root());
+        assert_eq!(
+            verify_range_proof(&empty_root(), &H256::zero(), &[], &[], &[]),
+            Ok(false)
+        );
+    }
+
+    #[test]
+    fn test_whole_trie_without_proof() {
+        let (keys, values) = entries(100);
+        let root = build(&keys, &values).hash();
+        assert_eq!(
+            verify_range_proof(&root, &H256::zero(), &keys, &values, &[]),
+            Ok(false)
+        );
+        assert_eq!(
+            verify_range_proof(&root, &H256::zero(), &keys[1..], &values[1..], &[]),
+            Err(ProofError::RootMismatch)
+        );
+    }
+
+    #[test]
+    fn test_range_with_edge_proofs() {
+        let (keys, values) = entries(100);
+        let trie = build(&keys, &values);
+        let root = trie.hash();
+
+        let proof = range_proof(&trie, &keys[10], Some(&keys[19]));
+        assert_eq!(
+            verify_range_proof(&root, &keys[10], &keys[10..20], &values[10..20], &proof),
+            Ok(true)
+        );
+        let proof = range_proof(&trie, &keys[90], Some(&keys[99]));
+        assert_eq!(
+            verify_range_proof(&root, &keys[90], &keys[90..], &values[90..], &proof),
+            Ok(false)
+        );
+
+        // origin that is not a key, first entry is proven by the origin path.
+        let mut origin = keys[10];
+        origin.as_bytes_mut()[31] = origin.as_bytes()[31].wrapping_sub(1);
+        let proof = range_proof(&trie, &origin, Some(&keys[19]));
+        assert_eq!(
+            verify_range_proof(&root, &origin, &keys[10..20], &values[10..20], &proof),
+            Ok(true)
+        );
+    }
+
+    #[test]
+    fn test_tampered_range_is_rejected() {
+        let (keys, values) = entries(100);
+        let trie = build(&keys, &values);
+        let root = trie.hash();
+        let proof = range_proof(&trie, &keys[10], Some(&keys[19]));
+
+        let mut tampered = values[10..20].to_vec();
+        tampered[5] = vec![0xff];
+        assert_eq!(
+            verify_range_proof(&root, &keys[10], &keys[10..20], &tampered, &proof),
+            Err(ProofError::RootMismatch)
+        );
+
+        let mut gap_keys = keys[10..20].to_vec();
+        let mut gap_values = values[10..20].to_vec();
+        gap_keys.remove(5);
+        gap_values.remove(5);
+        assert_eq!(
+            verify_range_proof(&root, &keys[10], &gap_keys, &gap_values, &proof),
+            Err(ProofError::RootMismatch)
+        );
+
+        let mut unordered = keys[10..20].to_vec();
+        unordered.swap(0, 1);
+        assert_eq!(
+            verify_range_proof(&root, &keys[10], &unordered, &values[10..20], &proof),
+            Err(ProofError::InvalidKeys)
+        );
+        assert!(verify_range_proof(&root, &keys[10], &keys[10..20], &values[10..20], &[]).is_err());
+    }
+
+    #[test]
+    fn test_absence_proof() {
+        let (keys, values) = entries(100);
+        let trie = build(&keys, &values);
+        let root = trie.hash();
+
+        let origin = H256::repeat_byte(0xff);
+        let proof = range_proof(&trie, &origin, None);
+        assert_eq!(
+            verify_range_proof(&root, &origin, &[], &[], &proof),
+            Ok(false)
+        );
+
+        // there are entries after origin, so empty response is not valid.
+        let proof = range_proof(&trie, &keys[50], None);
+        assert!(verify_range_proof(&root, &keys[50], &[], &[], &proof).is_err());
+    }
+}
@@ -0,0 +1,300 @@
+// Copyright 2021 Gnosis Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+use core::{Bytes, H256};
+use interfaces::snapshot::ManifestData;
+use keccak_hash::keccak;
+use rlp::{DecoderError, Rlp, RlpStream};
+
+pub fn hash(data: &[u8]) -> H256 {
+    H256::from_slice(keccak(data).as_bytes())
+}
+
+pub fn encode_get_snapshot_manifest() -> Vec<u8> {
+    RlpStream::new_list(0).out().to_vec()
+}
+
+/// Empty list means that peer does not have snapshot. Returns manifest and its hash.
+pub fn decode_snapshot_manifest(data: &[u8]) -> Result<Option<(ManifestData, H256)>, DecoderError> {
+    let rlp = Rlp::new(data);
+    if rlp.item_count()? == 0 {
+        return Ok(None);
+    }
+    let manifest_rlp = rlp.at(0)?;
+    let manifest: ManifestData = manifest_rlp.as_val()?;
+    Ok(Some((manifest, hash(manifest_rlp.as_raw()))))
+}
+
+pub fn encode_snapshot_manifest(manifest: Option<&[u8]>) -> Vec<u8> {
+    match manifest {
+        Some(manifest) => {
+            let mut stream = RlpStream::new_list(1);
+            stream.append_raw(manifest, 1);
+            stream.out().to_vec()
+        }
+        None => RlpStream::new_list(0).out().to_vec(),
+    }
+}
+
+pub fn encode_get_snapshot_data(chunk_hash: &H256) -> Vec<u8> {
+    let mut stream = RlpStream::new_list(1);
+    stream.append(chunk_hash);
+    stream.out().to_vec()
+}
+
+pub fn decode_get_snapshot_data(data: &[u8]) -> Result<H256, DecoderError> {
+    Rlp::new(data).val_at(0)
+}
+
+pub fn encode_snapshot_data(chunk: Option<&[u8]>) -> Vec<u8> {
+    match chunk {
+        Some(chunk) => {
+            let mut stream = RlpStream::new_list(1);
+            stream.append(&chunk);
+            stream.out().to_vec()
+        }
+        None => RlpStream::new_list(0).out().to_vec(),
+    }
+}
+
+/// Empty list means that peer does not have requested chunk.
+pub fn decode_snapshot_data(data: &[u8]) -> Result<Option<Vec<u8>>, DecoderError> {
+    let rlp = Rlp::new(data);
+    if rlp.item_count()? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(rlp.at(0)?.data()?.to_vec()))
+}
+
+/// Entry of account or storage range, values are as received (slim account or storage slot).
+pub type RangeEntry = (H256, Bytes);
+
+pub fn encode_get_account_range(
+    request_id: u64,
+    root: &H256,
+    origin: &H256,
+    limit: &H256,
+    response_bytes: u64,
+) -> Vec<u8> {
+    let mut stream = RlpStream::new_list(5);
+    stream.append(&request_id);
+    stream.append(root);
+    stream.append(origin);
+    stream.append(limit);
+    stream.append(&response_bytes);
+    stream.out().to_vec()
+}
+
+/// Accounts are in slim format and are returned raw. Returns request id, accounts and proof.
+pub fn decode_account_range(
+    data: &[u8],
+) -> Result<(u64, Vec<RangeEntry>, Vec<Bytes>), DecoderError> {
+    let rlp = Rlp::new(data);
+    let accounts = rlp
+        .at(1)?
+        .iter()
+        .map(|account| Ok((account.val_at(0)?, account.at(1)?.as_raw().to_vec())))
+        .collect::<Result<_, DecoderError>>()?;
+    Ok((rlp.val_at(0)?, accounts, decode_nodes(&rlp.at(2)?)?))
+}
+
+/// Origin and limit are empty for whole storage.
+pub fn encode_get_storage_ranges(
+    request_id: u64,
+    root: &H256,
+    accounts: &[H256],
+    origin: Option<&H256>,
+    response_bytes: u64,
+) -> Vec<u8> {
+    let mut stream = RlpStream::new_list(6);
+    stream.append(&request_id);
+    stream.append(root);
+    stream.append_list(accounts);
+    match origin {
+        Some(origin) => stream.append(origin),
+        None => stream.append_empty_data(),
+    };
+    stream.append_empty_data();
+    stream.append(&response_bytes);
+    stream.out().to_vec()
+}
+
+/// Slots are returned for requested accounts in order. Proof is only for last returned account
+/// if its range is not complete.
+pub fn decode_storage_ranges(
+    data: &[u8],
+) -> Result<(u64, Vec<Vec<RangeEntry>>, Vec<Bytes>), DecoderError> {
+    let rlp = Rlp::new(data);
+    let slots = rlp
+        .at(1)?
+        .iter()
+        .map(|account| {
+            account
+                .iter()
+                .map(|slot| Ok((slot.val_at(0)?, slot.at(1)?.data()?.to_vec())))
+                .collect()
+        })
+        .collect::<Result<_, DecoderError>>()?;
+    Ok((rlp.val_at(0)?, slots, decode_nodes(&rlp.at(2)?)?))
+}
+
+pub fn encode_get_byte_codes(request_id: u64, hashes: &[H256], response_bytes: u64) -> Vec<u8> {
+    let mut stream = RlpStream::new_list(3);
+    stream.append(&request_id);
+    stream.append_list(hashes);
+    stream.append(&response_bytes);
+    stream.out().to_vec()
+}
+
+pub fn decode_byte_codes(data: &[u8]) -> Result<(u64, Vec<Bytes>), DecoderError> {
+    let rlp = Rlp::new(data);
+    Ok((rlp.val_at(0)?, decode_nodes(&rlp.at(1)?)?))
+}
+
+/// Every path set is account path optionally followed by storage paths, all compact encoded.
+pub fn encode_get_trie_nodes(
+    request_id: u64,
+    root: &H256,
+    paths: &[Vec<Bytes>],
+    response_bytes: u64,
+) -> Vec<u8> {
+    let mut stream = RlpStream::new_list(4);
+    stream.append(&request_id);
+    stream.append(root);
+    stream.begin_list(paths.len());
+    for path_set in paths {
+        stream.begin_list(path_set.len());
+        for path in path_set {
+            stream.append(path);
+        }
+    }
+    stream.append(&response_bytes);
+    stream.out().to_vec()
+}
+
+/// Nodes are returned in order of requested paths.
+pub fn decode_trie_nodes(data: &[u8]) -> Result<(u64, Vec<Bytes>), DecoderError> {
+    let rlp = Rlp::new(data);
+    Ok((rlp.val_at(0)?, decode_nodes(&rlp.at(1)?)?))
+}
+
+fn decode_nodes(rlp: &Rlp) -> Result<Vec<Bytes>, DecoderError> {
+    rlp.iter().map(|node| Ok(node.data()?.to_vec())).collect()
+}
+
+/// Converts slim account from AccountRange to account as it is in state trie. Returns account
+/// with its storage root and code hash.
+pub fn full_account(
+    slim: &[u8],
+    empty_root: &H256,
+    empty_code: &H256,
+) -> Result<(Bytes, H256, H256), DecoderError> {
+    let rlp = Rlp::new(slim);
+    if rlp.item_count()? != 4 {
+        return Err(DecoderError::RlpIncorrectListLen);
+    }
+    let or_default = |index: usize, default: &H256| -> Result<H256, DecoderError> {
+        let item = rlp.at(index)?;
+        match item.is_empty() {
+            true => Ok(*default),
+            false => item.as_val(),
+        }
+    };
+    let storage_root = or_default(2, empty_root)?;
+    let code_hash = or_default(3, empty_code)?;
+    let mut stream = RlpStream::new_list(4);
+    stream.append_raw(rlp.at(0)?.as_raw(), 1);
+    stream.append_raw(rlp.at(1)?.as_raw(), 1);
+    stream.append(&storage_root);
+    stream.append(&code_hash);
+    Ok((stream.out().to_vec(), storage_root, code_hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest() -> ManifestData {
+        ManifestData {
+            version: 2,
+            state_hashes: vec![H256::repeat_byte(1), H256::repeat_byte(2)],
+            block_hashes: vec![H256::repeat_byte(3)],
+            state_root: H256::repeat_byte(4),
+            block_number: 12_345_678,
+            block_hash: H256::repeat_byte(5),
+        }
+    }
+
+    #[test]
+    fn test_snapshot_manifest_roundtrip() {
+        let manifest = manifest();
+        let manifest_rlp = rlp::encode(&manifest).to_vec();
+        let message = encode_snapshot_manifest(Some(&manifest_rlp));
+        let (decoded, manifest_hash) = decode_snapshot_manifest(&message).unwrap().unwrap();
+        assert_eq!(decoded, manifest);
+        assert_eq!(manifest_hash, hash(&manifest_rlp));
+        assert!(decode_snapshot_manifest(&encode_get_snapshot_manifest())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_snapshot_manifest_version_1() {
+        let manifest = manifest();
+        let mut stream = RlpStream::new_list(5);
+        stream.append_list(&manifest.state_hashes);
+        stream.append_list(&manifest.block_hashes);
+        stream.append(&manifest.state_root);
+        stream.append(&manifest.block_number);
+        stream.append(&manifest.block_hash);
+        let decoded: ManifestData = rlp::decode(&stream.out()).unwrap();
+        assert_eq!(decoded.version, 1);
+        assert_eq!(decoded.state_hashes, manifest.state_hashes);
+    }
+
+    #[test]
+    fn test_snapshot_data_roundtrip() {
+        let chunk_hash = H256::repeat_byte(7);
+        let request = encode_get_snapshot_data(&chunk_hash);
+        assert_eq!(decode_get_snapshot_data(&request).unwrap(), chunk_hash);
+        let chunk = vec![1u8, 2, 3];
+        let response = encode_snapshot_data(Some(&chunk));
+        assert_eq!(decode_snapshot_data(&response).unwrap(), Some(chunk));
+        assert_eq!(
+            decode_snapshot_data(&encode_snapshot_data(None)).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_storage_ranges_and_slim_account() {
+        let mut stream = RlpStream::new_list(3);
+        stream.append(&7u64);
+        stream.begin_list(2);
+        stream.begin_list(1);
+        stream.begin_list(2);
+        stream.append(&H256::repeat_byte(1));
+        stream.append(&vec![0x2au8]);
+        stream.begin_list(0);
+        stream.begin_list(1);
+        stream.append(&vec![0xc0u8]);
+        let (id, slots, proof) = decode_storage_ranges(&stream.out()).unwrap();
+        assert_eq!(id, 7);
+        assert_eq!(slots, vec![vec![(H256::repeat_byte(1), vec![0x2a])], vec![]]);
+        assert_eq!(proof, vec![vec![0xc0]]);
+
+        let mut slim = RlpStream::new_list(4);
+        slim.append(&1u64);
+        slim.append(&100u64);
+        slim.append_empty_data();
+        slim.append(&H256::repeat_byte(2));
+        let empty_root = H256::repeat_byte(3);
+        let (account, storage_root, code_hash) =
+            full_account(&slim.out(), &empty_root, &H256::zero()).unwrap();
+        assert_eq!(storage_root, empty_root);
+        assert_eq!(code_hash, H256::repeat_byte(2));
+        let account = Rlp::new(&account);
+        assert_eq!(account.val_at::<u64>(1).unwrap(), 100);
+        assert_eq!(account.val_at::<H256>(2).unwrap(), empty_root);
+    }
+}
@@ -0,0 +1,759 @@
+// Copyright 2021 Gnosis Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    scheduler::{
+        peer_organizer::{ErrorAct, InitialRequest, PeerId, Task},
+        protocol::{MessageId, SnapMessageId},
+    },
+    snapshot_manager::{
+        range_proof::{empty_root, verify_range_proof},
+        rlp_en_de::{
+            decode_account_range, decode_byte_codes, decode_storage_ranges, decode_trie_nodes,
+            encode_get_account_range, encode_get_byte_codes, encode_get_storage_ranges,
+            encode_get_trie_nodes, full_account, hash,
+        },
+    },
+};
+use core::{Bytes, H256, U256};
+use interfaces::snapshot::StateWriter;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Arc,
+};
+
+/// Soft limit of response size that we ask peers for.
+const RESPONSE_BYTES: u64 = 512 * 1024;
+/// Account hash space is split in this many ranges that are downloaded in parallel.
+const ACCOUNT_RANGES: u64 = 16;
+const MAX_STORAGE_ACCOUNTS: usize = 64;
+const MAX_BYTE_CODES: usize = 64;
+const MAX_TRIE_NODES: usize = 128;
+/// Number of missing trie nodes that we take from state writer at once while healing.
+const HEAL_BATCH: usize = 1024;
+
+#[derive(Clone, Debug, PartialEq)]
+struct AccountTask {
+    origin: H256,
+    limit: H256,
+}
+
+/// Storage of one account, origin is zero for storage that is not started yet.
+#[derive(Clone, Debug, PartialEq)]
+struct StorageTask {
+    account: H256,
+    root: H256,
+    origin: H256,
+}
+
+enum SnapRequest {
+    Accounts(AccountTask),
+    Storage(Vec<StorageTask>),
+    ByteCodes(Vec<H256>),
+    TrieNodes(Vec<(Vec<Bytes>, H256)>),
+}
+
+/// Downloads state of pivot block with snap protocol. Account ranges are downloaded first,
+/// storage and code of accounts follow, after that trie is healed with nodes that state writer
+/// reports as missing. Pivot root can move while downloading, ranges downloaded for older root
+/// are kept and fixed by healing.
+pub struct SnapSync {
+    writer: Arc<dyn StateWriter>,
+    root: H256,
+    next_request_id: u64,
+    /// Requests with lower id were made for older root.
+    root_request_id: u64,
+    account_tasks: VecDeque<AccountTask>,
+    storage_tasks: VecDeque<StorageTask>,
+    code_tasks: VecDeque<H256>,
+    /// Code hashes that are already queued, many accounts share the same code.
+    known_codes: HashSet<H256>,
+    heal_tasks: VecDeque<(Vec<Bytes>, H256)>,
+    requested: HashMap<PeerId, (u64, SnapRequest)>,
+    /// Peers that returned empty response, they don't have our state root anymore.
+    stale_peers: HashSet<PeerId>,
+    healing: bool,
+    done: bool,
+}
+
+impl SnapSync {
+    pub fn new(writer: Arc<dyn StateWriter>, root: H256) -> Self {
+        let step = U256::MAX / U256::from(ACCOUNT_RANGES);
+        let account_tasks = (0..ACCOUNT_RANGES)
+            .map(|index| {
+                let origin = step * U256::from(index) + U256::from(index);
+                let limit = match index + 1 == ACCOUNT_RANGES {
+                    true => U256::MAX,
+                    false => origin + step,
+                };
+                AccountTask {
+                    origin: to_hash(origin),
+                    limit: to_hash(limit),
+                }
+            })
+            .collect();
+        SnapSync {
+            writer,
+            root,
+            next_request_id: 0,
+            root_request_id: 0,
+            account_tasks,
+            storage_tasks: VecDeque::new(),
+            code_tasks: VecDeque::new(),
+            known_codes: HashSet::new(),
+            heal_tasks: VecDeque::new(),
+            requested: HashMap::new(),
+            stale_peers: HashSet::new(),
+            healing: false,
+            done: false,
+        }
+    }
+
+    pub fn root(&self) -> H256 {
+        self.root
+    }
+
+    /// Moves sync to root of newer pivot when peers stopped serving the old one. Remaining
+    /// account ranges and codes are downloaded for new root. Storage and trie node tasks are
+    /// tied to old root and are dropped, healing of new root finds what is missing.
+    pub fn set_root(&mut self, root: H256) {
+        info!(
+            "Snap: moving state sync from root {} to {}",
+            self.root, root
+        );
+        self.root = root;
+        self.root_request_id = self.next_request_id;
+        self.stale_peers.clear();
+        self.storage_tasks.clear();
+        self.heal_tasks.clear();
+        self.healing = false;
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    pub fn is_stale(&self, peer: &PeerId) -> bool {
+        self.stale_peers.contains(peer)
+    }
+
+    pub fn next_request(&mut self, peer: &PeerId) -> Option<InitialRequest> {
+        if self.done || self.is_stale(peer) || self.requested.contains_key(peer) {
+            return None;
+        }
+        let request_id = self.next_request_id;
+        let (message_id, data, request) = if let Some(task) = self.account_tasks.pop_front() {
+            let data = encode_get_account_range(
+                request_id,
+                &self.root,
+                &task.origin,
+                &task.limit,
+                RESPONSE_BYTES,
+            );
+            (
+                SnapMessageId::GetAccountRange,
+                data,
+                SnapRequest::Accounts(task),
+            )
+        } else if let Some(first) = self.storage_tasks.pop_front() {
+            // continuation of large storage is requested alone, as it needs origin.
+            let mut tasks = vec![first];
+            while tasks.len() < MAX_STORAGE_ACCOUNTS && tasks[0].origin.is_zero() {
+                match self.storage_tasks.front() {
+                    Some(task) if task.origin.is_zero() => {
+                        tasks.extend(self.storage_tasks.pop_front())
+                    }
+                    _ => break,
+                }
+            }
+            let accounts: Vec<H256> = tasks.iter().map(|task| task.account).collect();
+            let origin = Some(&tasks[0].origin).filter(|origin| !origin.is_zero());
+            let data = encode_get_storage_ranges(
+                request_id,
+                &self.root,
+                &accounts,
+                origin,
+                RESPONSE_BYTES,
+            );
+            (
+                SnapMessageId::GetStorageRanges,
+                data,
+                SnapRequest::Storage(tasks),
+            )
+        } else if !self.code_tasks.is_empty() {
+            let count = self.code_tasks.len().min(MAX_BYTE_CODES);
+            let hashes: Vec<H256> = self.code_tasks.drain(..count).collect();
+            let data = encode_get_byte_codes(request_id, &hashes, RESPONSE_BYTES);
+            (
+                SnapMessageId::GetByteCodes,
+                data,
+                SnapRequest::ByteCodes(hashes),
+            )
+        } else if !self.heal_tasks.is_empty() {
+            let count = self.heal_tasks.len().min(MAX_TRIE_NODES);
+            let nodes: Vec<_> = self.heal_tasks.drain(..count).collect();
+            let paths: Vec<Vec<Bytes>> = nodes.iter().map(|(path, _)| path.clone()).collect();
+            let data = encode_get_trie_nodes(request_id, &self.root, &paths, RESPONSE_BYTES);
+            (
+                SnapMessageId::GetTrieNodes,
+                data,
+                SnapRequest::TrieNodes(nodes),
+            )
+        } else {
+            return None;
+        };
+        self.next_request_id += 1;
+        self.requested.insert(*peer, (request_id, request));
+        Some(InitialRequest::new(MessageId::Snap(message_id), data))
+    }
+
+    /// Return tasks assigned to peer back to the queues.
+    pub fn release(&mut self, peer: &PeerId) {
+        if let Some((id, request)) = self.requested.remove(peer) {
+            self.return_request(id, request);
+        }
+    }
+
+    /// Request keeps its id, so response of new peer matches it.
+    pub fn reassign(&mut self, from: &PeerId, to: &PeerId) -> bool {
+        if self.is_stale(to) || self.requested.contains_key(to) {
+            return false;
+        }
+        match self.requested.remove(from) {
+            Some(request) => {
+                self.requested.insert(*to, request);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn remove_peer(&mut self, peer: &PeerId) {
+        self.release(peer);
+        self.stale_peers.remove(peer);
+    }
+
+    fn requeue(&mut self, request: SnapRequest) {
+        match request {
+            SnapRequest::Accounts(task) => self.account_tasks.push_front(task),
+            SnapRequest::Storage(tasks) => {
+                for task in tasks.into_iter().rev() {
+                    self.storage_tasks.push_front(task);
+                }
+            }
+            SnapRequest::ByteCodes(hashes) => self.code_tasks.extend(hashes),
+            SnapRequest::TrieNodes(nodes) => self.heal_tasks.extend(nodes),
+        }
+    }
+
+    /// Requeues tasks of request that was not answered. Storage and trie nodes of older root
+    /// are not needed anymore.
+    fn return_request(&mut self, id: u64, request: SnapRequest) {
+        match request {
+            SnapRequest::Storage(_) | SnapRequest::TrieNodes(_) if id < self.root_request_id => (),
+            request => self.requeue(request),
+        }
+    }
+
+    /// Takes request that response with given id answers. Response to unknown request is kicked.
+    /// Response to request for older root is dropped, None is returned for it.
+    fn take_request(
+        &mut self,
+        peer: &PeerId,
+        request_id: u64,
+    ) -> Result<Option<SnapRequest>, ErrorAct> {
+        match self.requested.remove(peer) {
+            Some((id, request)) if id == request_id && id < self.root_request_id => {
+                self.return_request(id, request);
+                Ok(None)
+            }
+            Some((id, request)) if id == request_id => Ok(Some(request)),
+            Some((id, request)) => {
+                self.return_request(id, request);
+                ErrorAct::new_kick_generic(format!(
+                    "Snap response id mismatch. Expected:{} got:{}",
+                    id, request_id
+                ))
+            }
+            None => ErrorAct::new_kick_generic("Unrequested snap response".into()),
+        }
+    }
+
+    /// Empty response means that peer does not have our root, it is not asked again.
+    fn stale_response(&mut self, peer: &PeerId, request: SnapRequest) -> Result<Task, ErrorAct> {
+        info!(
+            "Snap: peer {} does not serve state root {}",
+            peer, self.root
+        );
+        self.stale_peers.insert(*peer);
+        self.requeue(request);
+        Ok(Task::None)
+    }
+
+    pub fn process_account_range(&mut self, peer: &PeerId, data: &[u8]) -> Result<Task, ErrorAct> {
+        let (request_id, accounts, proof) = match decode_account_range(data) {
+            Ok(response) => response,
+            Err(err) => {
+                return self.invalid_response(peer, format!("Invalid AccountRange: {}", err))
+            }
+        };
+        let task = match self.take_request(peer, request_id)? {
+            Some(SnapRequest::Accounts(task)) => task,
+            None => return Ok(Task::None),
+            Some(request) => {
+                self.requeue(request);
+                return ErrorAct::new_kick_generic("Unexpected AccountRange".into());
+            }
+        };
+        if accounts.is_empty() && proof.is_empty() {
+            return self.stale_response(peer, SnapRequest::Accounts(task));
+        }
+        let (empty_root, empty_code) = (empty_root(), hash(&[]));
+        let mut keys = Vec::with_capacity(accounts.len());
+        let mut entries = Vec::with_capacity(accounts.len());
+        let mut storage = Vec::new();
+        let mut codes = Vec::new();
+        for (key, slim) in accounts.iter() {
+            let (account, storage_root, code_hash) =
+                match full_account(slim, &empty_root, &empty_code) {
+                    Ok(account) => account,
+                    Err(err) => {
+                        self.account_tasks.push_front(task);
+                        return ErrorAct::new_kick_generic(format!("Invalid account: {}", err));
+                    }
+                };
+            if storage_root != empty_root {
+                storage.push(StorageTask {
+                    account: *key,
+                    root: storage_root,
+                    origin: H256::zero(),
+                });
+            }
+            if code_hash != empty_code {
+                codes.push(code_hash);
+            }
+            keys.push(*key);
+            entries.push((*key, account));
+        }
+        let values: Vec<Bytes> = entries.iter().map(|(_, account)| account.clone()).collect();
+        let has_more = match verify_range_proof(&self.root, &task.origin, &keys, &values, &proof) {
+            Ok(has_more) => has_more,
+            Err(err) => {
+                self.account_tasks.push_front(task);
+                return ErrorAct::new_kick_generic(format!(
+                    "Invalid account range proof: {:?}",
+                    err
+                ));
+            }
+        };
+        self.writer.write_accounts(&entries);
+        self.storage_tasks.extend(storage);
+        for code_hash in codes {
+            if self.known_codes.insert(code_hash) {
+                self.code_tasks.push_back(code_hash);
+            }
+        }
+        match keys.last() {
+            Some(last) if has_more && *last < task.limit => {
+                self.account_tasks.push_front(AccountTask {
+                    origin: next_hash(last),
+                    limit: task.limit,
+                });
+            }
+            _ => (),
+        }
+        self.update_phase();
+        Ok(Task::None)
+    }
+
+    pub fn process_storage_ranges(&mut self, peer: &PeerId, data: &[u8]) -> Result<Task, ErrorAct> {
+        let (request_id, slots, proof) = match decode_storage_ranges(data) {
+            Ok(response) => response,
+            Err(err) => {
+                return self.invalid_response(peer, format!("Invalid StorageRanges: {}", err))
+            }
+        };
+        let mut tasks = match self.take_request(peer, request_id)? {
+            Some(SnapRequest::Storage(tasks)) => tasks,
+            None => return Ok(Task::None),
+            Some(request) => {
+                self.requeue(request);
+                return ErrorAct::new_kick_generic("Unexpected StorageRanges".into());
+            }
+        };
+        if slots.is_empty() && proof.is_empty() {
+            return self.stale_response(peer, SnapRequest::Storage(tasks));
+        }
+        if slots.is_empty() {
+            self.requeue(SnapRequest::Storage(tasks));
+            return ErrorAct::new_kick_generic("Storage proof without storage ranges".into());
+        }
+        if slots.len() > tasks.len() {
+            self.requeue(SnapRequest::Storage(tasks));
+            return ErrorAct::new_kick_generic("Too many storage ranges".into());
+        }
+        let remaining = tasks.split_off(slots.len());
+        let last = slots.len() - 1;
+        let mut continuation = None;
+        for (index, (task, slots)) in tasks.iter().zip(slots.iter()).enumerate() {
+            let (keys, values): (Vec<H256>, Vec<Bytes>) = slots.iter().cloned().unzip();
+            let proof: &[Bytes] = if index == last { &proof } else { &[] };
+            match verify_range_proof(&task.root, &task.origin, &keys, &values, proof) {
+                Ok(has_more) => {
+                    if has_more {
+                        continuation = keys.last().map(|last| StorageTask {
+                            origin: next_hash(last),
+                            ..task.clone()
+                        });
+                    }
+                }
+                Err(err) => {
+                    // already verified accounts are downloaded again.
+                    self.requeue(SnapRequest::Storage(tasks));
+                    self.requeue(SnapRequest::Storage(remaining));
+                    return ErrorAct::new_kick_generic(format!(
+                        "Invalid storage range proof: {:?}",
+                        err
+                    ));
+                }
+            }
+        }
+        for (task, slots) in tasks.iter().zip(slots.iter()) {
+            self.writer.write_storage(&task.account, slots);
+        }
+        self.requeue(SnapRequest::Storage(remaining));
+        if let Some(task) = continuation {
+            self.storage_tasks.push_front(task);
+        }
+        self.update_phase();
+        Ok(Task::None)
+    }
+
+    pub fn process_byte_codes(&mut self, peer: &PeerId, data: &[u8]) -> Result<Task, ErrorAct> {
+        let (request_id, codes) = match decode_byte_codes(data) {
+            Ok(response) => response,
+            Err(err) => return self.invalid_response(peer, format!("Invalid ByteCodes: {}", err)),
+        };
+        let mut hashes = match self.take_request(peer, request_id)? {
+            Some(SnapRequest::ByteCodes(hashes)) => hashes,
+            None => return Ok(Task::None),
+            Some(request) => {
+                self.requeue(request);
+                return ErrorAct::new_kick_generic("Unexpected ByteCodes".into());
+            }
+        };
+        if codes.is_empty() {
+            return self.stale_response(peer, SnapRequest::ByteCodes(hashes));
+        }
+        let codes: Vec<(H256, Bytes)> = codes.into_iter().map(|code| (hash(&code), code)).collect();
+        if codes
+            .iter()
+            .any(|(code_hash, _)| !hashes.contains(code_hash))
+        {
+            self.requeue(SnapRequest::ByteCodes(hashes));
+            return ErrorAct::new_kick_generic("Unrequested byte code".into());
+        }
+        for (code_hash, code) in codes.iter() {
+            self.writer.write_code(code_hash, code);
+        }
+        hashes.retain(|code_hash| !codes.iter().any(|(hash, _)| hash == code_hash));
+        self.requeue(SnapRequest::ByteCodes(hashes));
+        self.update_phase();
+        Ok(Task::None)
+    }
+
+    /// Nodes are returned in order of request and need to match hash of requested node.
+    pub fn process_trie_nodes(&mut self, peer: &PeerId, data: &[u8]) -> Result<Task, ErrorAct> {
+        let (request_id, nodes) = match decode_trie_nodes(data) {
+            Ok(response) => response,
+            Err(err) => return self.invalid_response(peer, format!("Invalid TrieNodes: {}", err)),
+        };
+        let mut requested = match self.take_request(peer, request_id)? {
+            Some(SnapRequest::TrieNodes(requested)) => requested,
+            None => return Ok(Task::None),
+            Some(request) => {
+                self.requeue(request);
+                return ErrorAct::new_kick_generic("Unexpected TrieNodes".into());
+            }
+        };
+        if nodes.is_empty() {
+            return self.stale_response(peer, SnapRequest::TrieNodes(requested));
+        }
+        let mismatch = nodes.len() > requested.len()
+            || nodes
+                .iter()
+                .zip(requested.iter())
+                .any(|(node, (_, node_hash))| hash(node) != *node_hash);
+        if mismatch {
+            self.requeue(SnapRequest::TrieNodes(requested));
+            return ErrorAct::new_kick_generic("Trie node hash mismatch".into());
+        }
+        let remaining = requested.split_off(nodes.len());
+        for (node, (_, node_hash)) in nodes.iter().zip(requested.iter()) {
+            self.writer.write_trie_node(node_hash, node);
+        }
+        self.requeue(SnapRequest::TrieNodes(remaining));
+        self.update_phase();
+        Ok(Task::None)
+    }
+
+    fn invalid_response(&mut self, peer: &PeerId, reason: String) -> Result<Task, ErrorAct> {
+        self.release(peer);
+        ErrorAct::new_kick_generic(reason)
+    }
+
+    /// Healing starts when all ranges, storage and codes are downloaded. It is done when
+    /// state writer does not miss any trie node.
+    fn update_phase(&mut self) {
+        let downloading = !self.account_tasks.is_empty()
+            || !self.storage_tasks.is_empty()
+            || !self.code_tasks.is_empty()
+            || self
+                .requested
+                .values()
+                .any(|(_, request)| !matches!(request, SnapRequest::TrieNodes(_)));
+        if downloading {
+            return;
+        }
+        if !self.healing {
+            info!("Snap: state ranges downloaded, healing trie {}", self.root);
+            self.healing = true;
+        }
+        if self.heal_tasks.is_empty() && self.requested.is_empty() {
+            let missing = self.writer.missing_trie_nodes(&self.root, HEAL_BATCH);
+            if missing.is_empty() {
+                info!("Snap: state sync of {} finished", self.root);
+                self.done = true;
+            } else {
+                info!("Snap: healing {} missing trie nodes", missing.len());
+                self.heal_tasks.extend(missing);
+            }
+        }
+    }
+}
+
+fn to_hash(value: U256) -> H256 {
+    let mut hash = H256::zero();
+    value.to_big_endian(hash.as_bytes_mut());
+    hash
+}
+
+/// Hash right after given one, used as origin of next range. Saturates at max hash.
+fn next_hash(hash: &H256) -> H256 {
+    let value = U256::from_big_endian(hash.as_bytes());
+    to_hash(value.saturating_add(U256::one()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot_manager::range_proof::trie_root;
+    use rlp::RlpStream;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct TestWriter {
+        accounts: Mutex<Vec<(H256, Bytes)>>,
+        codes: Mutex<Vec<H256>>,
+        missing: Mutex<Vec<(Vec<Bytes>, H256)>>,
+    }
+
+    impl StateWriter for TestWriter {
+        fn write_accounts(&self, accounts: &[(H256, Bytes)]) {
+            self.accounts.lock().unwrap().extend_from_slice(accounts);
+        }
+        fn write_storage(&self, _account: &H256, _slots: &[(H256, Bytes)]) {}
+        fn write_code(&self, hash: &H256, _code: &[u8]) {
+            self.codes.lock().unwrap().push(*hash);
+        }
+        fn write_trie_node(&self, hash: &H256, _node: &[u8]) {
+            self.missing
+                .lock()
+                .unwrap()
+                .retain(|(_, missing)| missing != hash);
+        }
+        fn missing_trie_nodes(&self, _root: &H256, limit: usize) -> Vec<(Vec<Bytes>, H256)> {
+            self.missing
+                .lock()
+                .unwrap()
+                .iter()
+                .take(limit)
+                .cloned()
+                .collect()
+        }
+    }
+
+    fn slim_account(code_hash: Option<H256>) -> Bytes {
+        let mut stream = RlpStream::new_list(4);
+        stream.append(&1u64);
+        stream.append(&1000u64);
+        stream.append_empty_data();
+        match code_hash {
+            Some(code_hash) => stream.append(&code_hash),
+            None => stream.append_empty_data(),
+        };
+        stream.out().to_vec()
+    }
+
+    /// Account range response, whole state is sent as one range without proof.
+    fn account_range(request_id: u64, accounts: &[(H256, Bytes)]) -> Vec<u8> {
+        let mut stream = RlpStream::new_list(3);
+        stream.append(&request_id);
+        stream.begin_list(accounts.len());
+        for (key, slim) in accounts {
+            stream.begin_list(2);
+            stream.append(key);
+            stream.append_raw(slim, 1);
+        }
+        stream.begin_list(0);
+        stream.out().to_vec()
+    }
+
+    fn byte_list(request_id: u64, items: &[Bytes]) -> Vec<u8> {
+        let mut stream = RlpStream::new_list(2);
+        stream.append(&request_id);
+        stream.begin_list(items.len());
+        for item in items {
+            stream.append(item);
+        }
+        stream.out().to_vec()
+    }
+
+    #[test]
+    fn test_account_ranges_cover_hash_space() {
+        let sync = SnapSync::new(Arc::new(TestWriter::default()), H256::zero());
+        let tasks: Vec<_> = sync.account_tasks.iter().cloned().collect();
+        assert_eq!(tasks.len() as u64, ACCOUNT_RANGES);
+        assert_eq!(tasks[0].origin, H256::zero());
+        assert_eq!(tasks.last().unwrap().limit, H256::repeat_byte(0xff));
+        for pair in tasks.windows(2) {
+            assert_eq!(next_hash(&pair[0].limit), pair[1].origin);
+        }
+    }
+
+    fn storage_ranges(request_id: u64, proof: &[Bytes]) -> Vec<u8> {
+        let mut stream = RlpStream::new_list(3);
+        stream.append(&request_id);
+        stream.begin_list(0);
+        stream.begin_list(proof.len());
+        for node in proof {
+            stream.append(node);
+        }
+        stream.out().to_vec()
+    }
+
+    #[test]
+    fn test_new_root_keeps_downloaded_ranges() {
+        let writer = Arc::new(TestWriter::default());
+        let mut sync = SnapSync::new(writer.clone(), H256::repeat_byte(1));
+        let tasks: Vec<AccountTask> = sync.account_tasks.drain(..2).collect();
+        sync.account_tasks = tasks.clone().into();
+        sync.storage_tasks.push_back(StorageTask {
+            account: H256::repeat_byte(0x01),
+            root: H256::repeat_byte(0x02),
+            origin: H256::zero(),
+        });
+        sync.heal_tasks
+            .push_back((vec![vec![0x00]], H256::repeat_byte(0x03)));
+        assert!(sync.next_request(&1).is_some());
+        assert!(sync.next_request(&2).is_some());
+        sync.process_account_range(&2, &account_range(1, &[]))
+            .unwrap();
+        assert!(sync.is_stale(&2));
+
+        sync.set_root(H256::repeat_byte(2));
+        assert!(!sync.is_stale(&2));
+        assert!(sync.storage_tasks.is_empty() && sync.heal_tasks.is_empty());
+        // late response for old root is dropped without penalty, its range is downloaded again.
+        let accounts = vec![(H256::repeat_byte(0x01), slim_account(None))];
+        assert!(sync
+            .process_account_range(&1, &account_range(0, &accounts))
+            .is_ok());
+        assert!(writer.accounts.lock().unwrap().is_empty());
+        assert_eq!(sync.account_tasks, VecDeque::from(tasks));
+        assert!(sync.next_request(&2).is_some());
+    }
+
+    #[test]
+    fn test_proof_without_storage_ranges() {
+        let mut sync = SnapSync::new(Arc::new(TestWriter::default()), H256::zero());
+        sync.account_tasks.clear();
+        let task = StorageTask {
+            account: H256::repeat_byte(0x01),
+            root: H256::repeat_byte(0x02),
+            origin: H256::zero(),
+        };
+        sync.storage_tasks.push_back(task.clone());
+
+        assert!(sync.next_request(&1).is_some());
+        assert!(sync
+            .process_storage_ranges(&1, &storage_ranges(0, &[vec![0x80]]))
+            .is_err());
+        assert!(!sync.is_stale(&1));
+        assert_eq!(sync.storage_tasks, vec![task]);
+    }
+
+    #[test]
+    fn test_snap_sync_download_and_heal() {
+        let code = vec![0x60u8, 0x00];
+        let accounts = vec![
+            (H256::repeat_byte(0x01), slim_account(Some(hash(&code)))),
+            (H256::repeat_byte(0x02), slim_account(None)),
+        ];
+        let (keys, values): (Vec<H256>, Vec<Bytes>) = accounts
+            .iter()
+            .map(|(key, slim)| {
+                (
+                    *key,
+                    full_account(slim, &empty_root(), &hash(&[])).unwrap().0,
+                )
+            })
+            .unzip();
+        let writer = Arc::new(TestWriter::default());
+        let node = vec![0xc1u8, 0x80];
+        writer
+            .missing
+            .lock()
+            .unwrap()
+            .push((vec![vec![0x00]], hash(&node)));
+        let state_root = trie_root(&keys, &values).unwrap();
+        let mut sync = SnapSync::new(writer.clone(), state_root);
+        sync.account_tasks = vec![AccountTask {
+            origin: H256::zero(),
+            limit: H256::repeat_byte(0xff),
+        }]
+        .into();
+
+        assert!(sync.next_request(&1).is_some());
+        assert!(sync.next_request(&1).is_none());
+        assert!(sync.next_request(&2).is_none());
+        assert!(sync
+            .process_account_range(&1, &account_range(5, &accounts))
+            .is_err());
+        // forged range does not leave code of its accounts to download.
+        let forged = vec![(H256::repeat_byte(0x03), slim_account(Some(hash(&[0x01]))))];
+        assert!(sync.next_request(&1).is_some());
+        assert!(sync
+            .process_account_range(&1, &account_range(1, &forged))
+            .is_err());
+        assert!(sync.code_tasks.is_empty() && sync.known_codes.is_empty());
+        assert!(sync.next_request(&1).is_some());
+        sync.process_account_range(&1, &account_range(2, &accounts))
+            .unwrap();
+        assert_eq!(writer.accounts.lock().unwrap().len(), 2);
+
+        assert!(sync.next_request(&2).is_some());
+        assert!(sync
+            .process_byte_codes(&2, &byte_list(3, &[vec![0x00]]))
+            .is_err());
+        assert!(sync.next_request(&2).is_some());
+        sync.process_byte_codes(&2, &byte_list(4, &[code.clone()]))
+            .unwrap();
+        assert_eq!(*writer.codes.lock().unwrap(), vec![hash(&code)]);
+        assert!(!sync.is_done());
+
+        assert!(sync.next_request(&2).is_some());
+        sync.process_trie_nodes(&2, &byte_list(5, &[node])).unwrap();
+        assert!(sync.is_done());
+    }
+}
@@ -1,2 +1,924 @@
 // Copyright 2020-2021 Gnosis Ltd.
 // SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    block_manager::Pivot,
+    scheduler::{
+        peer_organizer::{ErrorAct, InitialRequest, PeerId, Task},
+        protocol::{MessageId, ParityMessageId, SnapMessageId},
+        state::SchedulerState,
+    },
+    snapshot_manager::{
+        rlp_en_de::{
+            decode_get_snapshot_data, decode_snapshot_data, decode_snapshot_manifest,
+            encode_get_snapshot_data, encode_get_snapshot_manifest, encode_snapshot_data,
+            encode_snapshot_manifest, hash,
+        },
+        snap_sync::SnapSync,
+    },
+};
+use core::{BlockNumber, H256, U256};
+use interfaces::{
+    devp2p::ProtocolId,
+    snapshot::{ChunkType, Manifest, ManifestData, Snapshot, StateWriter},
+};
+use rlp::{Rlp, RlpStream};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// File in snapshot directory where we keep manifest and restored chunks so that warp can be resumed.
+const PROGRESS_FILE: &str = "warp_progress.rlp";
+/// Progress is saved at most this often while chunks are restored. Chunks restored after last
+/// save are downloaded again on resume.
+const PROGRESS_SAVE_INTERVAL: Duration = Duration::from_secs(10);
+/// Number of chunks that one peer can get from us in serving window.
+const MAX_SERVED_CHUNKS: usize = 32;
+const SERVING_WINDOW: Duration = Duration::from_secs(60);
+
+/// Chunks of a verified manifest that are being downloaded and fed to restoration.
+struct ChunkDownload {
+    manifest: ManifestData,
+    manifest_hash: H256,
+    pending: VecDeque<(H256, ChunkType)>,
+    requested: HashMap<PeerId, (H256, ChunkType)>,
+    /// Chunks that are restored, written to progress file.
+    done: HashSet<H256>,
+}
+
+impl ChunkDownload {
+    fn new(manifest: ManifestData, manifest_hash: H256, done: HashSet<H256>) -> Self {
+        let state = manifest
+            .state_hashes
+            .iter()
+            .map(|hash| (*hash, ChunkType::State));
+        let blocks = manifest
+            .block_hashes
+            .iter()
+            .map(|hash| (*hash, ChunkType::Block));
+        let pending = state
+            .chain(blocks)
+            .filter(|(hash, _)| !done.contains(hash))
+            .collect();
+        ChunkDownload {
+            manifest,
+            manifest_hash,
+            pending,
+            requested: HashMap::new(),
+            done,
+        }
+    }
+
+    fn total(&self) -> usize {
+        self.manifest.chunk_count()
+    }
+
+    fn is_done(&self) -> bool {
+        self.done.len() == self.total()
+    }
+
+    fn reassign(&mut self, from: &PeerId, to: &PeerId) -> bool {
+        if self.requested.contains_key(to) {
+            return false;
+        }
+        match self.requested.remove(from) {
+            Some(chunk) => {
+                self.requested.insert(*to, chunk);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Return chunk assigned to peer back to the queue.
+    fn release(&mut self, peer: &PeerId) {
+        if let Some(chunk) = self.requested.remove(peer) {
+            self.pending.push_front(chunk);
+        }
+    }
+}
+
+enum Restoration {
+    Idle,
+    /// Waiting for manifest from one of peers that advertised target snapshot.
+    Manifest(Option<PeerId>),
+    Chunks(ChunkDownload),
+    /// State of pivot block is downloaded with snap protocol.
+    Snap(SnapSync),
+}
+
+pub struct SnapshotManager {
+    snapshot: Arc<dyn Snapshot>,
+    /// Latest snapshot (hash, block number) that peer advertised in status message.
+    peers: HashMap<PeerId, (H256, BlockNumber)>,
+    min_peers: usize,
+    warp_distance: u64,
+    /// Directory where restoration progress is saved, if None warp can't be resumed after restart.
+    snapshot_dir: Option<PathBuf>,
+    target: Option<(H256, BlockNumber)>,
+    restoration: Restoration,
+    finished: bool,
+    /// Start of current serving window and number of chunks served to peer in it.
+    served_chunks: HashMap<PeerId, (Instant, usize)>,
+    /// Snap sync is only possible if there is somewhere to write downloaded state.
+    state_writer: Option<Arc<dyn StateWriter>>,
+    /// Peers that support snap protocol.
+    snap_peers: HashSet<PeerId>,
+    /// Block that snap sync would download state of.
+    pivot: Option<Pivot>,
+    /// Block whose state is being downloaded with snap, cleared if restoration is aborted.
+    snap_block: Option<Pivot>,
+    /// When progress file was last written.
+    progress_saved: Instant,
+}
+
+impl SnapshotManager {
+    pub fn new(
+        snapshot: Arc<dyn Snapshot>,
+        min_peers: usize,
+        warp_distance: u64,
+        snapshot_dir: Option<PathBuf>,
+        state_writer: Option<Arc<dyn StateWriter>>,
+    ) -> Self {
+        SnapshotManager {
+            snapshot,
+            peers: HashMap::new(),
+            min_peers,
+            warp_distance,
+            snapshot_dir,
+            target: None,
+            restoration: Restoration::Idle,
+            finished: false,
+            served_chunks: HashMap::new(),
+            state_writer,
+            snap_peers: HashSet::new(),
+            pivot: None,
+            snap_block: None,
+            progress_saved: Instant::now(),
+        }
+    }
+
+    pub fn insert_peer(&mut self, peer: &PeerId, snapshot: Option<(H256, U256)>) {
+        if let Some((hash, number)) = snapshot {
+            if !hash.is_zero() && !number.is_zero() {
+                self.peers.insert(*peer, (hash, number.low_u64()));
+            }
+        }
+    }
+
+    /// Peer announced snap capability on connection.
+    pub fn insert_snap_peer(&mut self, peer: &PeerId) {
+        self.snap_peers.insert(*peer);
+    }
+
+    pub fn remove_peer(&mut self, peer: &PeerId) {
+        self.peers.remove(peer);
+        self.served_chunks.remove(peer);
+        self.snap_peers.remove(peer);
+        if let Restoration::Snap(ref mut sync) = self.restoration {
+            sync.remove_peer(peer);
+        }
+        self.request_failed(peer);
+    }
+
+    pub fn set_pivot(&mut self, pivot: Option<Pivot>) {
+        self.pivot = pivot;
+    }
+
+    /// Local snapshot that we advertise in status message, zero if we don't have one.
+    pub fn local_manifest(&self) -> Manifest {
+        let manifest = self.snapshot.manifest().map(|manifest| Manifest {
+            block_number: manifest.block_number,
+            hash: hash(&rlp::encode(&manifest)),
+        });
+        manifest.unwrap_or(Manifest {
+            block_number: 0,
+            hash: H256::zero(),
+        })
+    }
+
+    pub fn api_get_snapshot_manifest(&self, peer: &PeerId) -> Result<Task, ErrorAct> {
+        let manifest = self
+            .snapshot
+            .manifest()
+            .map(|manifest| rlp::encode(&manifest));
+        Ok(Task::Responde(
+            *peer,
+            ProtocolId::Parity,
+            MessageId::Parity(ParityMessageId::SnapshotManifest),
+            encode_snapshot_manifest(manifest.as_deref()),
+        ))
+    }
+
+    /// Peer that requested too many chunks in current window gets empty response.
+    pub fn api_get_snapshot_data(&mut self, peer: &PeerId, data: &[u8]) -> Result<Task, ErrorAct> {
+        let chunk_hash = match decode_get_snapshot_data(data) {
+            Ok(hash) => hash,
+            Err(err) => {
+                return ErrorAct::new_kick_generic(format!(
+                    "Invalid GetSnapshotData request: {}",
+                    err
+                ))
+            }
+        };
+        let chunk = if self.allow_serving(peer, Instant::now()) {
+            self.snapshot.chunk(chunk_hash)
+        } else {
+            info!("Warp: peer {} exceeded chunk serving limit", peer);
+            None
+        };
+        Ok(Task::Responde(
+            *peer,
+            ProtocolId::Parity,
+            MessageId::Parity(ParityMessageId::SnapshotData),
+            encode_snapshot_data(chunk.as_deref()),
+        ))
+    }
+
+    fn allow_serving(&mut self, peer: &PeerId, now: Instant) -> bool {
+        let (since, count) = self.served_chunks.entry(*peer).or_insert((now, 0));
+        if now >= *since + SERVING_WINDOW {
+            *since = now;
+            *count = 0;
+        }
+        if *count >= MAX_SERVED_CHUNKS {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+
+    /// Snapshot that is advertised by enough peers and is far enough from our best block.
+    pub fn warp_target(&self, best_block: BlockNumber) -> Option<(H256, BlockNumber)> {
+        let mut counts: HashMap<(H256, BlockNumber), usize> = HashMap::new();
+        for snapshot in self.peers.values() {
+            if snapshot.1 > best_block + self.warp_distance {
+                *counts.entry(*snapshot).or_insert(0) += 1;
+            }
+        }
+        counts
+            .into_iter()
+            .filter(|(_, count)| *count >= self.min_peers)
+            .max_by_key(|(snapshot, count)| (*count, snapshot.1))
+            .map(|(snapshot, _)| snapshot)
+    }
+
+    /// Pivot that we can snap sync to if nobody serves Parity snapshot.
+    pub fn snap_target(&self, best_block: BlockNumber) -> Option<Pivot> {
+        if self.state_writer.is_none() || self.snap_peers.len() < self.min_peers {
+            return None;
+        }
+        self.pivot
+            .filter(|pivot| pivot.number > best_block + self.warp_distance)
+    }
+
+    /// Pivot block after its state is downloaded with snap, block sync continues from it.
+    pub fn snapped_block(&self) -> Option<Pivot> {
+        self.snap_block.filter(|_| self.finished)
+    }
+
+    pub fn warp_available(&self, best_block: BlockNumber) -> bool {
+        self.warp_target(best_block).is_some() || self.snap_target(best_block).is_some()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    pub fn state_changed(&mut self, state: SchedulerState, best_block: BlockNumber) {
+        match state {
+            SchedulerState::Warping => {
+                self.target = self.warp_target(best_block);
+                self.finished = false;
+                self.snap_block = None;
+                let target_hash = match (self.target, self.snap_target(best_block)) {
+                    (Some((hash, _)), _) => hash,
+                    (None, Some(pivot)) => {
+                        info!(
+                            "Snap: starting state sync of block {} root {}",
+                            pivot.number, pivot.state_root
+                        );
+                        let writer = Arc::clone(self.state_writer.as_ref().unwrap());
+                        self.restoration =
+                            Restoration::Snap(SnapSync::new(writer, pivot.state_root));
+                        self.snap_block = Some(pivot);
+                        return;
+                    }
+                    (None, None) => return self.abort("no snapshot to warp to"),
+                };
+                info!("Warp: starting restoration of snapshot {:?}", self.target);
+                self.restoration = match self.load_progress() {
+                    Some(download) if download.manifest_hash == target_hash => {
+                        info!(
+                            "Warp: resuming restoration with {}/{} chunks done",
+                            download.done.len(),
+                            download.total()
+                        );
+                        if !self.begin_restoration(&download.manifest) {
+                            return;
+                        }
+                        Restoration::Chunks(download)
+                    }
+                    _ => Restoration::Manifest(None),
+                };
+            }
+            _ => {
+                if let Restoration::Chunks(ref download) = self.restoration {
+                    self.save_progress(download);
+                }
+                self.target = None;
+                self.snap_block = None;
+                self.restoration = Restoration::Idle;
+            }
+        }
+    }
+
+    fn is_serving_target(&self, peer: &PeerId) -> bool {
+        match (self.peers.get(peer), self.target) {
+            (Some((hash, _)), Some((target, _))) => *hash == target,
+            _ => false,
+        }
+    }
+
+    /// Manifest is requested from one peer, chunks are requested in parallel from all peers
+    /// that advertised target snapshot.
+    pub fn next_request(&mut self, peer: &PeerId) -> Option<InitialRequest> {
+        if self.finished {
+            return None;
+        }
+        if let Restoration::Snap(ref mut sync) = self.restoration {
+            if !self.snap_peers.contains(peer) {
+                return None;
+            }
+            return sync.next_request(peer);
+        }
+        if !self.is_serving_target(peer) {
+            return None;
+        }
+        match self.restoration {
+            Restoration::Manifest(ref mut requested @ None) => {
+                *requested = Some(*peer);
+                info!("Warp: requesting manifest from {}", peer);
+                Some(InitialRequest::new(
+                    MessageId::Parity(ParityMessageId::GetSnapshotManifest),
+                    encode_get_snapshot_manifest(),
+                ))
+            }
+            Restoration::Chunks(ref mut download) => {
+                let chunk = download.pending.pop_front()?;
+                download.requested.insert(*peer, chunk);
+                Some(InitialRequest::new(
+                    MessageId::Parity(ParityMessageId::GetSnapshotData),
+                    encode_get_snapshot_data(&chunk.0),
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    /// Request timed out or peer disconnected.
+    pub fn request_failed(&mut self, peer: &PeerId) {
+        match self.restoration {
+            Restoration::Manifest(ref mut requested) if *requested == Some(*peer) => {
+                *requested = None
+            }
+            Restoration::Chunks(ref mut download) => download.release(peer),
+            Restoration::Snap(ref mut sync) => sync.release(peer),
+            _ => (),
+        }
+        self.abort_if_no_peers();
+    }
+
+    /// Timeouted request is retried with another peer, if that peer serves what we are downloading.
+    pub fn request_reassigned(&mut self, from: &PeerId, to: &PeerId) -> bool {
+        if let Restoration::Snap(ref mut sync) = self.restoration {
+            return self.snap_peers.contains(to) && sync.reassign(from, to);
+        }
+        if !self.is_serving_target(to) {
+            return false;
+        }
+        match self.restoration {
+            Restoration::Manifest(ref mut requested) if *requested == Some(*from) => {
+                *requested = Some(*to);
+                true
+            }
+            Restoration::Chunks(ref mut download) => download.reassign(from, to),
+            _ => false,
+        }
+    }
+
+    /// Responses are checked against request id, requests from peers are not served.
+    pub fn process_snap_message(
+        &mut self,
+        id: SnapMessageId,
+        peer: &PeerId,
+        data: &[u8],
+    ) -> Result<Task, ErrorAct> {
+        let sync = match self.restoration {
+            Restoration::Snap(ref mut sync) => sync,
+            _ if id.is_response() => {
+                return ErrorAct::new_kick_generic("Unrequested snap response".into())
+            }
+            _ => return Ok(Task::None),
+        };
+        let result = match id {
+            SnapMessageId::AccountRange => sync.process_account_range(peer, data),
+            SnapMessageId::StorageRanges => sync.process_storage_ranges(peer, data),
+            SnapMessageId::ByteCodes => sync.process_byte_codes(peer, data),
+            SnapMessageId::TrieNodes => sync.process_trie_nodes(peer, data),
+            _ => Ok(Task::None),
+        };
+        if sync.is_done() {
+            info!("Snap: state sync finished");
+            self.finished = true;
+            self.restoration = Restoration::Idle;
+        } else {
+            self.abort_if_no_peers();
+        }
+        result
+    }
+
+    /// Manifest hash needs to match snapshot hash that peer advertised in status.
+    pub fn process_manifest(&mut self, peer: &PeerId, data: &[u8]) -> Result<Task, ErrorAct> {
+        let target_hash = match (&self.restoration, self.target) {
+            (Restoration::Manifest(Some(requested)), Some((hash, _))) if requested == peer => hash,
+            _ => return ErrorAct::new_kick_generic("Unrequested snapshot manifest".into()),
+        };
+        self.restoration = Restoration::Manifest(None);
+        let (manifest, manifest_hash) = match decode_snapshot_manifest(data) {
+            Ok(Some(manifest)) => manifest,
+            Err(err) => {
+                self.abort_if_no_peers();
+                return ErrorAct::new_kick_generic(format!("Invalid SnapshotManifest: {}", err));
+            }
+            Ok(None) => {
+                info!("Warp: peer {} does not have snapshot manifest", peer);
+                self.peers.remove(peer);
+                self.abort_if_no_peers();
+                return Ok(Task::None);
+            }
+        };
+        if manifest_hash != target_hash {
+            self.abort_if_no_peers();
+            return ErrorAct::new_kick_generic(format!(
+                "Snapshot manifest hash mismatch. Expected:{} got:{}",
+                target_hash, manifest_hash
+            ));
+        }
+        info!(
+            "Warp: got manifest for block {} with {} state and {} block chunks",
+            manifest.block_number,
+            manifest.state_hashes.len(),
+            manifest.block_hashes.len()
+        );
+        if !self.begin_restoration(&manifest) {
+            return Ok(Task::None);
+        }
+        let download = ChunkDownload::new(manifest, manifest_hash, HashSet::new());
+        self.save_progress(&download);
+        self.progress_saved = Instant::now();
+        self.restoration = Restoration::Chunks(download);
+        Ok(Task::None)
+    }
+
+    /// Chunk is identified by keccak of its compressed data, it needs to match requested hash.
+    pub fn process_chunk(&mut self, peer: &PeerId, data: &[u8]) -> Result<Task, ErrorAct> {
+        let download = match self.restoration {
+            Restoration::Chunks(ref mut download) => download,
+            _ => return ErrorAct::new_kick_generic("Unrequested snapshot data".into()),
+        };
+        let (chunk_hash, chunk_type) = match download.requested.remove(peer) {
+            Some(chunk) => chunk,
+            None => return ErrorAct::new_kick_generic("Unrequested snapshot data".into()),
+        };
+        let chunk = match decode_snapshot_data(data) {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => {
+                info!("Warp: peer {} does not have chunk {}", peer, chunk_hash);
+                download.pending.push_front((chunk_hash, chunk_type));
+                self.peers.remove(peer);
+                self.abort_if_no_peers();
+                return Ok(Task::None);
+            }
+            Err(err) => {
+                download.pending.push_front((chunk_hash, chunk_type));
+                return ErrorAct::new_kick_generic(format!("Invalid SnapshotData: {}", err));
+            }
+        };
+        if hash(&chunk) != chunk_hash {
+            download.pending.push_front((chunk_hash, chunk_type));
+            return ErrorAct::new_kick_generic(format!(
+                "Snapshot chunk {} hash mismatch",
+                chunk_hash
+            ));
+        }
+        if let Err(err) = self.snapshot.restore_chunk(chunk_hash, chunk, chunk_type) {
+            // chunk matches manifest so snapshot itself is invalid, there is nothing to resume.
+            self.abort_restoration(&format!("could not restore chunk: {}", err));
+            return Ok(Task::None);
+        }
+        download.done.insert(chunk_hash);
+        info!(
+            "Warp: restored {:?} chunk {} ({}/{})",
+            chunk_type,
+            chunk_hash,
+            download.done.len(),
+            download.total()
+        );
+        if download.is_done() {
+            info!("Warp: restoration finished");
+            self.finished = true;
+            self.restoration = Restoration::Idle;
+            self.remove_progress();
+        } else if self.progress_saved.elapsed() >= PROGRESS_SAVE_INTERVAL {
+            self.progress_saved = Instant::now();
+            if let Restoration::Chunks(ref download) = self.restoration {
+                self.save_progress(download);
+            }
+        }
+        Ok(Task::None)
+    }
+
+    fn begin_restoration(&mut self, manifest: &ManifestData) -> bool {
+        match self.snapshot.begin_restoration(manifest) {
+            Ok(()) => true,
+            Err(err) => {
+                self.abort_restoration(&format!("could not begin restoration: {}", err));
+                false
+            }
+        }
+    }
+
+    /// There is nobody to download target snapshot from, continue with block sync.
+    fn abort_if_no_peers(&mut self) {
+        if let Restoration::Idle = self.restoration {
+            return;
+        }
+        let serving = match self.restoration {
+            Restoration::Snap(ref sync) => self.snap_peers.iter().any(|peer| !sync.is_stale(peer)),
+            _ => self.peers.keys().any(|peer| self.is_serving_target(peer)),
+        };
+        if !serving && !self.refresh_pivot() {
+            self.abort("no peers serving snapshot");
+        }
+    }
+
+    /// Peers keep only recent state, when none of them serves our root anymore snap sync moves
+    /// to newer pivot that block sync found. Returns false if there is no newer pivot.
+    fn refresh_pivot(&mut self) -> bool {
+        let (sync, current) = match (&mut self.restoration, self.snap_block) {
+            (Restoration::Snap(sync), Some(current)) => (sync, current),
+            _ => return false,
+        };
+        match self.pivot {
+            Some(pivot) if pivot.number > current.number && pivot.state_root != sync.root() => {
+                info!(
+                    "Snap: peers stopped serving block {}, moving pivot to {}",
+                    current.number, pivot.number
+                );
+                sync.set_root(pivot.state_root);
+                self.snap_block = Some(pivot);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Restored chunks and progress file are kept so that restoration can be resumed on next warp
+    /// to the same snapshot.
+    fn abort(&mut self, reason: &str) {
+        info!("Warp: aborting restoration, {}", reason);
+        if let Restoration::Chunks(ref download) = self.restoration {
+            self.save_progress(download);
+        }
+        self.restoration = Restoration::Idle;
+        self.snap_block = None;
+        self.finished = true;
+    }
+
+    /// Restored data is discarded, progress file goes with it as there is nothing to resume.
+    fn abort_restoration(&mut self, reason: &str) {
+        self.snapshot.abort_restoration();
+        self.remove_progress();
+        self.restoration = Restoration::Idle;
+        self.abort(reason);
+    }
+
+    fn progress_path(&self) -> Option<PathBuf> {
+        self.snapshot_dir
+            .as_ref()
+            .map(|dir| dir.join(PROGRESS_FILE))
+    }
+
+    fn load_progress(&self) -> Option<ChunkDownload> {
+        let data = fs::read(self.progress_path()?).ok()?;
+        match decode_progress(&data) {
+            Ok(download) => Some(download),
+            Err(err) => {
+                error!("Warp: invalid progress file: {:?}", err);
+                None
+            }
+        }
+    }
+
+    /// Progress is written to temporary file first, so that crash while writing does not leave
+    /// it half written.
+    fn save_progress(&self, download: &ChunkDownload) {
+        if let Some(path) = self.progress_path() {
+            let mut tmp_path = path.clone().into_os_string();
+            tmp_path.push(".tmp");
+            let tmp_path = PathBuf::from(tmp_path);
+            if let Err(err) = fs::write(&tmp_path, encode_progress(download))
+                .and_then(|_| fs::rename(&tmp_path, &path))
+            {
+                error!("Warp: could not save progress to {:?}: {}", path, err);
+            }
+        }
+    }
+
+    fn remove_progress(&self) {
+        if let Some(path) = self.progress_path() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+fn encode_progress(download: &ChunkDownload) -> Vec<u8> {
+    let mut stream = RlpStream::new_list(2);
+    stream.append_raw(&rlp::encode(&download.manifest), 1);
+    stream.begin_list(download.done.len());
+    for hash in download.done.iter() {
+        stream.append(hash);
+    }
+    stream.out().to_vec()
+}
+
+fn decode_progress(data: &[u8]) -> Result<ChunkDownload, rlp::DecoderError> {
+    let rlp = Rlp::new(data);
+    let manifest_rlp = rlp.at(0)?;
+    let manifest: ManifestData = manifest_rlp.as_val()?;
+    let done: Vec<H256> = rlp.list_at(1)?;
+    Ok(ChunkDownload::new(
+        manifest,
+        hash(manifest_rlp.as_raw()),
+        done.into_iter().collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::Bytes;
+    use interfaces::snapshot::{RestorationStatus, SnapshotError};
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct TestSnapshot {
+        restored: Mutex<Vec<(Vec<u8>, ChunkType)>>,
+    }
+
+    impl Snapshot for TestSnapshot {
+        fn create_snapshot(
+            &self,
+            block_number: BlockNumber,
+        ) -> Result<ManifestData, SnapshotError> {
+            Err(SnapshotError::BlockNotFound(block_number))
+        }
+        fn manifest(&self) -> Option<ManifestData> {
+            None
+        }
+        fn status(&self) -> RestorationStatus {
+            RestorationStatus::Inactive
+        }
+        fn chunk(&self, _hash: H256) -> Option<Bytes> {
+            Some(vec![1, 2, 3])
+        }
+        fn begin_restoration(&self, _manifest: &ManifestData) -> Result<(), SnapshotError> {
+            Ok(())
+        }
+        fn abort_restoration(&self) {}
+        fn restore_chunk(
+            &self,
+            _hash: H256,
+            chunk: Bytes,
+            chunk_type: ChunkType,
+        ) -> Result<(), SnapshotError> {
+            self.restored.lock().unwrap().push((chunk, chunk_type));
+            Ok(())
+        }
+    }
+
+    struct TestWriter;
+
+    impl StateWriter for TestWriter {
+        fn write_accounts(&self, _accounts: &[(H256, Bytes)]) {}
+        fn write_storage(&self, _account: &H256, _slots: &[(H256, Bytes)]) {}
+        fn write_code(&self, _hash: &H256, _code: &[u8]) {}
+        fn write_trie_node(&self, _hash: &H256, _node: &[u8]) {}
+        fn missing_trie_nodes(&self, _root: &H256, _limit: usize) -> Vec<(Vec<Bytes>, H256)> {
+            vec![]
+        }
+    }
+
+    /// AccountRange without accounts and proof, peer does not have requested root.
+    fn empty_account_range(request_id: u64) -> Vec<u8> {
+        let mut stream = RlpStream::new_list(3);
+        stream.append(&request_id);
+        stream.begin_list(0);
+        stream.begin_list(0);
+        stream.out().to_vec()
+    }
+
+    fn manifest_message(manifest: &ManifestData) -> Vec<u8> {
+        let mut stream = RlpStream::new_list(1);
+        stream.append_raw(&rlp::encode(manifest), 1);
+        stream.out().to_vec()
+    }
+
+    fn data_message(chunk: &[u8]) -> Vec<u8> {
+        let mut stream = RlpStream::new_list(1);
+        stream.append(&chunk);
+        stream.out().to_vec()
+    }
+
+    fn warping(snapshot: Arc<TestSnapshot>, manifest: &ManifestData) -> SnapshotManager {
+        let mut manager = SnapshotManager::new(snapshot, 2, 100, None, None);
+        let advertised = Some((
+            hash(&rlp::encode(manifest)),
+            manifest.block_number.into(),
+        ));
+        manager.insert_peer(&1, advertised);
+        manager.insert_peer(&2, advertised);
+        manager.state_changed(SchedulerState::Warping, 0);
+        manager
+    }
+
+    #[test]
+    fn test_warp_download_and_verify_chunks() {
+        let chunks = [vec![1u8; 10], vec![2u8; 10]];
+        let manifest = ManifestData {
+            version: 2,
+            state_hashes: vec![hash(&chunks[0])],
+            block_hashes: vec![hash(&chunks[1])],
+            state_root: H256::zero(),
+            block_number: 1000,
+            block_hash: H256::repeat_byte(1),
+        };
+        let snapshot = Arc::new(TestSnapshot::default());
+        let mut manager = warping(snapshot.clone(), &manifest);
+
+        assert!(manager.next_request(&1).is_some());
+        assert!(manager.next_request(&2).is_none());
+        assert!(manager
+            .process_manifest(&2, &manifest_message(&manifest))
+            .is_err());
+        manager
+            .process_manifest(&1, &manifest_message(&manifest))
+            .unwrap();
+
+        assert!(manager.next_request(&1).is_some());
+        assert!(manager.next_request(&2).is_some());
+        assert!(manager
+            .process_chunk(&2, &data_message(&chunks[0]))
+            .is_err());
+        manager
+            .process_chunk(&1, &data_message(&chunks[0]))
+            .unwrap();
+        assert!(!manager.is_finished());
+
+        // chunk from peer that sent wrong data is requested again.
+        assert!(manager.next_request(&1).is_some());
+        manager
+            .process_chunk(&1, &data_message(&chunks[1]))
+            .unwrap();
+        assert!(manager.is_finished());
+        let restored = snapshot.restored.lock().unwrap();
+        assert_eq!(restored[0], (chunks[0].clone(), ChunkType::State));
+        assert_eq!(restored[1], (chunks[1].clone(), ChunkType::Block));
+    }
+
+    #[test]
+    fn test_snap_pivot_moves_when_peers_stop_serving_root() {
+        let pivot = |number, root| Pivot {
+            number,
+            hash: H256::repeat_byte(root),
+            state_root: H256::repeat_byte(root),
+        };
+        let writer: Arc<dyn StateWriter> = Arc::new(TestWriter);
+        let snapshot = Arc::new(TestSnapshot::default());
+        let mut manager = SnapshotManager::new(snapshot, 2, 100, None, Some(writer));
+        manager.insert_snap_peer(&1);
+        manager.insert_snap_peer(&2);
+        manager.set_pivot(Some(pivot(1000, 1)));
+        manager.state_changed(SchedulerState::Warping, 0);
+
+        assert!(manager.next_request(&1).is_some());
+        assert!(manager.next_request(&2).is_some());
+        let id = SnapMessageId::AccountRange;
+        manager
+            .process_snap_message(id, &1, &empty_account_range(0))
+            .unwrap();
+        assert!(manager.next_request(&1).is_none());
+
+        manager.set_pivot(Some(pivot(1100, 2)));
+        manager
+            .process_snap_message(id, &2, &empty_account_range(1))
+            .unwrap();
+        assert!(!manager.is_finished());
+        assert_eq!(manager.snap_block, Some(pivot(1100, 2)));
+        // peers are asked again for new root.
+        assert!(manager.next_request(&1).is_some());
+        assert!(manager.next_request(&2).is_some());
+    }
+
+    #[test]
+    fn test_warp_progress_is_kept_on_abort_and_removed_with_restoration() {
+        let chunks = [vec![1u8; 10], vec![2u8; 10]];
+        let manifest = ManifestData {
+            version: 2,
+            state_hashes: vec![hash(&chunks[0])],
+            block_hashes: vec![hash(&chunks[1])],
+            state_root: H256::zero(),
+            block_number: 1000,
+            block_hash: H256::repeat_byte(1),
+        };
+        let dir = std::env::temp_dir().join(format!("reth-warp-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let snapshot = Arc::new(TestSnapshot::default());
+        let mut manager = warping(snapshot, &manifest);
+        manager.snapshot_dir = Some(dir.clone());
+        let path = dir.join(PROGRESS_FILE);
+        let done = |path: &PathBuf| decode_progress(&fs::read(path).unwrap()).unwrap().done;
+
+        manager.next_request(&1).unwrap();
+        manager
+            .process_manifest(&1, &manifest_message(&manifest))
+            .unwrap();
+        assert!(done(&path).is_empty());
+
+        // restored chunk is not written right away, but abort saves it.
+        manager.next_request(&1).unwrap();
+        manager
+            .process_chunk(&1, &data_message(&chunks[0]))
+            .unwrap();
+        assert!(done(&path).is_empty());
+        manager.remove_peer(&1);
+        manager.remove_peer(&2);
+        assert!(manager.is_finished());
+        assert_eq!(done(&path).len(), 1);
+        assert!(!dir.join("warp_progress.rlp.tmp").exists());
+
+        manager.abort_restoration("test");
+        assert!(!path.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_warp_progress_roundtrip() {
+        let manifest = ManifestData {
+            version: 2,
+            state_hashes: vec![H256::repeat_byte(1), H256::repeat_byte(2)],
+            block_hashes: vec![H256::repeat_byte(3)],
+            state_root: H256::zero(),
+            block_number: 1000,
+            block_hash: H256::repeat_byte(4),
+        };
+        let manifest_hash = hash(&rlp::encode(&manifest));
+        let done = vec![H256::repeat_byte(2)].into_iter().collect();
+        let download = ChunkDownload::new(manifest.clone(), manifest_hash, done);
+        let resumed = decode_progress(&encode_progress(&download)).unwrap();
+        assert_eq!(resumed.manifest_hash, manifest_hash);
+        assert_eq!(
+            resumed.pending,
+            vec![
+                (H256::repeat_byte(1), ChunkType::State),
+                (H256::repeat_byte(3), ChunkType::Block)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_chunk_serving_rate_limit() {
+        let snapshot = Arc::new(TestSnapshot::default());
+        let mut manager = SnapshotManager::new(snapshot, 2, 100, None, None);
+        let request = encode_get_snapshot_data(&H256::repeat_byte(1));
+        let served = |task: Task| match task {
+            Task::Responde(_, _, _, data) => decode_snapshot_data(&data).unwrap().is_some(),
+            _ => false,
+        };
+        for _ in 0..MAX_SERVED_CHUNKS {
+            assert!(served(manager.api_get_snapshot_data(&1, &request).unwrap()));
+        }
+        assert!(!served(
+            manager.api_get_snapshot_data(&1, &request).unwrap()
+        ));
+        assert!(served(manager.api_get_snapshot_data(&2, &request).unwrap()));
+
+        let later = Instant::now() + SERVING_WINDOW;
+        assert!(manager.allow_serving(&1, later));
+    }
+}
@@ -2,3 +2,5 @@
 // SPDX-License-Identifier: Apache-2.0
 
 mod transaction_manager;
+
+pub use transaction_manager::TransactionManager;
@@ -1,2 +1,626 @@
 // Copyright 2020-2021 Gnosis Ltd.
 // SPDX-License-Identifier: Apache-2.0
+
+use crate::scheduler::{
+    peer_organizer::{
+        ErrorAct, InitialRequest, PeerId, Task, MAX_MESSAGE_HASHES, MAX_MESSAGE_SIZE,
+    },
+    propagation::{fan_out, full_count, KnownHashes},
+    protocol::{EthMessageId, MessageId},
+    state::SchedulerState,
+};
+use core::{
+    transaction::{BlobSidecar, TxType},
+    Transaction, H256,
+};
+use interfaces::{
+    devp2p::ProtocolId,
+    transaction_pool::{PoolEvent, TransactionPool},
+};
+use rlp::{Rlp, RlpStream};
+use std::{
+    collections::HashMap,
+    sync::{mpsc::Receiver, Arc, Mutex},
+};
+
+/// Number of transaction hashes remembered per peer.
+pub const MAX_KNOWN_TXS: usize = 32768;
+
+/// Maximal number of announced transactions requested from peer at once.
+pub const MAX_FETCH_HASHES: usize = 256;
+
+/// First eth protocol version with transaction announcements.
+const ETH_65: u8 = 65;
+
+pub struct TransactionManager {
+    /// Transactions are propagated only when we are close to the head of the chain.
+    enabled: bool,
+    pool: Arc<Mutex<dyn TransactionPool>>,
+    pool_events: Receiver<PoolEvent>,
+    /// New pending transactions that are not propagated yet.
+    to_propagate: Vec<Arc<Transaction>>,
+    /// Transactions that peer sent, announced or got from us, they are not sent to it again.
+    known: HashMap<PeerId, KnownHashes>,
+    /// Announced transactions that are not in pool, requested from peer that announced them.
+    to_fetch: HashMap<PeerId, Vec<H256>>,
+}
+
+impl TransactionManager {
+    pub fn new(pool: Arc<Mutex<dyn TransactionPool>>) -> Self {
+        let pool_events = pool.lock().unwrap().subscribe();
+        TransactionManager {
+            enabled: false,
+            pool,
+            pool_events,
+            to_propagate: Vec::new(),
+            known: HashMap::new(),
+            to_fetch: HashMap::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn state_changed(&mut self, state: SchedulerState) {
+        self.enabled = state == SchedulerState::PassiveSync;
+        if !self.enabled {
+            self.to_propagate.clear();
+            self.to_fetch.clear();
+        }
+    }
+
+    /// Collects transactions that became pending since last call, events are dropped while
+    /// propagation is disabled.
+    pub fn process_pool_events(&mut self) {
+        for event in self.pool_events.try_iter() {
+            match event {
+                PoolEvent::AddedPending(tx)
+                | PoolEvent::Promoted(tx)
+                | PoolEvent::Replaced { new: tx, .. }
+                    if self.enabled =>
+                {
+                    self.to_propagate.push(tx)
+                }
+                _ => (),
+            }
+        }
+    }
+
+    pub fn take_to_propagate(&mut self) -> Vec<Arc<Transaction>> {
+        std::mem::take(&mut self.to_propagate)
+    }
+
+    /// Local transactions that are not mined for a while are propagated again, they reach
+    /// peers that connected since they were sent. It is called on timer.
+    pub fn rebroadcast_locals(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        let txs = self.pool.lock().unwrap().local_to_rebroadcast();
+        self.to_propagate.extend(txs);
+    }
+
+    /// Queued transactions that waited in pool for too long are removed, it is called on timer.
+    pub fn evict_expired(&mut self) {
+        self.pool.lock().unwrap().evict_expired();
+    }
+
+    pub fn peer_disconnected(&mut self, peer: &PeerId) {
+        self.known.remove(peer);
+        self.to_fetch.remove(peer);
+    }
+
+    fn mark_known(&mut self, peer: &PeerId, hash: H256) {
+        self.known
+            .entry(*peer)
+            .or_insert_with(|| KnownHashes::new(MAX_KNOWN_TXS))
+            .insert(hash);
+    }
+
+    fn is_known(&self, peer: &PeerId, hash: &H256) -> bool {
+        self.known
+            .get(peer)
+            .map_or(false, |known| known.contains(hash))
+    }
+
+    /// Handles Transactions message. Blob transactions are only announced, peer that
+    /// broadcasts them is kicked.
+    pub fn process_transactions(&mut self, peer: &PeerId, data: &[u8]) -> Result<Task, ErrorAct> {
+        let txs = match Transaction::rlp_decode_list(&Rlp::new(data)) {
+            Ok(txs) => txs,
+            Err(err) => {
+                return ErrorAct::new_kick_generic(format!("Invalid transactions: {:?}", err))
+            }
+        };
+        if txs.iter().any(|tx| matches!(tx.txtype(), TxType::Blob)) {
+            return ErrorAct::new_kick_generic("Broadcasted blob transaction".into());
+        }
+        self.insert_transactions(peer, txs.into_iter().map(|tx| (tx, None)).collect());
+        Ok(Task::None)
+    }
+
+    /// Handles PooledTransactions message, blob transactions come with sidecars.
+    pub fn process_pooled_transactions(
+        &mut self,
+        peer: &PeerId,
+        data: &[u8],
+    ) -> Result<Task, ErrorAct> {
+        let txs = match Transaction::rlp_decode_pooled_list(&Rlp::new(data)) {
+            Ok(txs) => txs,
+            Err(err) => {
+                return ErrorAct::new_kick_generic(format!("Invalid transactions: {:?}", err))
+            }
+        };
+        self.insert_transactions(peer, txs);
+        Ok(Task::None)
+    }
+
+    /// Transactions that are not accepted by pool are only logged, peer could have older
+    /// state than us.
+    fn insert_transactions(&mut self, peer: &PeerId, txs: Vec<(Transaction, Option<BlobSidecar>)>) {
+        if !self.enabled {
+            return;
+        }
+        for (tx, _) in txs.iter() {
+            self.mark_known(peer, tx.hash());
+        }
+        if let Some(to_fetch) = self.to_fetch.get_mut(peer) {
+            to_fetch.retain(|hash| !txs.iter().any(|(tx, _)| tx.hash() == *hash));
+        }
+        let mut pool = self.pool.lock().unwrap();
+        let mut regular = Vec::new();
+        let mut results = Vec::new();
+        for (tx, sidecar) in txs {
+            match sidecar {
+                Some(sidecar) => results.push(pool.insert_blob(tx, sidecar)),
+                None => regular.push(tx),
+            }
+        }
+        results.extend(pool.insert(regular));
+        for err in results.into_iter().filter_map(Result::err) {
+            debug!("Transaction from peer {} not inserted: {:?}", peer, err);
+        }
+    }
+
+    /// Handles NewPooledTransactionHashes, hashes that are not in pool are fetched later.
+    pub fn process_pooled_hashes(&mut self, peer: &PeerId, data: &[u8]) -> Result<Task, ErrorAct> {
+        let hashes: Vec<H256> = match Rlp::new(data).as_list() {
+            Ok(hashes) => hashes,
+            Err(err) => {
+                return ErrorAct::new_kick_generic(format!("Invalid transaction hashes: {:?}", err))
+            }
+        };
+        if !self.enabled {
+            return Ok(Task::None);
+        }
+        let unknown: Vec<H256> = {
+            let pool = self.pool.lock().unwrap();
+            hashes
+                .iter()
+                .filter(|hash| pool.find(hash).is_none())
+                .cloned()
+                .collect()
+        };
+        for hash in hashes {
+            self.mark_known(peer, hash);
+        }
+        let to_fetch = self.to_fetch.entry(*peer).or_default();
+        for hash in unknown {
+            if !to_fetch.contains(&hash) && to_fetch.len() < MAX_MESSAGE_HASHES {
+                to_fetch.push(hash);
+            }
+        }
+        Ok(Task::None)
+    }
+
+    /// Request for announced transactions that peer has and we do not.
+    pub fn next_request(&mut self, peer: &PeerId) -> Option<InitialRequest> {
+        let to_fetch = self.to_fetch.get_mut(peer)?;
+        {
+            let pool = self.pool.lock().unwrap();
+            to_fetch.retain(|hash| pool.find(hash).is_none());
+        }
+        if to_fetch.is_empty() {
+            self.to_fetch.remove(peer);
+            return None;
+        }
+        let count = to_fetch.len().min(MAX_FETCH_HASHES);
+        let hashes: Vec<H256> = to_fetch.drain(..count).collect();
+        let mut rlp = RlpStream::new();
+        rlp.append_list(&hashes);
+        Some(InitialRequest::new(
+            MessageId::Eth(EthMessageId::GetPooledTransactions),
+            rlp.out().to_vec(),
+        ))
+    }
+
+    /// Responds with requested transactions that we have, response is cut at size limit.
+    pub fn api_get_pooled_transactions(
+        &mut self,
+        peer: &PeerId,
+        data: &[u8],
+    ) -> Result<Task, ErrorAct> {
+        let hashes: Vec<H256> = match Rlp::new(data).as_list() {
+            Ok(hashes) => hashes,
+            Err(err) => {
+                return ErrorAct::new_kick_generic(format!("Invalid transaction hashes: {:?}", err))
+            }
+        };
+        let pool = self.pool.lock().unwrap();
+        let txs: Vec<Arc<Transaction>> = hashes
+            .iter()
+            .take(MAX_MESSAGE_HASHES)
+            .filter_map(|hash| pool.find(hash))
+            .collect();
+        let mut rlp = RlpStream::new();
+        rlp.begin_unbounded_list();
+        let mut size = 0;
+        let mut sent = Vec::new();
+        for tx in txs {
+            if size >= MAX_MESSAGE_SIZE {
+                break;
+            }
+            // blob transactions are served in network form together with blobs.
+            if let TxType::Blob = tx.txtype() {
+                let data = match pool.blob_sidecar(&tx.hash()) {
+                    Some(sidecar) => tx.encode_with_sidecar(&sidecar),
+                    None => continue,
+                };
+                size += data.len();
+                rlp.append(&data);
+            } else {
+                size += tx.encode().len();
+                tx.rlp_append(&mut rlp);
+            }
+            sent.push(tx.hash());
+        }
+        drop(pool);
+        for hash in sent {
+            self.mark_known(peer, hash);
+        }
+        rlp.finalize_unbounded_list();
+        Ok(Task::Responde(
+            *peer,
+            ProtocolId::Eth,
+            MessageId::Eth(EthMessageId::PooledTransactions),
+            rlp.out().to_vec(),
+        ))
+    }
+
+    /// Sends new pending transactions to connected peers with their eth versions. Square root
+    /// of peers that do not know transaction get it whole and the rest get its hash, peers
+    /// without eth/65 always get whole transactions. Blob transactions are only announced.
+    pub fn propagate(&mut self, peers: &[(PeerId, u8)]) -> Vec<Task> {
+        self.known
+            .retain(|known_peer, _| peers.iter().any(|(peer, _)| peer == known_peer));
+        let txs = self.take_to_propagate();
+        if txs.is_empty() || peers.is_empty() {
+            return Vec::new();
+        }
+        let mut peers = peers.to_vec();
+        peers.sort();
+
+        let mut full: HashMap<PeerId, Vec<Arc<Transaction>>> = HashMap::new();
+        let mut announce: HashMap<PeerId, Vec<H256>> = HashMap::new();
+        for tx in txs {
+            let hash = tx.hash();
+            let blob = matches!(tx.txtype(), TxType::Blob);
+            let targets: Vec<(PeerId, u8)> = peers
+                .iter()
+                .filter(|(peer, version)| {
+                    !self.is_known(peer, &hash) && !(blob && *version < ETH_65)
+                })
+                .cloned()
+                .collect();
+            if targets.is_empty() {
+                continue;
+            }
+            let full_peers = if blob { 0 } else { full_count(targets.len()) };
+            let whole = fan_out(&hash, targets.len(), full_peers);
+            for ((peer, version), whole) in targets.iter().zip(whole) {
+                if whole || *version < ETH_65 {
+                    full.entry(*peer).or_default().push(tx.clone());
+                } else {
+                    announce.entry(*peer).or_default().push(hash);
+                }
+                self.mark_known(peer, hash);
+            }
+        }
+
+        let mut tasks = Vec::new();
+        for (peer, txs) in full {
+            for data in encode_transactions(&txs) {
+                tasks.push(Task::Responde(
+                    peer,
+                    ProtocolId::Eth,
+                    MessageId::Eth(EthMessageId::Transactions),
+                    data,
+                ));
+            }
+        }
+        for (peer, hashes) in announce {
+            for chunk in hashes.chunks(MAX_MESSAGE_HASHES) {
+                let mut rlp = RlpStream::new();
+                rlp.append_list(chunk);
+                tasks.push(Task::Responde(
+                    peer,
+                    ProtocolId::Eth,
+                    MessageId::Eth(EthMessageId::NewPooledTransactionHashes),
+                    rlp.out().to_vec(),
+                ));
+            }
+        }
+        tasks
+    }
+}
+
+/// Transactions messages, new one is started when previous gets over size limit.
+fn encode_transactions(txs: &[Arc<Transaction>]) -> Vec<Vec<u8>> {
+    let mut messages = Vec::new();
+    let mut rlp = RlpStream::new();
+    rlp.begin_unbounded_list();
+    let mut size = 0;
+    for tx in txs {
+        if size >= MAX_MESSAGE_SIZE {
+            rlp.finalize_unbounded_list();
+            messages.push(rlp.out().to_vec());
+            rlp = RlpStream::new();
+            rlp.begin_unbounded_list();
+            size = 0;
+        }
+        size += tx.encode().len();
+        tx.rlp_append(&mut rlp);
+    }
+    rlp.finalize_unbounded_list();
+    messages.push(rlp.out().to_vec());
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::{
+        transaction::{
+            blob_sidecar::BYTES_PER_BLOB, BlobPayload, CallType, LegacyPayload, TypePayload,
+        },
+        Address, U256, U64,
+    };
+    use crypto::publickey::{Generator, Random};
+    use interfaces::state::StateProvider;
+    use std::time::Duration;
+    use txpool::{BlobVerifier, Pool, PoolConfig};
+
+    struct TestState;
+
+    impl StateProvider for TestState {
+        fn account_nonce(&self, _address: &Address) -> U64 {
+            U64::zero()
+        }
+
+        fn account_balance(&self, _address: &Address) -> U256 {
+            U256::from(u64::MAX)
+        }
+    }
+
+    fn manager() -> TransactionManager {
+        manager_with(PoolConfig::default())
+    }
+
+    struct AcceptBlobs;
+
+    impl BlobVerifier for AcceptBlobs {
+        fn verify(&self, _sidecar: &BlobSidecar) -> bool {
+            true
+        }
+    }
+
+    fn manager_with(config: PoolConfig) -> TransactionManager {
+        let mut pool = Pool::new(config, Arc::new(TestState));
+        pool.set_blob_verifier(Arc::new(AcceptBlobs));
+        let mut manager = TransactionManager::new(Arc::new(Mutex::new(pool)));
+        manager.state_changed(SchedulerState::PassiveSync);
+        manager
+    }
+
+    fn tx() -> Transaction {
+        let mut tx = Transaction::default();
+        tx.gas_limit = 21_000.into();
+        tx.type_payload = TypePayload::Legacy(LegacyPayload {
+            gas_price: 10.into(),
+        });
+        tx.sign(Random.generate().secret());
+        tx
+    }
+
+    fn encode(txs: &[Transaction]) -> Vec<u8> {
+        let mut rlp = RlpStream::new();
+        Transaction::rlp_append_list(&mut rlp, txs);
+        rlp.out().to_vec()
+    }
+
+    #[test]
+    fn test_propagate_to_peers_that_do_not_know_transaction() {
+        let mut manager = manager();
+        let tx = tx();
+        manager
+            .process_transactions(&0, &encode(&[tx.clone()]))
+            .unwrap();
+        manager.process_pool_events();
+
+        let peers: Vec<(PeerId, u8)> = (0..10).map(|peer| (peer, 65)).collect();
+        let tasks = manager.propagate(&peers);
+        let mut full = Vec::new();
+        let mut announced = Vec::new();
+        for task in tasks {
+            match task {
+                Task::Responde(peer, _, MessageId::Eth(EthMessageId::Transactions), data) => {
+                    let txs = Transaction::rlp_decode_list(&Rlp::new(&data)).unwrap();
+                    assert_eq!(txs[0].hash(), tx.hash());
+                    full.push(peer)
+                }
+                Task::Responde(
+                    peer,
+                    _,
+                    MessageId::Eth(EthMessageId::NewPooledTransactionHashes),
+                    data,
+                ) => {
+                    let hashes: Vec<H256> = Rlp::new(&data).as_list().unwrap();
+                    assert_eq!(hashes, vec![tx.hash()]);
+                    announced.push(peer)
+                }
+                task => panic!("Unexpected task {:?}", task),
+            }
+        }
+        // source of transaction is skipped, square root of remaining 9 peers get it whole.
+        assert_eq!(full.len(), 3);
+        assert_eq!(announced.len(), 6);
+        assert!(!full.contains(&0) && !announced.contains(&0));
+
+        // already known by everyone
+        manager.to_propagate.push(Arc::new(tx));
+        assert!(manager.propagate(&peers).is_empty());
+    }
+
+    #[test]
+    fn test_rebroadcast_local_transactions() {
+        let mut manager = manager_with(PoolConfig {
+            rebroadcast_interval: Duration::from_secs(0),
+            ..Default::default()
+        });
+        let tx = tx();
+        let hash = manager.pool.lock().unwrap().insert_local(tx);
+        assert!(hash.is_ok());
+        manager.process_pool_events();
+        assert_eq!(manager.propagate(&[(0, 65)]).len(), 1);
+
+        manager.rebroadcast_locals();
+        assert_eq!(manager.to_propagate.len(), 1);
+        // new peer gets it, the one that already has it is skipped.
+        assert_eq!(manager.propagate(&[(0, 65), (1, 65)]).len(), 1);
+
+        manager.state_changed(SchedulerState::ActiveSync);
+        manager.rebroadcast_locals();
+        assert!(manager.take_to_propagate().is_empty());
+    }
+
+    #[test]
+    fn test_old_peers_get_full_transactions() {
+        let mut manager = manager();
+        let tx = tx();
+        manager.pool.lock().unwrap().insert(vec![tx]);
+        manager.process_pool_events();
+
+        let peers: Vec<(PeerId, u8)> = (0..4).map(|peer| (peer, 64)).collect();
+        let tasks = manager.propagate(&peers);
+        assert_eq!(tasks.len(), 4);
+        assert!(tasks.iter().all(|task| matches!(
+            task,
+            Task::Responde(_, _, MessageId::Eth(EthMessageId::Transactions), _)
+        )));
+    }
+
+    #[test]
+    fn test_fetch_announced_transactions() {
+        let mut manager = manager();
+        let (known, unknown) = (tx(), tx());
+        manager.pool.lock().unwrap().insert(vec![known.clone()]);
+
+        let mut rlp = RlpStream::new();
+        rlp.append_list(&[known.hash(), unknown.hash()]);
+        manager.process_pooled_hashes(&1, &rlp.out()).unwrap();
+        assert!(manager.next_request(&2).is_none());
+
+        let request = manager.next_request(&1).unwrap();
+        assert!(matches!(
+            request.message_id,
+            MessageId::Eth(EthMessageId::GetPooledTransactions)
+        ));
+        let hashes: Vec<H256> = Rlp::new(&request.data).as_list().unwrap();
+        assert_eq!(hashes, vec![unknown.hash()]);
+        assert!(manager.next_request(&1).is_none());
+
+        // peer that announced transactions knows them
+        manager.process_pool_events();
+        assert!(manager.propagate(&[(1, 65)]).is_empty());
+    }
+
+    #[test]
+    fn test_serve_pooled_transactions() {
+        let mut manager = manager();
+        let tx = tx();
+        manager.pool.lock().unwrap().insert(vec![tx.clone()]);
+
+        let mut rlp = RlpStream::new();
+        rlp.append_list(&[tx.hash(), H256::from_low_u64_be(1)]);
+        match manager.api_get_pooled_transactions(&1, &rlp.out()).unwrap() {
+            Task::Responde(1, ProtocolId::Eth, _, data) => {
+                let txs = Transaction::rlp_decode_list(&Rlp::new(&data)).unwrap();
+                assert_eq!(txs.len(), 1);
+                assert_eq!(txs[0].hash(), tx.hash());
+            }
+            task => panic!("Unexpected task {:?}", task),
+        }
+        assert!(manager.process_transactions(&1, &[0xc1, 0x01]).is_err());
+    }
+
+    #[test]
+    fn test_blob_transactions_are_announced_and_served_with_sidecar() {
+        let dir = std::env::temp_dir().join(format!("reth-tm-blobs-{}", std::process::id()));
+        let mut manager = manager_with(PoolConfig {
+            blob_sidecars: Some(dir.clone()),
+            ..Default::default()
+        });
+        let sidecar = BlobSidecar {
+            blobs: vec![vec![1; BYTES_PER_BLOB]],
+            commitments: vec![vec![2; 48]],
+            proofs: vec![vec![3; 48]],
+        };
+        let mut tx = Transaction::default();
+        tx.chain_id = Some(100);
+        tx.gas_limit = 21_000.into();
+        tx.to = CallType::CallMessage(Address::from_low_u64_be(1));
+        tx.type_payload = TypePayload::Blob(BlobPayload {
+            max_priority_fee_per_gas: 2.into(),
+            max_fee_per_gas: 10.into(),
+            access_list: Vec::new(),
+            max_fee_per_blob_gas: 5.into(),
+            blob_versioned_hashes: sidecar.versioned_hashes(),
+        });
+        tx.sign(Random.generate().secret());
+
+        // blob transaction can't be broadcast
+        assert!(manager
+            .process_transactions(&0, &encode(&[tx.clone()]))
+            .is_err());
+        let mut rlp = RlpStream::new_list(1);
+        rlp.append(&tx.encode_with_sidecar(&sidecar));
+        manager.process_pooled_transactions(&0, &rlp.out()).unwrap();
+        manager.process_pool_events();
+
+        let peers: Vec<(PeerId, u8)> = (0..5).map(|peer| (peer, 65)).collect();
+        let tasks = manager.propagate(&peers);
+        assert_eq!(tasks.len(), 4);
+        assert!(tasks.iter().all(|task| matches!(
+            task,
+            Task::Responde(
+                _,
+                _,
+                MessageId::Eth(EthMessageId::NewPooledTransactionHashes),
+                _
+            )
+        )));
+
+        let mut rlp = RlpStream::new();
+        rlp.append_list(&[tx.hash()]);
+        match manager.api_get_pooled_transactions(&1, &rlp.out()).unwrap() {
+            Task::Responde(1, ProtocolId::Eth, _, data) => {
+                let txs = Transaction::rlp_decode_pooled_list(&Rlp::new(&data)).unwrap();
+                assert_eq!(txs[0].0.hash(), tx.hash());
+                assert_eq!(txs[0].1, Some(sidecar));
+            }
+            task => panic!("Unexpected task {:?}", task),
+        }
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,570 @@
+// Copyright 2021 Gnosis Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::source::{SnapshotSource, StateAccount};
+use core::{BlockBody, BlockHeader, Bytes, Transaction, H256};
+use interfaces::snapshot::SnapshotError;
+use keccak_hash::keccak;
+use rlp::RlpStream;
+use std::collections::HashSet;
+
+/// Number of accounts or storage slots read from source at once.
+const READ_BATCH: usize = 1024;
+/// Space reserved for list headers, nonce and balance when estimating size of account entry.
+const ENTRY_OVERHEAD: usize = 128;
+
+/// How account code is stored in state chunk.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CodeState {
+    Empty = 0,
+    /// Code follows account.
+    Inline = 1,
+    /// Code is already written with one of previous accounts, only its hash is stored.
+    Hash = 2,
+}
+
+pub fn keccak256(data: &[u8]) -> H256 {
+    H256::from_slice(keccak(data).as_bytes())
+}
+
+/// Storage for compressed chunks.
+pub trait ChunkSink {
+    fn write_chunk(&mut self, hash: H256, chunk: &[u8]) -> Result<(), SnapshotError>;
+}
+
+/// Compresses rlp list with snappy and writes it to sink. Chunk hash is keccak of compressed data.
+fn write_chunk(sink: &mut dyn ChunkSink, stream: RlpStream) -> Result<H256, SnapshotError> {
+    let compressed = snap::raw::Encoder::new()
+        .compress_vec(&stream.out())
+        .map_err(|err| SnapshotError::Io(err.to_string()))?;
+    let hash = keccak256(&compressed);
+    sink.write_chunk(hash, &compressed)?;
+    Ok(hash)
+}
+
+/// Packs accounts into chunks of at most `chunk_size` bytes before compression.
+/// Chunk is rlp list of `[account_hash, [nonce, balance, code_state, code, [[key, value], ...]]]`.
+/// Account with large storage is split into several entries with the same hash, only first one
+/// can carry code inline, the following ones refer to it by hash.
+struct StateChunker<'a> {
+    sink: &'a mut dyn ChunkSink,
+    chunk_size: usize,
+    entries: Vec<Bytes>,
+    size: usize,
+    used_code: HashSet<H256>,
+    hashes: Vec<H256>,
+}
+
+impl<'a> StateChunker<'a> {
+    fn new(sink: &'a mut dyn ChunkSink, chunk_size: usize) -> Self {
+        StateChunker {
+            sink,
+            chunk_size,
+            entries: vec![],
+            size: 0,
+            used_code: HashSet::new(),
+            hashes: vec![],
+        }
+    }
+
+    fn push_entry(&mut self, entry: Bytes) -> Result<(), SnapshotError> {
+        if self.size + entry.len() > self.chunk_size {
+            self.flush()?;
+        }
+        self.size += entry.len();
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), SnapshotError> {
+        if self.entries.is_empty() {
+            return Ok(());
+        }
+        let mut stream = RlpStream::new_list(self.entries.len());
+        for entry in self.entries.drain(..) {
+            stream.append_raw(&entry, 1);
+        }
+        self.size = 0;
+        self.hashes.push(write_chunk(self.sink, stream)?);
+        Ok(())
+    }
+
+    fn code(
+        &mut self,
+        source: &dyn SnapshotSource,
+        account: &StateAccount,
+    ) -> Result<(CodeState, Bytes), SnapshotError> {
+        if account.code_hash == keccak256(&[]) {
+            return Ok((CodeState::Empty, vec![]));
+        }
+        if self.used_code.contains(&account.code_hash) {
+            return Ok((CodeState::Hash, account.code_hash.as_bytes().to_vec()));
+        }
+        let code = source
+            .code(&account.code_hash)
+            .ok_or(SnapshotError::MissingState(account.code_hash))?;
+        self.used_code.insert(account.code_hash);
+        Ok((CodeState::Inline, code))
+    }
+
+    fn push_account(
+        &mut self,
+        source: &dyn SnapshotSource,
+        hash: &H256,
+        account: &StateAccount,
+    ) -> Result<(), SnapshotError> {
+        let (mut code_state, mut code) = self.code(source, account)?;
+        let mut storage: Vec<Bytes> = vec![];
+        let mut size = ENTRY_OVERHEAD + code.len();
+        let mut after = None;
+        loop {
+            let slots = source.storage(&account.storage_root, after, READ_BATCH);
+            for (key, value) in slots.iter() {
+                let mut pair = RlpStream::new_list(2);
+                pair.append(key).append(value);
+                let pair = pair.out().to_vec();
+                if size + pair.len() > self.chunk_size && !storage.is_empty() {
+                    self.push_entry(account_entry(hash, account, code_state, &code, &storage))?;
+                    // code is already written, so restoration must not reset it.
+                    if code_state != CodeState::Empty {
+                        code_state = CodeState::Hash;
+                        code = account.code_hash.as_bytes().to_vec();
+                    }
+                    storage.clear();
+                    size = ENTRY_OVERHEAD;
+                }
+                size += pair.len();
+                storage.push(pair);
+            }
+            if slots.len() < READ_BATCH {
+                break;
+            }
+            after = slots.last().map(|(key, _)| *key);
+        }
+        self.push_entry(account_entry(hash, account, code_state, &code, &storage))
+    }
+
+    fn finish(mut self) -> Result<Vec<H256>, SnapshotError> {
+        self.flush()?;
+        Ok(self.hashes)
+    }
+}
+
+fn account_entry(
+    hash: &H256,
+    account: &StateAccount,
+    code_state: CodeState,
+    code: &[u8],
+    storage: &[Bytes],
+) -> Bytes {
+    let mut stream = RlpStream::new_list(2);
+    stream.append(hash);
+    stream
+        .begin_list(5)
+        .append(&account.nonce)
+        .append(&account.balance)
+        .append(&(code_state as u8))
+        .append(&code);
+    stream.begin_list(storage.len());
+    for pair in storage {
+        stream.append_raw(pair, 1);
+    }
+    stream.out().to_vec()
+}
+
+/// Walks all accounts of state and writes them into state chunks. Returns chunk hashes.
+pub fn chunk_state(
+    source: &dyn SnapshotSource,
+    state_root: &H256,
+    chunk_size: usize,
+    sink: &mut dyn ChunkSink,
+) -> Result<Vec<H256>, SnapshotError> {
+    let mut chunker = StateChunker::new(sink, chunk_size);
+    let mut after = None;
+    loop {
+        let accounts = source.accounts(state_root, after, READ_BATCH);
+        for (hash, account) in accounts.iter() {
+            chunker.push_account(source, hash, account)?;
+        }
+        if accounts.len() < READ_BATCH {
+            break;
+        }
+        after = accounts.last().map(|(hash, _)| *hash);
+    }
+    chunker.finish()
+}
+
+/// Abridged block leaves out fields that restoration recovers from context: parent hash and number
+/// from previous block, transactions root and ommers hash from body and receipts root from receipts
+/// stored next to block. It is list of 8 header fields, transactions, ommers and 2 seal fields.
+fn encode_abridged_block(stream: &mut RlpStream, header: &BlockHeader, body: &BlockBody) {
+    stream
+        .begin_list(12)
+        .append(&header.beneficiary_address)
+        .append(&header.state_root)
+        .append(&header.logs_bloom)
+        .append(&header.difficulty)
+        .append(&header.gas_limit)
+        .append(&header.gas_used)
+        .append(&header.timestamp)
+        .append(&header.extra_data);
+    Transaction::rlp_append_list(stream, &body.transactions);
+    stream.begin_list(body.ommers.len());
+    for ommer in body.ommers.iter() {
+        stream.append(ommer);
+    }
+    stream.append(&header.mix_hash).append(&header.nonce);
+}
+
+/// Block chunk is `[parent_number, parent_hash, parent_total_difficulty, [abridged_block, receipts], ...]`,
+/// where parent is the block before first block of chunk.
+fn write_block_chunk(
+    source: &dyn SnapshotSource,
+    sink: &mut dyn ChunkSink,
+    first: &BlockHeader,
+    items: &mut Vec<Bytes>,
+) -> Result<H256, SnapshotError> {
+    let parent_number = first.number - 1;
+    let parent_total_difficulty = source
+        .total_difficulty(&first.parent_hash)
+        .ok_or(SnapshotError::BlockNotFound(parent_number))?;
+    let mut stream = RlpStream::new_list(3 + items.len());
+    stream
+        .append(&parent_number)
+        .append(&first.parent_hash)
+        .append(&parent_total_difficulty);
+    for item in items.drain(..) {
+        stream.append_raw(&item, 1);
+    }
+    write_chunk(sink, stream)
+}
+
+/// Writes last `blocks` blocks up to and including `head` with their receipts into block chunks.
+pub fn chunk_blocks(
+    source: &dyn SnapshotSource,
+    head: &BlockHeader,
+    blocks: u64,
+    chunk_size: usize,
+    sink: &mut dyn ChunkSink,
+) -> Result<Vec<H256>, SnapshotError> {
+    let first = head.number.saturating_sub(blocks.saturating_sub(1)).max(1);
+    let mut hashes = vec![];
+    let mut items: Vec<Bytes> = vec![];
+    let mut size = 0;
+    let mut chunk_start: Option<BlockHeader> = None;
+    for number in first..=head.number {
+        let header = source
+            .header(number)
+            .ok_or(SnapshotError::BlockNotFound(number))?;
+        let hash = header.hash();
+        let body = source
+            .body(&hash)
+            .ok_or(SnapshotError::BlockNotFound(number))?;
+        let receipts = source
+            .receipts(&hash)
+            .ok_or(SnapshotError::BlockNotFound(number))?;
+        let mut item = RlpStream::new_list(2);
+        encode_abridged_block(&mut item, &header, &body);
+        item.append_raw(&receipts, 1);
+        let item = item.out().to_vec();
+
+        if size + item.len() > chunk_size && !items.is_empty() {
+            let start = chunk_start.take().unwrap();
+            hashes.push(write_block_chunk(source, sink, &start, &mut items)?);
+            size = 0;
+        }
+        if chunk_start.is_none() {
+            chunk_start = Some(header);
+        }
+        size += item.len();
+        items.push(item);
+    }
+    if let Some(start) = chunk_start {
+        hashes.push(write_block_chunk(source, sink, &start, &mut items)?);
+    }
+    Ok(hashes)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use core::{BlockNumber, H160, U256};
+    use rlp::Rlp;
+    use std::collections::{BTreeMap, HashMap};
+
+    /// Chain of empty blocks and state kept in memory.
+    #[derive(Default)]
+    pub struct MemorySource {
+        pub headers: Vec<BlockHeader>,
+        pub accounts: BTreeMap<H256, StateAccount>,
+        pub storage: HashMap<H256, BTreeMap<H256, Bytes>>,
+        pub code: HashMap<H256, Bytes>,
+    }
+
+    impl MemorySource {
+        pub fn with_blocks(count: u64) -> Self {
+            let mut source = MemorySource::default();
+            let mut parent_hash = H256::zero();
+            for number in 0..count {
+                let header = BlockHeader {
+                    parent_hash,
+                    ommers_hash: H256::zero(),
+                    beneficiary_address: H160::zero(),
+                    state_root: H256::repeat_byte(0xaa),
+                    transactions_root: H256::zero(),
+                    receipts_root: H256::zero(),
+                    logs_bloom: vec![0; 256],
+                    difficulty: 1,
+                    number,
+                    gas_limit: 0,
+                    gas_used: 0,
+                    timestamp: number,
+                    extra_data: vec![],
+                    mix_hash: H256::zero(),
+                    nonce: 0,
+                };
+                parent_hash = header.hash();
+                source.headers.push(header);
+            }
+            source
+        }
+
+        pub fn insert_account(&mut self, seed: u8, code: Option<Bytes>, slots: usize) {
+            let storage_root = H256::repeat_byte(seed);
+            let storage = (0..slots)
+                .map(|i| (H256::from_low_u64_be(i as u64), vec![0x80 + seed; 32]))
+                .collect();
+            self.storage.insert(storage_root, storage);
+            let code_hash = match code {
+                Some(code) => {
+                    let hash = keccak256(&code);
+                    self.code.insert(hash, code);
+                    hash
+                }
+                None => keccak256(&[]),
+            };
+            let account = StateAccount {
+                nonce: U256::from(seed),
+                balance: U256::from(1000),
+                storage_root,
+                code_hash,
+            };
+            self.accounts.insert(keccak256(&[seed]), account);
+        }
+    }
+
+    impl SnapshotSource for MemorySource {
+        fn header(&self, number: BlockNumber) -> Option<BlockHeader> {
+            self.headers.get(number as usize).cloned()
+        }
+        fn body(&self, _hash: &H256) -> Option<BlockBody> {
+            Some(BlockBody {
+                transactions: vec![],
+                ommers: vec![],
+            })
+        }
+        fn receipts(&self, _hash: &H256) -> Option<Bytes> {
+            Some(RlpStream::new_list(0).out().to_vec())
+        }
+        fn total_difficulty(&self, hash: &H256) -> Option<U256> {
+            self.headers
+                .iter()
+                .position(|header| header.hash() == *hash)
+                .map(|number| U256::from(number + 1))
+        }
+        fn accounts(
+            &self,
+            _state_root: &H256,
+            after: Option<H256>,
+            limit: usize,
+        ) -> Vec<(H256, StateAccount)> {
+            self.accounts
+                .iter()
+                .filter(|(hash, _)| after.map_or(true, |after| **hash > after))
+                .take(limit)
+                .map(|(hash, account)| (*hash, account.clone()))
+                .collect()
+        }
+        fn storage(
+            &self,
+            storage_root: &H256,
+            after: Option<H256>,
+            limit: usize,
+        ) -> Vec<(H256, Bytes)> {
+            self.storage
+                .get(storage_root)
+                .map(|storage| {
+                    storage
+                        .iter()
+                        .filter(|(key, _)| after.map_or(true, |after| **key > after))
+                        .take(limit)
+                        .map(|(key, value)| (*key, value.clone()))
+                        .collect()
+                })
+                .unwrap_or_default()
+        }
+        fn code(&self, code_hash: &H256) -> Option<Bytes> {
+            self.code.get(code_hash).cloned()
+        }
+    }
+
+    #[derive(Default)]
+    pub struct MemorySink {
+        pub chunks: Vec<(H256, Bytes)>,
+    }
+
+    impl ChunkSink for MemorySink {
+        fn write_chunk(&mut self, hash: H256, chunk: &[u8]) -> Result<(), SnapshotError> {
+            self.chunks.push((hash, chunk.to_vec()));
+            Ok(())
+        }
+    }
+
+    fn decompress(chunk: &[u8]) -> Bytes {
+        snap::raw::Decoder::new().decompress_vec(chunk).unwrap()
+    }
+
+    #[test]
+    fn test_state_chunks_are_bounded_and_split_accounts() {
+        let code = vec![0x60; 100];
+        let mut source = MemorySource::default();
+        source.insert_account(1, Some(code.clone()), 2);
+        source.insert_account(2, Some(code.clone()), 200);
+        source.insert_account(3, None, 0);
+        let chunk_size = 2048;
+        let mut sink = MemorySink::default();
+        let hashes = chunk_state(&source, &H256::zero(), chunk_size, &mut sink).unwrap();
+        assert!(hashes.len() > 1);
+
+        let mut entries = vec![];
+        for (hash, chunk) in sink.chunks.iter() {
+            assert_eq!(keccak256(chunk), *hash);
+            let raw = decompress(chunk);
+            assert!(raw.len() <= chunk_size + 8);
+            let rlp = Rlp::new(&raw);
+            for entry in rlp.iter() {
+                let account_hash: H256 = entry.val_at(0).unwrap();
+                let code_state: u8 = entry.at(1).unwrap().val_at(2).unwrap();
+                let slots = entry.at(1).unwrap().at(4).unwrap().item_count().unwrap();
+                entries.push((account_hash, code_state, slots));
+            }
+        }
+        let split: Vec<_> = entries
+            .iter()
+            .filter(|(hash, _, _)| *hash == keccak256(&[2]))
+            .collect();
+        assert!(split.len() > 1);
+        assert_eq!(split.iter().map(|(_, _, slots)| slots).sum::<usize>(), 200);
+        // account 1 and 2 share code, it is inlined only once.
+        let code_states: HashSet<u8> = entries.iter().map(|(_, state, _)| *state).collect();
+        assert!(code_states.contains(&(CodeState::Inline as u8)));
+        assert!(code_states.contains(&(CodeState::Hash as u8)));
+        assert_eq!(
+            entries
+                .iter()
+                .filter(|(_, state, _)| *state == CodeState::Inline as u8)
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_split_account_refers_to_code_by_hash() {
+        let code = vec![0x60; 100];
+        let mut source = MemorySource::default();
+        source.insert_account(1, Some(code.clone()), 200);
+        let mut sink = MemorySink::default();
+        chunk_state(&source, &H256::zero(), 2048, &mut sink).unwrap();
+
+        let mut entries = vec![];
+        for (_, chunk) in sink.chunks.iter() {
+            let raw = decompress(chunk);
+            for entry in Rlp::new(&raw).iter() {
+                let account = entry.at(1).unwrap();
+                let code_state: u8 = account.val_at(2).unwrap();
+                let code: Bytes = account.val_at(3).unwrap();
+                entries.push((code_state, code));
+            }
+        }
+        assert!(entries.len() > 1);
+        assert_eq!(entries[0], (CodeState::Inline as u8, code.clone()));
+        let code_hash = keccak256(&code).as_bytes().to_vec();
+        for entry in entries.iter().skip(1) {
+            assert_eq!(*entry, (CodeState::Hash as u8, code_hash.clone()));
+        }
+    }
+
+    fn from_hex(hex: &str) -> Bytes {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_abridged_block_matches_openethereum() {
+        let header = BlockHeader {
+            parent_hash: H256::repeat_byte(0x44),
+            ommers_hash: H256::repeat_byte(0x55),
+            beneficiary_address: H160::repeat_byte(0x11),
+            state_root: H256::repeat_byte(0x22),
+            transactions_root: H256::repeat_byte(0x66),
+            receipts_root: H256::repeat_byte(0x77),
+            logs_bloom: vec![0; 256],
+            difficulty: 131_072,
+            number: 1,
+            gas_limit: 8_000_000,
+            gas_used: 21_000,
+            timestamp: 1_600_000_000,
+            extra_data: b"reth".to_vec(),
+            mix_hash: H256::repeat_byte(0x33),
+            nonce: 0x0102_0304_0506_0708,
+        };
+        let body = BlockBody {
+            transactions: vec![],
+            ommers: vec![],
+        };
+        let mut stream = RlpStream::new();
+        encode_abridged_block(&mut stream, &header, &body);
+
+        // [author, state_root, log_bloom, difficulty, gas_limit, gas_used, timestamp, extra_data,
+        //  transactions, ommers, mix_hash, nonce] as written by OpenEthereum's AbridgedBlock.
+        let expected = format!(
+            "{}{}{}",
+            "f9017a941111111111111111111111111111111111111111\
+             a02222222222222222222222222222222222222222222222222222222222222222b90100",
+            "00".repeat(256),
+            "83020000837a1200825208845f5e10008472657468c0c0\
+             a03333333333333333333333333333333333333333333333333333333333333333880102030405060708"
+        );
+        assert_eq!(stream.out().to_vec(), from_hex(&expected));
+    }
+
+    #[test]
+    fn test_block_chunks_cover_recent_blocks() {
+        let source = MemorySource::with_blocks(20);
+        let head = source.headers[19].clone();
+        let mut sink = MemorySink::default();
+        let hashes = chunk_blocks(&source, &head, 10, 1024, &mut sink).unwrap();
+        assert!(hashes.len() > 1);
+
+        let mut next_number = 10;
+        for (_, chunk) in sink.chunks.iter() {
+            let raw = decompress(chunk);
+            let rlp = Rlp::new(&raw);
+            // header of chunk describes parent of its first block.
+            let parent_number: u64 = rlp.val_at(0).unwrap();
+            let parent_hash: H256 = rlp.val_at(1).unwrap();
+            let parent_total_difficulty: U256 = rlp.val_at(2).unwrap();
+            assert_eq!(parent_number + 1, next_number);
+            let parent = &source.headers[parent_number as usize];
+            assert_eq!(parent_hash, parent.hash());
+            assert_eq!(
+                Some(parent_total_difficulty),
+                source.total_difficulty(&parent_hash)
+            );
+            next_number += rlp.item_count().unwrap() as u64 - 3;
+        }
+        assert_eq!(next_number, 20);
+    }
+}
@@ -0,0 +1,12 @@
+// Copyright 2021 Gnosis Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+#[macro_use]
+extern crate log;
+
+mod chunker;
+mod producer;
+mod source;
+
+pub use producer::SnapshotProducer;
+pub use source::{SnapshotSource, StateAccount};
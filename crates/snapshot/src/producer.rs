@@ -0,0 +1,221 @@
+// Copyright 2021 Gnosis Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    chunker::{chunk_blocks, chunk_state, ChunkSink},
+    source::SnapshotSource,
+};
+use core::{BlockNumber, Bytes, H256};
+use interfaces::snapshot::{ChunkType, ManifestData, RestorationStatus, Snapshot, SnapshotError};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Chunks are at most this big before compression.
+const PREFERRED_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+/// Number of most recent blocks that are included in snapshot.
+const SNAPSHOT_BLOCKS: u64 = 30_000;
+const MANIFEST_FILE: &str = "MANIFEST";
+/// Directory with latest finished snapshot.
+const CURRENT_DIR: &str = "current";
+/// Snapshot is written here and moved to `CURRENT_DIR` when it is finished.
+const NEXT_DIR: &str = "next";
+/// Previous snapshot is moved here while `NEXT_DIR` takes its place, and removed after.
+const OLD_DIR: &str = "old";
+
+fn io_error(err: std::io::Error) -> SnapshotError {
+    SnapshotError::Io(err.to_string())
+}
+
+/// Every chunk is stored in its own file named by its hash.
+struct DirSink {
+    dir: PathBuf,
+}
+
+impl ChunkSink for DirSink {
+    fn write_chunk(&mut self, hash: H256, chunk: &[u8]) -> Result<(), SnapshotError> {
+        fs::write(self.dir.join(format!("{:x}", hash)), chunk).map_err(io_error)
+    }
+}
+
+/// Creates snapshots in OpenEthereum format and serves chunks of the latest one.
+/// Restoration is not done here, restoration calls return `RestorationInactive`.
+pub struct SnapshotProducer {
+    source: Arc<dyn SnapshotSource>,
+    dir: PathBuf,
+    chunk_size: usize,
+    blocks: u64,
+    manifest: Mutex<Option<ManifestData>>,
+    creating: AtomicBool,
+}
+
+impl SnapshotProducer {
+    /// Loads manifest of snapshot that was created before, if there is one in `dir`.
+    pub fn new(source: Arc<dyn SnapshotSource>, dir: &Path) -> Self {
+        // interrupted swap leaves previous snapshot only in `OLD_DIR`.
+        if !dir.join(CURRENT_DIR).exists() {
+            let _ = fs::rename(dir.join(OLD_DIR), dir.join(CURRENT_DIR));
+        }
+        let manifest = fs::read(dir.join(CURRENT_DIR).join(MANIFEST_FILE))
+            .ok()
+            .and_then(|data| rlp::decode(&data).ok());
+        SnapshotProducer {
+            source,
+            dir: dir.to_path_buf(),
+            chunk_size: PREFERRED_CHUNK_SIZE,
+            blocks: SNAPSHOT_BLOCKS,
+            manifest: Mutex::new(manifest),
+            creating: AtomicBool::new(false),
+        }
+    }
+
+    fn write_snapshot(&self, block_number: BlockNumber) -> Result<ManifestData, SnapshotError> {
+        let header = self
+            .source
+            .header(block_number)
+            .ok_or(SnapshotError::BlockNotFound(block_number))?;
+        info!("Snapshot: creating snapshot at block {}", block_number);
+        let next = self.dir.join(NEXT_DIR);
+        let _ = fs::remove_dir_all(&next);
+        fs::create_dir_all(&next).map_err(io_error)?;
+        let mut sink = DirSink { dir: next.clone() };
+
+        let state_hashes = chunk_state(
+            &*self.source,
+            &header.state_root,
+            self.chunk_size,
+            &mut sink,
+        )?;
+        let block_hashes = chunk_blocks(
+            &*self.source,
+            &header,
+            self.blocks,
+            self.chunk_size,
+            &mut sink,
+        )?;
+        let manifest = ManifestData {
+            version: 2,
+            state_hashes,
+            block_hashes,
+            state_root: header.state_root,
+            block_number,
+            block_hash: header.hash(),
+        };
+        fs::write(next.join(MANIFEST_FILE), rlp::encode(&manifest)).map_err(io_error)?;
+
+        // chunks of previous snapshot are not served anymore, it is kept until new one is
+        // in place so that there is always a snapshot on disk.
+        let current = self.dir.join(CURRENT_DIR);
+        let old = self.dir.join(OLD_DIR);
+        let _ = fs::remove_dir_all(&old);
+        if current.exists() {
+            fs::rename(&current, &old).map_err(io_error)?;
+        }
+        if let Err(err) = fs::rename(&next, &current) {
+            let _ = fs::rename(&old, &current);
+            return Err(io_error(err));
+        }
+        let _ = fs::remove_dir_all(&old);
+        info!(
+            "Snapshot: created snapshot at block {} with {} state and {} block chunks",
+            block_number,
+            manifest.state_hashes.len(),
+            manifest.block_hashes.len()
+        );
+        *self.manifest.lock().unwrap() = Some(manifest.clone());
+        Ok(manifest)
+    }
+}
+
+impl Snapshot for SnapshotProducer {
+    fn create_snapshot(&self, block_number: BlockNumber) -> Result<ManifestData, SnapshotError> {
+        if self.creating.swap(true, Ordering::SeqCst) {
+            return Err(SnapshotError::SnapshotInProgress);
+        }
+        let result = self.write_snapshot(block_number);
+        if let Err(ref err) = result {
+            error!(
+                "Snapshot: creation at block {} failed: {}",
+                block_number, err
+            );
+        }
+        self.creating.store(false, Ordering::SeqCst);
+        result
+    }
+
+    fn manifest(&self) -> Option<ManifestData> {
+        self.manifest.lock().unwrap().clone()
+    }
+
+    fn status(&self) -> RestorationStatus {
+        RestorationStatus::Inactive
+    }
+
+    fn chunk(&self, hash: H256) -> Option<Bytes> {
+        fs::read(self.dir.join(CURRENT_DIR).join(format!("{:x}", hash))).ok()
+    }
+
+    fn begin_restoration(&self, _manifest: &ManifestData) -> Result<(), SnapshotError> {
+        Err(SnapshotError::RestorationInactive)
+    }
+
+    fn abort_restoration(&self) {}
+
+    fn restore_chunk(
+        &self,
+        _hash: H256,
+        _chunk: Bytes,
+        _chunk_type: ChunkType,
+    ) -> Result<(), SnapshotError> {
+        Err(SnapshotError::RestorationInactive)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunker::{keccak256, tests::MemorySource};
+
+    #[test]
+    fn test_create_snapshot_and_serve_chunks() {
+        let dir = std::env::temp_dir().join(format!("reth-snapshot-{}", std::process::id()));
+        let mut source = MemorySource::with_blocks(10);
+        source.insert_account(1, Some(vec![0x60; 10]), 5);
+        source.insert_account(2, None, 0);
+        let producer = SnapshotProducer::new(Arc::new(source), &dir);
+        assert!(producer.manifest().is_none());
+
+        let manifest = producer.create_snapshot(9).unwrap();
+        assert_eq!(manifest.block_number, 9);
+        assert_eq!(manifest.state_hashes.len(), 1);
+        assert_eq!(manifest.block_hashes.len(), 1);
+        for hash in manifest
+            .state_hashes
+            .iter()
+            .chain(manifest.block_hashes.iter())
+        {
+            assert_eq!(keccak256(&producer.chunk(*hash).unwrap()), *hash);
+        }
+        assert!(producer.create_snapshot(20).is_err());
+
+        let reloaded = SnapshotProducer::new(Arc::new(MemorySource::default()), &dir);
+        assert_eq!(reloaded.manifest(), Some(manifest.clone()));
+
+        // newer snapshot replaces previous one.
+        let newer = producer.create_snapshot(5).unwrap();
+        assert_eq!(newer.block_number, 5);
+        assert!(!dir.join(OLD_DIR).exists());
+        assert_eq!(producer.manifest(), Some(newer.clone()));
+
+        // interrupted swap is recovered from previous snapshot.
+        fs::rename(dir.join(CURRENT_DIR), dir.join(OLD_DIR)).unwrap();
+        let reloaded = SnapshotProducer::new(Arc::new(MemorySource::default()), &dir);
+        assert_eq!(reloaded.manifest(), Some(newer));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,35 @@
+// Copyright 2021 Gnosis Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+use core::{BlockBody, BlockHeader, BlockNumber, Bytes, H256, U256};
+
+/// Account as it is stored in state trie.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StateAccount {
+    pub nonce: U256,
+    pub balance: U256,
+    pub storage_root: H256,
+    pub code_hash: H256,
+}
+
+/// Read access to state and chain that snapshot is taken from.
+pub trait SnapshotSource: Send + Sync {
+    /// Canonical header with given number.
+    fn header(&self, number: BlockNumber) -> Option<BlockHeader>;
+    fn body(&self, hash: &H256) -> Option<BlockBody>;
+    /// Rlp encoded list of block receipts.
+    fn receipts(&self, hash: &H256) -> Option<Bytes>;
+    fn total_difficulty(&self, hash: &H256) -> Option<U256>;
+
+    /// Accounts of state with given root ordered by hashed address, starting after `after`.
+    fn accounts(
+        &self,
+        state_root: &H256,
+        after: Option<H256>,
+        limit: usize,
+    ) -> Vec<(H256, StateAccount)>;
+    /// Storage of account ordered by hashed key, starting after `after`. Values are rlp encoded.
+    fn storage(&self, storage_root: &H256, after: Option<H256>, limit: usize)
+        -> Vec<(H256, Bytes)>;
+    fn code(&self, code_hash: &H256) -> Option<Bytes>;
+}
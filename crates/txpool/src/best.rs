@@ -0,0 +1,96 @@
+// Copyright 2021 Gnosis Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+use core::{Address, Transaction, U256};
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashSet},
+    sync::Arc,
+};
+
+/// Next pending transaction of sender, ordered by effective tip.
+struct BestTx {
+    tip: U256,
+    tx: Arc<Transaction>,
+    sender: usize,
+    index: usize,
+}
+
+impl PartialEq for BestTx {
+    fn eq(&self, other: &Self) -> bool {
+        self.tip == other.tip
+    }
+}
+
+impl Eq for BestTx {}
+
+impl PartialOrd for BestTx {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for BestTx {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.tip.cmp(&other.tip)
+    }
+}
+
+/// Iterator over pending transactions for block builder, transactions with the highest
+/// effective tip come first and transactions of one sender are in nonce order.
+pub struct BestTransactions {
+    base_fee: U256,
+    pending: Vec<Vec<Arc<Transaction>>>,
+    best: BinaryHeap<BestTx>,
+    invalid: HashSet<Address>,
+}
+
+impl BestTransactions {
+    pub(crate) fn new(base_fee: U256, pending: Vec<Vec<Arc<Transaction>>>) -> Self {
+        let mut best = BestTransactions {
+            base_fee,
+            pending,
+            best: BinaryHeap::new(),
+            invalid: HashSet::new(),
+        };
+        for sender in 0..best.pending.len() {
+            best.push(sender, 0);
+        }
+        best
+    }
+
+    /// Transaction failed in block execution, following transactions of its sender are skipped.
+    pub fn mark_invalid(&mut self, tx: &Transaction) {
+        if let Some((sender, _)) = tx.author() {
+            self.invalid.insert(sender);
+        }
+    }
+
+    fn push(&mut self, sender: usize, index: usize) {
+        if let Some(tx) = self.pending[sender].get(index) {
+            self.best.push(BestTx {
+                tip: tx.effective_tip(self.base_fee).unwrap_or_default(),
+                tx: tx.clone(),
+                sender,
+                index,
+            });
+        }
+    }
+}
+
+impl Iterator for BestTransactions {
+    type Item = Arc<Transaction>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let next = self.best.pop()?;
+            if let Some((sender, _)) = next.tx.author() {
+                if self.invalid.contains(&sender) {
+                    continue;
+                }
+            }
+            self.push(next.sender, next.index + 1);
+            return Some(next.tx);
+        }
+    }
+}
@@ -0,0 +1,745 @@
+// Copyright 2021 Gnosis Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{best::BestTransactions, pool::is_bumped, sidecar_store::SidecarStore};
+use core::{
+    transaction::{BlobSidecar, GAS_PER_BLOB},
+    Address, Transaction, H256, U256,
+};
+use interfaces::transaction_pool::{DropReason, Error};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    sync::Arc,
+};
+
+/// Number of blocks that can still be removed by reorg, sidecars of blob transactions mined in
+/// them are kept so that transactions can return to pool.
+const REORG_WINDOW: u64 = 64;
+
+/// Blob transaction that was accepted and transactions that left pool because of it.
+pub struct BlobInsert {
+    pub tx: Arc<Transaction>,
+    pub replaced: Option<Arc<Transaction>>,
+    pub evicted: Vec<Arc<Transaction>>,
+}
+
+/// Maximal amount that blob transaction can take from sender balance.
+fn max_cost(tx: &Transaction) -> U256 {
+    let blob_gas = U256::from(GAS_PER_BLOB) * tx.blob_versioned_hashes().len();
+    tx.gas_limit
+        .saturating_mul(tx.gas_price())
+        .saturating_add(tx.value)
+        .saturating_add(blob_gas.saturating_mul(tx.max_fee_per_blob_gas()))
+}
+
+/// Checks KZG proofs of sidecar blobs against their commitments.
+pub trait BlobVerifier: Send + Sync {
+    fn verify(&self, sidecar: &BlobSidecar) -> bool;
+}
+
+/// Verifier used until there is KZG library with trusted setup in tree. No proof can be checked
+/// without it, so every sidecar is rejected instead of storing and serving blobs that may be junk.
+pub struct NoKzgVerifier;
+
+impl BlobVerifier for NoKzgVerifier {
+    fn verify(&self, _sidecar: &BlobSidecar) -> bool {
+        false
+    }
+}
+
+/// Blob transactions are kept apart from regular ones. Sender's transactions have no nonce gaps,
+/// so that every transaction can be included once blob fee allows it, and sidecars are on disk.
+pub struct BlobPool {
+    max_transactions: usize,
+    max_blobs_per_sender: usize,
+    price_bump: u64,
+    by_hash: HashMap<H256, Arc<Transaction>>,
+    /// Transactions of sender by nonce, first one has nonce of sender account.
+    senders: HashMap<Address, BTreeMap<u64, Arc<Transaction>>>,
+    /// Blob base fee of next block.
+    blob_base_fee: U256,
+    store: SidecarStore,
+    verifier: Arc<dyn BlobVerifier>,
+    /// Transactions with sidecars still on disk by number of block that included them.
+    mined: BTreeMap<u64, Vec<H256>>,
+}
+
+impl BlobPool {
+    pub fn new(
+        sidecars: PathBuf,
+        max_transactions: usize,
+        max_blobs_per_sender: usize,
+        price_bump: u64,
+    ) -> Self {
+        BlobPool {
+            max_transactions,
+            max_blobs_per_sender,
+            price_bump,
+            by_hash: HashMap::new(),
+            senders: HashMap::new(),
+            blob_base_fee: U256::zero(),
+            store: SidecarStore::new(sidecars),
+            verifier: Arc::new(NoKzgVerifier),
+            mined: BTreeMap::new(),
+        }
+    }
+
+    pub fn set_verifier(&mut self, verifier: Arc<dyn BlobVerifier>) {
+        self.verifier = verifier;
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_hash.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_hash.is_empty()
+    }
+
+    pub fn contains_sender(&self, sender: &Address) -> bool {
+        self.senders.contains_key(sender)
+    }
+
+    pub fn sender_count(&self) -> usize {
+        self.senders.len()
+    }
+
+    /// Encoded size of transactions, sidecars on disk are not counted.
+    pub fn bytes(&self) -> usize {
+        self.by_hash.values().map(|tx| tx.encode().len()).sum()
+    }
+
+    pub fn find(&self, hash: &H256) -> Option<Arc<Transaction>> {
+        self.by_hash.get(hash).cloned()
+    }
+
+    pub fn filter(&self, sender: &Address) -> Vec<Arc<Transaction>> {
+        self.senders
+            .get(sender)
+            .map(|queue| queue.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn sidecar(&self, hash: &H256) -> Option<BlobSidecar> {
+        if !self.by_hash.contains_key(hash) {
+            return None;
+        }
+        self.store.get(hash)
+    }
+
+    pub fn next_nonce(&self, sender: &Address) -> Option<u64> {
+        let (nonce, _) = self.senders.get(sender)?.iter().next_back()?;
+        Some(nonce + 1)
+    }
+
+    pub fn blob_base_fee(&self) -> U256 {
+        self.blob_base_fee
+    }
+
+    pub fn set_blob_base_fee(&mut self, blob_base_fee: U256) {
+        self.blob_base_fee = blob_base_fee;
+    }
+
+    /// Transaction can be included in next block with given base fee.
+    pub fn is_executable(&self, tx: &Transaction, base_fee: U256) -> bool {
+        tx.gas_price() >= base_fee && tx.max_fee_per_blob_gas() >= self.blob_base_fee
+    }
+
+    /// Executable transactions, of every sender only those before first one that does not
+    /// pay base fee or blob base fee.
+    pub fn best_transactions(&self, base_fee: U256) -> BestTransactions {
+        let pending = self
+            .senders
+            .values()
+            .map(|queue| {
+                queue
+                    .values()
+                    .take_while(|tx| self.is_executable(tx, base_fee))
+                    .cloned()
+                    .collect::<Vec<_>>()
+            })
+            .filter(|txs| !txs.is_empty())
+            .collect();
+        BestTransactions::new(base_fee, pending)
+    }
+
+    /// Number of executable transactions and of the ones waiting for lower fees.
+    pub fn counts(&self, base_fee: U256) -> (usize, usize) {
+        let pending = self
+            .senders
+            .values()
+            .map(|queue| {
+                queue
+                    .values()
+                    .take_while(|tx| self.is_executable(tx, base_fee))
+                    .count()
+            })
+            .sum::<usize>();
+        (pending, self.len() - pending)
+    }
+
+    /// Max fee, tip and blob fee of replacement need to be higher by price bump.
+    fn is_replacement_bumped(&self, old: &Transaction, new: &Transaction) -> bool {
+        let bump = self.price_bump;
+        is_bumped(old.gas_price(), new.gas_price(), bump)
+            && is_bumped(
+                old.max_priority_fee_per_gas(),
+                new.max_priority_fee_per_gas(),
+                bump,
+            )
+            && is_bumped(old.max_fee_per_blob_gas(), new.max_fee_per_blob_gas(), bump)
+    }
+
+    /// Last transaction of sender with the lowest blob fee, it is evicted first.
+    fn worst_transaction(&self, except: &Address) -> Option<Arc<Transaction>> {
+        self.senders
+            .iter()
+            .filter(|(sender, _)| *sender != except)
+            .filter_map(|(_, queue)| queue.values().next_back())
+            .min_by_key(|tx| tx.max_fee_per_blob_gas())
+            .cloned()
+    }
+
+    /// Sender and nonce of account are already known, gas price and limit checks are done
+    /// by pool for both kinds of transactions.
+    pub fn insert(
+        &mut self,
+        tx: Transaction,
+        sender: Address,
+        sidecar: BlobSidecar,
+        account_nonce: u64,
+        balance: U256,
+    ) -> Result<BlobInsert, Error> {
+        let hash = tx.hash();
+        if self.by_hash.contains_key(&hash) {
+            return Err(Error::AlreadyKnown);
+        }
+        let blobs = tx.blob_versioned_hashes().len();
+        if blobs == 0
+            || sidecar.blobs.len() != blobs
+            || sidecar.proofs.len() != blobs
+            || !sidecar.has_valid_sizes()
+            || sidecar.versioned_hashes() != tx.blob_versioned_hashes()
+            || !self.verifier.verify(&sidecar)
+        {
+            return Err(Error::InvalidSidecar);
+        }
+        let nonce = tx.nonce.as_u64();
+        if nonce < account_nonce {
+            return Err(Error::StaleNonce);
+        }
+        let empty = BTreeMap::new();
+        let queue = self.senders.get(&sender).unwrap_or(&empty);
+        let next_nonce = account_nonce + queue.len() as u64;
+        if nonce > next_nonce {
+            return Err(Error::NonceGap);
+        }
+        let old = queue.get(&nonce);
+        if let Some(old) = old {
+            if !self.is_replacement_bumped(old, &tx) {
+                return Err(Error::ReplacementUnderpriced);
+            }
+        }
+        let other = queue
+            .values()
+            .filter(|queued| queued.nonce.as_u64() != nonce);
+        let other_blobs: usize = other
+            .clone()
+            .map(|tx| tx.blob_versioned_hashes().len())
+            .sum();
+        if other_blobs + blobs > self.max_blobs_per_sender {
+            return Err(Error::BlobLimitReached);
+        }
+        let cost = other.fold(max_cost(&tx), |cost, tx| cost.saturating_add(max_cost(tx)));
+        if cost > balance {
+            return Err(Error::InsufficientFunds);
+        }
+
+        let worst = if old.is_none() && self.by_hash.len() >= self.max_transactions {
+            match self.worst_transaction(&sender) {
+                Some(worst) if worst.max_fee_per_blob_gas() < tx.max_fee_per_blob_gas() => {
+                    Some(worst.hash())
+                }
+                _ => return Err(Error::Underpriced),
+            }
+        } else {
+            None
+        };
+
+        let tx = Arc::new(tx);
+        let replaced = self
+            .senders
+            .entry(sender)
+            .or_default()
+            .insert(nonce, tx.clone());
+        self.by_hash.insert(hash, tx.clone());
+        // transaction is announced to peers, so it stays only if its sidecar can be served.
+        if let Err(err) = self.store.insert(&hash, &sidecar) {
+            error!("Txpool: could not save sidecar of {}: {}", hash, err);
+            self.by_hash.remove(&hash);
+            let queue = self
+                .senders
+                .get_mut(&sender)
+                .expect("Transaction is just inserted");
+            match replaced {
+                Some(replaced) => {
+                    queue.insert(nonce, replaced);
+                }
+                None => {
+                    queue.remove(&nonce);
+                    if queue.is_empty() {
+                        self.senders.remove(&sender);
+                    }
+                }
+            }
+            return Err(Error::SidecarNotStored);
+        }
+        if let Some(ref replaced) = replaced {
+            self.by_hash.remove(&replaced.hash());
+            self.store.remove(&replaced.hash());
+        }
+        let evicted = match worst {
+            Some(worst) => {
+                debug!("Blob pool is full, evicting {}", worst);
+                self.remove(&[worst])
+            }
+            None => Vec::new(),
+        };
+        Ok(BlobInsert {
+            tx,
+            replaced,
+            evicted,
+        })
+    }
+
+    /// Removes transactions without touching other transactions of their senders.
+    pub fn take(&mut self, hashes: &[H256]) -> Vec<Arc<Transaction>> {
+        let removed = self.detach(hashes);
+        for tx in removed.iter() {
+            self.store.remove(&tx.hash());
+        }
+        removed
+    }
+
+    /// Takes transactions included in block `number`, their sidecars stay on disk until block
+    /// is out of reorg window. Sidecars of blocks that left the window are removed.
+    pub fn take_mined(&mut self, hashes: &[H256], number: u64) -> Vec<Arc<Transaction>> {
+        let mined = self.detach(hashes);
+        if !mined.is_empty() {
+            self.mined
+                .entry(number)
+                .or_default()
+                .extend(mined.iter().map(|tx| tx.hash()));
+        }
+        let kept = self.mined.split_off(&number.saturating_sub(REORG_WINDOW));
+        for hash in std::mem::replace(&mut self.mined, kept).values().flatten() {
+            self.store.remove(hash);
+        }
+        mined
+    }
+
+    /// Sidecar of transaction mined in block that is removed by reorg. It is taken from disk,
+    /// transaction needs to be inserted again with it.
+    pub fn take_mined_sidecar(&mut self, hash: &H256) -> Option<BlobSidecar> {
+        let number = self
+            .mined
+            .iter()
+            .find(|(_, hashes)| hashes.contains(hash))
+            .map(|(number, _)| *number)?;
+        if let Some(hashes) = self.mined.get_mut(&number) {
+            hashes.retain(|mined| mined != hash);
+            if hashes.is_empty() {
+                self.mined.remove(&number);
+            }
+        }
+        let sidecar = self.store.get(hash);
+        self.store.remove(hash);
+        sidecar
+    }
+
+    /// Removes transactions from pool and keeps their sidecars on disk.
+    fn detach(&mut self, hashes: &[H256]) -> Vec<Arc<Transaction>> {
+        let mut removed = Vec::new();
+        for hash in hashes {
+            let tx = match self.by_hash.remove(hash) {
+                Some(tx) => tx,
+                None => continue,
+            };
+            let sender = tx
+                .author()
+                .expect("Pool contains only signed transactions")
+                .0;
+            if let Some(queue) = self.senders.get_mut(&sender) {
+                queue.remove(&tx.nonce.as_u64());
+                if queue.is_empty() {
+                    self.senders.remove(&sender);
+                }
+            }
+            removed.push(tx);
+        }
+        removed
+    }
+
+    /// Removes transactions together with following transactions of their senders, as those
+    /// can't be included anymore.
+    pub fn remove(&mut self, hashes: &[H256]) -> Vec<Arc<Transaction>> {
+        let mut removed = Vec::new();
+        for hash in hashes {
+            let tx = match self.by_hash.get(hash) {
+                Some(tx) => tx.clone(),
+                None => continue,
+            };
+            let sender = tx
+                .author()
+                .expect("Pool contains only signed transactions")
+                .0;
+            let following: Vec<H256> = self
+                .senders
+                .get(&sender)
+                .map(|queue| {
+                    queue
+                        .range(tx.nonce.as_u64()..)
+                        .map(|(_, tx)| tx.hash())
+                        .collect()
+                })
+                .unwrap_or_default();
+            removed.extend(self.take(&following));
+        }
+        removed
+    }
+
+    /// Checks transactions of sender against account in new state. Transactions with mined
+    /// nonces are removed, and from first one that sender can't afford all following.
+    pub fn revalidate(
+        &mut self,
+        sender: &Address,
+        account_nonce: u64,
+        balance: U256,
+    ) -> Vec<(Arc<Transaction>, DropReason)> {
+        let queue = match self.senders.get(sender) {
+            Some(queue) => queue,
+            None => return Vec::new(),
+        };
+        let stale: Vec<H256> = queue
+            .range(..account_nonce)
+            .map(|(_, tx)| tx.hash())
+            .collect();
+        let mut cost = U256::zero();
+        let unaffordable = queue.range(account_nonce..).find_map(|(_, tx)| {
+            cost = cost.saturating_add(max_cost(tx));
+            Some(tx.hash()).filter(|_| cost > balance)
+        });
+        // gap after stale transactions means that none of remaining can be included.
+        let gap = queue
+            .range(account_nonce..)
+            .next()
+            .filter(|(nonce, _)| **nonce != account_nonce)
+            .map(|(_, tx)| tx.hash());
+
+        let mut dropped = Vec::new();
+        for tx in self.take(&stale) {
+            dropped.push((tx, DropReason::StaleNonce));
+        }
+        for tx in self.remove(&gap.into_iter().collect::<Vec<_>>()) {
+            dropped.push((tx, DropReason::StaleNonce));
+        }
+        for tx in self.remove(&unaffordable.into_iter().collect::<Vec<_>>()) {
+            dropped.push((tx, DropReason::InsufficientFunds));
+        }
+        dropped
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use core::transaction::{blob_sidecar::BYTES_PER_BLOB, BlobPayload, CallType, TypePayload};
+    use crypto::publickey::{Generator, KeyPair, Random};
+
+    /// Test sidecars have no valid proofs, verification is skipped for them.
+    pub struct AcceptBlobs;
+
+    impl BlobVerifier for AcceptBlobs {
+        fn verify(&self, _sidecar: &BlobSidecar) -> bool {
+            true
+        }
+    }
+
+    fn sidecar(blobs: u8) -> BlobSidecar {
+        BlobSidecar {
+            blobs: (0..blobs).map(|blob| vec![blob; BYTES_PER_BLOB]).collect(),
+            commitments: (0..blobs).map(|blob| vec![blob; 48]).collect(),
+            proofs: (0..blobs).map(|blob| vec![blob; 48]).collect(),
+        }
+    }
+
+    /// All fees grow with blob fee, so that replacement with higher blob fee is bumped.
+    fn blob_tx(key: &KeyPair, nonce: u64, blob_fee: u64, sidecar: &BlobSidecar) -> Transaction {
+        let mut tx = Transaction::default();
+        tx.chain_id = Some(100);
+        tx.nonce = nonce.into();
+        tx.gas_limit = 21_000.into();
+        tx.to = CallType::CallMessage(Address::from_low_u64_be(1));
+        tx.type_payload = TypePayload::Blob(BlobPayload {
+            max_priority_fee_per_gas: blob_fee.into(),
+            max_fee_per_gas: (10 * blob_fee).into(),
+            access_list: Vec::new(),
+            max_fee_per_blob_gas: blob_fee.into(),
+            blob_versioned_hashes: sidecar.versioned_hashes(),
+        });
+        tx.sign(key.secret());
+        tx
+    }
+
+    fn blob_pool(name: &str, max_transactions: usize) -> BlobPool {
+        let dir = std::env::temp_dir().join(format!("reth-{}-{}", name, std::process::id()));
+        let mut pool = BlobPool::new(dir, max_transactions, 4, 10);
+        pool.set_verifier(Arc::new(AcceptBlobs));
+        pool
+    }
+
+    #[test]
+    fn test_transaction_is_not_kept_without_stored_sidecar() {
+        let dir = std::env::temp_dir().join(format!("reth-blobs-store-{}", std::process::id()));
+        let mut pool = BlobPool::new(dir.clone(), 10, 4, 10);
+        pool.set_verifier(Arc::new(AcceptBlobs));
+        let key = Random.generate();
+        let first = blob_tx(&key, 0, 5, &sidecar(1));
+        let first = pool
+            .insert(first, key.address(), sidecar(1), 0, U256::MAX)
+            .ok()
+            .unwrap()
+            .tx;
+
+        // sidecars can't be written anymore.
+        std::fs::remove_dir_all(&dir).unwrap();
+        let replacement = blob_tx(&key, 0, 6, &sidecar(1));
+        assert_eq!(
+            pool.insert(replacement, key.address(), sidecar(1), 0, U256::MAX)
+                .err(),
+            Some(Error::SidecarNotStored)
+        );
+        let next = blob_tx(&key, 1, 5, &sidecar(1));
+        assert_eq!(
+            pool.insert(next, key.address(), sidecar(1), 0, U256::MAX)
+                .err(),
+            Some(Error::SidecarNotStored)
+        );
+        assert_eq!(pool.len(), 1);
+        assert!(pool.by_hash.contains_key(&first.hash()));
+        assert_eq!(pool.senders.get(&key.address()).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_mined_sidecars_are_kept_for_reorg_window() {
+        let mut pool = blob_pool("blobs-mined", 10);
+        let (a, b) = (Random.generate(), Random.generate());
+        let a_tx = pool
+            .insert(
+                blob_tx(&a, 0, 5, &sidecar(1)),
+                a.address(),
+                sidecar(1),
+                0,
+                U256::MAX,
+            )
+            .ok()
+            .unwrap()
+            .tx;
+        let b_tx = pool
+            .insert(
+                blob_tx(&b, 0, 5, &sidecar(2)),
+                b.address(),
+                sidecar(2),
+                0,
+                U256::MAX,
+            )
+            .ok()
+            .unwrap()
+            .tx;
+        assert_eq!(pool.take_mined(&[a_tx.hash(), b_tx.hash()], 10).len(), 2);
+        assert!(pool.is_empty());
+        assert_eq!(pool.sidecar(&a_tx.hash()), None);
+        assert_eq!(pool.take_mined_sidecar(&a_tx.hash()), Some(sidecar(1)));
+        assert_eq!(pool.take_mined_sidecar(&a_tx.hash()), None);
+
+        // block 10 is out of reorg window at this head.
+        pool.take_mined(&[], 10 + REORG_WINDOW + 1);
+        assert_eq!(pool.take_mined_sidecar(&b_tx.hash()), None);
+    }
+
+    #[test]
+    fn test_unverified_sidecar_is_rejected() {
+        let dir = std::env::temp_dir().join(format!("reth-blobs-kzg-{}", std::process::id()));
+        let mut pool = BlobPool::new(dir, 10, 4, 10);
+        let key = Random.generate();
+        let tx = blob_tx(&key, 0, 5, &sidecar(1));
+        assert_eq!(
+            pool.insert(tx, key.address(), sidecar(1), 0, U256::MAX)
+                .err(),
+            Some(Error::InvalidSidecar)
+        );
+        assert!(pool.is_empty());
+    }
+
+    #[test]
+    fn test_insert_checks_sidecar_nonce_and_blob_limit() {
+        let mut pool = blob_pool("blobs-insert", 10);
+        let key = Random.generate();
+        let sender = key.address();
+        let balance = U256::from(u64::MAX);
+
+        let tx = blob_tx(&key, 0, 5, &sidecar(2));
+        assert_eq!(
+            pool.insert(tx.clone(), sender, sidecar(1), 0, balance)
+                .err(),
+            Some(Error::InvalidSidecar)
+        );
+        let mut short_blob = sidecar(2);
+        short_blob.blobs[1].pop();
+        assert_eq!(
+            pool.insert(tx.clone(), sender, short_blob, 0, balance)
+                .err(),
+            Some(Error::InvalidSidecar)
+        );
+        let mut long_proof = sidecar(2);
+        long_proof.proofs[0].push(0);
+        assert_eq!(
+            pool.insert(tx.clone(), sender, long_proof, 0, balance)
+                .err(),
+            Some(Error::InvalidSidecar)
+        );
+        let inserted = pool
+            .insert(tx, sender, sidecar(2), 0, balance)
+            .ok()
+            .unwrap();
+        assert_eq!(pool.sidecar(&inserted.tx.hash()), Some(sidecar(2)));
+
+        let gapped = blob_tx(&key, 2, 5, &sidecar(1));
+        assert_eq!(
+            pool.insert(gapped, sender, sidecar(1), 0, balance).err(),
+            Some(Error::NonceGap)
+        );
+        let too_many = blob_tx(&key, 1, 5, &sidecar(3));
+        assert_eq!(
+            pool.insert(too_many, sender, sidecar(3), 0, balance).err(),
+            Some(Error::BlobLimitReached)
+        );
+        let next = blob_tx(&key, 1, 5, &sidecar(2));
+        assert!(pool.insert(next, sender, sidecar(2), 0, balance).is_ok());
+        assert_eq!(pool.next_nonce(&sender), Some(2));
+
+        // replacement needs to bump blob fee too
+        let replacement = blob_tx(&key, 1, 5, &sidecar(1));
+        assert_eq!(
+            pool.insert(replacement, sender, sidecar(1), 0, balance)
+                .err(),
+            Some(Error::ReplacementUnderpriced)
+        );
+        let replacement = blob_tx(&key, 1, 6, &sidecar(1));
+        let inserted = pool
+            .insert(replacement, sender, sidecar(1), 0, balance)
+            .ok()
+            .unwrap();
+        let replaced = inserted.replaced.unwrap();
+        assert_eq!(pool.sidecar(&replaced.hash()), None);
+        assert_eq!(pool.len(), 2);
+
+        // removing first transaction removes the one after it
+        let first = pool.filter(&sender)[0].hash();
+        assert_eq!(pool.remove(&[first]).len(), 2);
+        assert!(!pool.contains_sender(&sender));
+    }
+
+    #[test]
+    fn test_replacement_with_huge_fees() {
+        let mut pool = blob_pool("blobs-huge", 10);
+        let key = Random.generate();
+        let sender = key.address();
+        let huge_tx = || {
+            let mut tx = blob_tx(&key, 0, 1, &sidecar(1));
+            tx.type_payload = TypePayload::Blob(BlobPayload {
+                max_priority_fee_per_gas: U256::MAX,
+                max_fee_per_gas: U256::MAX,
+                access_list: Vec::new(),
+                max_fee_per_blob_gas: U256::MAX,
+                blob_versioned_hashes: sidecar(1).versioned_hashes(),
+            });
+            tx.sign(key.secret());
+            tx
+        };
+        let tx = blob_tx(&key, 0, 5, &sidecar(1));
+        assert!(pool.insert(tx, sender, sidecar(1), 0, U256::MAX).is_ok());
+        assert!(pool
+            .insert(huge_tx(), sender, sidecar(1), 0, U256::MAX)
+            .is_ok());
+        let tx = blob_tx(&key, 0, 6, &sidecar(1));
+        assert_eq!(
+            pool.insert(tx, sender, sidecar(1), 0, U256::MAX).err(),
+            Some(Error::ReplacementUnderpriced)
+        );
+    }
+
+    #[test]
+    fn test_blob_fee_ordering_and_eviction() {
+        let mut pool = blob_pool("blobs-evict", 2);
+        let balance = U256::from(u64::MAX);
+        let (a, b, c) = (Random.generate(), Random.generate(), Random.generate());
+        pool.insert(
+            blob_tx(&a, 0, 3, &sidecar(1)),
+            a.address(),
+            sidecar(1),
+            0,
+            balance,
+        )
+        .ok()
+        .unwrap();
+        pool.insert(
+            blob_tx(&b, 0, 8, &sidecar(1)),
+            b.address(),
+            sidecar(1),
+            0,
+            balance,
+        )
+        .ok()
+        .unwrap();
+
+        pool.set_blob_base_fee(5.into());
+        assert_eq!(pool.counts(U256::zero()), (1, 1));
+        let best: Vec<_> = pool.best_transactions(U256::zero()).collect();
+        assert_eq!(best.len(), 1);
+        assert_eq!(best[0].author().unwrap().0, b.address());
+
+        // pool is full, transaction paying the lowest blob fee is evicted
+        assert_eq!(
+            pool.insert(
+                blob_tx(&c, 0, 2, &sidecar(1)),
+                c.address(),
+                sidecar(1),
+                0,
+                balance
+            )
+            .err(),
+            Some(Error::Underpriced)
+        );
+        let inserted = pool
+            .insert(
+                blob_tx(&c, 0, 4, &sidecar(1)),
+                c.address(),
+                sidecar(1),
+                0,
+                balance,
+            )
+            .ok()
+            .unwrap();
+        assert_eq!(inserted.evicted.len(), 1);
+        assert!(!pool.contains_sender(&a.address()));
+
+        // mined nonce and missing balance
+        let dropped = pool.revalidate(&b.address(), 1, balance);
+        assert_eq!(dropped[0].1, DropReason::StaleNonce);
+        let dropped = pool.revalidate(&c.address(), 0, U256::one());
+        assert_eq!(dropped[0].1, DropReason::InsufficientFunds);
+        assert_eq!(pool.len(), 0);
+    }
+}
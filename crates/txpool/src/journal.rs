@@ -0,0 +1,49 @@
+// Copyright 2021 Gnosis Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+use core::Transaction;
+use rlp::{Rlp, RlpStream};
+use std::{fs, path::PathBuf};
+
+/// Local transactions stored on disk so that they are not lost on restart. File contains rlp
+/// list of transactions in the same encoding as on the wire and it is rewritten on every change.
+#[derive(Debug, Clone)]
+pub struct Journal {
+    path: PathBuf,
+}
+
+impl Journal {
+    pub fn new(path: PathBuf) -> Self {
+        Journal { path }
+    }
+
+    /// Missing journal is empty, invalid one is reported and ignored.
+    pub fn load(&self) -> Vec<Transaction> {
+        let data = match fs::read(&self.path) {
+            Ok(data) => data,
+            Err(_) => return Vec::new(),
+        };
+        match Transaction::rlp_decode_list(&Rlp::new(&data)) {
+            Ok(txs) => txs,
+            Err(err) => {
+                error!("Txpool: invalid journal {:?}: {:?}", self.path, err);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Journal is written to temporary file first, so that crash while writing does not
+    /// leave it half written.
+    pub fn save(&self, txs: &[Transaction]) {
+        let mut rlp = RlpStream::new();
+        Transaction::rlp_append_list(&mut rlp, txs);
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        if let Err(err) =
+            fs::write(&tmp_path, rlp.out()).and_then(|_| fs::rename(&tmp_path, &self.path))
+        {
+            error!("Txpool: could not save journal to {:?}: {}", self.path, err);
+        }
+    }
+}
@@ -1,10 +1,19 @@
 // Copyright 2021 Gnosis Ltd.
 // SPDX-License-Identifier: Apache-2.0
 
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn it_works() {
-        assert_eq!(2 + 2, 4);
-    }
-}
+#[macro_use]
+extern crate log;
+
+mod best;
+mod blob_pool;
+mod journal;
+mod pool;
+mod sender_queue;
+mod sidecar_store;
+
+pub use best::BestTransactions;
+pub use blob_pool::{BlobPool, BlobVerifier, NoKzgVerifier};
+pub use journal::Journal;
+pub use pool::{Pool, PoolConfig};
+pub use sender_queue::SenderQueue;
+pub use sidecar_store::SidecarStore;
@@ -0,0 +1,1471 @@
+// Copyright 2021 Gnosis Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    best::BestTransactions,
+    blob_pool::{BlobPool, BlobVerifier},
+    journal::Journal,
+    sender_queue::SenderQueue,
+};
+use core::{
+    transaction::{BlobSidecar, TxType},
+    Address, Transaction, WireBlock, H256, U256, U64,
+};
+use interfaces::{
+    state::StateProvider,
+    transaction_pool::{DropReason, Error, PoolEvent, PoolStatus, TransactionPool},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    /// Maximal number of transactions in pool, worst paying transactions are evicted over it.
+    pub max_transactions: usize,
+    /// Maximal number of transactions of one sender.
+    pub max_per_sender: usize,
+    /// Replacement transaction needs to pay this many percent more than the one it replaces.
+    pub price_bump: u64,
+    /// Minimal tip that transaction needs to pay to block author.
+    pub min_gas_price: U256,
+    pub block_gas_limit: U256,
+    /// File where local transactions are kept across restarts.
+    pub journal: Option<PathBuf>,
+    /// Local transactions that are not mined are broadcast again after this interval.
+    pub rebroadcast_interval: Duration,
+    /// Remote transactions that wait for nonce gap to be filled are removed after this time.
+    pub lifetime: Duration,
+    /// Directory for blob sidecars, they are kept in its subdirectory which is cleared on
+    /// start. Blob transactions are not accepted without it.
+    pub blob_sidecars: Option<PathBuf>,
+    pub max_blob_transactions: usize,
+    pub max_blobs_per_sender: usize,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            max_transactions: 5120,
+            max_per_sender: 64,
+            price_bump: 10,
+            min_gas_price: U256::one(),
+            block_gas_limit: U256::from(30_000_000),
+            journal: None,
+            rebroadcast_interval: Duration::from_secs(300),
+            lifetime: Duration::from_secs(3 * 60 * 60),
+            blob_sidecars: None,
+            max_blob_transactions: 1024,
+            max_blobs_per_sender: 16,
+        }
+    }
+}
+
+/// In memory transaction pool. Transactions are kept per sender in nonce order.
+pub struct Pool {
+    config: PoolConfig,
+    by_hash: HashMap<H256, Arc<Transaction>>,
+    /// Time when transaction entered pool, queued ones expire after lifetime.
+    arrivals: HashMap<H256, Instant>,
+    /// Encoded size of transactions in `by_hash`.
+    bytes: usize,
+    senders: HashMap<Address, SenderQueue>,
+    /// Latest state, transactions are validated against nonce and balance of sender.
+    state: Arc<dyn StateProvider>,
+    /// Base fee of next block, zero before London.
+    base_fee: U256,
+    /// Senders of transactions submitted over RPC, all their transactions are local.
+    local_senders: HashSet<Address>,
+    /// Local transactions with time they were last broadcast.
+    locals: HashMap<H256, Instant>,
+    journal: Option<Journal>,
+    blobs: Option<BlobPool>,
+    subscribers: Vec<Sender<PoolEvent>>,
+}
+
+fn sender(tx: &Transaction) -> Address {
+    tx.author()
+        .expect("Pool contains only transactions with author")
+        .0
+}
+
+/// Transactions from blocks come without author, so it is recovered when missing.
+fn block_sender(tx: &Transaction) -> Option<Address> {
+    if let Some((author, _)) = tx.author() {
+        return Some(author);
+    }
+    let mut tx = tx.clone();
+    tx.recover_author().ok()?;
+    tx.author().map(|(author, _)| author)
+}
+
+/// Whether `new` price is higher than `old` by `price_bump` percent. Prices come from peers,
+/// so they are compared in full width to not overflow.
+pub(crate) fn is_bumped(old: U256, new: U256, price_bump: u64) -> bool {
+    new.full_mul(U256::from(100)) >= old.full_mul(U256::from(100 + price_bump))
+}
+
+/// Maximal amount that transaction can take from sender balance.
+fn max_cost(tx: &Transaction) -> U256 {
+    tx.gas_limit
+        .saturating_mul(tx.gas_price())
+        .saturating_add(tx.value)
+}
+
+impl Pool {
+    /// Local transactions from journal are inserted right away.
+    pub fn new(config: PoolConfig, state: Arc<dyn StateProvider>) -> Self {
+        let mut pool = Pool {
+            journal: config.journal.clone().map(Journal::new),
+            blobs: config.blob_sidecars.clone().map(|dir| {
+                BlobPool::new(
+                    dir,
+                    config.max_blob_transactions,
+                    config.max_blobs_per_sender,
+                    config.price_bump,
+                )
+            }),
+            config,
+            by_hash: HashMap::new(),
+            arrivals: HashMap::new(),
+            bytes: 0,
+            senders: HashMap::new(),
+            state,
+            base_fee: U256::zero(),
+            local_senders: HashSet::new(),
+            locals: HashMap::new(),
+            subscribers: Vec::new(),
+        };
+        pool.replay_journal();
+        pool
+    }
+
+    pub fn config(&self) -> &PoolConfig {
+        &self.config
+    }
+
+    /// Blob transactions are accepted only with sidecars that `verifier` accepts.
+    pub fn set_blob_verifier(&mut self, verifier: Arc<dyn BlobVerifier>) {
+        if let Some(ref mut blobs) = self.blobs {
+            blobs.set_verifier(verifier);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_hash.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_hash.is_empty()
+    }
+
+    pub fn base_fee(&self) -> U256 {
+        self.base_fee
+    }
+
+    /// Pending transactions for block builder, best effective tip first.
+    pub fn best_transactions(&self) -> BestTransactions {
+        let pending = self
+            .senders
+            .values()
+            .map(|queue| queue.pending().cloned().collect::<Vec<_>>())
+            .filter(|txs| !txs.is_empty())
+            .collect();
+        BestTransactions::new(self.base_fee, pending)
+    }
+
+    /// Executable blob transactions for block builder, ordered like `best_transactions`.
+    pub fn best_blob_transactions(&self) -> Option<BestTransactions> {
+        Some(self.blobs.as_ref()?.best_transactions(self.base_fee))
+    }
+
+    /// Hashes of blob transactions that can be included in next block.
+    fn executable_blobs(&self) -> HashSet<H256> {
+        self.best_blob_transactions()
+            .map(|best| best.map(|tx| tx.hash()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Blob transactions that can be included in next block and were not in `before`.
+    fn promoted_blobs(&self, before: HashSet<H256>) -> Vec<Arc<Transaction>> {
+        self.best_blob_transactions()
+            .map(|best| best.filter(|tx| !before.contains(&tx.hash())).collect())
+            .unwrap_or_default()
+    }
+
+    fn is_blob_sender(&self, sender: &Address) -> bool {
+        self.blobs
+            .as_ref()
+            .map_or(false, |blobs| blobs.contains_sender(sender))
+    }
+
+    /// Inserts local transactions from journal, it is called once on startup.
+    fn replay_journal(&mut self) -> usize {
+        let txs = match self.journal {
+            Some(ref journal) => journal.load(),
+            None => return 0,
+        };
+        let count = txs.len();
+        let inserted = txs
+            .into_iter()
+            .map(|tx| self.insert_one(tx, true))
+            .filter(|result| result.is_ok())
+            .count();
+        info!(
+            "Txpool: replayed {} of {} journaled local transactions",
+            inserted, count
+        );
+        // drops transactions that are not valid anymore.
+        self.save_journal();
+        inserted
+    }
+
+    /// Local transactions sorted by sender and nonce so that they can be replayed in order.
+    fn save_journal(&self) {
+        if let Some(ref journal) = self.journal {
+            let mut txs: Vec<Transaction> = self
+                .locals
+                .keys()
+                .filter_map(|hash| self.by_hash.get(hash))
+                .map(|tx| Transaction::clone(tx))
+                .collect();
+            txs.sort_by_key(|tx| (sender(tx), tx.nonce));
+            journal.save(&txs);
+        }
+    }
+
+    fn index(&mut self, tx: Arc<Transaction>) {
+        self.bytes += tx.encode().len();
+        self.arrivals.insert(tx.hash(), Instant::now());
+        self.by_hash.insert(tx.hash(), tx);
+    }
+
+    /// Removes transaction from lookups, sender queue is updated by caller.
+    fn unindex(&mut self, hash: &H256) -> Option<Arc<Transaction>> {
+        let tx = self.by_hash.remove(hash)?;
+        self.bytes -= tx.encode().len();
+        self.arrivals.remove(hash);
+        self.locals.remove(hash);
+        Some(tx)
+    }
+
+    /// Removes remote queued transactions that entered pool more than lifetime before `now`.
+    fn evict_expired_at(&mut self, now: Instant) -> Vec<Arc<Transaction>> {
+        let lifetime = self.config.lifetime;
+        let expired: Vec<H256> = self
+            .senders
+            .iter()
+            .filter(|(sender, _)| !self.local_senders.contains(sender))
+            .flat_map(|(_, queue)| queue.queued())
+            .map(|tx| tx.hash())
+            .filter(|hash| {
+                self.arrivals
+                    .get(hash)
+                    .map_or(false, |arrival| *arrival + lifetime <= now)
+            })
+            .collect();
+        if !expired.is_empty() {
+            debug!("Txpool: {} queued transactions expired", expired.len());
+        }
+        self.drop_transactions(&expired, DropReason::Expired)
+    }
+
+    fn rebroadcast_at(&mut self, now: Instant) -> Vec<Arc<Transaction>> {
+        let interval = self.config.rebroadcast_interval;
+        let mut txs = Vec::new();
+        for (hash, broadcast) in self.locals.iter_mut() {
+            if *broadcast + interval <= now {
+                *broadcast = now;
+                txs.extend(self.by_hash.get(hash).cloned());
+            }
+        }
+        txs
+    }
+
+    /// Both max fee and tip of replacement need to be higher by price bump.
+    fn is_replacement_bumped(&self, old: &Transaction, new: &Transaction) -> bool {
+        let bump = self.config.price_bump;
+        is_bumped(old.gas_price(), new.gas_price(), bump)
+            && is_bumped(
+                old.max_priority_fee_per_gas(),
+                new.max_priority_fee_per_gas(),
+                bump,
+            )
+    }
+
+    fn insert_one(&mut self, mut tx: Transaction, local: bool) -> Result<H256, Error> {
+        if let TxType::Blob = tx.txtype() {
+            return Err(Error::InvalidSidecar);
+        }
+        if !tx.has_author() {
+            tx.recover_author().map_err(|_| Error::InvalidSignature)?;
+        }
+        let sender = sender(&tx);
+        if self.is_blob_sender(&sender) {
+            return Err(Error::SenderReserved);
+        }
+        let local = local || self.local_senders.contains(&sender);
+        let hash = self.insert_checked(tx, sender, local)?;
+        if local {
+            self.local_senders.insert(sender);
+            self.locals.insert(hash, Instant::now());
+        }
+        Ok(hash)
+    }
+
+    /// Blob transactions are validated like remote ones, they are never local.
+    fn insert_blob_checked(
+        &mut self,
+        tx: Transaction,
+        sidecar: BlobSidecar,
+    ) -> Result<H256, Error> {
+        let hash = tx.hash();
+        let sender = sender(&tx);
+        if self.by_hash.contains_key(&hash) {
+            return Err(Error::AlreadyKnown);
+        }
+        if tx.max_priority_fee_per_gas() > tx.gas_price() {
+            return Err(Error::TipAboveFeeCap);
+        }
+        if tx.max_priority_fee_per_gas() < self.config.min_gas_price {
+            return Err(Error::Underpriced);
+        }
+        if tx.gas_limit > self.config.block_gas_limit {
+            return Err(Error::GasLimitExceeded);
+        }
+        if self.senders.contains_key(&sender) {
+            return Err(Error::SenderReserved);
+        }
+        let account_nonce = self.state.account_nonce(&sender).as_u64();
+        let balance = self.state.account_balance(&sender);
+        let base_fee = self.base_fee;
+        let blobs = self.blobs.as_mut().ok_or(Error::TxTypeNotSupported)?;
+        let inserted = blobs.insert(tx, sender, sidecar, account_nonce, balance)?;
+        let added = if blobs.is_executable(&inserted.tx, base_fee) {
+            PoolEvent::AddedPending(inserted.tx.clone())
+        } else {
+            PoolEvent::AddedQueued(inserted.tx.clone())
+        };
+        for tx in inserted.evicted {
+            self.notify(PoolEvent::Dropped(tx, DropReason::Evicted));
+        }
+        match inserted.replaced {
+            Some(old) => self.notify(PoolEvent::Replaced {
+                old,
+                new: inserted.tx,
+            }),
+            None => self.notify(added),
+        }
+        Ok(hash)
+    }
+
+    /// Local transactions don't need to pay minimal gas price.
+    fn insert_checked(
+        &mut self,
+        tx: Transaction,
+        sender: Address,
+        local: bool,
+    ) -> Result<H256, Error> {
+        let hash = tx.hash();
+        if self.by_hash.contains_key(&hash) {
+            return Err(Error::AlreadyKnown);
+        }
+        if tx.max_priority_fee_per_gas() > tx.gas_price() {
+            return Err(Error::TipAboveFeeCap);
+        }
+        if !local && tx.max_priority_fee_per_gas() < self.config.min_gas_price {
+            return Err(Error::Underpriced);
+        }
+        if tx.gas_limit > self.config.block_gas_limit {
+            return Err(Error::GasLimitExceeded);
+        }
+        let nonce = tx.nonce.as_u64();
+        let account_nonce = match self.senders.get(&sender) {
+            Some(queue) => queue.nonce(),
+            None => self.state.account_nonce(&sender).as_u64(),
+        };
+        if nonce < account_nonce {
+            return Err(Error::StaleNonce);
+        }
+        // sender needs to afford all of its transactions, the replaced one is not counted.
+        let pooled_cost = self
+            .senders
+            .get(&sender)
+            .map(|queue| {
+                queue
+                    .iter()
+                    .filter(|queued| queued.nonce != tx.nonce)
+                    .fold(U256::zero(), |cost, tx| cost.saturating_add(max_cost(tx)))
+            })
+            .unwrap_or_default();
+        if pooled_cost.saturating_add(max_cost(&tx)) > self.state.account_balance(&sender) {
+            return Err(Error::InsufficientFunds);
+        }
+
+        if let Some(queue) = self.senders.get(&sender) {
+            if let Some(old) = queue.get(nonce) {
+                if !self.is_replacement_bumped(old, &tx) {
+                    return Err(Error::ReplacementUnderpriced);
+                }
+                let tx = Arc::new(tx);
+                let old = self
+                    .senders
+                    .get_mut(&sender)
+                    .and_then(|queue| queue.insert(tx.clone()));
+                if let Some(old) = old {
+                    self.unindex(&old.hash());
+                    self.notify(PoolEvent::Replaced {
+                        old,
+                        new: tx.clone(),
+                    });
+                }
+                self.index(tx);
+                self.notify_promoted(&sender, Some(hash));
+                return Ok(hash);
+            }
+            if queue.len() >= self.config.max_per_sender {
+                return Err(Error::SenderLimitReached);
+            }
+        }
+        if self.by_hash.len() >= self.config.max_transactions {
+            // local transaction is accepted even if pool is full of local transactions.
+            match self.worst_gas_price_tx() {
+                Some(worst) if local || worst.gas_price() < tx.gas_price() => {
+                    debug!("Pool is full, evicting {}", worst.hash());
+                    self.drop_transactions(&[worst.hash()], DropReason::Evicted);
+                }
+                None if local => (),
+                _ => return Err(Error::Underpriced),
+            }
+        }
+        let tx = Arc::new(tx);
+        let base_fee = self.base_fee;
+        let queue = self
+            .senders
+            .entry(sender)
+            .or_insert_with(|| SenderQueue::new(account_nonce, base_fee));
+        queue.insert(tx.clone());
+        let event = if queue.is_pending(nonce) {
+            PoolEvent::AddedPending(tx.clone())
+        } else {
+            PoolEvent::AddedQueued(tx.clone())
+        };
+        self.index(tx);
+        self.notify(event);
+        self.notify_promoted(&sender, Some(hash));
+        Ok(hash)
+    }
+
+    /// Sends promoted transactions of sender, `added` transaction has its own event.
+    fn notify_promoted(&mut self, sender: &Address, added: Option<H256>) {
+        let promoted = match self.senders.get_mut(sender) {
+            Some(queue) => queue.take_promoted(),
+            None => return,
+        };
+        for tx in promoted {
+            if Some(tx.hash()) != added {
+                self.notify(PoolEvent::Promoted(tx));
+            }
+        }
+    }
+
+    /// Subscribers that dropped their receiver are removed.
+    fn notify(&mut self, event: PoolEvent) {
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
+    /// Removes transactions from pool without notifying subscribers.
+    fn take(&mut self, tx_hash_list: &[H256]) -> Vec<Arc<Transaction>> {
+        let mut removed = Vec::new();
+        for hash in tx_hash_list {
+            let tx = match self.unindex(hash) {
+                Some(tx) => tx,
+                None => continue,
+            };
+            let sender = sender(&tx);
+            if let Some(queue) = self.senders.get_mut(&sender) {
+                queue.remove(tx.nonce.as_u64());
+                if queue.is_empty() {
+                    self.senders.remove(&sender);
+                }
+            }
+            removed.push(tx);
+        }
+        removed
+    }
+
+    fn drop_transactions(
+        &mut self,
+        tx_hash_list: &[H256],
+        reason: DropReason,
+    ) -> Vec<Arc<Transaction>> {
+        let mut removed = self.take(tx_hash_list);
+        if let Some(ref mut blobs) = self.blobs {
+            removed.extend(blobs.remove(tx_hash_list));
+        }
+        for tx in removed.iter() {
+            self.notify(PoolEvent::Dropped(tx.clone(), reason));
+        }
+        removed
+    }
+
+    /// Removes transactions that don't satisfy predicate anymore, it gets transaction and
+    /// whether it is local.
+    fn retain<F>(&mut self, reason: DropReason, mut keep: F)
+    where
+        F: FnMut(&Transaction, bool) -> bool,
+    {
+        let locals = &self.locals;
+        let removed: Vec<H256> = self
+            .by_hash
+            .values()
+            .filter(|tx| !keep(tx, locals.contains_key(&tx.hash())))
+            .map(|tx| tx.hash())
+            .collect();
+        self.drop_transactions(&removed, reason);
+    }
+}
+
+impl TransactionPool for Pool {
+    fn raise_min_gas_price(&mut self, min_gas_price: U256) {
+        self.config.min_gas_price = min_gas_price;
+        self.retain(DropReason::Underpriced, |tx, local| {
+            local || tx.max_priority_fee_per_gas() >= min_gas_price
+        });
+    }
+
+    fn raise_block_gas_limit(&mut self, block_gas_limit: U256) {
+        self.config.block_gas_limit = block_gas_limit;
+        self.retain(DropReason::GasLimitExceeded, |tx, _| {
+            tx.gas_limit <= block_gas_limit
+        });
+    }
+
+    fn set_base_fee(&mut self, base_fee: U256) {
+        let pending = self.status().pending;
+        let blobs_before = self.executable_blobs();
+        self.base_fee = base_fee;
+        let mut promoted = Vec::new();
+        for queue in self.senders.values_mut() {
+            queue.set_base_fee(base_fee);
+            promoted.extend(queue.take_promoted());
+        }
+        promoted.extend(self.promoted_blobs(blobs_before));
+        for tx in promoted {
+            self.notify(PoolEvent::Promoted(tx));
+        }
+        debug!(
+            "Base fee set to {}, pending transactions {} -> {}",
+            base_fee,
+            pending,
+            self.status().pending
+        );
+    }
+
+    fn insert(&mut self, txs: Vec<Transaction>) -> Vec<Result<H256, Error>> {
+        let results: Vec<_> = txs
+            .into_iter()
+            .map(|tx| self.insert_one(tx, false))
+            .collect();
+        let locals = &self.locals;
+        if results
+            .iter()
+            .any(|result| matches!(result, Ok(hash) if locals.contains_key(hash)))
+        {
+            self.save_journal();
+        }
+        results
+    }
+
+    fn insert_local(&mut self, tx: Transaction) -> Result<H256, Error> {
+        let hash = self.insert_one(tx, true)?;
+        self.save_journal();
+        Ok(hash)
+    }
+
+    fn local_to_rebroadcast(&mut self) -> Vec<Arc<Transaction>> {
+        self.rebroadcast_at(Instant::now())
+    }
+
+    fn evict_expired(&mut self) -> Vec<Arc<Transaction>> {
+        self.evict_expired_at(Instant::now())
+    }
+
+    fn find(&self, tx_hash: &H256) -> Option<Arc<Transaction>> {
+        match self.by_hash.get(tx_hash) {
+            Some(tx) => Some(tx.clone()),
+            None => self.blobs.as_ref()?.find(tx_hash),
+        }
+    }
+
+    fn filter(&self, sender: &Address) -> Vec<Arc<Transaction>> {
+        match self.senders.get(sender) {
+            Some(queue) => queue.iter().cloned().collect(),
+            None => self
+                .blobs
+                .as_ref()
+                .map(|blobs| blobs.filter(sender))
+                .unwrap_or_default(),
+        }
+    }
+
+    fn remove(&mut self, tx_hash_list: &[H256]) -> Vec<Arc<Transaction>> {
+        self.drop_transactions(tx_hash_list, DropReason::Removed)
+    }
+
+    fn pending(&self, limit: usize) -> Vec<Arc<Transaction>> {
+        self.best_transactions().take(limit).collect()
+    }
+
+    fn insert_blob(&mut self, mut tx: Transaction, sidecar: BlobSidecar) -> Result<H256, Error> {
+        if !tx.has_author() {
+            tx.recover_author().map_err(|_| Error::InvalidSignature)?;
+        }
+        self.insert_blob_checked(tx, sidecar)
+    }
+
+    fn blob_sidecar(&self, tx_hash: &H256) -> Option<BlobSidecar> {
+        self.blobs.as_ref()?.sidecar(tx_hash)
+    }
+
+    fn set_blob_base_fee(&mut self, blob_base_fee: U256) {
+        let before = self.executable_blobs();
+        if let Some(ref mut blobs) = self.blobs {
+            blobs.set_blob_base_fee(blob_base_fee);
+        }
+        for tx in self.promoted_blobs(before) {
+            self.notify(PoolEvent::Promoted(tx));
+        }
+    }
+
+    fn pending_blobs(&self, limit: usize) -> Vec<Arc<Transaction>> {
+        self.best_blob_transactions()
+            .map(|best| best.take(limit).collect())
+            .unwrap_or_default()
+    }
+
+    fn worst_gas_price_tx(&self) -> Option<Arc<Transaction>> {
+        self.senders
+            .iter()
+            .filter(|(sender, _)| !self.local_senders.contains(sender))
+            .filter_map(|(_, queue)| queue.last())
+            .min_by_key(|tx| tx.gas_price())
+            .cloned()
+    }
+
+    fn next_account_nonce(&self, sender: &Address) -> U64 {
+        if let Some(queue) = self.senders.get(sender) {
+            return queue.next_nonce().into();
+        }
+        match self
+            .blobs
+            .as_ref()
+            .and_then(|blobs| blobs.next_nonce(sender))
+        {
+            Some(nonce) => nonce.into(),
+            None => self.state.account_nonce(sender),
+        }
+    }
+
+    fn set_account_nonce(&mut self, sender: &Address, nonce: U64) {
+        if self.is_blob_sender(sender) {
+            let dropped = match self.blobs {
+                Some(ref mut blobs) => blobs.revalidate(sender, nonce.as_u64(), U256::MAX),
+                None => Vec::new(),
+            };
+            for (tx, reason) in dropped {
+                self.notify(PoolEvent::Dropped(tx, reason));
+            }
+            return;
+        }
+        let stale = match self.senders.get_mut(sender) {
+            Some(queue) => queue.set_nonce(nonce.as_u64()),
+            None => return,
+        };
+        if self
+            .senders
+            .get(sender)
+            .map_or(false, SenderQueue::is_empty)
+        {
+            self.senders.remove(sender);
+        }
+        for tx in stale {
+            self.unindex(&tx.hash());
+            self.notify(PoolEvent::Dropped(tx, DropReason::StaleNonce));
+        }
+        self.notify_promoted(sender, None);
+    }
+
+    fn revalidate(&mut self, senders: &[Address]) {
+        for sender in senders {
+            if self.is_blob_sender(sender) {
+                let nonce = self.state.account_nonce(sender).as_u64();
+                let balance = self.state.account_balance(sender);
+                let dropped = match self.blobs {
+                    Some(ref mut blobs) => blobs.revalidate(sender, nonce, balance),
+                    None => Vec::new(),
+                };
+                for (tx, reason) in dropped {
+                    self.notify(PoolEvent::Dropped(tx, reason));
+                }
+                continue;
+            }
+            if !self.senders.contains_key(sender) {
+                continue;
+            }
+            let nonce = self.state.account_nonce(sender);
+            self.set_account_nonce(sender, nonce);
+            let balance = self.state.account_balance(sender);
+            // from first transaction that sender can't afford, none of them can be included.
+            let mut cost = U256::zero();
+            let unaffordable: Vec<H256> = self
+                .filter(sender)
+                .iter()
+                .skip_while(|tx| {
+                    cost = cost.saturating_add(max_cost(tx));
+                    cost <= balance
+                })
+                .map(|tx| tx.hash())
+                .collect();
+            if !unaffordable.is_empty() {
+                debug!(
+                    "Removing {} transactions that {} can't afford",
+                    unaffordable.len(),
+                    sender
+                );
+                self.drop_transactions(&unaffordable, DropReason::InsufficientFunds);
+            }
+        }
+    }
+
+    fn on_canonical_state_change(&mut self, added: &[WireBlock], removed: &[WireBlock]) {
+        let mined: HashSet<H256> = added
+            .iter()
+            .flat_map(|block| block.body.transactions.iter())
+            .map(|tx| tx.hash())
+            .collect();
+        let orphaned: Vec<Transaction> = removed
+            .iter()
+            .flat_map(|block| block.body.transactions.iter())
+            .filter(|tx| !mined.contains(&tx.hash()))
+            .cloned()
+            .collect();
+        let mined_local = mined.iter().any(|hash| self.locals.contains_key(hash));
+        let mined: Vec<H256> = mined.into_iter().collect();
+        let mut mined_txs = self.take(&mined);
+        if let Some(ref mut blobs) = self.blobs {
+            for block in added {
+                let hashes: Vec<H256> =
+                    block.body.transactions.iter().map(|tx| tx.hash()).collect();
+                mined_txs.extend(blobs.take_mined(&hashes, block.header.number));
+            }
+        }
+        for tx in mined_txs {
+            self.notify(PoolEvent::Mined(tx));
+        }
+
+        // only senders of transactions in changed blocks have new nonces, and they
+        // can move in both directions on reorg.
+        let senders: Vec<Address> = added
+            .iter()
+            .chain(removed.iter())
+            .flat_map(|block| block.body.transactions.iter())
+            .filter_map(block_sender)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        self.revalidate(&senders);
+
+        if !orphaned.is_empty() {
+            let count = orphaned.len();
+            let (blob_txs, txs): (Vec<Transaction>, Vec<Transaction>) = orphaned
+                .into_iter()
+                .partition(|tx| matches!(tx.txtype(), TxType::Blob));
+            let mut results = self.insert(txs);
+            for tx in blob_txs {
+                let sidecar = self
+                    .blobs
+                    .as_mut()
+                    .and_then(|blobs| blobs.take_mined_sidecar(&tx.hash()));
+                results.push(match sidecar {
+                    Some(sidecar) => self.insert_blob(tx, sidecar),
+                    None => Err(Error::InvalidSidecar),
+                });
+            }
+            let reinserted = results.iter().filter(|result| result.is_ok()).count();
+            debug!(
+                "Reinserted {} of {} transactions from removed blocks",
+                reinserted, count
+            );
+        }
+        if mined_local {
+            self.save_journal();
+        }
+    }
+
+    fn subscribe(&mut self) -> Receiver<PoolEvent> {
+        let (tx, rx) = channel();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    fn status(&self) -> PoolStatus {
+        let mut status = self
+            .senders
+            .values()
+            .fold(PoolStatus::default(), |mut status, queue| {
+                status.pending += queue.pending_count();
+                status.base_fee += queue.base_fee_count();
+                status.queued += queue.queued_count();
+                status
+            });
+        status.bytes = self.bytes;
+        status.senders = self.senders.len();
+        // blob transactions have no nonce gaps, they wait only for fees.
+        if let Some(ref blobs) = self.blobs {
+            let (pending, base_fee) = blobs.counts(self.base_fee);
+            status.pending += pending;
+            status.base_fee += base_fee;
+            status.bytes += blobs.bytes();
+            status.senders += blobs.sender_count();
+        }
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blob_pool::tests::AcceptBlobs;
+    use core::transaction::{
+        blob_sidecar::BYTES_PER_BLOB, BlobPayload, CallType, DynamicFeePayload, LegacyPayload,
+        TypePayload,
+    };
+    use crypto::publickey::{Generator, KeyPair, Random};
+    use std::sync::Mutex;
+
+    /// Accounts that are not set have zero nonce and enough balance for anything in tests.
+    #[derive(Default)]
+    struct TestState {
+        accounts: Mutex<HashMap<Address, (U64, U256)>>,
+    }
+
+    impl TestState {
+        fn set(&self, address: Address, nonce: u64, balance: u64) {
+            self.accounts
+                .lock()
+                .unwrap()
+                .insert(address, (nonce.into(), balance.into()));
+        }
+    }
+
+    impl StateProvider for TestState {
+        fn account_nonce(&self, address: &Address) -> U64 {
+            let accounts = self.accounts.lock().unwrap();
+            accounts
+                .get(address)
+                .map(|account| account.0)
+                .unwrap_or_default()
+        }
+
+        fn account_balance(&self, address: &Address) -> U256 {
+            let accounts = self.accounts.lock().unwrap();
+            accounts
+                .get(address)
+                .map(|account| account.1)
+                .unwrap_or_else(|| U256::from(u64::MAX))
+        }
+    }
+
+    fn new_pool(config: PoolConfig) -> Pool {
+        Pool::new(config, Arc::new(TestState::default()))
+    }
+
+    fn tx(key: &KeyPair, nonce: u64, gas_price: u64) -> Transaction {
+        let mut tx = Transaction::default();
+        tx.nonce = nonce.into();
+        tx.gas_limit = 21_000.into();
+        tx.type_payload = TypePayload::Legacy(LegacyPayload {
+            gas_price: gas_price.into(),
+        });
+        tx.sign(key.secret());
+        tx
+    }
+
+    fn dynamic_tx(key: &KeyPair, nonce: u64, max_fee: u64, tip: u64) -> Transaction {
+        fee_tx(key, nonce, max_fee.into(), tip.into())
+    }
+
+    fn fee_tx(key: &KeyPair, nonce: u64, max_fee: U256, tip: U256) -> Transaction {
+        let mut tx = Transaction::default();
+        tx.chain_id = Some(100);
+        tx.nonce = nonce.into();
+        tx.gas_limit = 21_000.into();
+        tx.type_payload = TypePayload::DynamicFee(DynamicFeePayload {
+            max_priority_fee_per_gas: tip,
+            max_fee_per_gas: max_fee,
+            access_list: Vec::new(),
+        });
+        tx.sign(key.secret());
+        tx
+    }
+
+    fn prices(txs: &[Arc<Transaction>]) -> Vec<u64> {
+        txs.iter().map(|tx| tx.gas_price().as_u64()).collect()
+    }
+
+    fn size(txs: &[Arc<Transaction>]) -> usize {
+        txs.iter().map(|tx| tx.encode().len()).sum()
+    }
+
+    #[test]
+    fn test_pending_and_queued() {
+        let state = Arc::new(TestState::default());
+        let mut pool = Pool::new(PoolConfig::default(), state.clone());
+        let key = Random.generate();
+        let sender = key.address();
+        state.set(sender, 3, 1_000_000_000);
+
+        let results = pool.insert(vec![tx(&key, 3, 10), tx(&key, 5, 10), tx(&key, 2, 10)]);
+        assert!(results[0].is_ok() && results[1].is_ok());
+        assert_eq!(results[2], Err(Error::StaleNonce));
+        assert_eq!(
+            pool.status(),
+            PoolStatus {
+                pending: 1,
+                base_fee: 0,
+                queued: 1,
+                bytes: size(&pool.filter(&sender)),
+                senders: 1,
+            }
+        );
+        assert_eq!(pool.next_account_nonce(&sender), 4.into());
+
+        assert!(pool.insert(vec![tx(&key, 4, 10)])[0].is_ok());
+        assert_eq!(pool.status().pending, 3);
+        assert_eq!(pool.next_account_nonce(&sender), 6.into());
+
+        // block with first two transactions is mined.
+        pool.set_account_nonce(&sender, 5.into());
+        assert_eq!(pool.len(), 1);
+        assert_eq!(pool.filter(&sender)[0].nonce, 5.into());
+        assert_eq!(
+            pool.insert(vec![tx(&key, 5, 10)]),
+            vec![Err(Error::AlreadyKnown)]
+        );
+    }
+
+    #[test]
+    fn test_replacement_needs_price_bump() {
+        let mut pool = new_pool(PoolConfig::default());
+        let key = Random.generate();
+        let first = pool.insert(vec![tx(&key, 0, 100)])[0].unwrap();
+
+        assert_eq!(
+            pool.insert(vec![tx(&key, 0, 109)]),
+            vec![Err(Error::ReplacementUnderpriced)]
+        );
+        let second = pool.insert(vec![tx(&key, 0, 110)])[0].unwrap();
+        assert!(pool.find(&first).is_none());
+        assert!(pool.find(&second).is_some());
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn test_replacement_with_huge_prices() {
+        let mut pool = new_pool(PoolConfig::default());
+        let key = Random.generate();
+        // zero gas limit makes any price affordable.
+        let free_tx = |nonce, max_fee, tip| {
+            let mut tx = fee_tx(&key, nonce, max_fee, tip);
+            tx.gas_limit = U256::zero();
+            tx.sign(key.secret());
+            tx
+        };
+        assert!(pool.insert(vec![dynamic_tx(&key, 0, 10, 1)])[0].is_ok());
+        assert_eq!(
+            pool.insert(vec![free_tx(0, 20.into(), U256::MAX)]),
+            vec![Err(Error::TipAboveFeeCap)]
+        );
+
+        assert!(pool.insert(vec![free_tx(0, U256::MAX, U256::MAX)])[0].is_ok());
+        assert!(pool.insert(vec![free_tx(1, U256::MAX, U256::MAX)])[0].is_ok());
+        assert_eq!(
+            pool.insert(vec![free_tx(1, U256::MAX, U256::MAX - 1)]),
+            vec![Err(Error::ReplacementUnderpriced)]
+        );
+    }
+
+    #[test]
+    fn test_pending_ordered_by_price_and_nonce() {
+        let mut pool = new_pool(PoolConfig::default());
+        let (a, b) = (Random.generate(), Random.generate());
+        pool.insert(vec![
+            tx(&a, 0, 50),
+            tx(&a, 1, 100),
+            tx(&a, 2, 5),
+            tx(&b, 0, 60),
+            tx(&b, 1, 20),
+            tx(&b, 3, 1000),
+        ]);
+        // nonce order of sender wins over price, queued transaction is not returned.
+        assert_eq!(prices(&pool.pending(10)), vec![60, 50, 100, 20, 5]);
+        assert_eq!(prices(&pool.pending(2)), vec![60, 50]);
+
+        pool.raise_min_gas_price(10.into());
+        assert_eq!(prices(&pool.pending(10)), vec![60, 50, 100, 20]);
+        pool.raise_block_gas_limit(20_000.into());
+        assert_eq!(pool.len(), 0);
+    }
+
+    #[test]
+    fn test_limits_and_eviction() {
+        let mut pool = new_pool(PoolConfig {
+            max_transactions: 3,
+            max_per_sender: 2,
+            ..Default::default()
+        });
+        let (a, b, c) = (Random.generate(), Random.generate(), Random.generate());
+        pool.insert(vec![tx(&a, 0, 30), tx(&a, 1, 10)]);
+        assert_eq!(
+            pool.insert(vec![tx(&a, 2, 100)]),
+            vec![Err(Error::SenderLimitReached)]
+        );
+        assert!(pool.insert(vec![tx(&b, 0, 20)])[0].is_ok());
+        assert_eq!(pool.worst_gas_price_tx().unwrap().gas_price(), 10.into());
+
+        assert_eq!(
+            pool.insert(vec![tx(&c, 0, 10)]),
+            vec![Err(Error::Underpriced)]
+        );
+        assert!(pool.insert(vec![tx(&c, 0, 15)])[0].is_ok());
+        assert_eq!(pool.len(), 3);
+        assert_eq!(prices(&pool.filter(&a.address())), vec![30]);
+    }
+
+    #[test]
+    fn test_base_fee_subpools() {
+        let mut pool = new_pool(PoolConfig::default());
+        let (a, b) = (Random.generate(), Random.generate());
+        pool.set_base_fee(10.into());
+        pool.insert(vec![
+            dynamic_tx(&a, 0, 100, 3),
+            dynamic_tx(&a, 1, 8, 8),
+            dynamic_tx(&a, 2, 100, 50),
+            dynamic_tx(&b, 0, 15, 10),
+            tx(&b, 1, 12),
+        ]);
+        assert_eq!(
+            pool.status(),
+            PoolStatus {
+                pending: 3,
+                base_fee: 2,
+                queued: 0,
+                bytes: size(&pool.filter(&a.address())) + size(&pool.filter(&b.address())),
+                senders: 2,
+            }
+        );
+        // effective tips: a0 = 3, b0 = min(10, 15 - 10) = 5, b1 = 2.
+        assert_eq!(prices(&pool.pending(10)), vec![15, 100, 12]);
+
+        // base fee drops and transactions of a are promoted.
+        pool.set_base_fee(5.into());
+        assert_eq!(pool.status().pending, 5);
+        assert_eq!(prices(&pool.pending(10)), vec![15, 12, 100, 8, 100]);
+
+        // base fee rises and legacy transaction of b is demoted, tip of b drops under tip of a.
+        pool.set_base_fee(13.into());
+        assert_eq!(pool.status().base_fee, 3);
+        assert_eq!(prices(&pool.pending(10)), vec![100, 15]);
+
+        assert_eq!(
+            pool.insert(vec![dynamic_tx(&a, 0, 200, 3)]),
+            vec![Err(Error::ReplacementUnderpriced)]
+        );
+        // first two transactions of a pay too low tip, the last one waits for them.
+        pool.raise_min_gas_price(9.into());
+        assert_eq!(pool.len(), 3);
+        assert_eq!(pool.status().queued, 1);
+    }
+
+    #[test]
+    fn test_best_transactions_skip_invalid_sender() {
+        let mut pool = new_pool(PoolConfig::default());
+        let (a, b) = (Random.generate(), Random.generate());
+        pool.insert(vec![
+            tx(&a, 0, 50),
+            tx(&a, 1, 40),
+            tx(&b, 0, 30),
+            tx(&b, 1, 20),
+        ]);
+        let mut best = pool.best_transactions();
+        let first = best.next().unwrap();
+        assert_eq!(first.gas_price(), 50.into());
+        best.mark_invalid(&first);
+        assert_eq!(prices(&best.collect::<Vec<_>>()), vec![30, 20]);
+    }
+
+    #[test]
+    fn test_state_validation() {
+        let state = Arc::new(TestState::default());
+        let mut pool = Pool::new(PoolConfig::default(), state.clone());
+        let key = Random.generate();
+        let sender = key.address();
+        // enough for four transactions paying 10 for 21000 gas.
+        state.set(sender, 2, 840_000);
+
+        let results = pool.insert(vec![
+            tx(&key, 1, 10),
+            tx(&key, 2, 10),
+            tx(&key, 3, 31),
+            tx(&key, 3, 19),
+            tx(&key, 4, 10),
+            tx(&key, 5, 2),
+        ]);
+        assert_eq!(results[0], Err(Error::StaleNonce));
+        // every transaction is affordable alone, but not together with the previous ones.
+        assert_eq!(results[2], Err(Error::InsufficientFunds));
+        assert_eq!(results[5], Err(Error::InsufficientFunds));
+        assert!(results[1].is_ok() && results[3].is_ok() && results[4].is_ok());
+        assert_eq!(pool.next_account_nonce(&sender), 5.into());
+        // replacement is checked without the transaction it replaces.
+        assert!(pool.insert(vec![tx(&key, 4, 11)])[0].is_ok());
+
+        // block with nonce 2 is committed and it spent most of the balance.
+        state.set(sender, 3, 500_000);
+        pool.revalidate(&[sender]);
+        let left = pool.filter(&sender);
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].nonce, 3.into());
+        assert_eq!(
+            pool.status(),
+            PoolStatus {
+                pending: 1,
+                base_fee: 0,
+                queued: 0,
+                bytes: size(&left),
+                senders: 1,
+            }
+        );
+        assert_eq!(pool.next_account_nonce(&sender), 4.into());
+    }
+
+    fn block(txs: &[&Transaction]) -> WireBlock {
+        WireBlock {
+            header: Default::default(),
+            body: core::BlockBody {
+                transactions: txs.iter().map(|tx| (*tx).clone()).collect(),
+                ommers: Vec::new(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_reorg_reinserts_orphaned_transactions() {
+        let state = Arc::new(TestState::default());
+        let mut pool = Pool::new(PoolConfig::default(), state.clone());
+        let (a, b) = (Random.generate(), Random.generate());
+        let a_txs: Vec<Transaction> = (0..3).map(|nonce| tx(&a, nonce, 10)).collect();
+        let b_tx = tx(&b, 0, 10);
+        pool.insert(a_txs.clone());
+        pool.insert(vec![b_tx.clone()]);
+
+        let old_block = [&a_txs[0], &a_txs[1], &b_tx];
+        state.set(a.address(), 2, 1_000_000_000);
+        state.set(b.address(), 1, 1_000_000_000);
+        pool.on_canonical_state_change(&[block(&old_block)], &[]);
+        assert_eq!(pool.len(), 1);
+        assert_eq!(pool.status().pending, 1);
+
+        // block is replaced by one that includes only first transaction of a.
+        state.set(a.address(), 1, 1_000_000_000);
+        state.set(b.address(), 0, 1_000_000_000);
+        pool.on_canonical_state_change(&[block(&[&a_txs[0]])], &[block(&old_block)]);
+        assert_eq!(pool.len(), 3);
+        assert!(pool.find(&a_txs[0].hash()).is_none());
+        let nonces: Vec<U64> = pool
+            .filter(&a.address())
+            .iter()
+            .map(|tx| tx.nonce)
+            .collect();
+        assert_eq!(nonces, vec![1.into(), 2.into()]);
+        assert_eq!(pool.status().pending, 3);
+        assert_eq!(pool.next_account_nonce(&b.address()), 1.into());
+    }
+
+    #[test]
+    fn test_canonical_change_revalidates_block_senders() {
+        let state = Arc::new(TestState::default());
+        let mut pool = Pool::new(PoolConfig::default(), state.clone());
+        let (a, b) = (Random.generate(), Random.generate());
+        let (a_tx, b_tx) = (tx(&a, 0, 10), tx(&b, 0, 10));
+        pool.insert(vec![a_tx.clone(), b_tx.clone()]);
+
+        // b is not in block, so its transaction stays until it is included.
+        state.set(a.address(), 1, 1_000_000_000);
+        state.set(b.address(), 1, 1_000_000_000);
+        // transactions decoded from block body come without author.
+        let mined = Transaction::decode(&a_tx.encode()).unwrap();
+        assert!(!mined.has_author());
+        pool.on_canonical_state_change(&[block(&[&mined])], &[]);
+        assert!(pool.find(&a_tx.hash()).is_none());
+        assert!(pool.find(&b_tx.hash()).is_some());
+        assert_eq!(pool.next_account_nonce(&a.address()), 1.into());
+    }
+
+    #[test]
+    fn test_local_transactions_are_not_evicted() {
+        let mut pool = new_pool(PoolConfig {
+            max_transactions: 2,
+            ..Default::default()
+        });
+        let (local, remote) = (Random.generate(), Random.generate());
+        assert!(pool.insert_local(tx(&local, 0, 1)).is_ok());
+        assert!(pool.insert(vec![tx(&remote, 0, 5)])[0].is_ok());
+        assert_eq!(pool.worst_gas_price_tx().unwrap().gas_price(), 5.into());
+
+        // transactions from local sender are local even if they come from network.
+        assert!(pool.insert(vec![tx(&local, 1, 2)])[0].is_ok());
+        assert!(pool.filter(&remote.address()).is_empty());
+        assert!(pool.worst_gas_price_tx().is_none());
+        assert_eq!(
+            pool.insert(vec![tx(&remote, 0, 100)]),
+            vec![Err(Error::Underpriced)]
+        );
+
+        pool.raise_min_gas_price(10.into());
+        assert!(pool.insert_local(tx(&local, 2, 3)).is_ok());
+        assert_eq!(prices(&pool.filter(&local.address())), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_queued_transactions_expire() {
+        let mut pool = new_pool(PoolConfig::default());
+        let (local, remote) = (Random.generate(), Random.generate());
+        assert!(pool.insert_local(tx(&local, 1, 10)).is_ok());
+        pool.insert(vec![tx(&remote, 0, 10), tx(&remote, 2, 10)]);
+        let events = pool.subscribe();
+
+        let now = Instant::now();
+        assert!(pool.evict_expired_at(now).is_empty());
+        let expired = pool.evict_expired_at(now + pool.config().lifetime);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].nonce, 2.into());
+        assert!(matches!(
+            events.try_recv(),
+            Ok(PoolEvent::Dropped(_, DropReason::Expired))
+        ));
+
+        // pending transaction of remote and queued local one are kept.
+        assert_eq!(pool.len(), 2);
+        let status = pool.status();
+        assert_eq!((status.pending, status.queued, status.senders), (1, 1, 2));
+        assert_eq!(
+            status.bytes,
+            size(&pool.filter(&local.address())) + size(&pool.filter(&remote.address()))
+        );
+    }
+
+    #[test]
+    fn test_journal_replay_and_rebroadcast() {
+        let path = std::env::temp_dir().join(format!("reth-txpool-{}", std::process::id()));
+        let config = PoolConfig {
+            journal: Some(path.clone()),
+            ..Default::default()
+        };
+        let key = Random.generate();
+        let local_txs = [tx(&key, 0, 10), tx(&key, 1, 10)];
+
+        let mut pool = new_pool(config.clone());
+        for tx in local_txs.iter().rev() {
+            assert!(pool.insert_local(tx.clone()).is_ok());
+        }
+        pool.insert(vec![tx(&Random.generate(), 0, 10)]);
+
+        let now = Instant::now();
+        assert!(pool.rebroadcast_at(now).is_empty());
+        let later = now + config.rebroadcast_interval;
+        assert_eq!(pool.rebroadcast_at(later).len(), 2);
+        assert!(pool.rebroadcast_at(later).is_empty());
+
+        // restart
+        let mut pool = new_pool(config.clone());
+        assert_eq!(pool.status().pending, 2);
+        assert!(pool.find(&local_txs[1].hash()).is_some());
+        assert_eq!(pool.replay_journal(), 0);
+
+        // mined transaction is removed from journal.
+        pool.on_canonical_state_change(&[block(&[&local_txs[0]])], &[]);
+        let pool = new_pool(config);
+        assert_eq!(pool.len(), 1);
+        assert!(pool.find(&local_txs[1].hash()).is_some());
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_pool_events() {
+        let state = Arc::new(TestState::default());
+        let mut pool = Pool::new(PoolConfig::default(), state.clone());
+        let events = pool.subscribe();
+        let dropped = pool.subscribe();
+        drop(dropped);
+        let key = Random.generate();
+        let (first, gapped) = (tx(&key, 0, 10), tx(&key, 2, 10));
+        pool.insert(vec![first.clone(), gapped.clone(), tx(&key, 0, 20)]);
+        pool.raise_min_gas_price(15.into());
+        state.set(key.address(), 1, 1_000_000_000);
+        pool.on_canonical_state_change(&[block(&[&first])], &[]);
+
+        let events: Vec<PoolEvent> = events.try_iter().collect();
+        assert_eq!(events.len(), 5);
+        assert!(matches!(events[0], PoolEvent::AddedPending(ref tx) if tx.hash() == first.hash()));
+        assert!(matches!(events[1], PoolEvent::AddedQueued(ref tx) if tx.hash() == gapped.hash()));
+        assert!(
+            matches!(events[2], PoolEvent::Replaced { ref old, .. } if old.hash() == first.hash())
+        );
+        assert!(matches!(
+            events[3],
+            PoolEvent::Dropped(ref tx, DropReason::Underpriced) if tx.hash() == gapped.hash()
+        ));
+        // replacement is dropped when transaction it replaced is mined.
+        assert!(matches!(
+            events[4],
+            PoolEvent::Dropped(_, DropReason::StaleNonce)
+        ));
+        assert!(pool.subscribers.len() == 1);
+    }
+
+    #[test]
+    fn test_promoted_events() {
+        let mut pool = new_pool(PoolConfig::default());
+        let (a, b) = (Random.generate(), Random.generate());
+        pool.set_base_fee(10.into());
+        let events = pool.subscribe();
+        pool.insert(vec![
+            tx(&a, 0, 20),
+            tx(&a, 2, 20),
+            tx(&a, 1, 5),
+            tx(&b, 2, 20),
+        ]);
+        let promoted = |events: &Receiver<PoolEvent>| -> Vec<u64> {
+            events
+                .try_iter()
+                .filter_map(|event| match event {
+                    PoolEvent::Promoted(tx) => Some(tx.nonce.as_u64()),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert!(promoted(&events).is_empty());
+
+        pool.set_base_fee(5.into());
+        assert_eq!(promoted(&events), vec![1, 2]);
+        pool.set_account_nonce(&b.address(), 2.into());
+        assert_eq!(promoted(&events), vec![2]);
+        assert_eq!(pool.status().pending, 4);
+    }
+
+    fn signed_blob_tx(key: &KeyPair) -> (Transaction, BlobSidecar) {
+        let sidecar = BlobSidecar {
+            blobs: vec![vec![1; BYTES_PER_BLOB]],
+            commitments: vec![vec![2; 48]],
+            proofs: vec![vec![3; 48]],
+        };
+        let mut tx = Transaction::default();
+        tx.chain_id = Some(100);
+        tx.gas_limit = 21_000.into();
+        tx.to = CallType::CallMessage(Address::from_low_u64_be(1));
+        tx.type_payload = TypePayload::Blob(BlobPayload {
+            max_priority_fee_per_gas: 2.into(),
+            max_fee_per_gas: 10.into(),
+            access_list: Vec::new(),
+            max_fee_per_blob_gas: 5.into(),
+            blob_versioned_hashes: sidecar.versioned_hashes(),
+        });
+        tx.sign(key.secret());
+        (tx, sidecar)
+    }
+
+    #[test]
+    fn test_blob_transactions() {
+        let key = Random.generate();
+        let (blob_tx, sidecar) = signed_blob_tx(&key);
+
+        let mut pool = new_pool(PoolConfig::default());
+        assert_eq!(
+            pool.insert_blob(blob_tx.clone(), sidecar.clone()),
+            Err(Error::TxTypeNotSupported)
+        );
+
+        let dir = std::env::temp_dir().join(format!("reth-pool-blobs-{}", std::process::id()));
+        let mut pool = new_pool(PoolConfig {
+            blob_sidecars: Some(dir.clone()),
+            ..Default::default()
+        });
+        assert_eq!(
+            pool.insert(vec![blob_tx.clone()]),
+            vec![Err(Error::InvalidSidecar)]
+        );
+        // proofs can't be verified without KZG library.
+        assert_eq!(
+            pool.insert_blob(blob_tx.clone(), sidecar.clone()),
+            Err(Error::InvalidSidecar)
+        );
+        pool.set_blob_verifier(Arc::new(AcceptBlobs));
+        let hash = pool.insert_blob(blob_tx.clone(), sidecar.clone()).unwrap();
+        assert!(pool.find(&hash).is_some());
+        assert_eq!(pool.blob_sidecar(&hash), Some(sidecar));
+        assert_eq!(pool.next_account_nonce(&key.address()), 1.into());
+        assert_eq!(
+            pool.insert(vec![tx(&key, 1, 10)]),
+            vec![Err(Error::SenderReserved)]
+        );
+        assert_eq!(pool.pending(10).len(), 0);
+        assert_eq!(pool.pending_blobs(10).len(), 1);
+        pool.set_blob_base_fee(6.into());
+        assert_eq!(pool.pending_blobs(10).len(), 0);
+        assert_eq!(pool.status().base_fee, 1);
+
+        pool.on_canonical_state_change(&[block(&[&blob_tx])], &[]);
+        assert!(pool.find(&hash).is_none());
+        assert_eq!(pool.blob_sidecar(&hash), None);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_reorg_reinserts_orphaned_blob_transaction() {
+        let dir = std::env::temp_dir().join(format!("reth-pool-reorg-{}", std::process::id()));
+        let state = Arc::new(TestState::default());
+        let mut pool = Pool::new(
+            PoolConfig {
+                blob_sidecars: Some(dir.clone()),
+                ..Default::default()
+            },
+            state.clone(),
+        );
+        pool.set_blob_verifier(Arc::new(AcceptBlobs));
+        let key = Random.generate();
+        let (blob_tx, sidecar) = signed_blob_tx(&key);
+        let hash = pool.insert_blob(blob_tx.clone(), sidecar.clone()).unwrap();
+
+        // transactions in block body come without author.
+        let mined = Transaction::decode(&blob_tx.encode()).unwrap();
+        state.set(key.address(), 1, 1_000_000_000);
+        pool.on_canonical_state_change(&[block(&[&mined])], &[]);
+        assert!(pool.find(&hash).is_none());
+        assert_eq!(pool.blob_sidecar(&hash), None);
+
+        state.set(key.address(), 0, 1_000_000_000);
+        pool.on_canonical_state_change(&[block(&[])], &[block(&[&mined])]);
+        assert!(pool.find(&hash).is_some());
+        assert_eq!(pool.blob_sidecar(&hash), Some(sidecar));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
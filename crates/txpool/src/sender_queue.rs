@@ -0,0 +1,241 @@
+// Copyright 2021 Gnosis Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+use core::{Transaction, U256};
+use std::{collections::BTreeMap, sync::Arc};
+
+/// Transactions of one sender ordered by nonce. Transactions that follow account nonce without
+/// a gap are pending until first one that does not pay base fee, the rest of them are in
+/// base fee subpool. Transactions after a gap are queued until the gap is filled.
+#[derive(Debug, Clone, Default)]
+pub struct SenderQueue {
+    /// Nonce of sender account in latest state.
+    nonce: u64,
+    /// Base fee of next block.
+    base_fee: U256,
+    txs: BTreeMap<u64, Arc<Transaction>>,
+    /// Number of pending transactions, recalculated on every change.
+    pending: usize,
+    /// Number of transactions that follow account nonce without a gap.
+    gapless: usize,
+    /// Transactions that became pending since last `take_promoted`.
+    promoted: Vec<Arc<Transaction>>,
+}
+
+impl SenderQueue {
+    pub fn new(nonce: u64, base_fee: U256) -> Self {
+        SenderQueue {
+            nonce,
+            base_fee,
+            txs: BTreeMap::new(),
+            pending: 0,
+            gapless: 0,
+            promoted: Vec::new(),
+        }
+    }
+
+    pub fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    pub fn len(&self) -> usize {
+        self.txs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.txs.is_empty()
+    }
+
+    pub fn get(&self, nonce: u64) -> Option<&Arc<Transaction>> {
+        self.txs.get(&nonce)
+    }
+
+    /// Returns replaced transaction with the same nonce.
+    pub fn insert(&mut self, tx: Arc<Transaction>) -> Option<Arc<Transaction>> {
+        let pending_end = self.pending_end();
+        let replaced = self.txs.insert(tx.nonce.as_u64(), tx);
+        self.update_subpools(pending_end);
+        replaced
+    }
+
+    pub fn remove(&mut self, nonce: u64) -> Option<Arc<Transaction>> {
+        let pending_end = self.pending_end();
+        let removed = self.txs.remove(&nonce);
+        self.update_subpools(pending_end);
+        removed
+    }
+
+    /// Transactions that moved from queued or base fee subpool to pending since last call,
+    /// in nonce order.
+    pub fn take_promoted(&mut self) -> Vec<Arc<Transaction>> {
+        std::mem::take(&mut self.promoted)
+    }
+
+    /// All transactions in nonce order.
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<Transaction>> {
+        self.txs.values()
+    }
+
+    /// Transactions that can be executed one after another on top of latest state.
+    pub fn pending(&self) -> impl Iterator<Item = &Arc<Transaction>> {
+        self.txs
+            .range(self.nonce..)
+            .take(self.pending)
+            .map(|(_, tx)| tx)
+    }
+
+    /// Transactions that wait for nonce gap to be filled.
+    pub fn queued(&self) -> impl Iterator<Item = &Arc<Transaction>> {
+        self.txs.range(self.next_nonce()..).map(|(_, tx)| tx)
+    }
+
+    pub fn is_pending(&self, nonce: u64) -> bool {
+        nonce >= self.nonce && nonce < self.nonce + self.pending as u64
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.pending
+    }
+
+    pub fn base_fee_count(&self) -> usize {
+        self.gapless - self.pending
+    }
+
+    pub fn queued_count(&self) -> usize {
+        self.len() - self.gapless
+    }
+
+    /// Nonce that next transaction of sender needs to have.
+    pub fn next_nonce(&self) -> u64 {
+        self.nonce + self.gapless as u64
+    }
+
+    /// Transaction with the highest nonce, it is the first one to go if pool is full.
+    pub fn last(&self) -> Option<&Arc<Transaction>> {
+        self.txs.values().next_back()
+    }
+
+    /// Sets account nonce and returns transactions that can't be included anymore.
+    pub fn set_nonce(&mut self, nonce: u64) -> Vec<Arc<Transaction>> {
+        let pending_end = self.pending_end();
+        self.nonce = nonce;
+        let valid = self.txs.split_off(&nonce);
+        let stale = std::mem::replace(&mut self.txs, valid);
+        self.update_subpools(pending_end);
+        stale.into_values().collect()
+    }
+
+    /// Promotes transactions that pay new base fee to pending, or demotes the ones that don't.
+    pub fn set_base_fee(&mut self, base_fee: U256) {
+        let pending_end = self.pending_end();
+        self.base_fee = base_fee;
+        self.update_subpools(pending_end);
+    }
+
+    /// Nonce after the last pending transaction.
+    fn pending_end(&self) -> u64 {
+        self.nonce + self.pending as u64
+    }
+
+    /// Pending transactions at or after `pending_end` of previous state are promoted.
+    fn update_subpools(&mut self, pending_end: u64) {
+        let (mut pending, mut gapless) = (0, 0);
+        for ((nonce, tx), expected) in self.txs.range(self.nonce..).zip(self.nonce..) {
+            if *nonce != expected {
+                break;
+            }
+            // transaction that can't pay base fee blocks all transactions after it.
+            if pending == gapless && tx.gas_price() >= self.base_fee {
+                pending += 1;
+            }
+            gapless += 1;
+        }
+        self.pending = pending;
+        self.gapless = gapless;
+        let (start, end) = (pending_end.max(self.nonce), self.pending_end());
+        if start < end {
+            self.promoted
+                .extend(self.txs.range(start..end).map(|(_, tx)| tx.clone()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::transaction::{LegacyPayload, TypePayload};
+
+    fn tx(nonce: u64) -> Arc<Transaction> {
+        let mut tx = Transaction::default();
+        tx.nonce = nonce.into();
+        Arc::new(tx)
+    }
+
+    fn priced_tx(nonce: u64, gas_price: u64) -> Arc<Transaction> {
+        let mut tx = Transaction::default();
+        tx.nonce = nonce.into();
+        tx.type_payload = TypePayload::Legacy(LegacyPayload {
+            gas_price: gas_price.into(),
+        });
+        Arc::new(tx)
+    }
+
+    #[test]
+    fn test_pending_stops_at_gap() {
+        let mut queue = SenderQueue::new(5, U256::zero());
+        for nonce in [5, 6, 8, 9].iter() {
+            queue.insert(tx(*nonce));
+        }
+        assert_eq!(queue.pending_count(), 2);
+        assert_eq!(queue.queued_count(), 2);
+        assert_eq!(queue.next_nonce(), 7);
+
+        queue.insert(tx(7));
+        assert_eq!(queue.pending_count(), 5);
+        assert_eq!(queue.last().unwrap().nonce.as_u64(), 9);
+
+        let stale = queue.set_nonce(8);
+        assert_eq!(stale.len(), 3);
+        assert_eq!(queue.pending_count(), 2);
+        assert!(queue.set_nonce(20).len() == 2 && queue.is_empty());
+    }
+
+    #[test]
+    fn test_base_fee_subpool() {
+        let mut queue = SenderQueue::new(0, 10.into());
+        for (nonce, gas_price) in [(0, 20), (1, 5), (2, 20), (4, 20)].iter() {
+            queue.insert(priced_tx(*nonce, *gas_price));
+        }
+        assert_eq!(queue.pending_count(), 1);
+        assert_eq!(queue.base_fee_count(), 2);
+        assert_eq!(queue.queued_count(), 1);
+        assert_eq!(queue.next_nonce(), 3);
+
+        queue.set_base_fee(5.into());
+        assert_eq!(queue.pending_count(), 3);
+        assert_eq!(queue.base_fee_count(), 0);
+
+        queue.set_base_fee(25.into());
+        assert_eq!(queue.pending().count(), 0);
+        assert_eq!(queue.base_fee_count(), 3);
+    }
+
+    #[test]
+    fn test_promoted_transactions() {
+        let mut queue = SenderQueue::new(0, 10.into());
+        for (nonce, gas_price) in [(0, 20), (1, 5), (2, 20), (4, 20)].iter() {
+            queue.insert(priced_tx(*nonce, *gas_price));
+        }
+        let nonces = |txs: Vec<Arc<Transaction>>| -> Vec<u64> {
+            txs.iter().map(|tx| tx.nonce.as_u64()).collect()
+        };
+        assert_eq!(nonces(queue.take_promoted()), vec![0]);
+
+        queue.set_base_fee(5.into());
+        assert_eq!(nonces(queue.take_promoted()), vec![1, 2]);
+        queue.insert(priced_tx(3, 20));
+        assert_eq!(nonces(queue.take_promoted()), vec![3, 4]);
+        queue.set_base_fee(25.into());
+        assert!(queue.take_promoted().is_empty());
+    }
+}
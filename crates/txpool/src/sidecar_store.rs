@@ -0,0 +1,117 @@
+// Copyright 2021 Gnosis Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+use core::{transaction::BlobSidecar, H256};
+use std::{fs, io, path::PathBuf};
+
+/// Subdirectory of configured directory that store owns.
+const SIDECARS_DIR: &str = "blob_sidecars";
+
+/// Blob sidecars kept on disk, one file per transaction named by its hash. Blobs are large
+/// and they are needed only when transaction is requested by peer or included in block.
+#[derive(Debug, Clone)]
+pub struct SidecarStore {
+    dir: PathBuf,
+}
+
+/// Name of sidecar file is hex of transaction hash.
+fn is_sidecar_file(name: &str) -> bool {
+    name.len() == 64 && name.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+impl SidecarStore {
+    /// Blob transactions are not kept across restarts, sidecars of previous run are removed.
+    pub fn new(dir: PathBuf) -> Self {
+        let dir = dir.join(SIDECARS_DIR);
+        if let Err(err) = fs::create_dir_all(&dir) {
+            error!(
+                "Txpool: could not create sidecar directory {:?}: {}",
+                dir, err
+            );
+        }
+        let store = SidecarStore { dir };
+        store.clear();
+        store
+    }
+
+    /// Removes only files that look like sidecars, anything else in directory is left alone.
+    fn clear(&self) {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                error!(
+                    "Txpool: could not clear sidecars in {:?}: {}",
+                    self.dir, err
+                );
+                return;
+            }
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let is_sidecar = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map_or(false, is_sidecar_file);
+            if is_sidecar && path.is_file() {
+                if let Err(err) = fs::remove_file(&path) {
+                    error!("Txpool: could not remove sidecar {:?}: {}", path, err);
+                }
+            }
+        }
+    }
+
+    fn path(&self, hash: &H256) -> PathBuf {
+        self.dir.join(format!("{:x}", hash))
+    }
+
+    pub fn insert(&self, hash: &H256, sidecar: &BlobSidecar) -> io::Result<()> {
+        fs::write(self.path(hash), rlp::encode(sidecar))
+    }
+
+    pub fn get(&self, hash: &H256) -> Option<BlobSidecar> {
+        let data = fs::read(self.path(hash)).ok()?;
+        match rlp::decode(&data) {
+            Ok(sidecar) => Some(sidecar),
+            Err(err) => {
+                error!("Txpool: invalid sidecar of {}: {:?}", hash, err);
+                None
+            }
+        }
+    }
+
+    pub fn remove(&self, hash: &H256) {
+        // sidecar could have failed to save, there is nothing to report then.
+        let _ = fs::remove_file(self.path(hash));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sidecar_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("reth-sidecars-{}", std::process::id()));
+        let store = SidecarStore::new(dir.clone());
+        let hash = H256::from_low_u64_be(1);
+        let sidecar = BlobSidecar {
+            blobs: vec![vec![1; 64]],
+            commitments: vec![vec![2; 48]],
+            proofs: vec![vec![3; 48]],
+        };
+        store.insert(&hash, &sidecar).unwrap();
+        assert_eq!(store.get(&hash), Some(sidecar));
+        let other = dir.join(SIDECARS_DIR).join("other");
+        fs::write(&other, b"other").unwrap();
+        fs::write(dir.join("config"), b"config").unwrap();
+
+        // new store starts empty and keeps files it did not create
+        let store = SidecarStore::new(dir.clone());
+        assert_eq!(store.get(&hash), None);
+        assert!(other.exists() && dir.join("config").exists());
+        store.insert(&hash, &BlobSidecar::default());
+        store.remove(&hash);
+        assert_eq!(store.get(&hash), None);
+        let _ = fs::remove_dir_all(dir);
+    }
+}